@@ -0,0 +1,563 @@
+//! Reading grammars written in ABNF, as specified by RFC 5234.
+//!
+//! ABNF describes strings of characters, so the grammars produced have `char` terminals.
+
+use std::char;
+use std::collections::HashMap;
+
+use super::{Cfg, Rule, SourceMap, Span, Symbol, SyntaxError, Variable};
+use import::{Scanner, Symbols, body_span, error_at, is_space, span};
+
+/// The core rules of RFC 5234 appendix B.1, which are added to a grammar that uses them without
+/// defining them.
+static CORE_RULES: &'static [(&'static str, &'static str)] = &[
+    ("ALPHA", "%x41-5A / %x61-7A"),
+    ("BIT", "\"0\" / \"1\""),
+    ("CHAR", "%x01-7F"),
+    ("CR", "%x0D"),
+    ("CRLF", "CR LF"),
+    ("CTL", "%x00-1F / %x7F"),
+    ("DIGIT", "%x30-39"),
+    ("DQUOTE", "%x22"),
+    ("HEXDIG", "DIGIT / \"A\" / \"B\" / \"C\" / \"D\" / \"E\" / \"F\""),
+    ("HTAB", "%x09"),
+    ("LF", "%x0A"),
+    ("LWSP", "*(WSP / CRLF WSP)"),
+    ("OCTET", "%x00-FF"),
+    ("SP", "%x20"),
+    ("VCHAR", "%x21-7E"),
+    ("WSP", "SP / HTAB")
+];
+
+/// The largest number of characters a value range such as `%x41-5A` may cover.
+static MAX_RANGE: u32 = 0x10000;
+
+/// The largest count a repetition such as `1*8DIGIT` may give, as each copy of the element up
+/// to the maximum is a symbol or a variable of its own.
+static MAX_REPEAT: u32 = 0x1000;
+
+/// Parse a grammar written in ABNF.
+///
+/// Rule names are case-insensitive, and each variable is named by the spelling of its first
+/// occurrence. Both `=` and incremental `=/` definitions are accepted. Quoted strings match
+/// case-insensitively unless written `%s"..."`. Value ranges become a variable with one rule per
+/// character in the range, and repetitions are lowered to fresh variables named after the rule
+/// they appear in. Prose values (`<...>`) can't be converted and are reported as errors. The
+/// variable of the first rule is the start symbol.
+pub fn parse(src: &str) -> Result<Cfg<char>, SyntaxError> {
+    parse_with_spans(src).map(|(cfg, _)| cfg)
+}
+
+/// Parse a grammar written in ABNF as for `parse`, also giving where each variable was first
+/// defined and each rule written. The core rules added, and the rules of the variables made for
+/// groups, repetitions and value ranges, have no spans.
+pub fn parse_with_spans(src: &str) -> Result<(Cfg<char>, SourceMap), SyntaxError> {
+    let mut reader = Reader {
+        syms: Symbols::new(),
+        names: HashMap::new(),
+        aux: HashMap::new()
+    };
+    try!(reader.rules(src, true));
+    loop {
+        let mut added = false;
+        for &(name, def) in CORE_RULES.iter() {
+            let used = match reader.names.get(&fold(name)) {
+                Some(spelling) => !reader.syms.is_defined(spelling.as_slice()),
+                None => false
+            };
+            if used {
+                try!(reader.rules(format!("{} = {}", name, def).as_slice(), false));
+                added = true;
+            }
+        }
+        if !added {
+            break
+        }
+    }
+    reader.syms.finish_with_spans()
+}
+
+struct Reader {
+    syms: Symbols<char>,
+    /// The spelling used for each rule name, keyed by its lowercase form.
+    names: HashMap<String, String>,
+    /// Variables standing for value ranges and case-insensitive letters.
+    aux: HashMap<String, Variable>
+}
+
+impl Reader {
+    fn spelling(&mut self, name: String) -> String {
+        let key = fold(name.as_slice());
+        match self.names.get(&key) {
+            Some(spelling) => return spelling.clone(),
+            None => { }
+        }
+        self.names.insert(key, name.clone());
+        name
+    }
+
+    /// Read the rules in `src`, recording their spans if it's the source being parsed.
+    fn rules(&mut self, src: &str, spanned: bool) -> Result<(), SyntaxError> {
+        let mut s = Scanner::new(src);
+        skip_space(&mut s);
+        while !s.at_end() {
+            if !s.peek().map_or(false, is_alpha) {
+                return Err(s.error("expected a rule name"))
+            }
+            let at = s.mark();
+            let name = s.take_while(in_rulename);
+            let name_span = span(at, s.mark());
+            skip_space(&mut s);
+            if !s.eat("=") {
+                return Err(s.error("expected `=` or `=/`"))
+            }
+            s.eat("/");
+            let name = self.spelling(name);
+            let lhs = if spanned {
+                self.syms.define_at(name.as_slice(), name_span)
+            } else {
+                self.syms.define(name.as_slice())
+            };
+            let bodies = try!(self.alternation(&mut s, name.as_slice()));
+            for (body, rule_span) in bodies.into_iter() {
+                if spanned {
+                    self.syms.add_rule_at(lhs, body, rule_span);
+                } else {
+                    self.syms.add_rule(lhs, body);
+                }
+            }
+            skip_space(&mut s);
+        }
+        Ok(())
+    }
+
+    fn alternation(&mut self, s: &mut Scanner, rule: &str)
+                   -> Result<Vec<(Rule, Span)>, SyntaxError> {
+        let mut bodies = Vec::new();
+        loop {
+            bodies.push(try!(self.concatenation(s, rule)));
+            skip_space(s);
+            if !s.eat("/") {
+                return Ok(bodies)
+            }
+        }
+    }
+
+    /// Read the body of a rule, along with where it's written.
+    fn concatenation(&mut self, s: &mut Scanner, rule: &str)
+                     -> Result<(Rule, Span), SyntaxError> {
+        let before = s.mark();
+        skip_space(s);
+        let start = s.mark();
+        let mut end = start;
+        let mut body = Vec::new();
+        loop {
+            skip_space(s);
+            let item = match s.peek() {
+                Some(c) if c == '*' || digit_value(c, 10).is_some() => {
+                    let counts = s.mark();
+                    let min = try!(number(s, 10));
+                    let max = if s.eat("*") {
+                        try!(number(s, 10))
+                    } else {
+                        match min {
+                            Some(n) => Some(n),
+                            None => return Err(s.error("expected a repetition count"))
+                        }
+                    };
+                    let at = s.mark();
+                    let item = match try!(self.element(s, rule)) {
+                        Some(item) => item,
+                        None => return Err(s.error("expected an element to repeat"))
+                    };
+                    let min = min.unwrap_or(0);
+                    if min > MAX_REPEAT || max.map_or(false, |max| max > MAX_REPEAT) {
+                        s.reset(counts);
+                        return Err(s.error("repetition count is too large"))
+                    }
+                    match max {
+                        Some(max) if max < min => {
+                            s.reset(at);
+                            return Err(s.error("repetition maximum is less than its minimum"))
+                        },
+                        _ => { }
+                    }
+                    self.repeat(rule, item, min, max)
+                },
+                Some(c) if is_alpha(c) && starts_rule(s) => {
+                    return Ok((body, body_span(before, start, end)))
+                },
+                _ => match try!(self.element(s, rule)) {
+                    Some(item) => item,
+                    None => return Ok((body, body_span(before, start, end)))
+                }
+            };
+            body.extend(item.into_iter());
+            end = s.mark();
+        }
+    }
+
+    /// Parse a single element, giving the sequence of symbols it stands for, or `None` if there
+    /// is no element at the cursor.
+    fn element(&mut self, s: &mut Scanner, rule: &str)
+               -> Result<Option<Vec<Symbol>>, SyntaxError> {
+        let at = s.mark();
+        let item = match s.peek() {
+            Some(c) if is_alpha(c) => {
+                let name = s.take_while(in_rulename);
+                let name = self.spelling(name);
+                vec![Symbol::Var(self.syms.variable(name.as_slice(), at))]
+            },
+            Some('(') => try!(self.group(s, rule, ")")),
+            Some('[') => {
+                let item = try!(self.group(s, rule, "]"));
+                self.repeat(rule, item, 0, Some(1))
+            },
+            Some('"') => try!(self.char_val(s, false)),
+            Some('%') => {
+                s.bump();
+                match s.peek() {
+                    Some('s') | Some('S') => {
+                        s.bump();
+                        try!(self.char_val(s, true))
+                    },
+                    Some('i') | Some('I') => {
+                        s.bump();
+                        try!(self.char_val(s, false))
+                    },
+                    Some('x') | Some('X') => try!(self.num_val(s, 16)),
+                    Some('d') | Some('D') => try!(self.num_val(s, 10)),
+                    Some('b') | Some('B') => try!(self.num_val(s, 2)),
+                    _ => return Err(s.error("expected `x`, `d`, `b`, `s` or `i` after `%`"))
+                }
+            },
+            Some('<') => return Err(s.error("prose values can't be converted to rules")),
+            _ => return Ok(None)
+        };
+        Ok(Some(item))
+    }
+
+    fn group(&mut self, s: &mut Scanner, rule: &str, close: &str)
+             -> Result<Vec<Symbol>, SyntaxError> {
+        let open = s.mark();
+        s.bump();
+        let bodies = try!(self.alternation(s, rule));
+        skip_space(s);
+        if !s.eat(close) {
+            s.reset(open);
+            return Err(s.error(format!("unclosed group, expected `{}`", close).as_slice()))
+        }
+        Ok(self.syms.choice(rule, bodies.into_iter().map(|(body, _)| body).collect()))
+    }
+
+    /// Parse a quoted string. ABNF strings have no escapes.
+    fn char_val(&mut self, s: &mut Scanner, case_sensitive: bool)
+                -> Result<Vec<Symbol>, SyntaxError> {
+        let open = s.mark();
+        if !s.eat("\"") {
+            return Err(s.error("expected a string"))
+        }
+        let mut item = Vec::new();
+        loop {
+            match s.bump() {
+                Some('"') => return Ok(item),
+                Some('\n') | None => {
+                    s.reset(open);
+                    return Err(s.error("unterminated string"))
+                },
+                Some(c) => {
+                    let (lower, upper) = (to_lower(c), to_upper(c));
+                    if case_sensitive || lower == upper {
+                        item.push(self.syms.token(c));
+                    } else {
+                        let name = format!("\"{}\"", lower);
+                        let v = self.auxiliary(name, &[lower, upper]);
+                        item.push(Symbol::Var(v));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parse a numeric value such as `%x41`, `%x41.42` or `%x41-5A`, after the `%`.
+    fn num_val(&mut self, s: &mut Scanner, radix: u32) -> Result<Vec<Symbol>, SyntaxError> {
+        let base = s.bump().unwrap();
+        let lo = try!(char_value(s, radix));
+        if s.eat("-") {
+            let hi = try!(char_value(s, radix));
+            let (lo, hi) = (lo as u32, hi as u32);
+            if hi < lo {
+                return Err(s.error("value range is empty"))
+            }
+            if hi - lo >= MAX_RANGE {
+                return Err(s.error("value range is too large"))
+            }
+            let chars: Vec<char> = range(lo as uint, hi as uint + 1)
+                .filter_map(|c| char::from_u32(c as u32))
+                .collect();
+            let name = format!("%{}{:X}-{:X}", base, lo, hi);
+            return Ok(vec![Symbol::Var(self.auxiliary(name, chars.as_slice()))])
+        }
+        let mut item = vec![self.syms.token(lo)];
+        while s.eat(".") {
+            let c = try!(char_value(s, radix));
+            item.push(self.syms.token(c));
+        }
+        Ok(item)
+    }
+
+    /// A variable with the given name deriving each of `chars`.
+    fn auxiliary(&mut self, name: String, chars: &[char]) -> Variable {
+        match self.aux.get(&name) {
+            Some(&v) => return v,
+            None => { }
+        }
+        let v = self.syms.lookup(name.as_slice());
+        for &c in chars.iter() {
+            let t = self.syms.token(c);
+            self.syms.add_rule(v, vec![t]);
+        }
+        self.aux.insert(name, v);
+        v
+    }
+
+    /// Lower a repetition of `item` between `min` and `max` times, if there is a maximum.
+    ///
+    /// An item of several symbols that would be copied more than once is first given a fresh
+    /// variable of its own, so nested repetitions grow by one symbol per copy rather than
+    /// multiplying.
+    fn repeat(&mut self, rule: &str, item: Vec<Symbol>, min: u32, max: Option<u32>)
+              -> Vec<Symbol> {
+        let copies = min + max.map_or(1, |max| max - min);
+        let item = if item.len() > 1 && copies > 1 {
+            let v = self.syms.fresh(format!("{}_group", rule).as_slice());
+            self.syms.add_rule(v, item);
+            vec![Symbol::Var(v)]
+        } else {
+            item
+        };
+        let mut seq = Vec::new();
+        for _ in range(0, min as uint) {
+            seq.push_all(item.as_slice());
+        }
+        match max {
+            None => seq.push(self.syms.star(rule, item)),
+            Some(max) => {
+                let mut tail = None;
+                for _ in range(min as uint, max as uint) {
+                    let v = self.syms.fresh(format!("{}_opt", rule).as_slice());
+                    let mut body = item.clone();
+                    match tail {
+                        Some(t) => body.push(Symbol::Var(t)),
+                        None => { }
+                    }
+                    self.syms.add_rule(v, body);
+                    self.syms.add_rule(v, vec![]);
+                    tail = Some(v);
+                }
+                match tail {
+                    Some(t) => seq.push(Symbol::Var(t)),
+                    None => { }
+                }
+            }
+        }
+        seq
+    }
+}
+
+/// Skip whitespace and `;` comments.
+fn skip_space(s: &mut Scanner) {
+    loop {
+        s.skip_while(is_space);
+        if s.peek() != Some(';') {
+            return
+        }
+        s.skip_line();
+    }
+}
+
+/// Whether the cursor is at the `name =` beginning the next rule.
+fn starts_rule(s: &mut Scanner) -> bool {
+    let mark = s.mark();
+    s.skip_while(in_rulename);
+    skip_space(s);
+    let starts = s.looking_at("=");
+    s.reset(mark);
+    starts
+}
+
+fn is_alpha(c: char) -> bool {
+    ('a' <= c && c <= 'z') || ('A' <= c && c <= 'Z')
+}
+
+fn in_rulename(c: char) -> bool {
+    is_alpha(c) || digit_value(c, 10).is_some() || c == '-'
+}
+
+fn to_lower(c: char) -> char {
+    if 'A' <= c && c <= 'Z' { ((c as u8) + 32) as char } else { c }
+}
+
+fn to_upper(c: char) -> char {
+    if 'a' <= c && c <= 'z' { ((c as u8) - 32) as char } else { c }
+}
+
+fn fold(name: &str) -> String {
+    name.chars().map(to_lower).collect()
+}
+
+fn digit_value(c: char, radix: u32) -> Option<u32> {
+    let d = match c {
+        '0'...'9' => c as u32 - '0' as u32,
+        'a'...'f' => c as u32 - 'a' as u32 + 10,
+        'A'...'F' => c as u32 - 'A' as u32 + 10,
+        _ => return None
+    };
+    if d < radix { Some(d) } else { None }
+}
+
+/// Read a number in the given radix, if there is one at the cursor, reporting one too large for
+/// a `u32`.
+fn number(s: &mut Scanner, radix: u32) -> Result<Option<u32>, SyntaxError> {
+    let start = s.mark();
+    let mut n: Option<u32> = None;
+    loop {
+        match s.peek().and_then(|c| digit_value(c, radix)) {
+            Some(d) => {
+                s.bump();
+                n = match n.unwrap_or(0).checked_mul(radix).and_then(|n| n.checked_add(d)) {
+                    Some(n) => Some(n),
+                    None => return Err(error_at(start, "number is too large"))
+                };
+            },
+            None => return Ok(n)
+        }
+    }
+}
+
+fn char_value(s: &mut Scanner, radix: u32) -> Result<char, SyntaxError> {
+    match try!(number(s, radix)) {
+        Some(n) => match char::from_u32(n) {
+            Some(c) => Ok(c),
+            None => Err(s.error("value is not a valid character"))
+        },
+        None => Err(s.error("expected a number"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use super::super::{Cfg, Rule, Symbol};
+
+    fn rules(cfg: &Cfg<char>, symbol: Symbol) -> &[Rule] {
+        match symbol {
+            Symbol::Var(v) => cfg.get_rules(v).unwrap(),
+            Symbol::Tok(_) => panic!("expected a variable")
+        }
+    }
+
+    fn token(cfg: &Cfg<char>, c: char) -> Symbol {
+        Symbol::Tok(cfg.token(&c).unwrap())
+    }
+
+    #[test]
+    fn strings_and_values() {
+        let cfg = parse("s = %s\"ab\" / \"c\" / %x41.42 / %d65-67\n").unwrap();
+        let s = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(s.len(), 4);
+        assert_eq!(s[0], vec![token(&cfg, 'a'), token(&cfg, 'b')]);
+        assert_eq!(s[1].len(), 1);
+        let expected = [vec![token(&cfg, 'c')], vec![token(&cfg, 'C')]];
+        assert_eq!(rules(&cfg, s[1][0]), expected.as_slice());
+        assert_eq!(s[2], vec![token(&cfg, 'A'), token(&cfg, 'B')]);
+        assert_eq!(s[3].len(), 1);
+        let expected = [vec![token(&cfg, 'A')], vec![token(&cfg, 'B')], vec![token(&cfg, 'C')]];
+        assert_eq!(rules(&cfg, s[3][0]), expected.as_slice());
+    }
+
+    #[test]
+    fn repetition() {
+        let cfg = parse("s = 2*3%x78 [%x79]\n").unwrap();
+        let (x, y) = (token(&cfg, 'x'), token(&cfg, 'y'));
+        let s = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(s.len(), 1);
+        assert_eq!(s[0].len(), 4);
+        assert_eq!((s[0][0], s[0][1]), (x, x));
+        assert_eq!(rules(&cfg, s[0][2]), [vec![x], vec![]].as_slice());
+        assert_eq!(rules(&cfg, s[0][3]), [vec![y], vec![]].as_slice());
+
+        let cfg = parse("s = *%x78\n").unwrap();
+        let x = token(&cfg, 'x');
+        let s = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(s.len(), 1);
+        let star = s[0][0];
+        assert_eq!(rules(&cfg, star), [vec![x, star], vec![]].as_slice());
+    }
+
+    #[test]
+    fn rule_names() {
+        let cfg = parse("
+            Greeting = hello / ( hello SP WORD )  ; a comment
+            HELLO = %s\"hi\"
+            greeting =/ %s\"yo\"
+            word = 1*ALPHA
+        ").unwrap();
+        assert_eq!(cfg.name(cfg.get_start()), Some("Greeting"));
+        let greeting = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(greeting.len(), 3);
+        assert_eq!(greeting[2], vec![token(&cfg, 'y'), token(&cfg, 'o')]);
+        assert_eq!(cfg.name(match greeting[0][0] { Symbol::Var(v) => v, _ => panic!() }),
+                   Some("hello"));
+        // The core rules used are added, along with the rules they use in turn.
+        assert_eq!(greeting[1].len(), 3);
+        assert_eq!(rules(&cfg, greeting[1][1]).len(), 1);
+        assert!(cfg.token(&'z').is_some() && cfg.token(&' ').is_some());
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse("s = %x41 <prose>\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 10));
+        let error = parse("s = 3*2%x41\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 8));
+        let error = parse("s = (%x41\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 5));
+        assert!(parse("s = %x5A-41\n").is_err());
+        assert!(parse("s = %x0-FFFFF\n").is_err());
+        assert!(parse("s = t\n").is_err());
+    }
+
+    #[test]
+    fn repetition_counts() {
+        assert!(parse("word = 1*8ALPHA\n").is_ok());
+        assert!(parse("word = 4096ALPHA\n").is_ok());
+        let error = parse("word = 4097ALPHA\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 8));
+        let error = parse("word = 1*99999999999ALPHA\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 10));
+        assert_eq!(error.message.as_slice(), "number is too large");
+        assert!(parse("word = 99999999999*ALPHA\n").is_err());
+        assert!(parse("s = %x100000000\n").is_err());
+    }
+
+    #[test]
+    fn nested_repetition() {
+        let cfg = parse("x = 4096(4096(4096ALPHA))\n").unwrap();
+        let x = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(x.len(), 1);
+        assert_eq!(x[0].len(), 4096);
+        let outer = rules(&cfg, x[0][0]);
+        assert_eq!(outer.len(), 1);
+        assert_eq!(outer[0].len(), 4096);
+        assert_eq!(rules(&cfg, outer[0][0])[0].len(), 4096);
+
+        let cfg = parse("s = 2*3(%x61 %x62)\n").unwrap();
+        let (a, b) = (token(&cfg, 'a'), token(&cfg, 'b'));
+        let s = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(s[0].len(), 3);
+        assert_eq!(s[0][0], s[0][1]);
+        assert_eq!(rules(&cfg, s[0][0]), [vec![a, b]].as_slice());
+        assert_eq!(rules(&cfg, s[0][2]), [vec![s[0][0]], vec![]].as_slice());
+    }
+}
@@ -0,0 +1,263 @@
+//! Building values bottom-up as a parser recognizes each rule, instead of a parse tree.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use super::{Cfg, CfgError, RuleTable, Terminal, Token, Variable};
+use source::{ByteSpan, TokenStream};
+use tree::{ParseTree, SpannedTree};
+
+/// Builds a value for each token and each use of a rule recognized by a parser, from the values
+/// of the symbols of the rule's body: the semantic actions of a parser generator.
+///
+/// Parsers call `shift` for each token and `reduce` for each rule, with the values of its body
+/// in order, only once those values are all built.
+pub trait Reducer<V> {
+    /// The value of a token, found at `position` in the input.
+    fn shift(&mut self, token: Token, position: uint) -> V;
+
+    /// The value of a variable derived by a rule, given by its index in `get_rules`.
+    fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<V>) -> V;
+}
+
+impl<'r, V, R: Reducer<V>> Reducer<V> for &'r mut R {
+    fn shift(&mut self, token: Token, position: uint) -> V {
+        (**self).shift(token, position)
+    }
+
+    fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<V>) -> V {
+        (**self).reduce(variable, rule, children)
+    }
+}
+
+/// A `Reducer` building the parse tree.
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+pub struct TreeBuilder;
+
+impl Reducer<ParseTree> for TreeBuilder {
+    fn shift(&mut self, token: Token, _position: uint) -> ParseTree {
+        ParseTree::Leaf(token)
+    }
+
+    fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<ParseTree>) -> ParseTree {
+        ParseTree::Node(variable, rule, children)
+    }
+}
+
+/// A `Reducer` calling a function attached to each rule of a grammar.
+///
+/// Functions are attached by rule id, so they stay with their rules if the grammar is edited.
+/// Rules with no function attached are reduced by the default given when the actions were
+/// created.
+pub struct Actions<'a, T: 'a, V> {
+    cfg: &'a Cfg<T>,
+    token: fn(Token) -> V,
+    default: fn(Variable, uint, Vec<V>) -> V,
+    actions: RuleTable<fn(Vec<V>) -> V>
+}
+
+impl<'a, T: Terminal, V> Actions<'a, T, V> {
+    /// Reduce tokens by `token`, and rules by `default` until given their own functions.
+    pub fn new(cfg: &'a Cfg<T>, token: fn(Token) -> V, default: fn(Variable, uint, Vec<V>) -> V)
+               -> Actions<'a, T, V> {
+        Actions { cfg: cfg, token: token, default: default, actions: RuleTable::new() }
+    }
+
+    /// Reduce a rule, by its index in `get_rules`, with `action`.
+    pub fn set_action(&mut self, variable: Variable, rule: uint, action: fn(Vec<V>) -> V)
+                      -> Result<(), CfgError> {
+        match self.cfg.rule_id(variable, rule) {
+            Some(id) => {
+                self.actions.set_rule_data(id, action);
+                Ok(())
+            },
+            None => Err(CfgError::RuleOutOfRange { variable: variable, rule: rule })
+        }
+    }
+}
+
+impl<'a, T: Terminal, V> Reducer<V> for Actions<'a, T, V> {
+    fn shift(&mut self, token: Token, _position: uint) -> V {
+        (self.token)(token)
+    }
+
+    fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<V>) -> V {
+        let action = self.cfg.rule_id(variable, rule).and_then(|id| self.actions.rule_data(id));
+        match action {
+            Some(&action) => action(children),
+            None => (self.default)(variable, rule, children)
+        }
+    }
+}
+
+/// A type of syntax tree built directly by parsers, with a constructor for tokens and one for
+/// the rules of the grammar, in place of a `ParseTree`.
+///
+/// Each parser's `parse_with` builds one given an `AstBuilder`, and `ParseTree::to_ast` builds
+/// one from a tree already parsed.
+pub trait FromParse {
+    /// The node for a token, found at `position` in the input.
+    fn from_token(token: Token, position: uint) -> Self;
+
+    /// The node for a variable derived by a rule, given by its index in `get_rules`, from the
+    /// nodes of the symbols of the rule's body, in order.
+    fn from_rule(variable: Variable, rule: uint, children: Vec<Self>) -> Self;
+}
+
+/// A `Reducer` building a syntax tree of type `A` by its `FromParse` constructors.
+pub struct AstBuilder<A>;
+
+impl<A> AstBuilder<A> {
+    pub fn new() -> AstBuilder<A> {
+        AstBuilder
+    }
+}
+
+impl<A: FromParse> Reducer<A> for AstBuilder<A> {
+    fn shift(&mut self, token: Token, position: uint) -> A {
+        FromParse::from_token(token, position)
+    }
+
+    fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<A>) -> A {
+        FromParse::from_rule(variable, rule, children)
+    }
+}
+
+/// A `Reducer` building the parse tree with the span of text each subtree covers, from the
+/// tokens read by a lexer.
+pub struct SpanBuilder<'s, P: 's> {
+    stream: &'s TokenStream<P>,
+    /// The position after the last token shifted, where an empty rule is taken to be.
+    position: uint
+}
+
+impl<'s, P> SpanBuilder<'s, P> {
+    pub fn new(stream: &'s TokenStream<P>) -> SpanBuilder<'s, P> {
+        SpanBuilder { stream: stream, position: 0 }
+    }
+}
+
+impl<'s, P> Reducer<SpannedTree> for SpanBuilder<'s, P> {
+    fn shift(&mut self, token: Token, position: uint) -> SpannedTree {
+        self.position = position + 1;
+        SpannedTree::Leaf(token, self.stream.span(position))
+    }
+
+    fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<SpannedTree>)
+              -> SpannedTree {
+        let span = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => ByteSpan { start: first.span().start,
+                                                    end: last.span().end },
+            _ => self.stream.span_of(self.position, self.position)
+        };
+        SpannedTree::Node(variable, rule, span, children)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{Actions, AstBuilder, FromParse, Reducer, TreeBuilder};
+    use super::super::{Cfg, CfgError, Token, Variable};
+    use earley;
+    use lr::LrParser;
+    use testing::{DIGITS, EXPRESSIONS, grammar, tokens, variable};
+
+    /// Evaluates the sums and products of `DIGITS`.
+    struct Calculator<'a> {
+        cfg: &'a Cfg<String>
+    }
+
+    impl<'a> Reducer<i64> for Calculator<'a> {
+        fn shift(&mut self, token: Token, _position: uint) -> i64 {
+            self.cfg.terminal(token).unwrap().as_slice().parse().unwrap_or(0)
+        }
+
+        fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<i64>) -> i64 {
+            match (self.cfg.name(variable).unwrap(), rule) {
+                ("e", 0) => children[0] + children[2],
+                ("t", 0) => children[0] * children[2],
+                ("f", 0) => children[1],
+                _ => children[0]
+            }
+        }
+    }
+
+    #[test]
+    fn reducer() {
+        let cfg = grammar(DIGITS);
+        let input = tokens(&cfg, "2 + 3 * ( 1 + 2 )");
+        let parser = LrParser::new(&cfg).unwrap();
+        assert_eq!(parser.parse_with(input.as_slice(), &mut Calculator { cfg: &cfg }), Ok(11));
+        let value = earley::parse_with(&cfg, input.as_slice(), &mut Calculator { cfg: &cfg });
+        assert_eq!(value, Some(11));
+        let tree = parser.parse_with(input.as_slice(), &mut TreeBuilder).unwrap();
+        assert_eq!(Ok(tree), parser.parse(input.as_slice()));
+    }
+
+    fn one(_: Token) -> uint {
+        1
+    }
+
+    fn sum(_: Variable, _: uint, children: Vec<uint>) -> uint {
+        children.iter().fold(0, |sum, &n| sum + n)
+    }
+
+    fn inside_parentheses(children: Vec<uint>) -> uint {
+        children[1]
+    }
+
+    #[test]
+    fn actions() {
+        let cfg = grammar(EXPRESSIONS);
+        let f = variable(&cfg, "f");
+        let input = tokens(&cfg, "( id + id ) * ( id )");
+        let mut actions = Actions::new(&cfg, one, sum);
+        assert_eq!(earley::parse_with(&cfg, input.as_slice(), &mut actions), Some(9));
+        actions.set_action(f, 0, inside_parentheses).unwrap();
+        assert_eq!(earley::parse_with(&cfg, input.as_slice(), &mut actions), Some(5));
+        assert_eq!(actions.set_action(f, 2, inside_parentheses),
+                   Err(CfgError::RuleOutOfRange { variable: f, rule: 2 }));
+    }
+
+    /// A syntax tree keeping the positions of its tokens.
+    #[deriving(PartialEq, Show)]
+    enum Ast {
+        Leaf(Token, uint),
+        Node(Variable, uint, Vec<Ast>)
+    }
+
+    impl FromParse for Ast {
+        fn from_token(token: Token, position: uint) -> Ast {
+            Ast::Leaf(token, position)
+        }
+
+        fn from_rule(variable: Variable, rule: uint, children: Vec<Ast>) -> Ast {
+            Ast::Node(variable, rule, children)
+        }
+    }
+
+    fn positions(ast: &Ast, found: &mut Vec<uint>) {
+        match *ast {
+            Ast::Leaf(_, position) => found.push(position),
+            Ast::Node(_, _, ref children) => {
+                for child in children.iter() {
+                    positions(child, found);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ast() {
+        let cfg = grammar(DIGITS);
+        let input = tokens(&cfg, "2 * ( 1 + 3 )");
+        let parser = LrParser::new(&cfg).unwrap();
+        let ast: Ast = parser.parse_with(input.as_slice(), &mut AstBuilder::new()).unwrap();
+        let tree = parser.parse(input.as_slice()).unwrap();
+        assert_eq!(tree.to_ast::<Ast>(), ast);
+        let mut found = vec![];
+        positions(&ast, &mut found);
+        assert_eq!(found, range(0, input.len()).collect::<Vec<_>>());
+        assert_eq!(tree.fold(&mut Calculator { cfg: &cfg }), 8);
+    }
+}
@@ -0,0 +1,1034 @@
+//! Properties of the symbols of a grammar.
+
+use std::cmp::min;
+use std::collections::{BTreeSet, HashMap, RingBuf};
+use std::hash::Hash;
+
+use super::{Cfg, Symbol, Token, TokenSet, Variable};
+use graph;
+use tree::ParseTree;
+
+/// The ways a variable can be left recursive.
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+pub enum LeftRecursionKind {
+    /// Through one of its own rules, as in `A -> A x`.
+    Direct,
+    /// Through a cycle of other variables, as in `A -> B x`, `B -> A y`.
+    Indirect,
+    /// Only through rules where a nullable prefix comes first, as in `A -> B A x` with `B`
+    /// nullable.
+    Hidden
+}
+
+/// The strongly connected components of the graph with an edge from `A` to `B` whenever
+/// `A -> x B y` is a rule with `x` and `y` nullable, so that `A =>+ B`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Cycles {
+    /// The variables of each component, in order. Each component comes after every component
+    /// it has an edge to.
+    pub components: Vec<Vec<Variable>>,
+    /// Whether each component contains a cycle, so that its variables `A` satisfy `A =>+ A`.
+    pub cyclic: Vec<bool>
+}
+
+impl Cycles {
+    /// Whether any variable derives itself.
+    pub fn is_cyclic(&self) -> bool {
+        self.cyclic.iter().any(|&c| c)
+    }
+
+    /// The components containing a cycle.
+    pub fn cyclic_components(&self) -> Vec<&[Variable]> {
+        range(0, self.components.len()).filter(|&i| self.cyclic[i])
+                                       .map(|i| self.components[i].as_slice()).collect()
+    }
+}
+
+/// A left recursive variable, with a derivation showing it.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct LeftRecursion {
+    pub variable: Variable,
+    pub kind: LeftRecursionKind,
+    /// The rules, each given as a variable and the index of the rule in `get_rules`, applied in
+    /// turn to the leftmost possible variable to derive a sentential form beginning with
+    /// `variable` from itself. The first rule is one of `variable`'s own.
+    pub chain: Vec<(Variable, uint)>
+}
+
+/// A sentence with two different parse trees, and so two different leftmost derivations.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Ambiguity {
+    pub sentence: Vec<Token>,
+    pub first: ParseTree,
+    pub second: ParseTree
+}
+
+/// Bounds on how many times each token occurs in the sentences a variable derives: a coarse form
+/// of the Parikh image of its language.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Occurrences {
+    /// The fewest times each token, by index, occurs in a sentence.
+    pub min: Vec<uint>,
+    /// The most times each token occurs in a sentence, or `None` if there's no bound.
+    pub max: Vec<Option<uint>>
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Find the variables that can derive the empty string.
+    pub fn nullable(&self) -> BTreeSet<Variable> {
+        let nullable = self.nullable_flags();
+        range(0, nullable.len()).filter(|&v| nullable[v]).map(|v| Variable(v as u32)).collect()
+    }
+
+    /// Find the variables and tokens that don't appear in any sentential form derived from the
+    /// start symbol.
+    pub fn unreachable_symbols(&self) -> BTreeSet<Symbol> {
+        let (variables, tokens) = self.reachable_flags(self.get_start());
+        let mut unreachable = BTreeSet::new();
+        for v in range(0, variables.len()).filter(|&v| !variables[v]) {
+            unreachable.insert(Symbol::Var(Variable(v as u32)));
+        }
+        for t in range(0, tokens.len()).filter(|&t| !tokens[t]) {
+            unreachable.insert(Symbol::Tok(Token(t as u32)));
+        }
+        unreachable
+    }
+
+    /// Whether each variable and each token, by index, can be reached from `root`.
+    fn reachable_flags(&self, root: Variable) -> (Vec<bool>, Vec<bool>) {
+        let mut variables = Vec::from_elem(self.num_variables(), false);
+        let mut tokens = Vec::from_elem(self.num_tokens(), false);
+        if root.index() >= variables.len() {
+            return (variables, tokens)
+        }
+        let mut stack = vec![root];
+        variables[root.index()] = true;
+        loop {
+            let v = match stack.pop() {
+                Some(v) => v,
+                None => break
+            };
+            let rules = match self.get_rules(v) {
+                Some(rules) => rules,
+                None => continue
+            };
+            for rule in rules.iter() {
+                for &symbol in rule.iter() {
+                    match symbol {
+                        Symbol::Tok(t) => tokens[t.index()] = true,
+                        Symbol::Var(w) => {
+                            if !variables[w.index()] {
+                                variables[w.index()] = true;
+                                stack.push(w);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        (variables, tokens)
+    }
+
+    /// Find the variables that can't derive any string of tokens, such as `A` in `A -> A B` when
+    /// `A` has no other rules.
+    pub fn nonproductive_symbols(&self) -> BTreeSet<Variable> {
+        let productive = self.productive_flags();
+        range(0, productive.len()).filter(|&v| !productive[v]).map(|v| Variable(v as u32))
+                                  .collect()
+    }
+
+    /// Whether the grammar's language is empty, because the start symbol can't derive any string
+    /// of tokens.
+    pub fn is_empty_language(&self) -> bool {
+        let productive = self.productive_flags();
+        !productive.get(self.get_start().index()).map_or(false, |&p| p)
+    }
+
+    /// Whether the grammar's language is finite. When it isn't, `pumping_cycle` gives a chain of
+    /// rules showing it.
+    pub fn is_finite_language(&self) -> bool {
+        self.pumping_cycle().is_none()
+    }
+
+    /// Find a chain of rules through which a useful variable derives itself with tokens around
+    /// it, as in `A =>+ x A y` with `x y` deriving some tokens, so that the chain can be repeated
+    /// to derive infinitely many sentences. There is one exactly when the language is infinite.
+    ///
+    /// The rules are given as in `LeftRecursion::chain`, each applied in turn to the variable
+    /// that the one before it introduced. The first rule is one that brings in the tokens.
+    pub fn pumping_cycle(&self) -> Option<Vec<(Variable, uint)>> {
+        let productive = self.productive_flags();
+        let (reachable, _) = self.reachable_flags(self.get_start());
+        let nonempty = self.nonempty_flags(productive.as_slice());
+        let n = self.num_variables();
+        // The variables in each useful variable's productive rules, as (variable, rule, pumping)
+        // where pumping is whether the rest of the rule can derive some token.
+        let mut edges = Vec::from_fn(n, |_| Vec::new());
+        for v in range(0, n).filter(|&v| productive[v] && reachable[v]) {
+            let rules = match self.get_rules(Variable(v as u32)) {
+                Some(rules) => rules,
+                None => continue
+            };
+            for (i, rule) in rules.iter().enumerate() {
+                let usable = rule.iter().all(|&s| match s {
+                    Symbol::Var(w) => productive[w.index()],
+                    Symbol::Tok(_) => true
+                });
+                if !usable {
+                    continue
+                }
+                for (p, &symbol) in rule.iter().enumerate() {
+                    let w = match symbol {
+                        Symbol::Var(w) => w,
+                        Symbol::Tok(_) => continue
+                    };
+                    let pumping = rule.iter().enumerate().any(|(q, &s)| q != p && match s {
+                        Symbol::Var(u) => nonempty[u.index()],
+                        Symbol::Tok(_) => true
+                    });
+                    edges[v].push((w, i, pumping));
+                }
+            }
+        }
+
+        for v in range(0, n) {
+            for &(w, i, _) in edges[v].iter().filter(|&&(_, _, pumping)| pumping) {
+                match path(edges.as_slice(), w, Variable(v as u32)) {
+                    Some(rest) => {
+                        let mut chain = vec![(Variable(v as u32), i)];
+                        chain.extend(rest.into_iter());
+                        return Some(chain)
+                    },
+                    None => { }
+                }
+            }
+        }
+        None
+    }
+
+    /// The variables that are self-embedding, deriving themselves with tokens on both sides, as
+    /// in `A =>+ x A y` with both `x` and `y` deriving some tokens. Only derivations that can be
+    /// completed to strings of tokens are considered.
+    pub fn self_embedding_variables(&self) -> BTreeSet<Variable> {
+        let productive = self.productive_flags();
+        let nonempty = self.nonempty_flags(productive.as_slice());
+        let n = self.num_variables();
+        let derives_tokens = |symbols: &[Symbol]| symbols.iter().any(|&s| match s {
+            Symbol::Var(w) => nonempty[w.index()],
+            Symbol::Tok(_) => true
+        });
+        // The variables in each productive variable's productive rules, with whether what's to
+        // their left and to their right in the rule can derive some tokens.
+        let mut edges = Vec::from_fn(n, |_| Vec::new());
+        for v in range(0, n).filter(|&v| productive[v]) {
+            for rule in self.get_rules(Variable(v as u32)).unwrap_or(&[]).iter() {
+                let usable = rule.iter().all(|&s| match s {
+                    Symbol::Var(w) => productive[w.index()],
+                    Symbol::Tok(_) => true
+                });
+                if !usable {
+                    continue
+                }
+                for (p, &symbol) in rule.iter().enumerate() {
+                    match symbol {
+                        Symbol::Var(w) => edges[v].push((w.index(),
+                                                         derives_tokens(rule.slice_to(p)),
+                                                         derives_tokens(rule.slice_from(p + 1)))),
+                        Symbol::Tok(_) => { }
+                    }
+                }
+            }
+        }
+
+        let mut embedding = BTreeSet::new();
+        for v in range(0, n).filter(|&v| productive[v]) {
+            // Search the variables derived from `v`, with whether tokens can be derived to their
+            // left and their right.
+            let mut seen = Vec::from_elem(4 * n, false);
+            let mut stack: Vec<(uint, bool, bool)> = edges[v].clone();
+            loop {
+                let (w, left, right) = match stack.pop() {
+                    Some(top) => top,
+                    None => break
+                };
+                let state = 4 * w + if left { 2 } else { 0 } + if right { 1 } else { 0 };
+                if seen[state] {
+                    continue
+                }
+                seen[state] = true;
+                if w == v && left && right {
+                    embedding.insert(Variable(v as u32));
+                    break
+                }
+                stack.extend(edges[w].iter().map(|&(u, l, r)| (u, left || l, right || r)));
+            }
+        }
+        embedding
+    }
+
+    /// Whether the start symbol derives a self-embedding variable. A grammar that isn't
+    /// self-embedding has a regular language, and `regular_approximation` gives an automaton
+    /// accepting exactly it; one that is may still have a regular language.
+    pub fn is_self_embedding(&self) -> bool {
+        let (reachable, _) = self.reachable_flags(self.get_start());
+        self.self_embedding_variables().iter().any(|v| reachable[v.index()])
+    }
+
+    /// Bounds on the number of occurrences of each token in the sentences each variable derives,
+    /// by index, or `None` for variables that derive no sentences.
+    ///
+    /// Each bound is reached by some sentence, though not necessarily the same sentence for every
+    /// token. A token is unbounded when the variable derives some `A` with `A =>+ x A y`, where
+    /// `x y` can contain the token, since the derivation can be repeated.
+    pub fn occurrences(&self) -> Vec<Option<Occurrences>> {
+        let productive = self.productive_flags();
+        let (n, tokens) = (self.num_variables(), self.num_tokens());
+        // The productive rules of each variable.
+        let bodies: Vec<Vec<&[Symbol]>> = range(0, n).map(|v| {
+            self.get_rules(Variable(v as u32)).unwrap_or(&[]).iter().map(|r| r.as_slice())
+                .filter(|rule| rule.iter().all(|&s| match s {
+                    Symbol::Var(w) => productive[w.index()],
+                    Symbol::Tok(_) => true
+                })).collect()
+        }).collect();
+
+        // The tokens each variable's sentences can contain.
+        let mut contains = Vec::from_fn(n, |_| TokenSet::new());
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                for rule in bodies[v].iter() {
+                    for &symbol in rule.iter() {
+                        let added = match symbol {
+                            Symbol::Tok(t) => contains[v].insert(t),
+                            Symbol::Var(w) => {
+                                let other = contains[w.index()].clone();
+                                contains[v].union_with(&other)
+                            }
+                        };
+                        changed = changed || added;
+                    }
+                }
+            }
+        }
+
+        // A variable pumps the tokens that the rest of a rule can contain around a variable of
+        // its own strongly connected component.
+        let mut edges = Vec::from_fn(n, |_| Vec::new());
+        for v in range(0, n) {
+            for rule in bodies[v].iter() {
+                for &symbol in rule.iter() {
+                    match symbol {
+                        Symbol::Var(w) if !edges[v].contains(&w) => edges[v].push(w),
+                        _ => { }
+                    }
+                }
+            }
+        }
+        let components = graph::components(edges.as_slice());
+        let mut component = Vec::from_elem(n, 0u);
+        for (c, members) in components.iter().enumerate() {
+            for &v in members.iter() {
+                component[v.index()] = c;
+            }
+        }
+        // Components come after those they have edges to, so each has its successors' tokens.
+        let mut unbounded: Vec<TokenSet> = Vec::with_capacity(components.len());
+        for (c, members) in components.iter().enumerate() {
+            let mut pumped = TokenSet::new();
+            for &v in members.iter() {
+                for rule in bodies[v.index()].iter() {
+                    for (p, &symbol) in rule.iter().enumerate() {
+                        match symbol {
+                            Symbol::Var(w) if component[w.index()] == c => { },
+                            _ => continue
+                        }
+                        for (_, &other) in rule.iter().enumerate().filter(|&(q, _)| q != p) {
+                            match other {
+                                Symbol::Tok(t) => { pumped.insert(t); },
+                                Symbol::Var(u) => { pumped.union_with(&contains[u.index()]); }
+                            }
+                        }
+                    }
+                }
+                for &w in edges[v.index()].iter() {
+                    let d = component[w.index()];
+                    if d != c {
+                        pumped.union_with(&unbounded[d]);
+                    }
+                }
+            }
+            unbounded.push(pumped);
+        }
+
+        // The fewest and most occurrences, improving each count until nothing changes. The most
+        // can only grow around a cycle if the token is unbounded, so it's left out of those.
+        let mut lows: Vec<Option<Vec<uint>>> = Vec::from_elem(n, None);
+        let mut highs: Vec<Vec<uint>> = Vec::from_fn(n, |_| Vec::from_elem(tokens, 0u));
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                for rule in bodies[v].iter() {
+                    let mut fewest = Vec::from_elem(tokens, 0u);
+                    let mut most = Vec::from_elem(tokens, 0u);
+                    let mut complete = true;
+                    for &symbol in rule.iter() {
+                        match symbol {
+                            Symbol::Tok(t) => {
+                                fewest[t.index()] += 1;
+                                most[t.index()] += 1;
+                            },
+                            Symbol::Var(w) => {
+                                match lows[w.index()] {
+                                    Some(ref counts) => for t in range(0, tokens) {
+                                        fewest[t] += counts[t];
+                                    },
+                                    None => complete = false
+                                }
+                                for t in range(0, tokens) {
+                                    most[t] += highs[w.index()][t];
+                                }
+                            }
+                        }
+                    }
+                    let bounds = &unbounded[component[v]];
+                    for t in range(0, tokens) {
+                        if !bounds.contains(Token(t as u32)) && most[t] > highs[v][t] {
+                            highs[v][t] = most[t];
+                            changed = true;
+                        }
+                    }
+                    if !complete {
+                        continue
+                    }
+                    let improved = match lows[v] {
+                        Some(ref counts) => range(0, tokens).any(|t| fewest[t] < counts[t]),
+                        None => true
+                    };
+                    if improved {
+                        let merged = match lows[v] {
+                            Some(ref counts) => {
+                                range(0, tokens).map(|t| min(fewest[t], counts[t])).collect()
+                            },
+                            None => fewest
+                        };
+                        lows[v] = Some(merged);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        range(0, n).map(|v| lows[v].as_ref().map(|low| Occurrences {
+            min: low.clone(),
+            max: range(0, tokens).map(|t| {
+                if unbounded[component[v]].contains(Token(t as u32)) {
+                    None
+                } else {
+                    Some(highs[v][t])
+                }
+            }).collect()
+        })).collect()
+    }
+
+    /// Whether each variable, by index, derives some string of at least one token, given which
+    /// are productive.
+    fn nonempty_flags(&self, productive: &[bool]) -> Vec<bool> {
+        let n = self.num_variables();
+        let mut nonempty = Vec::from_elem(n, false);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                if nonempty[v] {
+                    continue
+                }
+                let rules = match self.get_rules(Variable(v as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                let derives = rules.iter().any(|rule| {
+                    rule.iter().all(|&s| match s {
+                        Symbol::Var(w) => productive[w.index()],
+                        Symbol::Tok(_) => true
+                    }) && rule.iter().any(|&s| match s {
+                        Symbol::Var(w) => nonempty[w.index()],
+                        Symbol::Tok(_) => true
+                    })
+                });
+                if derives {
+                    nonempty[v] = true;
+                    changed = true;
+                }
+            }
+        }
+        nonempty
+    }
+
+    /// Whether each variable, by index, derives some string of tokens.
+    fn productive_flags(&self) -> Vec<bool> {
+        let n = self.num_variables();
+        let mut productive = Vec::from_elem(n, false);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                if productive[v] {
+                    continue
+                }
+                let rules = match self.get_rules(Variable(v as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                let generates = rules.iter().any(|rule| rule.iter().all(|&s| match s {
+                    Symbol::Var(w) => productive[w.index()],
+                    Symbol::Tok(_) => true
+                }));
+                if generates {
+                    productive[v] = true;
+                    changed = true;
+                }
+            }
+        }
+        productive
+    }
+
+    /// The shortest string of tokens each variable derives, by index, or `None` for variables that
+    /// derive none.
+    pub fn shortest_sentences(&self) -> Vec<Option<Vec<Token>>> {
+        let n = self.num_variables();
+        // The length of the shortest string of each variable, and the rule giving it.
+        let mut best: Vec<Option<(uint, uint)>> = Vec::from_elem(n, None);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                let rules = match self.get_rules(Variable(v as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                for (i, rule) in rules.iter().enumerate() {
+                    let mut len = Some(0);
+                    for &symbol in rule.iter() {
+                        len = match (len, symbol) {
+                            (Some(len), Symbol::Tok(_)) => Some(len + 1),
+                            (Some(len), Symbol::Var(w)) => best[w.index()].map(|(l, _)| len + l),
+                            (None, _) => None
+                        };
+                    }
+                    let len = match len {
+                        Some(len) => len,
+                        None => continue
+                    };
+                    if best[v].map_or(true, |(l, _)| len < l) {
+                        best[v] = Some((len, i));
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        // A rule is only chosen by improving on the length, so the rules chosen can't form a
+        // cycle, and every variable's string can be put together from those of its rule.
+        let mut sentences: Vec<Option<Vec<Token>>> = Vec::from_elem(n, None);
+        for v in range(0, n) {
+            shortest(self, best.as_slice(), sentences.as_mut_slice(), v);
+        }
+        sentences
+    }
+
+    /// Find every left recursive variable, those that derive a sentential form beginning with
+    /// themselves, in order.
+    ///
+    /// Each is classified by the simplest way it's left recursive: directly if possible, then
+    /// indirectly, and hidden only if every derivation goes past a nullable prefix.
+    pub fn left_recursion(&self) -> Vec<LeftRecursion> {
+        let nullable = self.nullable_flags();
+        let n = self.num_variables();
+        // The variables each variable's rules can begin with, as (variable, rule, hidden).
+        let mut edges = Vec::from_fn(n, |_| Vec::new());
+        for v in range(0, n) {
+            let rules = match self.get_rules(Variable(v as u32)) {
+                Some(rules) => rules,
+                None => continue
+            };
+            for (i, rule) in rules.iter().enumerate() {
+                for (p, &symbol) in rule.iter().enumerate() {
+                    match symbol {
+                        Symbol::Var(w) => {
+                            edges[v].push((w, i, p > 0));
+                            if !nullable[w.index()] {
+                                break
+                            }
+                        },
+                        Symbol::Tok(_) => break
+                    }
+                }
+            }
+        }
+
+        let mut found = Vec::new();
+        for v in range(0, n) {
+            let variable = Variable(v as u32);
+            let direct = edges[v].iter().find(|&&(w, _, hidden)| w == variable && !hidden);
+            let (kind, chain) = match direct {
+                Some(&(_, i, _)) => (LeftRecursionKind::Direct, vec![(variable, i)]),
+                None => match left_cycle(edges.as_slice(), variable, false) {
+                    Some(chain) => (LeftRecursionKind::Indirect, chain),
+                    None => match left_cycle(edges.as_slice(), variable, true) {
+                        Some(chain) => (LeftRecursionKind::Hidden, chain),
+                        None => continue
+                    }
+                }
+            };
+            found.push(LeftRecursion { variable: variable, kind: kind, chain: chain });
+        }
+        found
+    }
+
+    /// Find the cycles among the variables, where a variable derives itself without producing
+    /// anything else.
+    pub fn cycles(&self) -> Cycles {
+        let nullable = self.nullable_flags();
+        let n = self.num_variables();
+        let mut edges = Vec::from_fn(n, |_| Vec::new());
+        for v in range(0, n) {
+            let rules = match self.get_rules(Variable(v as u32)) {
+                Some(rules) => rules,
+                None => continue
+            };
+            for rule in rules.iter() {
+                let vanishing = |s: &Symbol| match *s {
+                    Symbol::Var(w) => nullable[w.index()],
+                    Symbol::Tok(_) => false
+                };
+                for (p, &symbol) in rule.iter().enumerate() {
+                    let w = match symbol {
+                        Symbol::Var(w) => w,
+                        Symbol::Tok(_) => continue
+                    };
+                    if rule.slice_to(p).iter().all(|s| vanishing(s)) &&
+                       rule.slice_from(p + 1).iter().all(|s| vanishing(s)) &&
+                       !edges[v].contains(&w) {
+                        edges[v].push(w);
+                    }
+                }
+            }
+        }
+
+        let mut components = graph::components(edges.as_slice());
+        for component in components.iter_mut() {
+            component.sort();
+        }
+        let cyclic = components.iter().map(|c| {
+            c.len() > 1 || edges[c[0].index()].contains(&c[0])
+        }).collect();
+        Cycles { components: components, cyclic: cyclic }
+    }
+
+    /// Search for a sentence of at most `max_len` tokens with two parse trees, giving the shortest
+    /// such sentence found.
+    ///
+    /// Whether a grammar is ambiguous can't be decided in general, so `None` only means that no
+    /// sentence up to that length is ambiguous. The search finds the sentences each variable
+    /// derives bottom-up, keeping up to two trees for each, so it takes time exponential in
+    /// `max_len` for most grammars.
+    pub fn find_ambiguity(&self, max_len: uint) -> Option<Ambiguity> {
+        let n = self.num_variables();
+        let mut derived: Vec<HashMap<Vec<Token>, Vec<ParseTree>>> =
+            Vec::from_fn(n, |_| HashMap::new());
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                let variable = Variable(v as u32);
+                let rules = match self.get_rules(variable) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                for (i, rule) in rules.iter().enumerate() {
+                    let found = derivations(derived.as_slice(), rule.as_slice(), max_len);
+                    for (sentence, children) in found.into_iter() {
+                        let tree = ParseTree::Node(variable, i, children);
+                        match derived[v].get_mut(&sentence) {
+                            Some(trees) => {
+                                if trees.len() < 2 && !trees.contains(&tree) {
+                                    trees.push(tree);
+                                    changed = true;
+                                }
+                                continue
+                            },
+                            None => { }
+                        }
+                        derived[v].insert(sentence, vec![tree]);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut ambiguous: Vec<(&Vec<Token>, &Vec<ParseTree>)> = derived[self.get_start().index()]
+            .iter().filter(|&(_, trees)| trees.len() == 2).collect();
+        ambiguous.sort_by(|&(a, _), &(b, _)| (a.len(), a).cmp(&(b.len(), b)));
+        ambiguous.get(0).map(|&(sentence, trees)| Ambiguity {
+            sentence: sentence.clone(),
+            first: trees[0].clone(),
+            second: trees[1].clone()
+        })
+    }
+
+    /// Whether each variable, by index, can derive the empty string.
+    fn nullable_flags(&self) -> Vec<bool> {
+        let n = self.num_variables();
+        let mut nullable = Vec::from_elem(n, false);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                if nullable[v] {
+                    continue
+                }
+                let rules = match self.get_rules(Variable(v as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                let derives_empty = rules.iter().any(|rule| rule.iter().all(|&s| match s {
+                    Symbol::Var(w) => nullable[w.index()],
+                    Symbol::Tok(_) => false
+                }));
+                if derives_empty {
+                    nullable[v] = true;
+                    changed = true;
+                }
+            }
+        }
+        nullable
+    }
+}
+
+/// The shortest chain of rules leading from `root` back to itself through the `edges` computed
+/// by `left_recursion`, using hidden edges only if `hidden` is set.
+fn left_cycle(edges: &[Vec<(Variable, uint, bool)>], root: Variable, hidden: bool)
+              -> Option<Vec<(Variable, uint)>> {
+    let mut parent: Vec<Option<(Variable, uint)>> = Vec::from_elem(edges.len(), None);
+    let mut queue = RingBuf::new();
+    queue.push_back(root);
+    loop {
+        let v = match queue.pop_front() {
+            Some(v) => v,
+            None => return None
+        };
+        for &(w, i, h) in edges[v.index()].iter() {
+            if h && !hidden {
+                continue
+            }
+            if w == root {
+                let mut chain = vec![(v, i)];
+                let mut at = v;
+                while at != root {
+                    let (u, j) = parent[at.index()].unwrap();
+                    chain.push((u, j));
+                    at = u;
+                }
+                chain.reverse();
+                return Some(chain)
+            }
+            if parent[w.index()].is_none() {
+                parent[w.index()] = Some((v, i));
+                queue.push_back(w);
+            }
+        }
+    }
+}
+
+/// The shortest chain of rules through the `edges` computed by `pumping_cycle` by which `from`
+/// derives a sentential form containing `to`.
+fn path(edges: &[Vec<(Variable, uint, bool)>], from: Variable, to: Variable)
+        -> Option<Vec<(Variable, uint)>> {
+    let mut parent: Vec<Option<(Variable, uint)>> = Vec::from_elem(edges.len(), None);
+    let mut seen = Vec::from_elem(edges.len(), false);
+    let mut queue = RingBuf::new();
+    queue.push_back(from);
+    seen[from.index()] = true;
+    loop {
+        let v = match queue.pop_front() {
+            Some(v) => v,
+            None => return None
+        };
+        if v == to {
+            let mut chain = Vec::new();
+            let mut at = v;
+            while at != from {
+                let (u, j) = parent[at.index()].unwrap();
+                chain.push((u, j));
+                at = u;
+            }
+            chain.reverse();
+            return Some(chain)
+        }
+        for &(w, i, _) in edges[v.index()].iter() {
+            if !seen[w.index()] {
+                seen[w.index()] = true;
+                parent[w.index()] = Some((v, i));
+                queue.push_back(w);
+            }
+        }
+    }
+}
+
+/// Put together the shortest string of variable `v` from the rules chosen by
+/// `shortest_sentences`.
+fn shortest<T: Eq + Hash + Clone>(cfg: &Cfg<T>, best: &[Option<(uint, uint)>],
+                                  sentences: &mut [Option<Vec<Token>>], v: uint) {
+    let rule = match best[v] {
+        Some((_, rule)) if sentences[v].is_none() => rule,
+        _ => return
+    };
+    let mut sentence = Vec::new();
+    for &symbol in cfg.get_rules(Variable(v as u32)).unwrap()[rule].iter() {
+        match symbol {
+            Symbol::Tok(t) => sentence.push(t),
+            Symbol::Var(w) => {
+                shortest(cfg, best, sentences, w.index());
+                sentence.push_all(sentences[w.index()].as_ref().unwrap().as_slice());
+            }
+        }
+    }
+    sentences[v] = Some(sentence);
+}
+
+/// Every way of deriving a sentence of at most `max_len` tokens from `body`, using the trees found
+/// so far for each variable by `find_ambiguity`, with the subtrees for the symbols of `body`.
+fn derivations(derived: &[HashMap<Vec<Token>, Vec<ParseTree>>], body: &[Symbol], max_len: uint)
+               -> Vec<(Vec<Token>, Vec<ParseTree>)> {
+    let mut partial = vec![(Vec::new(), Vec::new())];
+    for &symbol in body.iter() {
+        let mut next = Vec::new();
+        for &(ref prefix, ref children) in partial.iter() {
+            let mut extend = |sentence: &[Token], tree: &ParseTree| {
+                if prefix.len() + sentence.len() <= max_len {
+                    let (mut prefix, mut children) = (prefix.clone(), children.clone());
+                    prefix.push_all(sentence);
+                    children.push(tree.clone());
+                    next.push((prefix, children));
+                }
+            };
+            match symbol {
+                Symbol::Tok(t) => extend(&[t], &ParseTree::Leaf(t)),
+                Symbol::Var(w) => for (sentence, trees) in derived[w.index()].iter() {
+                    for tree in trees.iter() {
+                        extend(sentence.as_slice(), tree);
+                    }
+                }
+            }
+        }
+        partial = next;
+    }
+    partial
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{LeftRecursion, LeftRecursionKind};
+    use super::super::{Cfg, Symbol, Variable};
+    use testing::{EXPRESSIONS, EXPRESSIONS_LL1, check_tree, grammar, tokens, variable};
+
+    /// The variables with the given names.
+    fn variables(cfg: &Cfg<String>, names: &[&str]) -> BTreeSet<Variable> {
+        names.iter().map(|name| variable(cfg, *name)).collect()
+    }
+
+    #[test]
+    fn nullable() {
+        let cfg = grammar("
+            <s> ::= <a> <b> | \"x\"
+            <a> ::= \"\" | \"a\"
+            <b> ::= <a> <a> | <c>
+            <c> ::= \"c\" <c>
+        ");
+        assert_eq!(cfg.nullable(), variables(&cfg, &["s", "a", "b"]));
+        let cfg = grammar(EXPRESSIONS_LL1);
+        assert_eq!(cfg.nullable(), variables(&cfg, &["ep", "tp"]));
+    }
+
+    #[test]
+    fn unreachable_symbols() {
+        let cfg = grammar("
+            <s> ::= \"a\" <b>
+            <b> ::= \"b\" | <b> <b>
+            <c> ::= \"c\" <s>
+        ");
+        let mut expected = BTreeSet::new();
+        expected.insert(Symbol::Var(variable(&cfg, "c")));
+        expected.insert(Symbol::Tok(cfg.token(&"c".to_string()).unwrap()));
+        assert_eq!(cfg.unreachable_symbols(), expected);
+        assert!(grammar(EXPRESSIONS).unreachable_symbols().is_empty());
+    }
+
+    #[test]
+    fn nonproductive_symbols() {
+        let cfg = grammar("
+            <s> ::= \"a\" | <t> <s>
+            <t> ::= \"b\" <t>
+            <u> ::= <t> | <s>
+            <v> ::= <v> <u>
+        ");
+        assert_eq!(cfg.nonproductive_symbols(), variables(&cfg, &["t", "v"]));
+        assert!(grammar(EXPRESSIONS).nonproductive_symbols().is_empty());
+    }
+
+    #[test]
+    fn left_recursion() {
+        let cfg = grammar("
+            <s> ::= <s> \"a\" | <b> | <h>
+            <b> ::= <c> \"x\" | \"y\"
+            <c> ::= <b> \"z\"
+            <h> ::= <n> <h> \"w\" | \"v\"
+            <n> ::= \"\" | \"n\"
+        ");
+        let (s, b, c, h) = (variable(&cfg, "s"), variable(&cfg, "b"), variable(&cfg, "c"),
+                            variable(&cfg, "h"));
+        assert_eq!(cfg.left_recursion(), vec![
+            LeftRecursion { variable: s, kind: LeftRecursionKind::Direct, chain: vec![(s, 0)] },
+            LeftRecursion { variable: b, kind: LeftRecursionKind::Indirect,
+                            chain: vec![(b, 0), (c, 0)] },
+            LeftRecursion { variable: c, kind: LeftRecursionKind::Indirect,
+                            chain: vec![(c, 0), (b, 0)] },
+            LeftRecursion { variable: h, kind: LeftRecursionKind::Hidden, chain: vec![(h, 0)] }
+        ]);
+        assert!(grammar(EXPRESSIONS_LL1).left_recursion().is_empty());
+    }
+
+    #[test]
+    fn cycles() {
+        let cfg = grammar(EXPRESSIONS);
+        let cycles = cfg.cycles();
+        let (e, t, f) = (variable(&cfg, "e"), variable(&cfg, "t"), variable(&cfg, "f"));
+        assert_eq!(cycles.components, vec![vec![f], vec![t], vec![e]]);
+        assert!(!cycles.is_cyclic());
+
+        let cfg = grammar("
+            <s> ::= <a> | <s>
+            <a> ::= <b> | \"x\" <a> | \"\"
+            <b> ::= <a> \"y\" | <a> <n>
+            <n> ::= \"\" | \"n\"
+        ");
+        let cycles = cfg.cycles();
+        let (s, a, b, n) = (variable(&cfg, "s"), variable(&cfg, "a"), variable(&cfg, "b"),
+                            variable(&cfg, "n"));
+        assert_eq!(cycles.components, vec![vec![n], vec![a, b], vec![s]]);
+        assert_eq!(cycles.cyclic, vec![false, true, true]);
+        assert!(cycles.is_cyclic());
+        let (ab, s) = ([a, b], [s]);
+        assert_eq!(cycles.cyclic_components(), vec![ab.as_slice(), s.as_slice()]);
+    }
+
+    #[test]
+    fn find_ambiguity() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"x\"");
+        let ambiguity = cfg.find_ambiguity(5).unwrap();
+        assert_eq!(ambiguity.sentence, tokens(&cfg, "x + x + x"));
+        assert!(ambiguity.first != ambiguity.second);
+        check_tree(&cfg, &ambiguity.first, ambiguity.sentence.as_slice());
+        check_tree(&cfg, &ambiguity.second, ambiguity.sentence.as_slice());
+        assert!(cfg.find_ambiguity(4).is_none());
+        assert!(grammar(EXPRESSIONS).find_ambiguity(5).is_none());
+    }
+
+    #[test]
+    fn shortest_sentences() {
+        let cfg = grammar(EXPRESSIONS);
+        let shortest = cfg.shortest_sentences();
+        assert_eq!(shortest.len(), 3);
+        assert!(shortest.iter().all(|s| *s == Some(tokens(&cfg, "id"))));
+
+        let cfg = grammar("
+            <s> ::= <a> <a> | \"x\" \"y\" \"z\" | <n>
+            <a> ::= \"a\" \"a\"
+            <n> ::= <n> \"n\"
+        ");
+        let shortest = cfg.shortest_sentences();
+        assert_eq!(shortest[variable(&cfg, "s").index()], Some(tokens(&cfg, "x y z")));
+        assert_eq!(shortest[variable(&cfg, "a").index()], Some(tokens(&cfg, "a a")));
+        assert_eq!(shortest[variable(&cfg, "n").index()], None);
+    }
+
+    #[test]
+    fn empty_language() {
+        assert!(!grammar(EXPRESSIONS).is_empty_language());
+        assert!(!grammar("<s> ::= \"\"").is_empty_language());
+        assert!(grammar("<s> ::= <s> \"x\"").is_empty_language());
+        let cfg = grammar("<s> ::= <a> | <b> <s>\n<a> ::= <b> <a>\n<b> ::= \"b\"");
+        assert!(cfg.is_empty_language());
+    }
+
+    #[test]
+    fn finite_language() {
+        let cfg = grammar("<s> ::= <a> <a>\n<a> ::= \"x\" | \"y\" | \"\"");
+        assert!(cfg.is_finite_language());
+        assert_eq!(cfg.pumping_cycle(), None);
+        // A cycle only through unit rules, or through a useless variable, adds no sentences.
+        assert!(grammar("<s> ::= <t> | \"x\"\n<t> ::= <s>").is_finite_language());
+        assert!(grammar("<s> ::= \"x\"\n<u> ::= \"a\" <u>").is_finite_language());
+        assert!(grammar("<s> ::= <s> <n> | \"x\"\n<n> ::= <n>").is_finite_language());
+
+        let cfg = grammar("<s> ::= \"a\" <s> | \"b\"");
+        assert!(!cfg.is_finite_language());
+        assert_eq!(cfg.pumping_cycle(), Some(vec![(variable(&cfg, "s"), 0)]));
+
+        let cfg = grammar(EXPRESSIONS);
+        assert!(!cfg.is_finite_language());
+        let cycle = cfg.pumping_cycle().unwrap();
+        assert!(!cycle.is_empty());
+        // Each rule of the chain is applied to the variable the one before it brought in.
+        for (i, &(v, rule)) in cycle.iter().enumerate() {
+            let body = cfg.get_rules(v).unwrap()[rule].as_slice();
+            let (next, _) = cycle[(i + 1) % cycle.len()];
+            assert!(body.contains(&Symbol::Var(next)));
+        }
+    }
+
+    #[test]
+    fn occurrences() {
+        let cfg = grammar("
+            <s> ::= \"a\" <s> \"b\" | <c>
+            <c> ::= \"c\" | \"c\" \"c\"
+            <n> ::= <n> \"a\"
+        ");
+        let (a, b, c) = (tokens(&cfg, "a")[0], tokens(&cfg, "b")[0], tokens(&cfg, "c")[0]);
+        let occurrences = cfg.occurrences();
+        let s = occurrences[variable(&cfg, "s").index()].as_ref().unwrap();
+        assert_eq!((s.min[a.index()], s.max[a.index()]), (0, None));
+        assert_eq!((s.min[b.index()], s.max[b.index()]), (0, None));
+        assert_eq!((s.min[c.index()], s.max[c.index()]), (1, Some(2)));
+        let c_counts = occurrences[variable(&cfg, "c").index()].as_ref().unwrap();
+        assert_eq!((c_counts.min[a.index()], c_counts.max[a.index()]), (0, Some(0)));
+        assert_eq!((c_counts.min[c.index()], c_counts.max[c.index()]), (1, Some(2)));
+        assert!(occurrences[variable(&cfg, "n").index()].is_none());
+    }
+
+    #[test]
+    fn self_embedding() {
+        let cfg = grammar("
+            <s> ::= \"a\" <s> \"b\" | <t>
+            <t> ::= \"c\" <t> | \"\"
+        ");
+        assert_eq!(cfg.self_embedding_variables(), variables(&cfg, &["s"]));
+        assert!(cfg.is_self_embedding());
+
+        let cfg = grammar(EXPRESSIONS);
+        assert_eq!(cfg.self_embedding_variables(), variables(&cfg, &["e", "t", "f"]));
+
+        let cfg = grammar("<s> ::= \"a\" <s> | \"b\" <t>\n<t> ::= \"c\" <t> | \"\"");
+        assert!(cfg.self_embedding_variables().is_empty());
+        assert!(!cfg.is_self_embedding());
+        // `u` embeds itself, but isn't reachable from the start symbol.
+        let cfg = grammar("<s> ::= \"x\"\n<u> ::= \"a\" <u> \"b\" | \"c\"");
+        assert_eq!(cfg.self_embedding_variables(), variables(&cfg, &["u"]));
+        assert!(!cfg.is_self_embedding());
+    }
+}
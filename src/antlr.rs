@@ -0,0 +1,567 @@
+//! Reading the parser rules of an ANTLR4 `.g4` grammar.
+//!
+//! Lexer rules aren't converted; each non-fragment lexer rule becomes a token instead. Actions,
+//! predicates, labels, options and exception handlers are skipped.
+
+use std::collections::HashMap;
+
+use super::{Cfg, Rule, SourceMap, Span, Symbol, SyntaxError, Token, Variable};
+use import::{Scanner, Symbols, body_span, is_space, span};
+
+/// Parse the parser rules of an ANTLR4 grammar.
+///
+/// Tokens are named after the lexer rule defining them, or after names listed in a `tokens { }`
+/// block. A literal such as `'+'` used in a parser rule stands for the token whose lexer rule is
+/// exactly that literal, and otherwise for a token named by the quoted literal. `EOF` is dropped,
+/// since the end of input is implicit. The wildcard `.` and sets negated with `~` become
+/// variables deriving each matching token. Groups and the `?`, `*` and `+` operators are lowered
+/// to fresh variables named after the rule they appear in. The first parser rule is the start
+/// symbol.
+pub fn parse(src: &str) -> Result<Cfg<String>, SyntaxError> {
+    parse_with_spans(src).map(|(cfg, _)| cfg)
+}
+
+/// Parse the parser rules of an ANTLR4 grammar as for `parse`, also giving where each variable
+/// was defined and each alternative written. Rules of the variables made for groups, operators,
+/// wildcards and sets have no spans.
+pub fn parse_with_spans(src: &str) -> Result<(Cfg<String>, SourceMap), SyntaxError> {
+    let mut reader = Reader {
+        syms: Symbols::new(),
+        literals: HashMap::new(),
+        wildcards: Vec::new(),
+        lexer_pass: true
+    };
+    try!(reader.read(src));
+    reader.lexer_pass = false;
+    try!(reader.read(src));
+    let n = reader.syms.num_tokens();
+    for &(v, ref excluded) in reader.wildcards.iter() {
+        for i in range(0, n) {
+            let t = Symbol::Tok(Token(i as u32));
+            if !excluded.contains(&t) {
+                reader.syms.add_rule(v, vec![t]);
+            }
+        }
+    }
+    reader.syms.finish_with_spans()
+}
+
+struct Reader {
+    syms: Symbols<String>,
+    /// The token defined by each lexer rule consisting of a lone literal.
+    literals: HashMap<String, String>,
+    /// Variables standing for any token except those listed.
+    wildcards: Vec<(Variable, Vec<Symbol>)>,
+    /// Whether lexer rules are being read; parser rules are read in a second pass, once all the
+    /// tokens are known.
+    lexer_pass: bool
+}
+
+impl Reader {
+    fn read(&mut self, src: &str) -> Result<(), SyntaxError> {
+        let mut s = Scanner::new(src);
+        loop {
+            try!(skip_space(&mut s));
+            match s.peek() {
+                None => return Ok(()),
+                Some('@') => {
+                    s.bump();
+                    s.skip_while(in_action_name);
+                    try!(skip_space(&mut s));
+                    try!(skip_nested(&mut s, '{', '}'));
+                    continue
+                },
+                Some(c) if is_ident_start(c) => { },
+                Some(_) => return Err(s.error("expected a rule"))
+            }
+            let at = s.mark();
+            let word = s.take_while(in_ident);
+            let word_span = span(at, s.mark());
+            try!(skip_space(&mut s));
+            match word.as_slice() {
+                "grammar" | "parser" | "lexer" | "import" | "mode" => {
+                    try!(skip_past(&mut s, ';'));
+                    continue
+                },
+                "options" | "channels" if s.looking_at("{") => {
+                    try!(skip_nested(&mut s, '{', '}'));
+                    continue
+                },
+                "tokens" if s.looking_at("{") => {
+                    try!(self.tokens_block(&mut s));
+                    continue
+                },
+                "fragment" => {
+                    s.skip_while(in_ident);
+                    try!(skip_rule(&mut s));
+                    continue
+                },
+                "public" | "private" | "protected" => {
+                    let at = s.mark();
+                    let name = s.take_while(in_ident);
+                    let name_span = span(at, s.mark());
+                    try!(self.rule(&mut s, name, name_span));
+                },
+                _ => try!(self.rule(&mut s, word, word_span))
+            }
+        }
+    }
+
+    fn tokens_block(&mut self, s: &mut Scanner) -> Result<(), SyntaxError> {
+        s.eat("{");
+        loop {
+            try!(skip_space(s));
+            if s.eat("}") {
+                return Ok(())
+            }
+            let name = s.take_while(in_ident);
+            if name.is_empty() && !s.eat(",") {
+                return Err(s.error("expected a token name"))
+            }
+            if self.lexer_pass && !name.is_empty() {
+                self.syms.token(name);
+            }
+        }
+    }
+
+    /// Read a rule whose name, written at `name_span`, has been read.
+    fn rule(&mut self, s: &mut Scanner, name: String, name_span: Span)
+            -> Result<(), SyntaxError> {
+        if name.is_empty() {
+            return Err(s.error("expected a rule name"))
+        }
+        let is_lexer_rule = name.as_slice().char_at(0).is_uppercase();
+        if is_lexer_rule {
+            if !self.lexer_pass {
+                return skip_rule(s)
+            }
+            let literal = try!(lexer_literal(s));
+            self.syms.token(name.clone());
+            match literal {
+                Some(lit) => { self.literals.insert(lit, name); },
+                None => { }
+            }
+            return Ok(())
+        }
+        if self.lexer_pass {
+            return skip_rule(s)
+        }
+        try!(skip_prelude(s));
+        let lhs = self.syms.define_at(name.as_slice(), name_span);
+        let bodies = try!(self.alternatives(s, name.as_slice()));
+        for (body, rule_span) in bodies.into_iter() {
+            self.syms.add_rule_at(lhs, body, rule_span);
+        }
+        if !s.eat(";") {
+            return Err(s.error("expected `;`"))
+        }
+        skip_handlers(s)
+    }
+
+    fn alternatives(&mut self, s: &mut Scanner, rule: &str)
+                    -> Result<Vec<(Rule, Span)>, SyntaxError> {
+        let mut bodies = Vec::new();
+        loop {
+            bodies.push(try!(self.sequence(s, rule)));
+            try!(skip_space(s));
+            if s.eat("#") {
+                try!(skip_space(s));
+                s.skip_while(in_ident);
+                try!(skip_space(s));
+            }
+            if !s.eat("|") {
+                return Ok(bodies)
+            }
+        }
+    }
+
+    /// Read an alternative, along with where it's written.
+    fn sequence(&mut self, s: &mut Scanner, rule: &str) -> Result<(Rule, Span), SyntaxError> {
+        let before = s.mark();
+        try!(skip_space(s));
+        let start = s.mark();
+        let mut end = start;
+        let mut body = Vec::new();
+        loop {
+            try!(skip_space(s));
+            let mut item = match s.peek() {
+                Some('{') => {
+                    try!(skip_nested(s, '{', '}'));
+                    s.eat("?");
+                    end = s.mark();
+                    continue
+                },
+                Some('<') => {
+                    try!(skip_nested(s, '<', '>'));
+                    end = s.mark();
+                    continue
+                },
+                Some('(') => {
+                    let open = s.mark();
+                    s.bump();
+                    let bodies = try!(self.alternatives(s, rule));
+                    if !s.eat(")") {
+                        s.reset(open);
+                        return Err(s.error("unclosed group, expected `)`"))
+                    }
+                    end = s.mark();
+                    self.syms.choice(rule, bodies.into_iter().map(|(body, _)| body).collect())
+                },
+                Some('.') => {
+                    s.bump();
+                    end = s.mark();
+                    vec![self.wildcard(rule, vec![])]
+                },
+                Some('~') => {
+                    s.bump();
+                    try!(skip_space(s));
+                    let excluded = try!(self.set(s, rule));
+                    end = s.mark();
+                    vec![self.wildcard(rule, excluded)]
+                },
+                Some('\'') => {
+                    let literal = try!(self.literal(s));
+                    end = s.mark();
+                    vec![literal]
+                },
+                Some(c) if is_ident_start(c) => {
+                    let at = s.mark();
+                    let name = s.take_while(in_ident);
+                    end = s.mark();
+                    try!(skip_space(s));
+                    if s.eat("+=") || s.eat("=") {
+                        // A label; the element follows.
+                        continue
+                    }
+                    if name.as_slice().char_at(0).is_uppercase() {
+                        if name.as_slice() == "EOF" {
+                            vec![]
+                        } else {
+                            vec![self.syms.token(name)]
+                        }
+                    } else {
+                        vec![Symbol::Var(self.syms.variable(name.as_slice(), at))]
+                    }
+                },
+                _ => return Ok((body, body_span(before, start, end)))
+            };
+            loop {
+                try!(skip_space(s));
+                if s.eat("?") {
+                    item = vec![self.syms.optional(rule, item)];
+                } else if s.eat("*") {
+                    item = vec![self.syms.star(rule, item)];
+                } else if s.looking_at("+") && !s.looking_at("+=") {
+                    s.bump();
+                    let rest = self.syms.star(rule, item.clone());
+                    item.push(rest);
+                } else {
+                    break
+                }
+                // A non-greedy suffix doesn't change the language.
+                s.eat("?");
+                end = s.mark();
+            }
+            body.extend(item.into_iter());
+        }
+    }
+
+    /// The token a literal in a parser rule stands for.
+    fn literal(&mut self, s: &mut Scanner) -> Result<Symbol, SyntaxError> {
+        let lit = try!(s.quoted());
+        let name = match self.literals.get(&lit) {
+            Some(name) => name.clone(),
+            None => format!("'{}'", lit)
+        };
+        Ok(self.syms.token(name))
+    }
+
+    /// Read the operand of `~`: a token, a literal, or a parenthesised set of them.
+    fn set(&mut self, s: &mut Scanner, rule: &str) -> Result<Vec<Symbol>, SyntaxError> {
+        match s.peek() {
+            Some('\'') => Ok(vec![try!(self.literal(s))]),
+            Some('(') => {
+                s.bump();
+                let mut set = Vec::new();
+                loop {
+                    try!(skip_space(s));
+                    if s.eat(")") {
+                        return Ok(set)
+                    }
+                    if s.eat("|") {
+                        continue
+                    }
+                    set.extend(try!(self.set(s, rule)).into_iter());
+                }
+            },
+            Some(c) if c.is_uppercase() => {
+                let name = s.take_while(in_ident);
+                Ok(vec![self.syms.token(name)])
+            },
+            _ => Err(s.error("expected a token set after `~`"))
+        }
+    }
+
+    /// A variable deriving every token except those in `excluded`, filled in once all the tokens
+    /// are known.
+    fn wildcard(&mut self, rule: &str, excluded: Vec<Symbol>) -> Symbol {
+        let v = self.syms.fresh(format!("{}_any", rule).as_slice());
+        self.wildcards.push((v, excluded));
+        Symbol::Var(v)
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn in_ident(c: char) -> bool {
+    is_ident_start(c) || c.is_digit(10)
+}
+
+fn in_action_name(c: char) -> bool {
+    in_ident(c) || c == ':'
+}
+
+/// Skip whitespace and comments.
+fn skip_space(s: &mut Scanner) -> Result<(), SyntaxError> {
+    loop {
+        s.skip_while(is_space);
+        if s.looking_at("//") {
+            s.skip_line();
+        } else if s.looking_at("/*") {
+            let start = s.mark();
+            s.eat("/*");
+            while !s.eat("*/") {
+                if s.bump().is_none() {
+                    s.reset(start);
+                    return Err(s.error("unterminated comment"))
+                }
+            }
+        } else {
+            return Ok(())
+        }
+    }
+}
+
+/// Skip a bracketed block such as an action or argument list, which may itself contain nested
+/// brackets, strings, and comments.
+fn skip_nested(s: &mut Scanner, open: char, close: char) -> Result<(), SyntaxError> {
+    try!(skip_space(s));
+    if s.peek() != Some(open) {
+        return Err(s.error(format!("expected `{}`", open).as_slice()))
+    }
+    let start = s.mark();
+    let mut depth = 0u;
+    loop {
+        try!(skip_space(s));
+        match s.peek() {
+            Some(c) if c == open => depth += 1,
+            Some(c) if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    s.bump();
+                    return Ok(())
+                }
+            },
+            Some('"') | Some('\'') => {
+                try!(s.quoted());
+                continue
+            },
+            Some('\\') => { s.bump(); },
+            Some(_) => { },
+            None => {
+                s.reset(start);
+                return Err(s.error(format!("unclosed `{}`", open).as_slice()))
+            }
+        }
+        s.bump();
+    }
+}
+
+/// Skip up to and including the next `stop` outside any string, bracket, or comment.
+fn skip_past(s: &mut Scanner, stop: char) -> Result<(), SyntaxError> {
+    loop {
+        try!(skip_space(s));
+        match s.peek() {
+            Some(c) if c == stop => {
+                s.bump();
+                return Ok(())
+            },
+            Some('\'') | Some('"') => { try!(s.quoted()); },
+            Some('{') => try!(skip_nested(s, '{', '}')),
+            Some('[') => try!(skip_nested(s, '[', ']')),
+            Some(_) => { s.bump(); },
+            None => return Err(s.error(format!("expected `{}`", stop).as_slice()))
+        }
+    }
+}
+
+/// Skip an entire rule, from after its name up to and including any exception handlers.
+fn skip_rule(s: &mut Scanner) -> Result<(), SyntaxError> {
+    try!(skip_past(s, ';'));
+    skip_handlers(s)
+}
+
+/// Skip arguments, return values, locals, options and actions before a parser rule's `:`.
+fn skip_prelude(s: &mut Scanner) -> Result<(), SyntaxError> {
+    loop {
+        try!(skip_space(s));
+        match s.peek() {
+            Some(':') => {
+                s.bump();
+                return Ok(())
+            },
+            Some('[') => try!(skip_nested(s, '[', ']')),
+            Some('{') => try!(skip_nested(s, '{', '}')),
+            Some('@') | Some(',') => {
+                s.bump();
+                s.skip_while(in_ident);
+            },
+            Some(c) if is_ident_start(c) => s.skip_while(in_rule_prelude),
+            _ => return Err(s.error("expected `:`"))
+        }
+    }
+}
+
+fn in_rule_prelude(c: char) -> bool {
+    in_ident(c) || c == '.'
+}
+
+/// Skip `catch` and `finally` clauses after a parser rule.
+fn skip_handlers(s: &mut Scanner) -> Result<(), SyntaxError> {
+    loop {
+        try!(skip_space(s));
+        if eat_keyword(s, "catch") {
+            try!(skip_space(s));
+            try!(skip_nested(s, '[', ']'));
+        } else if !eat_keyword(s, "finally") {
+            return Ok(())
+        }
+        try!(skip_space(s));
+        try!(skip_nested(s, '{', '}'));
+    }
+}
+
+/// Consume `word` if it's at the cursor and not just the start of a longer identifier, such as
+/// the name of a rule `catchClause`.
+fn eat_keyword(s: &mut Scanner, word: &str) -> bool {
+    let len = word.chars().count();
+    if s.looking_at(word) && !s.peek_at(len).map_or(false, in_ident) {
+        s.eat(word)
+    } else {
+        false
+    }
+}
+
+/// Read the body of a lexer rule, giving its literal if the body is just a single literal.
+fn lexer_literal(s: &mut Scanner) -> Result<Option<String>, SyntaxError> {
+    try!(skip_space(s));
+    if !s.eat(":") {
+        return Err(s.error("expected `:`"))
+    }
+    try!(skip_space(s));
+    let mut literal = None;
+    if s.peek() == Some('\'') {
+        let lit = try!(s.quoted());
+        try!(skip_space(s));
+        if s.looking_at(";") || s.looking_at("->") {
+            literal = Some(lit);
+        }
+    }
+    try!(skip_rule(s));
+    Ok(literal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use super::super::Symbol;
+    use testing::{rules, variable};
+
+    static EXPR: &'static str = "
+    grammar Expr;
+    options { language = Java; }
+    @header { import java.util.*; }
+
+    prog : stat+ EOF ;
+    stat : expr NEWLINE             # print
+         | name=ID '=' expr NEWLINE # assign
+         ;
+    expr : expr ('*' | '/') expr | INT | ~('+' | NEWLINE) ;
+
+    MUL : '*' ;
+    ID : [a-z]+ ;
+    INT : [0-9]+ ;
+    NEWLINE : '\\r'? '\\n' -> skip ;
+    fragment DIGIT : [0-9] ;
+    ";
+
+    #[test]
+    fn expressions() {
+        let cfg = parse(EXPR).unwrap();
+        let token = |name: &str| Symbol::Tok(cfg.token(&name.to_string()).unwrap());
+        let stat = Symbol::Var(variable(&cfg, "stat"));
+        let expr = Symbol::Var(variable(&cfg, "expr"));
+        assert_eq!(cfg.get_start(), variable(&cfg, "prog"));
+        // The lexer rules, then the literals without a lexer rule of their own.
+        assert_eq!(cfg.num_tokens(), 7);
+        assert!(cfg.token(&"DIGIT".to_string()).is_none());
+
+        let prog = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(prog.len(), 1);
+        assert_eq!(prog[0].len(), 2);
+        assert_eq!(prog[0][0], stat);
+        assert_eq!(rules(&cfg, prog[0][1]), [vec![stat, prog[0][1]], vec![]].as_slice());
+
+        assert_eq!(cfg.get_rules(variable(&cfg, "stat")).unwrap(),
+                   [vec![expr, token("NEWLINE")],
+                    vec![token("ID"), token("'='"), expr, token("NEWLINE")]].as_slice());
+
+        let rules_of_expr = cfg.get_rules(variable(&cfg, "expr")).unwrap();
+        assert_eq!(rules_of_expr.len(), 3);
+        assert_eq!((rules_of_expr[0][0], rules_of_expr[0][2]), (expr, expr));
+        assert_eq!(rules(&cfg, rules_of_expr[0][1]),
+                   [vec![token("MUL")], vec![token("'/'")]].as_slice());
+        assert_eq!(rules_of_expr[1], vec![token("INT")]);
+        let any = rules(&cfg, rules_of_expr[2][0]);
+        assert_eq!(any.len(), 5);
+        assert!(!any.contains(&vec![token("'+'")]) && !any.contains(&vec![token("NEWLINE")]));
+    }
+
+    #[test]
+    fn handlers() {
+        let cfg = parse("
+            grammar Handlers;
+            a : 'x' b ;
+            catch [RecognitionException e] { recover(); }
+            finally { done(); }
+            b : 'y' ;
+        ").unwrap();
+        assert_eq!(cfg.get_rules(variable(&cfg, "a")).unwrap().len(), 1);
+        assert_eq!(cfg.get_rules(variable(&cfg, "b")).unwrap().len(), 1);
+        assert!(parse("a : 'x' ; catch { }").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse("a : ('x' | b ;\nb : 'y' ;").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 5));
+        assert!(parse("a : 'x'").is_err());
+        assert!(parse("a : c ;").is_err());
+    }
+
+    #[test]
+    fn rules_named_like_handlers() {
+        let cfg = parse("
+            grammar Try;
+            tryStatement : 'try' block catchClause* finallyBlock? ;
+            catchClause : 'catch' block ;
+            finallyBlock : 'finally' block ;
+            block : '{' '}' ;
+        ").unwrap();
+        for name in ["tryStatement", "catchClause", "finallyBlock", "block"].iter() {
+            assert!(cfg.get_rules(variable(&cfg, *name)).is_some());
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! Attribute grammars: values attached to the variables of parse trees, defined by equations on
+//! the rules of a grammar.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, RuleTable, Symbol, Token, Variable};
+use tree::ParseTree;
+
+/// An attribute of a variable, identified by its index among the attributes of an
+/// `AttributeGrammar`.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct Attribute(pub u32);
+
+impl Attribute {
+    pub fn index(self) -> uint {
+        let Attribute(i) = self;
+        i as uint
+    }
+}
+
+/// Which way an attribute's value flows through a parse tree.
+#[deriving(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub enum AttributeKind {
+    /// Defined by the rule expanding the variable, from the attributes of its body.
+    Synthesized,
+    /// Defined by the rule the variable appears in the body of, from the attributes of the rest
+    /// of the rule.
+    Inherited
+}
+
+/// An attribute of a symbol of a rule, or the token there.
+#[deriving(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub enum Occurrence {
+    /// An attribute of the symbol at a position of the rule: 0 for the variable the rule
+    /// expands, and `k` for the `k`th symbol of the body, counting from 1.
+    Attr(uint, Attribute),
+    /// The value of the token at a position of the body, counting from 1.
+    Token(uint)
+}
+
+/// The ways declaring or evaluating attributes can fail.
+#[deriving(Clone, PartialEq, Eq)]
+pub enum AttributeError {
+    /// A rule of a variable isn't in the grammar.
+    RuleOutOfRange { variable: Variable, rule: uint },
+    /// An occurrence in an equation of a rule doesn't name a symbol of the rule with that
+    /// attribute, or a token of the rule.
+    InvalidOccurrence { variable: Variable, rule: uint, occurrence: Occurrence },
+    /// An equation of a rule defines an attribute the rule can't define: an inherited one of the
+    /// variable it expands, or a synthesized one of its body.
+    WrongKind { variable: Variable, rule: uint, occurrence: Occurrence },
+    /// An attribute of a node of a parse tree, by its index in pre-order, has no equation to
+    /// define it, or no value given if it's an inherited attribute of the root.
+    Undefined { node: uint, attribute: Attribute },
+    /// The attributes of the nodes of a parse tree depend on each other in a cycle, each on the
+    /// next and the last on the first.
+    Circular { cycle: Vec<(uint, Attribute)> }
+}
+
+impl fmt::Show for AttributeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AttributeError::RuleOutOfRange { variable, rule } =>
+                write!(f, "variable {} has no rule {}", variable.index(), rule),
+            AttributeError::InvalidOccurrence { variable, rule, occurrence } =>
+                write!(f, "rule {} of variable {} has no {}", rule, variable.index(), occurrence),
+            AttributeError::WrongKind { variable, rule, occurrence } =>
+                write!(f, "rule {} of variable {} can't define {}", rule, variable.index(),
+                       occurrence),
+            AttributeError::Undefined { node, attribute } =>
+                write!(f, "attribute {} of node {} is undefined", attribute.index(), node),
+            AttributeError::Circular { ref cycle } => {
+                try!(write!(f, "attributes depend on each other:"));
+                for &(node, attribute) in cycle.iter() {
+                    try!(write!(f, " {} of node {}", attribute.index(), node));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for AttributeError {
+    fn description(&self) -> &str {
+        match *self {
+            AttributeError::RuleOutOfRange { .. } => "rule out of range",
+            AttributeError::InvalidOccurrence { .. } => "invalid occurrence",
+            AttributeError::WrongKind { .. } => "wrong kind of attribute",
+            AttributeError::Undefined { .. } => "undefined attribute",
+            AttributeError::Circular { .. } => "circular attributes"
+        }
+    }
+}
+
+/// An equation of a rule, computing an attribute from others of the rule.
+struct Equation<V> {
+    target: Occurrence,
+    depends: Vec<Occurrence>,
+    function: fn(Vec<V>) -> V
+}
+
+/// Attributes declared on the variables of a grammar, with equations on its rules defining them.
+///
+/// Each rule defines the synthesized attributes of the variable it expands and the inherited
+/// attributes of the variables in its body. Equations are kept by rule id, so they stay with
+/// their rules if the grammar is edited.
+pub struct AttributeGrammar<'a, T: 'a, V> {
+    cfg: &'a Cfg<T>,
+    /// The variable, kind and name of each attribute.
+    attributes: Vec<(Variable, AttributeKind, String)>,
+    equations: RuleTable<Vec<Equation<V>>>,
+    token: fn(Token) -> V
+}
+
+impl<'a, T: Eq + Hash + Clone, V: Clone> AttributeGrammar<'a, T, V> {
+    /// An attribute grammar with no attributes yet, giving each token the value `token` gives
+    /// it.
+    pub fn new(cfg: &'a Cfg<T>, token: fn(Token) -> V) -> AttributeGrammar<'a, T, V> {
+        AttributeGrammar {
+            cfg: cfg,
+            attributes: Vec::new(),
+            equations: RuleTable::new(),
+            token: token
+        }
+    }
+
+    /// Declare an attribute of a variable.
+    pub fn add_attribute(&mut self, variable: Variable, kind: AttributeKind, name: &str)
+                         -> Attribute {
+        self.attributes.push((variable, kind, name.to_string()));
+        Attribute((self.attributes.len() - 1) as u32)
+    }
+
+    /// The variable, kind and name of an attribute.
+    pub fn attribute(&self, attribute: Attribute) -> Option<(Variable, AttributeKind, &str)> {
+        self.attributes.get(attribute.index()).map(|&(v, kind, ref name)| {
+            (v, kind, name.as_slice())
+        })
+    }
+
+    /// Find an attribute of a variable by name.
+    pub fn find_attribute(&self, variable: Variable, name: &str) -> Option<Attribute> {
+        self.attributes.iter().position(|&(v, _, ref other)| {
+            v == variable && other.as_slice() == name
+        }).map(|i| Attribute(i as u32))
+    }
+
+    /// Define `target` in a rule, by its index in `get_rules`, as `function` of the values of
+    /// `depends`, in order.
+    pub fn add_equation(&mut self, variable: Variable, rule: uint, target: Occurrence,
+                        depends: Vec<Occurrence>, function: fn(Vec<V>) -> V)
+                        -> Result<(), AttributeError> {
+        let id = match self.cfg.rule_id(variable, rule) {
+            Some(id) => id,
+            None => return Err(AttributeError::RuleOutOfRange { variable: variable, rule: rule })
+        };
+        for &occurrence in depends.iter().chain(Some(target).iter()) {
+            if !self.occurs(variable, rule, occurrence) {
+                return Err(AttributeError::InvalidOccurrence { variable: variable, rule: rule,
+                                                               occurrence: occurrence })
+            }
+        }
+        let defines = match target {
+            Occurrence::Attr(0, a) => self.attributes[a.index()].1 == AttributeKind::Synthesized,
+            Occurrence::Attr(_, a) => self.attributes[a.index()].1 == AttributeKind::Inherited,
+            Occurrence::Token(_) => false
+        };
+        if !defines {
+            return Err(AttributeError::WrongKind { variable: variable, rule: rule,
+                                                   occurrence: target })
+        }
+        let equation = Equation { target: target, depends: depends, function: function };
+        if self.equations.rule_data(id).is_none() {
+            self.equations.set_rule_data(id, Vec::new());
+        }
+        self.equations.rule_data_mut(id).unwrap().push(equation);
+        Ok(())
+    }
+
+    /// Whether an occurrence names a symbol of a rule with that attribute, or a token of it.
+    fn occurs(&self, variable: Variable, rule: uint, occurrence: Occurrence) -> bool {
+        let body = self.cfg.get_rules(variable).unwrap()[rule].as_slice();
+        match occurrence {
+            Occurrence::Attr(position, a) => {
+                let symbol = if position == 0 {
+                    Symbol::Var(variable)
+                } else {
+                    match body.get(position - 1) {
+                        Some(&symbol) => symbol,
+                        None => return false
+                    }
+                };
+                match self.attributes.get(a.index()) {
+                    Some(&(v, _, _)) => symbol == Symbol::Var(v),
+                    None => false
+                }
+            },
+            Occurrence::Token(position) => {
+                position > 0 && match body.get(position - 1) {
+                    Some(&Symbol::Tok(_)) => true,
+                    _ => false
+                }
+            }
+        }
+    }
+
+    /// Compute every attribute of every variable in a parse tree of the grammar, given the
+    /// values of the inherited attributes of the root.
+    ///
+    /// Each attribute is computed once the ones its equation depends on are, so the order of
+    /// the equations doesn't matter. Fails if an attribute has no equation, or if attributes of
+    /// the tree depend on each other in a cycle.
+    pub fn evaluate(&self, tree: &ParseTree, root: &[(Attribute, V)])
+                    -> Result<Evaluation<V>, AttributeError> {
+        // The nodes in pre-order, with the node of each child.
+        let mut nodes: Vec<(&ParseTree, Vec<uint>)> = Vec::new();
+        let mut stack = vec![(tree, None)];
+        loop {
+            let (node, parent) = match stack.pop() {
+                Some(top) => top,
+                None => break
+            };
+            let id = nodes.len();
+            match parent {
+                Some(p) => { nodes[p].1.push(id); },
+                None => { }
+            }
+            nodes.push((node, Vec::new()));
+            for child in node.children().iter().rev() {
+                stack.push((child, Some(id)));
+            }
+        }
+
+        // The equation defining each attribute of each node, as the node of the rule, and the
+        // nodes of the equation's dependencies or the values of its tokens.
+        let mut definitions: HashMap<(uint, Attribute), (&Equation<V>, Vec<Source<V>>)> =
+            HashMap::new();
+        for (n, &(node, ref children)) in nodes.iter().enumerate() {
+            let (variable, rule) = match *node {
+                ParseTree::Node(variable, rule, _) => (variable, rule),
+                ParseTree::Leaf(_) => continue
+            };
+            let equations = match self.cfg.rule_id(variable, rule)
+                                          .and_then(|id| self.equations.rule_data(id)) {
+                Some(equations) => equations,
+                None => continue
+            };
+            let node_at = |position: uint| if position == 0 { n } else { children[position - 1] };
+            for equation in equations.iter() {
+                let target = match equation.target {
+                    Occurrence::Attr(position, a) => (node_at(position), a),
+                    Occurrence::Token(_) => continue
+                };
+                let sources = equation.depends.iter().map(|&occurrence| match occurrence {
+                    Occurrence::Attr(position, a) => Source::Attr(node_at(position), a),
+                    Occurrence::Token(position) => {
+                        let token = nodes[node_at(position)].0.tokens()[0];
+                        Source::Value((self.token)(token))
+                    }
+                }).collect();
+                definitions.insert(target, (equation, sources));
+            }
+        }
+
+        let mut values = HashMap::new();
+        for &(a, ref value) in root.iter() {
+            values.insert((0, a), value.clone());
+        }
+        for (n, &(node, _)) in nodes.iter().enumerate() {
+            let variable = match *node {
+                ParseTree::Node(variable, _, _) => variable,
+                ParseTree::Leaf(_) => continue
+            };
+            for a in range(0, self.attributes.len()).map(|a| Attribute(a as u32)) {
+                if self.attributes[a.index()].0 == variable {
+                    try!(self.compute(&definitions, &mut values, (n, a)));
+                }
+            }
+        }
+        Ok(Evaluation { values: values })
+    }
+
+    /// Compute an attribute of a node, and those it depends on first. The attributes being
+    /// computed are kept on an explicit stack, so deep trees can't overflow the call stack, and
+    /// finding one on it again means they're circular.
+    fn compute(&self, definitions: &HashMap<(uint, Attribute), (&Equation<V>, Vec<Source<V>>)>,
+               values: &mut HashMap<(uint, Attribute), V>, key: (uint, Attribute))
+               -> Result<(), AttributeError> {
+        let mut stack = vec![key];
+        loop {
+            let top = match stack.last() {
+                Some(&top) if values.contains_key(&top) => {
+                    stack.pop();
+                    continue
+                },
+                Some(&top) => top,
+                None => return Ok(())
+            };
+            let &(equation, ref sources) = match definitions.get(&top) {
+                Some(definition) => definition,
+                None => return Err(AttributeError::Undefined { node: top.0, attribute: top.1 })
+            };
+            let missing = sources.iter().filter_map(|source| match *source {
+                Source::Attr(n, a) if !values.contains_key(&(n, a)) => Some((n, a)),
+                _ => None
+            }).next();
+            match missing {
+                Some(next) => {
+                    match stack.iter().position(|&k| k == next) {
+                        Some(i) => {
+                            return Err(AttributeError::Circular { cycle: stack.slice_from(i)
+                                                                              .to_vec() })
+                        },
+                        None => stack.push(next)
+                    }
+                },
+                None => {
+                    let arguments = sources.iter().map(|source| match *source {
+                        Source::Attr(n, a) => values.get(&(n, a)).unwrap().clone(),
+                        Source::Value(ref value) => value.clone()
+                    }).collect();
+                    values.insert(top, (equation.function)(arguments));
+                    stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Where an argument of an equation comes from in a parse tree.
+enum Source<V> {
+    /// An attribute of a node.
+    Attr(uint, Attribute),
+    /// The value of a token.
+    Value(V)
+}
+
+/// The values of the attributes of the nodes of a parse tree, found by
+/// `AttributeGrammar::evaluate`.
+pub struct Evaluation<V> {
+    values: HashMap<(uint, Attribute), V>
+}
+
+impl<V> Evaluation<V> {
+    /// The value of an attribute of a node, by its index in the pre-order of the tree.
+    pub fn get(&self, node: uint, attribute: Attribute) -> Option<&V> {
+        self.values.get(&(node, attribute))
+    }
+
+    /// The value of an attribute of the root.
+    pub fn root(&self, attribute: Attribute) -> Option<&V> {
+        self.get(0, attribute)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Attribute, AttributeError, AttributeGrammar, AttributeKind, Occurrence};
+    use super::super::{Cfg, Token};
+    use ll1::Ll1Parser;
+    use testing::{grammar, tokens, variable};
+    use tree::ParseTree;
+
+    /// Binary numerals, read most significant digit first.
+    const BINARY: &'static str = "
+    <n> ::= <d> <rest>
+    <rest> ::= <d> <rest> | \"\"
+    <d> ::= \"0\" | \"1\"
+    ";
+
+    /// The tokens of `BINARY` are numbered as their digits.
+    fn digit(token: Token) -> i64 {
+        token.index() as i64
+    }
+
+    fn first(values: Vec<i64>) -> i64 {
+        values[0]
+    }
+
+    fn shift_in(values: Vec<i64>) -> i64 {
+        2 * values[0] + values[1]
+    }
+
+    fn parse(cfg: &Cfg<String>, text: &str) -> ParseTree {
+        Ll1Parser::new(cfg).unwrap().parse(tokens(cfg, text).as_slice()).unwrap()
+    }
+
+    /// The attributes of `BINARY` computing the value of a numeral: `value` of each variable, and
+    /// `acc`, the value of the digits before a `rest`.
+    fn binary<'a>(cfg: &'a Cfg<String>) -> (AttributeGrammar<'a, String, i64>, Attribute) {
+        let (n, rest, d) = (variable(cfg, "n"), variable(cfg, "rest"), variable(cfg, "d"));
+        let mut attributes = AttributeGrammar::new(cfg, digit);
+        let value = attributes.add_attribute(n, AttributeKind::Synthesized, "value");
+        let rest_value = attributes.add_attribute(rest, AttributeKind::Synthesized, "value");
+        let acc = attributes.add_attribute(rest, AttributeKind::Inherited, "acc");
+        let digit_value = attributes.add_attribute(d, AttributeKind::Synthesized, "value");
+        let equations = [
+            (n, 0, Occurrence::Attr(0, value), vec![Occurrence::Attr(2, rest_value)]),
+            (n, 0, Occurrence::Attr(2, acc), vec![Occurrence::Attr(1, digit_value)]),
+            (rest, 1, Occurrence::Attr(0, rest_value), vec![Occurrence::Attr(0, acc)]),
+            (rest, 0, Occurrence::Attr(0, rest_value), vec![Occurrence::Attr(2, rest_value)]),
+            (d, 0, Occurrence::Attr(0, digit_value), vec![Occurrence::Token(1)]),
+            (d, 1, Occurrence::Attr(0, digit_value), vec![Occurrence::Token(1)])
+        ];
+        for &(v, rule, target, ref depends) in equations.iter() {
+            attributes.add_equation(v, rule, target, depends.clone(), first).unwrap();
+        }
+        attributes.add_equation(rest, 0, Occurrence::Attr(2, acc),
+                                vec![Occurrence::Attr(0, acc), Occurrence::Attr(1, digit_value)],
+                                shift_in).unwrap();
+        (attributes, value)
+    }
+
+    #[test]
+    fn evaluate() {
+        let cfg = grammar(BINARY);
+        assert_eq!(cfg.token(&"1".to_string()), Some(Token(1)));
+        let (attributes, value) = binary(&cfg);
+        let rest = variable(&cfg, "rest");
+        assert_eq!(attributes.find_attribute(rest, "acc"), Some(Attribute(2)));
+        assert_eq!(attributes.find_attribute(rest, "other"), None);
+        let expected = (rest, AttributeKind::Inherited, "acc");
+        assert_eq!(attributes.attribute(Attribute(2)), Some(expected));
+
+        let evaluation = attributes.evaluate(&parse(&cfg, "1 0 1 1"), &[]).ok().unwrap();
+        assert_eq!(evaluation.root(value), Some(&11));
+        // The `d` of the root is node 1, and its `rest` is node 3.
+        assert_eq!(evaluation.get(1, Attribute(3)), Some(&1));
+        assert_eq!(evaluation.get(3, Attribute(2)), Some(&1));
+        assert_eq!(evaluation.get(3, value), None);
+        let evaluation = attributes.evaluate(&parse(&cfg, "0"), &[]).ok().unwrap();
+        assert_eq!(evaluation.root(value), Some(&0));
+    }
+
+    #[test]
+    fn errors() {
+        let cfg = grammar(BINARY);
+        let (n, rest) = (variable(&cfg, "n"), variable(&cfg, "rest"));
+        let mut attributes = AttributeGrammar::new(&cfg, digit);
+        let value = attributes.add_attribute(n, AttributeKind::Synthesized, "value");
+        let rest_value = attributes.add_attribute(rest, AttributeKind::Synthesized, "value");
+        let acc = attributes.add_attribute(rest, AttributeKind::Inherited, "acc");
+
+        assert_eq!(attributes.add_equation(n, 1, Occurrence::Attr(0, value), vec![], first).err(),
+                   Some(AttributeError::RuleOutOfRange { variable: n, rule: 1 }));
+        let target = Occurrence::Attr(1, value);
+        let expected = AttributeError::InvalidOccurrence {
+            variable: n,
+            rule: 0,
+            occurrence: target
+        };
+        assert_eq!(attributes.add_equation(n, 0, target, vec![], first).err(), Some(expected));
+        let target = Occurrence::Attr(0, acc);
+        assert_eq!(attributes.add_equation(rest, 1, target, vec![], first).err(),
+                   Some(AttributeError::WrongKind { variable: rest, rule: 1, occurrence: target }));
+        let depends = vec![Occurrence::Token(1)];
+        assert!(attributes.add_equation(rest, 1, Occurrence::Attr(0, rest_value), depends, first)
+                          .is_err());
+
+        let tree = parse(&cfg, "1");
+        assert_eq!(attributes.evaluate(&tree, &[]).err(),
+                   Some(AttributeError::Undefined { node: 0, attribute: value }));
+
+        // The value of `rest` is its `acc`, which is its value.
+        attributes.add_equation(n, 0, Occurrence::Attr(0, value),
+                                vec![Occurrence::Attr(2, rest_value)], first).unwrap();
+        attributes.add_equation(n, 0, Occurrence::Attr(2, acc),
+                                vec![Occurrence::Attr(2, rest_value)], first).unwrap();
+        attributes.add_equation(rest, 1, Occurrence::Attr(0, rest_value),
+                                vec![Occurrence::Attr(0, acc)], first).unwrap();
+        match attributes.evaluate(&tree, &[]).err() {
+            Some(AttributeError::Circular { cycle }) => {
+                let mut cycle = cycle;
+                cycle.sort();
+                assert_eq!(cycle, vec![(3, rest_value), (3, acc)]);
+            },
+            _ => panic!("expected a cycle")
+        }
+        // The inherited attributes of the root can be given.
+        let mut attributes = AttributeGrammar::new(&cfg, digit);
+        let acc = attributes.add_attribute(rest, AttributeKind::Inherited, "acc");
+        let tree = ParseTree::Node(rest, 1, vec![]);
+        let evaluation = attributes.evaluate(&tree, &[(acc, 5)]).ok().unwrap();
+        assert_eq!(evaluation.root(acc), Some(&5));
+    }
+}
@@ -0,0 +1,267 @@
+//! Command-line access to the analyses of the `cfg` crate, for a grammar read from a file.
+
+extern crate cfg;
+
+use std::io::stdio;
+use std::os;
+use std::rand;
+
+use cfg::{Cfg, Symbol, Token, Variable};
+use cfg::lint::Severity;
+use cfg::lr::Action;
+use cfg::lrgen::read_grammar;
+
+static USAGE: &'static str = "usage: cfg-tool <command> <grammar> [<args>]
+
+Reads the grammar as Yacc if its file ends in .y, as ANTLR if .g4, as EBNF if .ebnf, and as BNF
+otherwise.
+
+commands:
+    check                      report likely mistakes in the grammar
+    first                      the FIRST set of each variable
+    follow                     the FOLLOW set of each variable
+    ll1                        the LL(1) parse table, or its conflicts
+    lalr                       the LALR(1) parse table, or its conflicts
+    generate [count [depth]]   random sentences, 10 of them to a depth of 20 by default
+    to-cnf                     the grammar in Chomsky Normal Form, as BNF
+    dot                        the LR(0) automaton, as a Graphviz graph";
+
+fn main() {
+    let args = os::args();
+    if args.len() < 3 {
+        fail(USAGE);
+        return
+    }
+    let cfg = match read_grammar(&Path::new(args[2].as_slice())) {
+        Ok(cfg) => cfg,
+        Err(error) => {
+            fail(format!("cfg-tool: {}: {}", args[2], error).as_slice());
+            return
+        }
+    };
+    let ok = match (args[1].as_slice(), args.slice_from(3)) {
+        ("check", []) => check(&cfg),
+        ("first", []) => first(&cfg),
+        ("follow", []) => follow(&cfg),
+        ("ll1", []) => ll1(&cfg),
+        ("lalr", []) => lalr(&cfg),
+        ("generate", rest) if rest.len() <= 2 => {
+            let numbers: Vec<Option<uint>> = rest.iter().map(|n| from_str(n.as_slice())).collect();
+            match numbers.as_slice() {
+                [] => generate(&cfg, 10, 20),
+                [Some(count)] => generate(&cfg, count, 20),
+                [Some(count), Some(depth)] => generate(&cfg, count, depth),
+                _ => {
+                    fail(USAGE);
+                    return
+                }
+            }
+        },
+        ("to-cnf", []) => {
+            let (cnf, _) = cfg.to_cnf();
+            print!("{}", cnf);
+            true
+        },
+        ("dot", []) => dot(&cfg),
+        _ => {
+            fail(USAGE);
+            return
+        }
+    };
+    if !ok {
+        os::set_exit_status(1);
+    }
+}
+
+/// Print a message to stderr.
+fn error(message: &str) {
+    let _ = writeln!(&mut stdio::stderr(), "{}", message);
+}
+
+/// Print a message to stderr and set the exit status for being used wrongly.
+fn fail(message: &str) {
+    error(message);
+    os::set_exit_status(2);
+}
+
+fn variables(cfg: &Cfg<String>) -> Vec<Variable> {
+    cfg.variables().filter(|&v| cfg.get_rules(v).is_some()).collect()
+}
+
+fn lookahead_to_string(cfg: &Cfg<String>, lookahead: Option<Token>) -> String {
+    match lookahead {
+        Some(t) => cfg.symbol_to_bnf(Symbol::Tok(t)),
+        None => "$".to_string()
+    }
+}
+
+/// Print each lint found, failing if any is an error.
+fn check(cfg: &Cfg<String>) -> bool {
+    let diagnostics = cfg.lint();
+    for diagnostic in diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
+    diagnostics.iter().all(|d| d.severity() != Severity::Error)
+}
+
+fn first(cfg: &Cfg<String>) -> bool {
+    let sets = cfg.first_sets();
+    for v in variables(cfg).into_iter() {
+        let set = sets.get(v);
+        let mut line = format!("{}:", cfg.symbol_to_bnf(Symbol::Var(v)));
+        for t in set.tokens.iter() {
+            line.push(' ');
+            line.push_str(cfg.symbol_to_bnf(Symbol::Tok(t)).as_slice());
+        }
+        if set.epsilon {
+            line.push_str(" \"\"");
+        }
+        println!("{}", line);
+    }
+    true
+}
+
+fn follow(cfg: &Cfg<String>) -> bool {
+    let sets = cfg.follow_sets();
+    for v in variables(cfg).into_iter() {
+        let set = sets.get(v);
+        let mut line = format!("{}:", cfg.symbol_to_bnf(Symbol::Var(v)));
+        for t in set.tokens.iter() {
+            line.push(' ');
+            line.push_str(cfg.symbol_to_bnf(Symbol::Tok(t)).as_slice());
+        }
+        if set.end {
+            line.push_str(" $");
+        }
+        println!("{}", line);
+    }
+    true
+}
+
+/// Print the rule to expand each variable by on each lookahead, or every conflict.
+fn ll1(cfg: &Cfg<String>) -> bool {
+    let rule_to_string = |v: Variable, rule: uint| {
+        let mut text = format!("{} ::=", cfg.symbol_to_bnf(Symbol::Var(v)));
+        for &symbol in cfg.get_rules(v).unwrap()[rule].iter() {
+            text.push(' ');
+            text.push_str(cfg.symbol_to_bnf(symbol).as_slice());
+        }
+        text
+    };
+    let lookaheads: Vec<Option<Token>> =
+        cfg.tokens().map(|t| Some(t)).chain(Some(None).into_iter()).collect();
+    match cfg.ll1_table() {
+        Ok(table) => {
+            for v in variables(cfg).into_iter() {
+                for &lookahead in lookaheads.iter() {
+                    match table.get(v, lookahead) {
+                        Some(rule) => println!("{} {}: {}", cfg.symbol_to_bnf(Symbol::Var(v)),
+                                               lookahead_to_string(cfg, lookahead),
+                                               rule_to_string(v, rule)),
+                        None => { }
+                    }
+                }
+            }
+            true
+        },
+        Err(conflicts) => {
+            for conflict in conflicts.iter() {
+                println!("conflict on {} {}:", cfg.symbol_to_bnf(Symbol::Var(conflict.variable)),
+                         lookahead_to_string(cfg, conflict.lookahead));
+                for &rule in conflict.rules.iter() {
+                    println!("    {}", rule_to_string(conflict.variable, rule));
+                }
+            }
+            false
+        }
+    }
+}
+
+/// Print the actions and gotos of each state, or every conflict.
+fn lalr(cfg: &Cfg<String>) -> bool {
+    match cfg.lalr_table() {
+        Ok(table) => {
+            let lookaheads: Vec<Option<Token>> =
+                cfg.tokens().map(|t| Some(t)).chain(Some(None).into_iter()).collect();
+            for state in range(0, table.num_states()) {
+                println!("state {}:", state);
+                for &lookahead in lookaheads.iter() {
+                    let action = match table.action(state, lookahead) {
+                        Some(Action::Shift(next)) => format!("shift {}", next),
+                        Some(Action::Reduce(v, rule)) => {
+                            format!("reduce {} rule {}", cfg.symbol_to_bnf(Symbol::Var(v)), rule)
+                        },
+                        Some(Action::Accept) => "accept".to_string(),
+                        None => continue
+                    };
+                    println!("    {}: {}", lookahead_to_string(cfg, lookahead), action);
+                }
+                for v in variables(cfg).into_iter() {
+                    match table.goto(state, v) {
+                        Some(next) => {
+                            println!("    {}: goto {}", cfg.symbol_to_bnf(Symbol::Var(v)), next)
+                        },
+                        None => { }
+                    }
+                }
+            }
+            true
+        },
+        Err(conflicts) => {
+            for conflict in conflicts.iter() {
+                println!("{} conflict in state {} on {}:",
+                         if conflict.is_shift_reduce() { "shift/reduce" } else { "reduce/reduce" },
+                         conflict.state, lookahead_to_string(cfg, conflict.lookahead));
+                for &item in conflict.items.iter() {
+                    println!("    {}", cfg.item_to_string(item));
+                }
+            }
+            false
+        }
+    }
+}
+
+/// Print random sentences, one per line with their terminals separated by spaces.
+fn generate(cfg: &Cfg<String>, count: uint, depth: uint) -> bool {
+    let mut rng = rand::thread_rng();
+    for _ in range(0, count) {
+        let sentence = match cfg.generate(&mut rng, depth) {
+            Some(sentence) => sentence,
+            None => {
+                error(format!("cfg-tool: no sentence derives within a depth of {}",
+                             depth).as_slice());
+                return false
+            }
+        };
+        let terminals: Vec<&str> =
+            sentence.iter().map(|&t| cfg.terminal(t).unwrap().as_slice()).collect();
+        println!("{}", terminals.connect(" "));
+    }
+    true
+}
+
+/// Print the LR(0) automaton with each state labelled by its items.
+fn dot(cfg: &Cfg<String>) -> bool {
+    let automaton = cfg.lr0_automaton();
+    println!("digraph lr0 {{");
+    println!("    node [shape=box, fontname=monospace];");
+    for (s, state) in automaton.states.iter().enumerate() {
+        let mut label = format!("{}", s);
+        for &item in state.items.iter() {
+            label.push_str("\\l");
+            label.push_str(cfg.item_to_string(item).as_slice());
+        }
+        println!("    {} [label=\"{}\\l\"];", s, escape(label.as_slice()));
+    }
+    for &(from, symbol, to) in automaton.edges().iter() {
+        println!("    {} -> {} [label=\"{}\"];", from, to,
+                 escape(cfg.symbol_to_bnf(symbol).as_slice()));
+    }
+    println!("}}");
+    true
+}
+
+/// Escape the quotes in a Graphviz label, keeping the `\l` line breaks already in it.
+fn escape(label: &str) -> String {
+    label.replace("\"", "\\\"")
+}
@@ -0,0 +1,371 @@
+//! A compact binary format for grammars and parse tables, so they can be built once, such as by
+//! a build script, and loaded quickly when a program starts.
+//!
+//! Data starts with the bytes `cfg` and a zero, the version of the format and the kind of thing
+//! it holds, and numbers are written as LEB128 varints. Loading checks all three, so data from
+//! another version of the format is refused rather than misread.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+#[cfg(feature = "std")]
+use std::error::Error;
+use std::uint;
+
+use super::{Associativity, Cfg, Symbol, Token, Variable};
+
+/// The version of the format written.
+pub const VERSION: uint = 1;
+
+const MAGIC: &'static str = "cfg\0";
+
+/// The kinds of thing the format holds.
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+pub enum Kind {
+    Grammar,
+    Ll1Table,
+    LrTable
+}
+
+impl Kind {
+    fn code(self) -> uint {
+        match self {
+            Kind::Grammar => 0,
+            Kind::Ll1Table => 1,
+            Kind::LrTable => 2
+        }
+    }
+}
+
+/// The ways loading data in the format can fail.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum LoadError {
+    /// The data doesn't start with the format's magic bytes.
+    NotCfgData,
+    /// The data was written by another version of the format.
+    UnsupportedVersion { version: uint },
+    /// The data holds another kind of thing, given by its code.
+    WrongKind { expected: Kind, found: uint },
+    /// The data ends before what it holds does.
+    Truncated,
+    /// The data is in the format but doesn't describe a valid grammar or table.
+    Invalid
+}
+
+#[cfg(feature = "std")]
+impl Error for LoadError {
+    fn description(&self) -> &str {
+        match *self {
+            LoadError::NotCfgData => "not cfg data",
+            LoadError::UnsupportedVersion { .. } => "unsupported version",
+            LoadError::WrongKind { .. } => "wrong kind",
+            LoadError::Truncated => "truncated",
+            LoadError::Invalid => "invalid"
+        }
+    }
+}
+
+/// Writes data in the format, after its header.
+pub struct Writer {
+    bytes: Vec<u8>
+}
+
+impl Writer {
+    /// Start writing data of a kind, with the header for it.
+    pub fn new(kind: Kind) -> Writer {
+        let mut writer = Writer { bytes: MAGIC.as_bytes().to_vec() };
+        writer.uint(VERSION);
+        writer.uint(kind.code());
+        writer
+    }
+
+    pub fn uint(&mut self, mut n: uint) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.bytes.push(byte);
+                return
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    /// Write a string as its length in bytes followed by its UTF-8.
+    pub fn str(&mut self, s: &str) {
+        self.uint(s.len());
+        self.bytes.push_all(s.as_bytes());
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads data in the format, after checking its header.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: uint
+}
+
+impl<'a> Reader<'a> {
+    /// Start reading data, which must be of the current version and of `kind`.
+    pub fn new(bytes: &'a [u8], kind: Kind) -> Result<Reader<'a>, LoadError> {
+        if !bytes.starts_with(MAGIC.as_bytes()) {
+            return Err(LoadError::NotCfgData)
+        }
+        let mut reader = Reader { bytes: bytes, position: MAGIC.len() };
+        let version = try!(reader.uint());
+        if version != VERSION {
+            return Err(LoadError::UnsupportedVersion { version: version })
+        }
+        let found = try!(reader.uint());
+        if found != kind.code() {
+            return Err(LoadError::WrongKind { expected: kind, found: found })
+        }
+        Ok(reader)
+    }
+
+    pub fn uint(&mut self) -> Result<uint, LoadError> {
+        let mut n = 0u;
+        let mut shift = 0u;
+        loop {
+            let byte = match self.bytes.get(self.position) {
+                Some(&byte) => byte,
+                None => return Err(LoadError::Truncated)
+            };
+            self.position += 1;
+            if shift >= uint::BITS {
+                return Err(LoadError::Invalid)
+            }
+            n |= ((byte & 0x7f) as uint) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n)
+            }
+            shift += 7;
+        }
+    }
+
+    /// A number that must be less than `bound`, such as an index.
+    pub fn below(&mut self, bound: uint) -> Result<uint, LoadError> {
+        let n = try!(self.uint());
+        if n < bound { Ok(n) } else { Err(LoadError::Invalid) }
+    }
+
+    pub fn string(&mut self) -> Result<String, LoadError> {
+        let len = try!(self.uint());
+        if len > self.bytes.len() - self.position {
+            return Err(LoadError::Truncated)
+        }
+        let bytes = self.bytes.slice(self.position, self.position + len).to_vec();
+        self.position += len;
+        String::from_utf8(bytes).map_err(|_| LoadError::Invalid)
+    }
+
+    /// Finish reading, failing if there's anything left over.
+    pub fn finish(self) -> Result<(), LoadError> {
+        if self.position == self.bytes.len() { Ok(()) } else { Err(LoadError::Invalid) }
+    }
+}
+
+impl Cfg<String> {
+    /// The grammar in the binary format: its terminals, variable names, start symbol, rules
+    /// and precedences. Rule ids aren't kept.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new(Kind::Grammar);
+        w.uint(self.num_tokens());
+        for t in self.tokens() {
+            w.str(self.terminal(t).unwrap().as_slice());
+        }
+        w.uint(self.num_variables());
+        for v in self.variables() {
+            w.str(self.name(v).unwrap_or(""));
+        }
+        w.uint(self.get_start().index());
+        for v in self.variables() {
+            let rules = self.get_rules(v).unwrap_or(&[]);
+            w.uint(rules.len());
+            for rule in rules.iter() {
+                w.uint(rule.len());
+                for &symbol in rule.iter() {
+                    w.uint(match symbol {
+                        Symbol::Tok(t) => t.index() * 2,
+                        Symbol::Var(v) => v.index() * 2 + 1
+                    });
+                }
+            }
+        }
+
+        let precedences: Vec<(Token, (uint, Associativity))> = self.tokens()
+            .filter_map(|t| self.precedence(t).map(|p| (t, p)))
+            .collect();
+        w.uint(precedences.len());
+        for &(t, (level, associativity)) in precedences.iter() {
+            w.uint(t.index());
+            w.uint(level);
+            w.uint(match associativity {
+                Associativity::Left => 0,
+                Associativity::Right => 1,
+                Associativity::NonAssoc => 2,
+                Associativity::Unspecified => 3
+            });
+        }
+        let mut rule_precedences = Vec::new();
+        for (v, rules) in self.rules.iter() {
+            for rule in range(0, rules.len()) {
+                match self.rule_precedence_token(Variable(v as u32), rule) {
+                    Some(t) => rule_precedences.push((Variable(v as u32), rule, t)),
+                    None => { }
+                }
+            }
+        }
+        w.uint(rule_precedences.len());
+        for &(v, rule, t) in rule_precedences.iter() {
+            w.uint(v.index());
+            w.uint(rule);
+            w.uint(t.index());
+        }
+        w.finish()
+    }
+
+    /// Load a grammar written by `to_bytes`, which must be well-formed as `validate` checks.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cfg<String>, LoadError> {
+        let mut r = try!(Reader::new(bytes, Kind::Grammar));
+        let mut cfg = Cfg::new();
+        let tokens = try!(r.uint());
+        for _ in range(0, tokens) {
+            let terminal = try!(r.string());
+            if cfg.token(&terminal).is_some() {
+                return Err(LoadError::Invalid)
+            }
+            cfg.add_token(terminal);
+        }
+        let variables = try!(r.uint());
+        for _ in range(0, variables) {
+            let name = try!(r.string());
+            cfg.add_variable(name.as_slice());
+        }
+        let start = try!(r.below(variables));
+        try!(cfg.set_start(Variable(start as u32)).map_err(|_| LoadError::Invalid));
+        for v in range(0, variables) {
+            let rules = try!(r.uint());
+            for _ in range(0, rules) {
+                let len = try!(r.uint());
+                let mut rule = Vec::new();
+                for _ in range(0, len) {
+                    let code = try!(r.uint());
+                    rule.push(if code % 2 == 0 {
+                        Symbol::Tok(Token((code / 2) as u32))
+                    } else {
+                        Symbol::Var(Variable((code / 2) as u32))
+                    });
+                }
+                try!(cfg.add_rule(Variable(v as u32), rule).map_err(|_| LoadError::Invalid));
+            }
+        }
+
+        let precedences = try!(r.uint());
+        for _ in range(0, precedences) {
+            let t = Token(try!(r.below(tokens)) as u32);
+            let level = try!(r.uint());
+            let associativity = match try!(r.uint()) {
+                0 => Associativity::Left,
+                1 => Associativity::Right,
+                2 => Associativity::NonAssoc,
+                3 => Associativity::Unspecified,
+                _ => return Err(LoadError::Invalid)
+            };
+            try!(cfg.set_precedence(t, level, associativity).map_err(|_| LoadError::Invalid));
+        }
+        let rule_precedences = try!(r.uint());
+        for _ in range(0, rule_precedences) {
+            let v = Variable(try!(r.below(variables)) as u32);
+            let rule = try!(r.uint());
+            let t = Token(try!(r.below(tokens)) as u32);
+            try!(cfg.set_rule_precedence(v, rule, t).map_err(|_| LoadError::Invalid));
+        }
+        try!(r.finish());
+        try!(cfg.validate().map_err(|_| LoadError::Invalid));
+        Ok(cfg)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{Kind, LoadError, Reader, Writer};
+    use super::super::{Associativity, Cfg};
+    use std::uint;
+    use testing::{EXPRESSIONS, grammar, variable};
+
+    #[test]
+    fn grammar_round_trip() {
+        let mut cfg = grammar(EXPRESSIONS);
+        let plus = cfg.token(&"+".to_string()).unwrap();
+        let times = cfg.token(&"*".to_string()).unwrap();
+        cfg.set_precedence(plus, 1, Associativity::Left).unwrap();
+        cfg.set_precedence(times, 2, Associativity::Left).unwrap();
+        let f = variable(&cfg, "f");
+        cfg.set_rule_precedence(f, 1, times).unwrap();
+
+        let bytes = cfg.to_bytes();
+        let loaded = Cfg::from_bytes(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.to_bnf(), cfg.to_bnf());
+        assert_eq!(loaded.precedence(plus), Some((1, Associativity::Left)));
+        assert_eq!(loaded.rule_precedence(f, 1), Some((2, Associativity::Left)));
+        assert_eq!(loaded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn bad_headers() {
+        assert_eq!(Cfg::from_bytes(b"").err(), Some(LoadError::NotCfgData));
+        assert_eq!(Cfg::from_bytes(b"grammar").err(), Some(LoadError::NotCfgData));
+        let table = Writer::new(Kind::LrTable).finish();
+        assert_eq!(Cfg::from_bytes(table.as_slice()).err(),
+                   Some(LoadError::WrongKind { expected: Kind::Grammar, found: 2 }));
+        let mut future = b"cfg\0".to_vec();
+        future.push(2);
+        assert_eq!(Reader::new(future.as_slice(), Kind::Grammar).err(),
+                   Some(LoadError::UnsupportedVersion { version: 2 }));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = grammar(EXPRESSIONS).to_bytes();
+        for len in range(4, bytes.len()) {
+            assert!(Cfg::from_bytes(bytes.slice_to(len)).is_err());
+        }
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(Cfg::from_bytes(extra.as_slice()).err(), Some(LoadError::Invalid));
+    }
+
+    #[test]
+    fn huge_counts() {
+        // A count far beyond the data must fail when the data runs out, not by allocating for it.
+        let mut w = Writer::new(Kind::Grammar);
+        w.uint(uint::MAX);
+        assert_eq!(Cfg::from_bytes(w.finish().as_slice()).err(), Some(LoadError::Truncated));
+
+        let mut w = Writer::new(Kind::Grammar);
+        w.uint(1);
+        w.uint(uint::MAX);
+        assert_eq!(Cfg::from_bytes(w.finish().as_slice()).err(), Some(LoadError::Truncated));
+    }
+
+    #[test]
+    fn undefined_variable() {
+        // `<s> ::= <t>`, where `t` has no rules.
+        let mut w = Writer::new(Kind::Grammar);
+        w.uint(0);
+        w.uint(2);
+        w.str("s");
+        w.str("t");
+        w.uint(0);
+        w.uint(1);
+        w.uint(1);
+        w.uint(3);
+        w.uint(0);
+        w.uint(0);
+        w.uint(0);
+        assert_eq!(Cfg::from_bytes(w.finish().as_slice()).err(), Some(LoadError::Invalid));
+    }
+}
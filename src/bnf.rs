@@ -0,0 +1,341 @@
+//! Reading and writing grammars in BNF.
+
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, ParseError, Rule, SourceMap, Span, Symbol, SyntaxError, Token, Variable};
+use import::{Scanner, Symbols, body_span, is_space, span};
+
+impl Cfg<String> {
+    /// Parse a grammar written in BNF.
+    ///
+    /// Each rule has the form `<name> ::= alternatives`, where the alternatives are separated by
+    /// `|` and are sequences of variables written as `<name>` and terminals quoted with `"` or
+    /// `'`. An empty alternative, or the literal `""`, derives the empty string. The variable of
+    /// the first rule is the start symbol.
+    pub fn parse_bnf(src: &str) -> Result<Cfg<String>, SyntaxError> {
+        Cfg::parse_bnf_with_spans(src).map(|(cfg, _)| cfg)
+    }
+
+    /// Parse a grammar written in BNF as for `parse_bnf`, also giving where each variable was
+    /// first defined and each rule written.
+    pub fn parse_bnf_with_spans(src: &str) -> Result<(Cfg<String>, SourceMap), SyntaxError> {
+        let mut s = Scanner::new(src);
+        let mut syms = Symbols::new();
+        s.skip_while(is_space);
+        while !s.at_end() {
+            let at = s.mark();
+            let name = try!(variable_name(&mut s));
+            let lhs = syms.define_at(name.as_slice(), span(at, s.mark()));
+            s.skip_while(is_space);
+            if !s.eat("::=") {
+                return Err(s.error("expected `::=`"))
+            }
+            loop {
+                let (body, rule_span) = try!(alternative(&mut s, &mut syms));
+                syms.add_rule_at(lhs, body, rule_span);
+                if !s.eat("|") {
+                    break
+                }
+            }
+        }
+        syms.finish_with_spans()
+    }
+}
+
+fn variable_name(s: &mut Scanner) -> Result<String, SyntaxError> {
+    if !s.eat("<") {
+        return Err(s.error("expected `<`"))
+    }
+    let name = s.take_while(in_name);
+    if s.eat(">") {
+        Ok(name)
+    } else {
+        Err(s.error("unterminated variable name"))
+    }
+}
+
+fn in_name(c: char) -> bool {
+    c != '>' && c != '\n'
+}
+
+/// Whether the cursor is at the `<name> ::=` beginning the next rule.
+fn starts_rule(s: &mut Scanner) -> bool {
+    let mark = s.mark();
+    let starts = variable_name(s).is_ok() && {
+        s.skip_while(is_space);
+        s.looking_at("::=")
+    };
+    s.reset(mark);
+    starts
+}
+
+/// Read the body of a rule, along with where it's written.
+fn alternative(s: &mut Scanner, syms: &mut Symbols<String>)
+               -> Result<(Rule, Span), SyntaxError> {
+    let before = s.mark();
+    s.skip_while(is_space);
+    let start = s.mark();
+    let mut end = start;
+    let mut body = Vec::new();
+    loop {
+        s.skip_while(is_space);
+        match s.peek() {
+            Some('<') => {
+                if starts_rule(s) {
+                    break
+                }
+                let at = s.mark();
+                let name = try!(variable_name(s));
+                body.push(Symbol::Var(syms.variable(name.as_slice(), at)));
+            },
+            Some('"') | Some('\'') => {
+                let terminal = try!(s.quoted());
+                if !terminal.is_empty() {
+                    body.push(syms.token(terminal));
+                }
+            },
+            Some('|') | None => break,
+            Some(c) => return Err(s.error(format!("unexpected `{}`", c).as_slice()))
+        }
+        end = s.mark();
+    }
+    Ok((body, body_span(before, start, end)))
+}
+
+impl<T: fmt::Show + Eq + Hash + Clone> Cfg<T> {
+    /// Render the grammar as BNF, in the form read by `Cfg::parse_bnf`.
+    pub fn to_bnf(&self) -> String {
+        format!("{}", self)
+    }
+
+    /// Render a symbol as it appears in BNF: a variable's name in angle brackets, or a quoted
+    /// terminal.
+    pub fn symbol_to_bnf(&self, symbol: Symbol) -> String {
+        format!("{}", BnfSymbol { cfg: self, symbol: symbol })
+    }
+
+    /// Describe a parse error with the terminals of its tokens quoted as in BNF, such as
+    /// `unexpected "+" at token 3, expected "(", "x" or end of input`.
+    pub fn parse_error_to_string(&self, error: &ParseError) -> String {
+        let describe = |token: Option<Token>| match token {
+            Some(t) => self.symbol_to_bnf(Symbol::Tok(t)),
+            None => "end of input".to_string()
+        };
+        let mut text = match error.found {
+            Some(t) => format!("unexpected {} at token {}", describe(Some(t)), error.position),
+            None => "unexpected end of input".to_string()
+        };
+        for (i, &token) in error.expected.iter().enumerate() {
+            text.push_str(if i == 0 {
+                ", expected "
+            } else if i + 1 == error.expected.len() {
+                " or "
+            } else {
+                ", "
+            });
+            text.push_str(describe(token).as_slice());
+        }
+        text
+    }
+}
+
+/// Writes the grammar as BNF, one line per variable with its alternatives separated by `|`.
+///
+/// The start symbol comes first, followed by the other variables in order. Variables are written
+/// using their names, or their index if they have none, and terminals are quoted.
+impl<T: fmt::Show + Eq + Hash + Clone> fmt::Show for Cfg<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let start = self.get_start();
+        let mut order = vec![start];
+        order.extend(range(0, self.num_variables()).map(|v| Variable(v as u32))
+                                                   .filter(|&v| v != start));
+        for &v in order.iter() {
+            let rules = match self.get_rules(v) {
+                Some(rules) => rules,
+                None => continue
+            };
+            try!(write_variable(f, self, v));
+            try!(write!(f, " ::="));
+            for (i, rule) in rules.iter().enumerate() {
+                if i > 0 {
+                    try!(write!(f, " |"));
+                }
+                if rule.is_empty() {
+                    try!(write!(f, " \"\""));
+                }
+                for &symbol in rule.iter() {
+                    try!(write!(f, " "));
+                    try!(write_symbol(f, self, symbol));
+                }
+            }
+            try!(writeln!(f, ""));
+        }
+        Ok(())
+    }
+}
+
+/// A symbol to be written as BNF.
+struct BnfSymbol<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    symbol: Symbol
+}
+
+impl<'a, T: fmt::Show + Eq + Hash + Clone> fmt::Show for BnfSymbol<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_symbol(f, self.cfg, self.symbol)
+    }
+}
+
+fn write_symbol<T: fmt::Show + Eq + Hash + Clone>(f: &mut fmt::Formatter, cfg: &Cfg<T>,
+                                                  symbol: Symbol) -> fmt::Result {
+    match symbol {
+        Symbol::Var(v) => write_variable(f, cfg, v),
+        Symbol::Tok(t) => match cfg.terminal(t) {
+            Some(terminal) => write_terminal(f, terminal),
+            None => write!(f, "\"#{}\"", t.index())
+        }
+    }
+}
+
+fn write_variable<T: Eq + Hash + Clone>(f: &mut fmt::Formatter, cfg: &Cfg<T>, v: Variable)
+                                        -> fmt::Result {
+    match cfg.name(v) {
+        Some(name) => write!(f, "<{}>", name),
+        None => write!(f, "<{}>", v.index())
+    }
+}
+
+fn write_terminal<T: fmt::Show>(f: &mut fmt::Formatter, terminal: &T) -> fmt::Result {
+    let text = format!("{}", terminal);
+    try!(write!(f, "\""));
+    for c in text.as_slice().chars() {
+        match c {
+            '"' => try!(write!(f, "\\\"")),
+            '\\' => try!(write!(f, "\\\\")),
+            '\n' => try!(write!(f, "\\n")),
+            '\r' => try!(write!(f, "\\r")),
+            '\t' => try!(write!(f, "\\t")),
+            c => try!(write!(f, "{}", c))
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Cfg, CfgBuilder, SourceMap, Span, Symbol, Variable};
+    use earley;
+    use lr::LrParser;
+    use testing::{EXPRESSIONS, EXPRESSIONS_LL1, grammar, tokens, variable};
+
+    #[test]
+    fn expressions() {
+        let cfg = grammar(EXPRESSIONS);
+        let (e, t, f) = (variable(&cfg, "e"), variable(&cfg, "t"), variable(&cfg, "f"));
+        let token = |name: &str| Symbol::Tok(cfg.token(&name.to_string()).unwrap());
+        assert_eq!(cfg.get_start(), e);
+        assert_eq!(cfg.num_tokens(), 5);
+        let expected = [vec![Symbol::Var(e), token("+"), Symbol::Var(t)], vec![Symbol::Var(t)]];
+        assert_eq!(cfg.get_rules(e).unwrap(), expected.as_slice());
+        assert_eq!(cfg.get_rules(f).unwrap(),
+                   [vec![token("("), Symbol::Var(e), token(")")], vec![token("id")]].as_slice());
+    }
+
+    #[test]
+    fn empty_rules() {
+        let cfg = grammar("<s> ::= | \"a\" <s> | \"\"");
+        let rules = cfg.get_rules(variable(&cfg, "s")).unwrap();
+        assert_eq!(rules.len(), 3);
+        assert!(rules[0].is_empty() && rules[2].is_empty());
+        let a = cfg.token(&"a".to_string()).unwrap();
+        assert_eq!(rules[1], vec![Symbol::Tok(a), Symbol::Var(variable(&cfg, "s"))]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = Cfg::parse_bnf("<s> ::= \"a\"\n<t> = \"b\"").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 5));
+        let error = Cfg::parse_bnf("<s> ::= \"a").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 9));
+        let error = Cfg::parse_bnf("<s> ::= \"a\" <t>").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 13));
+        assert!(Cfg::parse_bnf("<s ::= \"a\"").is_err());
+        assert!(Cfg::parse_bnf("<s> ::= a").is_err());
+        assert!(Cfg::parse_bnf("").is_err());
+    }
+
+    #[test]
+    fn print() {
+        assert_eq!(grammar(EXPRESSIONS).to_bnf().as_slice(), "\
+    <e> ::= <e> \"+\" <t> | <t>
+    <t> ::= <t> \"*\" <f> | <f>
+    <f> ::= \"(\" <e> \")\" | \"id\"
+    ");
+    }
+
+    #[test]
+    fn print_start_first() {
+        let mut builder = CfgBuilder::new();
+        let a = builder.add_variable("a");
+        let b = builder.add_variable("b");
+        let x = builder.add_token('x');
+        builder.add_rule(a, vec![Symbol::Tok(x), Symbol::Var(a)]).unwrap();
+        builder.add_rule(a, vec![]).unwrap();
+        builder.add_rule(b, vec![Symbol::Var(a), Symbol::Tok(x)]).unwrap();
+        builder.set_start(b).unwrap();
+        assert_eq!(format!("{}", builder.build().unwrap()).as_slice(), "\
+    <b> ::= <a> \"x\"
+    <a> ::= \"x\" <a> | \"\"
+    ");
+    }
+
+    #[test]
+    fn round_trip() {
+        let escapes = "<s> ::= \"a\\\"b\" <s> | '\\\\' | \"\\t\\n\"";
+        for src in [EXPRESSIONS, EXPRESSIONS_LL1, escapes].iter() {
+            let cfg = grammar(*src);
+            let printed = cfg.to_bnf();
+            assert_eq!(grammar(printed.as_slice()).to_bnf(), printed);
+        }
+    }
+
+    /// Where a rule was written, as `line:column-line:column`.
+    fn rule_span(cfg: &Cfg<String>, spans: &SourceMap, variable: Variable, rule: uint) -> String {
+        format!("{}", spans.rule_span(cfg.rule_id(variable, rule).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn spans() {
+        let (cfg, spans) = Cfg::parse_bnf_with_spans("<s> ::= <a> \"x\" | \"\"\n<a> ::= | \"a\"\n")
+                               .unwrap();
+        let (s, a) = (variable(&cfg, "s"), variable(&cfg, "a"));
+        let expected = Span { line: 1, column: 1, end_line: 1, end_column: 4 };
+        assert_eq!(spans.variable_span(s), Some(expected));
+        assert_eq!(format!("{}", spans.variable_span(a).unwrap()).as_slice(), "2:1-2:4");
+        assert_eq!(rule_span(&cfg, &spans, s, 0).as_slice(), "1:9-1:16");
+        assert_eq!(rule_span(&cfg, &spans, s, 1).as_slice(), "1:19-1:21");
+        // An empty body is placed just after what precedes it.
+        assert_eq!(rule_span(&cfg, &spans, a, 0).as_slice(), "2:8-2:8");
+        assert_eq!(rule_span(&cfg, &spans, a, 1).as_slice(), "2:11-2:14");
+    }
+
+    fn describe(cfg: &Cfg<String>, text: &str) -> String {
+        let input = tokens(cfg, text);
+        let error = earley::chart(cfg, input.as_slice()).error(cfg, input.as_slice()).unwrap();
+        cfg.parse_error_to_string(&error)
+    }
+
+    #[test]
+    fn parse_error_to_string() {
+        let cfg = grammar(EXPRESSIONS);
+        assert_eq!(describe(&cfg, "id id"),
+                   "unexpected \"id\" at token 1, expected \"+\", \"*\" or end of input");
+        assert_eq!(describe(&cfg, "id +"), "unexpected end of input, expected \"(\" or \"id\"");
+        let input = tokens(&cfg, "( )");
+        let error = LrParser::new(&cfg).unwrap().parse(input.as_slice()).unwrap_err();
+        assert_eq!(error.found, cfg.token(&")".to_string()));
+        assert_eq!(cfg.parse_error_to_string(&error),
+                   "unexpected \")\" at token 1, expected \"(\" or \"id\"");
+    }
+}
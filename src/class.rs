@@ -0,0 +1,270 @@
+//! Terminals standing for classes of input values, such as ranges of characters.
+//!
+//! A grammar whose terminals are classes, like `[a-z]` or "any identifier token", would need a
+//! token for every member of each class to be written with plain terminals. Instead, the input
+//! values are split into atoms, each a set of values matched by the same classes, and
+//! `expand_classes` gives a grammar over the atoms where each class is a variable deriving the
+//! atoms it contains. Every parser runs on that grammar as it is, and `Classes::tokenize` maps
+//! input values to atoms for it.
+
+use std::char;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, Symbol, Token, Variable};
+use tree::ParseTree;
+
+/// A terminal that matches a class of input values of type `C`.
+pub trait Class<C> {
+    fn matches(&self, value: &C) -> bool;
+}
+
+/// A class of characters: those in any of a list of ranges, or those in none if it's negated.
+/// It's shown as in a regular expression, such as `[^a-z_]`.
+#[deriving(Clone, PartialEq, Eq, Hash)]
+pub struct CharClass {
+    /// Inclusive ranges of characters.
+    pub ranges: Vec<(char, char)>,
+    pub negated: bool
+}
+
+impl CharClass {
+    /// The class of a single character.
+    pub fn single(c: char) -> CharClass {
+        CharClass { ranges: vec![(c, c)], negated: false }
+    }
+
+    /// The class of the characters from `first` to `last`, inclusive.
+    pub fn range(first: char, last: char) -> CharClass {
+        CharClass { ranges: vec![(first, last)], negated: false }
+    }
+
+    /// The class of every character not in `self`.
+    pub fn negate(&self) -> CharClass {
+        CharClass { ranges: self.ranges.clone(), negated: !self.negated }
+    }
+}
+
+impl Class<char> for CharClass {
+    fn matches(&self, value: &char) -> bool {
+        self.ranges.iter().any(|&(first, last)| first <= *value && *value <= last) != self.negated
+    }
+}
+
+impl fmt::Show for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "["));
+        if self.negated {
+            try!(write!(f, "^"));
+        }
+        for &(first, last) in self.ranges.iter() {
+            try!(write!(f, "{}", first.escape_default().collect::<String>()));
+            if last != first {
+                try!(write!(f, "-{}", last.escape_default().collect::<String>()));
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+/// A grammar with class terminals expanded over the atoms of its input, by `expand_classes`.
+///
+/// The terminal of each atom token is the list of tokens of the original grammar whose classes
+/// match it. The original variables keep their numbering and rules, except that each token in
+/// a rule is replaced by a variable for its class, numbered after them in order of the tokens,
+/// with a rule for each atom in the class.
+pub struct Classes<T> {
+    cfg: Cfg<Vec<Token>>,
+    /// The terminal of each token of the original grammar.
+    classes: Vec<T>,
+    /// The number of variables of the original grammar.
+    variables: uint
+}
+
+impl<T> Classes<T> {
+    /// The grammar over the atoms, to parse their tokens with.
+    pub fn cfg(&self) -> &Cfg<Vec<Token>> {
+        &self.cfg
+    }
+
+    /// The variable of the expanded grammar standing for a token of the original grammar.
+    pub fn class_variable(&self, token: Token) -> Variable {
+        Variable((self.variables + token.index()) as u32)
+    }
+
+    /// The atom token of each input value, or the index of the first value that isn't in any
+    /// atom, because no class matches it or because it matches a combination of classes not
+    /// seen when the grammar was expanded.
+    pub fn tokenize<C>(&self, input: &[C]) -> Result<Vec<Token>, uint> where T: Class<C> {
+        let mut tokens = Vec::with_capacity(input.len());
+        for (i, value) in input.iter().enumerate() {
+            match self.cfg.token(&self.matching(value)) {
+                Some(t) => tokens.push(t),
+                None => return Err(i)
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// A parse tree of the expanded grammar as one of the original grammar, with each class
+    /// variable and its atom replaced by the original token.
+    pub fn to_original(&self, tree: &ParseTree) -> ParseTree {
+        match *tree {
+            ParseTree::Leaf(t) => ParseTree::Leaf(t),
+            ParseTree::Node(v, _, _) if v.index() >= self.variables => {
+                ParseTree::Leaf(Token((v.index() - self.variables) as u32))
+            },
+            ParseTree::Node(v, rule, ref children) => {
+                ParseTree::Node(v, rule, children.iter().map(|c| self.to_original(c)).collect())
+            }
+        }
+    }
+
+    /// The tokens of the original grammar whose classes match a value.
+    fn matching<C>(&self, value: &C) -> Vec<Token> where T: Class<C> {
+        range(0, self.classes.len()).filter(|&t| self.classes[t].matches(value))
+                                    .map(|t| Token(t as u32)).collect()
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Expand the class terminals of the grammar over the atoms of the input values: the sets of
+    /// values matched by the same classes.
+    ///
+    /// `alphabet` must have a value from every atom, since values matching a combination of
+    /// classes none of its values match can't be given a token.
+    pub fn expand_classes<C, I: Iterator<C>>(&self, alphabet: I) -> Classes<T> where T: Class<C> {
+        let mut classes = Classes {
+            cfg: Cfg::new(),
+            classes: self.terminals.clone(),
+            variables: self.num_variables()
+        };
+        let mut atoms: Vec<Vec<Token>> = Vec::new();
+        for value in alphabet {
+            let matching = classes.matching(&value);
+            if !matching.is_empty() {
+                atoms.push(matching);
+            }
+        }
+        atoms.sort();
+        atoms.dedup();
+
+        let mut cfg = Cfg::new();
+        for atom in atoms.iter() {
+            cfg.add_token(atom.clone());
+        }
+        cfg.variables = (self.num_variables() + self.num_tokens()) as u32;
+        cfg.variable_names = self.variable_names.clone();
+        for v in self.variables() {
+            for rule in self.get_rules(v).unwrap_or(&[]).iter() {
+                let body = rule.iter().map(|&s| match s {
+                    Symbol::Tok(t) => Symbol::Var(classes.class_variable(t)),
+                    Symbol::Var(w) => Symbol::Var(w)
+                }).collect();
+                cfg.add_rule(v, body).unwrap();
+            }
+        }
+        for t in self.tokens() {
+            let class = classes.class_variable(t);
+            for (a, atom) in atoms.iter().enumerate() {
+                if atom.contains(&t) {
+                    cfg.add_rule(class, vec![Symbol::Tok(Token(a as u32))]).unwrap();
+                }
+            }
+        }
+        cfg.start = self.get_start();
+        classes.cfg = cfg;
+        classes
+    }
+}
+
+impl Cfg<CharClass> {
+    /// Expand the character classes of the grammar over the atoms of the characters, as
+    /// `expand_classes` does, finding the atoms from the bounds of the ranges of the classes.
+    pub fn expand_char_classes(&self) -> Classes<CharClass> {
+        // Every character matches the same classes as the nearest bound at or below it.
+        let mut bounds = vec!['\0'];
+        for class in self.terminals.iter() {
+            for &(first, last) in class.ranges.iter() {
+                bounds.push(first);
+                match char::from_u32(last as u32 + 1) {
+                    Some(c) => bounds.push(c),
+                    // Past the surrogates, the next character is the first after them.
+                    None if last < '\uE000' => bounds.push('\uE000'),
+                    None => { }
+                }
+            }
+        }
+        self.expand_classes(bounds.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CharClass, Class};
+    use super::super::{Cfg, Symbol, Token};
+    use earley;
+    use tree::ParseTree;
+
+    #[test]
+    fn char_class() {
+        let lower = CharClass::range('a', 'z');
+        assert!(lower.matches(&'q'));
+        assert!(!lower.matches(&'Q'));
+        assert!(lower.negate().matches(&'Q'));
+        assert!(CharClass::single('_').matches(&'_'));
+        assert_eq!(format!("{}", lower.negate()), "[^a-z]");
+        assert_eq!(format!("{}", CharClass::single('\n')), "[\\n]");
+    }
+
+    /// Words of letters and digits ending in `x`.
+    fn words() -> Cfg<CharClass> {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let letter = cfg.add_token(CharClass::range('a', 'z'));
+        let digit = cfg.add_token(CharClass::range('0', '9'));
+        let x = cfg.add_token(CharClass::single('x'));
+        cfg.add_rule(s, vec![Symbol::Tok(letter), Symbol::Var(s)]).unwrap();
+        cfg.add_rule(s, vec![Symbol::Tok(digit), Symbol::Var(s)]).unwrap();
+        cfg.add_rule(s, vec![Symbol::Tok(x)]).unwrap();
+        cfg
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    fn leaf(t: u32) -> ParseTree {
+        ParseTree::Leaf(Token(t))
+    }
+
+    #[test]
+    fn expand_char_classes() {
+        let cfg = words();
+        let classes = cfg.expand_char_classes();
+        let expanded = classes.cfg();
+        let atoms: Vec<&Vec<Token>> = expanded.tokens().map(|t| expanded.terminal(t).unwrap())
+                                                       .collect();
+        assert_eq!(atoms, vec![&vec![Token(0)], &vec![Token(0), Token(2)], &vec![Token(1)]]);
+        assert_eq!(classes.tokenize(chars("a-").as_slice()), Err(1));
+        assert_eq!(classes.tokenize(chars("").as_slice()), Ok(vec![]));
+
+        let input = classes.tokenize(chars("a9x").as_slice()).unwrap();
+        assert_eq!(input, vec![Token(0), Token(2), Token(1)]);
+        let tree = earley::parse(expanded, input.as_slice()).unwrap().tree();
+        let s = cfg.get_start();
+        let x = ParseTree::Node(s, 2, vec![leaf(2)]);
+        let rest = ParseTree::Node(s, 1, vec![leaf(1), x]);
+        let expected = ParseTree::Node(s, 0, vec![leaf(0), rest]);
+        assert_eq!(classes.to_original(&tree), expected);
+        for text in ["x", "xx", "0ax"].iter() {
+            let input = classes.tokenize(chars(*text).as_slice()).unwrap();
+            assert!(earley::recognize(expanded, input.as_slice()));
+        }
+        for text in ["xa", "a9"].iter() {
+            let input = classes.tokenize(chars(*text).as_slice()).unwrap();
+            assert!(!earley::recognize(expanded, input.as_slice()));
+        }
+    }
+}
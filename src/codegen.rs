@@ -0,0 +1,78 @@
+//! Pieces shared by the generators of parser source.
+
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, Token};
+
+/// The parse tree type of generated parsers.
+pub static TREE: &'static str = r#"
+/// A derivation of part of the input.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum Tree {
+    /// A token of the input, by its index in `TERMINALS`.
+    Leaf(uint),
+    /// A variable expanded by a rule, by their indices in the grammar, with a subtree for each
+    /// symbol of the rule.
+    Node(uint, uint, Vec<Tree>)
+}
+"#;
+
+/// Write the `TERMINALS` array of the grammar's terminals, by token index.
+pub fn write_terminals<T: fmt::Show + Eq + Hash + Clone>(f: &mut fmt::Formatter, cfg: &Cfg<T>)
+                                                         -> fmt::Result {
+    try!(writeln!(f, "/// The terminals of the grammar, by token index."));
+    try!(write!(f, "pub static TERMINALS: &'static [&'static str] = &["));
+    for t in range(0, cfg.num_tokens()) {
+        if t > 0 {
+            try!(write!(f, ", "));
+        }
+        match cfg.terminal(Token(t as u32)) {
+            Some(terminal) => try!(write_string(f, format!("{}", terminal).as_slice())),
+            None => try!(write_string(f, ""))
+        }
+    }
+    writeln!(f, "];")
+}
+
+/// Write text as a Rust string literal.
+pub fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    try!(write!(f, "\""));
+    for c in text.chars() {
+        match c {
+            '"' => try!(write!(f, "\\\"")),
+            '\\' => try!(write!(f, "\\\\")),
+            '\n' => try!(write!(f, "\\n")),
+            '\r' => try!(write!(f, "\\r")),
+            '\t' => try!(write!(f, "\\t")),
+            c => try!(write!(f, "{}", c))
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::write_terminals;
+    use super::super::Cfg;
+    use std::fmt;
+
+    struct Terminals<'a>(&'a Cfg<String>);
+
+    impl<'a> fmt::Show for Terminals<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write_terminals(f, self.0)
+        }
+    }
+
+    #[test]
+    fn terminals() {
+        let mut cfg = Cfg::new();
+        cfg.add_token("say \"hi\"\n".to_string());
+        cfg.add_token("a\\b\t".to_string());
+        assert_eq!(format!("{}", Terminals(&cfg)),
+                   "/// The terminals of the grammar, by token index.\n\
+                    pub static TERMINALS: &'static [&'static str] = \
+                    &[\"say \\\"hi\\\"\\n\", \"a\\\\b\\t\"];\n");
+    }
+}
@@ -0,0 +1,186 @@
+//! Combining the languages of grammars.
+
+use std::hash::Hash;
+
+use super::{Cfg, Symbol, SymbolMap, Token, Variable};
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// A grammar for the strings of either this grammar's language or `other`'s.
+    ///
+    /// Tokens with the same terminal are shared, while the variables of the two grammars are
+    /// kept apart, those of `other` renamed where their names clash. The new start symbol is
+    /// named `start`, and has a rule for each of the old ones.
+    pub fn union(&self, other: &Cfg<T>) -> Cfg<T> {
+        let mut cfg = Cfg::new();
+        let first = cfg.merge(self);
+        let second = cfg.merge(other);
+        let start = add_named_variable(&mut cfg, "start");
+        let rules = vec![vec![Symbol::Var(first.variable(self.get_start()).unwrap())],
+                         vec![Symbol::Var(second.variable(other.get_start()).unwrap())]];
+        for rule in rules.into_iter() {
+            cfg.add_rule(start, rule).unwrap();
+        }
+        cfg.start = start;
+        cfg
+    }
+
+    /// A grammar for the strings made of a sentence of this grammar followed by one of `other`,
+    /// with the symbols combined as for `union`.
+    pub fn concatenation(&self, other: &Cfg<T>) -> Cfg<T> {
+        let mut cfg = Cfg::new();
+        let first = cfg.merge(self);
+        let second = cfg.merge(other);
+        let start = add_named_variable(&mut cfg, "start");
+        let rule = vec![Symbol::Var(first.variable(self.get_start()).unwrap()),
+                        Symbol::Var(second.variable(other.get_start()).unwrap())];
+        cfg.add_rule(start, rule).unwrap();
+        cfg.start = start;
+        cfg
+    }
+
+    /// A grammar for the strings made of any number of sentences of this grammar, with a new
+    /// start symbol named `start` repeating the old one by right recursion.
+    pub fn star(&self) -> Cfg<T> {
+        let mut cfg = Cfg::new();
+        let map = cfg.merge(self);
+        let start = add_named_variable(&mut cfg, "start");
+        let old = map.variable(self.get_start()).unwrap();
+        cfg.add_rule(start, vec![Symbol::Var(old), Symbol::Var(start)]).unwrap();
+        cfg.add_rule(start, vec![]).unwrap();
+        cfg.start = start;
+        cfg
+    }
+
+    /// Add the symbols, rules and precedences of `other` to this grammar, returning where each
+    /// of its symbols went. The start symbol is unchanged.
+    ///
+    /// A terminal already in this grammar keeps its token, and its precedence if it has one;
+    /// other terminals get new tokens. Each variable of `other` gets a new variable, named the
+    /// same unless that name is taken, in which case it's named `name_N` with a number `N` that
+    /// makes it unique.
+    pub fn merge(&mut self, other: &Cfg<T>) -> SymbolMap {
+        let mut map = SymbolMap::new();
+        for t in range(0, other.num_tokens()) {
+            let new = self.add_token(other.terminals[t].clone());
+            map.insert_token(Token(t as u32), new);
+            match other.precedence.get(&t) {
+                Some(&p) if !self.precedence.contains_key(&new.index()) => {
+                    self.precedence.insert(new.index(), p);
+                },
+                _ => { }
+            }
+        }
+        for v in range(0, other.num_variables()) {
+            let new = match other.name(Variable(v as u32)) {
+                Some(name) => add_named_variable(self, name),
+                None => {
+                    self.variables += 1;
+                    Variable(self.variables - 1)
+                }
+            };
+            map.insert_variable(Variable(v as u32), new);
+        }
+        for (v, rules) in other.rules.iter() {
+            let new = map.variable(Variable(v as u32)).unwrap();
+            for (i, rule) in rules.iter().enumerate() {
+                let body = rule.iter().map(|&s| map.symbol(s).unwrap()).collect();
+                self.add_rule(new, body).unwrap();
+                match other.rule_precedence_token(Variable(v as u32), i) {
+                    Some(t) => {
+                        let rule = self.rules[new.index()].len() - 1;
+                        self.set_rule_precedence(new, rule, map.token(t).unwrap()).unwrap();
+                    },
+                    None => { }
+                }
+            }
+        }
+        map
+    }
+}
+
+/// Allocate a new variable named `name`, or `name_N` with a number `N` that makes it unique if
+/// `name` is taken.
+fn add_named_variable<T: Eq + Hash + Clone>(cfg: &mut Cfg<T>, name: &str) -> Variable {
+    if !cfg.variable_names.values().any(|x| x.as_slice() == name) {
+        return cfg.add_variable(name)
+    }
+    let mut n = cfg.variables as uint;
+    loop {
+        let fresh = format!("{}_{}", name, n);
+        if !cfg.variable_names.values().any(|x| *x == fresh) {
+            return cfg.add_variable(fresh.as_slice())
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::super::{Associativity, Token, Variable};
+    use testing::{grammar, sentences, variable};
+
+    const BALANCED: &'static str = "<s> ::= \"a\" <s> \"b\" | \"\"";
+    const LETTER: &'static str = "<s> ::= \"b\" | \"c\"";
+
+    /// The sentences written with their terminals separated by spaces.
+    fn language(sentences: &[&str]) -> BTreeSet<Vec<String>> {
+        sentences.iter().map(|sentence| sentence.words().map(|w| w.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn union() {
+        let (first, second) = (grammar(BALANCED), grammar(LETTER));
+        let cfg = first.union(&second);
+        assert_eq!(cfg.num_variables(), 3);
+        assert_eq!(cfg.num_tokens(), 3);
+        assert_eq!(cfg.get_start(), variable(&cfg, "start"));
+        assert!(cfg.name(Variable(1)) != Some("s"));
+        assert_eq!(sentences(&cfg, 4), language(&["", "a b", "a a b b", "b", "c"]));
+    }
+
+    #[test]
+    fn concatenation() {
+        let (first, second) = (grammar(BALANCED), grammar(LETTER));
+        let cfg = first.concatenation(&second);
+        assert_eq!(cfg.num_tokens(), 3);
+        assert_eq!(sentences(&cfg, 3), language(&["b", "c", "a b b", "a b c"]));
+        let cfg = second.concatenation(&first);
+        assert_eq!(sentences(&cfg, 3), language(&["b", "c", "b a b", "c a b"]));
+    }
+
+    #[test]
+    fn star() {
+        let cfg = grammar(LETTER).star();
+        assert_eq!(cfg.get_start(), variable(&cfg, "start"));
+        assert_eq!(sentences(&cfg, 2), language(&["", "b", "c", "b b", "b c", "c b", "c c"]));
+        let cfg = grammar(BALANCED).star();
+        assert_eq!(sentences(&cfg, 4), language(&["", "a b", "a b a b", "a a b b"]));
+    }
+
+    #[test]
+    fn merge() {
+        let mut cfg = grammar("<e> ::= \"id\" \"=\" <e> | \"id\"");
+        let mut other = grammar("<e> ::= <e> \"+\" \"id\" | \"id\"");
+        let plus = other.token(&"+".to_string()).unwrap();
+        other.set_precedence(plus, 1, Associativity::Left).unwrap();
+        let start = cfg.get_start();
+        let map = cfg.merge(&other);
+
+        assert_eq!(cfg.get_start(), start);
+        assert_eq!(cfg.num_variables(), 2);
+        assert_eq!(cfg.num_tokens(), 3);
+        let id = other.token(&"id".to_string()).unwrap();
+        assert_eq!(map.token(id), cfg.token(&"id".to_string()));
+        let plus = map.token(plus).unwrap();
+        assert_eq!(plus, Token(2));
+        assert_eq!(cfg.precedence(plus), Some((1, Associativity::Left)));
+
+        let e = map.variable(other.get_start()).unwrap();
+        assert!(e != start);
+        assert!(cfg.name(e) != Some("e"));
+        assert_eq!(cfg.get_rules(e).unwrap().len(), 2);
+        assert_eq!(sentences(&cfg, 3), language(&["id", "id = id"]));
+    }
+}
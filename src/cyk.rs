@@ -0,0 +1,438 @@
+//! Cocke-Younger-Kasami parsing, through the Chomsky Normal Form of a grammar.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::{Cfg, Rule, Symbol, Token, Variable};
+use tree::ParseTree;
+
+/// A CYK parser for a grammar, holding its Chomsky Normal Form.
+///
+/// Parse trees are given in terms of the original grammar rather than its normal form. The
+/// `_bitwise` methods keep, for each variable, the ends of the spans it derives from each start and
+/// the starts of those to each end as bitsets, so a binary rule is checked against many split
+/// points at once; they give the same results, and are much faster on long inputs.
+pub struct Cyk<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    /// The number of variables in the normal form.
+    variables: uint,
+    /// The rules of the normal form with a body of two variables.
+    binary: Vec<(Variable, Variable, Variable)>,
+    /// The variables of the normal form deriving each token, by index.
+    unary: Vec<Vec<Variable>>,
+    /// For each variable of the original grammar that can derive the empty string, a rule it
+    /// can do so by without deriving itself again.
+    empty: Vec<Option<uint>>
+}
+
+impl<'a, T: Eq + Hash + Clone> Cyk<'a, T> {
+    pub fn new(cfg: &'a Cfg<T>) -> Cyk<'a, T> {
+        let (cnf, _) = cfg.to_cnf();
+        let mut binary = Vec::new();
+        let mut unary = Vec::from_fn(cnf.num_tokens(), |_| Vec::new());
+        for v in range(0, cnf.num_variables()) {
+            for rule in rules(&cnf, Variable(v as u32)).iter() {
+                match (rule.get(0), rule.get(1)) {
+                    (Some(&Symbol::Var(b)), Some(&Symbol::Var(c))) => {
+                        binary.push((Variable(v as u32), b, c));
+                    },
+                    (Some(&Symbol::Tok(t)), None) => unary[t.index()].push(Variable(v as u32)),
+                    _ => { }
+                }
+            }
+        }
+
+        let mut empty = Vec::from_elem(cfg.num_variables(), None);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, empty.len()) {
+                if empty[v].is_some() {
+                    continue
+                }
+                let found = rules(cfg, Variable(v as u32)).iter().position(|rule| {
+                    rule.iter().all(|&s| match s {
+                        Symbol::Var(w) => empty[w.index()].is_some(),
+                        Symbol::Tok(_) => false
+                    })
+                });
+                if found.is_some() {
+                    empty[v] = found;
+                    changed = true;
+                }
+            }
+        }
+
+        Cyk {
+            cfg: cfg,
+            variables: cnf.num_variables(),
+            binary: binary,
+            unary: unary,
+            empty: empty
+        }
+    }
+
+    /// Whether the grammar derives `input`.
+    pub fn recognize(&self, input: &[Token]) -> bool {
+        self.accepts(&self.table(input), input.len())
+    }
+
+    /// Parse `input`, giving one of its parse trees, or `None` if the grammar doesn't derive it.
+    pub fn parse(&self, input: &[Token]) -> Option<ParseTree> {
+        self.build(&self.table(input), input)
+    }
+
+    /// Whether the grammar derives `input`, using bitsets.
+    pub fn recognize_bitwise(&self, input: &[Token]) -> bool {
+        self.accepts(&self.bit_table(input), input.len())
+    }
+
+    /// Parse `input` using bitsets, giving one of its parse trees, or `None` if the grammar
+    /// doesn't derive it.
+    pub fn parse_bitwise(&self, input: &[Token]) -> Option<ParseTree> {
+        self.build(&self.bit_table(input), input)
+    }
+
+    fn build<S: Spans>(&self, table: &S, input: &[Token]) -> Option<ParseTree> {
+        if !self.accepts(table, input.len()) {
+            return None
+        }
+        let mut builder = Builder {
+            cyk: self,
+            input: input,
+            table: table,
+            witnesses: HashMap::new()
+        };
+        Some(builder.tree(self.cfg.get_start(), 0, input.len()))
+    }
+
+    fn accepts<S: Spans>(&self, table: &S, n: uint) -> bool {
+        let start = self.cfg.get_start();
+        if start.index() >= self.cfg.num_variables() {
+            false
+        } else if n == 0 {
+            self.empty[start.index()].is_some()
+        } else {
+            table.get(0, n, start)
+        }
+    }
+
+    /// Find the variables of the normal form deriving each nonempty span of `input`.
+    fn table(&self, input: &[Token]) -> Table {
+        let n = input.len();
+        let mut table = Table {
+            n: n,
+            variables: self.variables,
+            cells: Vec::from_elem((n + 1) * (n + 1) * self.variables, false)
+        };
+        for (i, t) in input.iter().enumerate() {
+            if t.index() >= self.unary.len() {
+                continue
+            }
+            for &v in self.unary[t.index()].iter() {
+                table.set(i, i + 1, v);
+            }
+        }
+        for len in range(2, n + 1) {
+            for i in range(0, n + 1 - len) {
+                let j = i + len;
+                for k in range(i + 1, j) {
+                    for &(a, b, c) in self.binary.iter() {
+                        if table.get(i, k, b) && table.get(k, j, c) {
+                            table.set(i, j, a);
+                        }
+                    }
+                }
+            }
+        }
+        table
+    }
+
+    /// Find the variables of the normal form deriving each nonempty span of `input`, as bitsets.
+    fn bit_table(&self, input: &[Token]) -> BitTable {
+        let n = input.len();
+        let words = n / 64 + 1;
+        let size = self.variables * (n + 1) * words;
+        let mut table = BitTable {
+            n: n,
+            words: words,
+            ends: Vec::from_elem(size, 0),
+            starts: Vec::from_elem(size, 0)
+        };
+        for (i, t) in input.iter().enumerate() {
+            if t.index() >= self.unary.len() {
+                continue
+            }
+            for &v in self.unary[t.index()].iter() {
+                table.set(i, i + 1, v);
+            }
+        }
+        for len in range(2, n + 1) {
+            for i in range(0, n + 1 - len) {
+                let j = i + len;
+                for &(a, b, c) in self.binary.iter() {
+                    if !table.get(i, j, a) && table.meet(b, i, c, j) {
+                        table.set(i, j, a);
+                    }
+                }
+            }
+        }
+        table
+    }
+}
+
+/// Whether the grammar derives `input`.
+pub fn recognize<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> bool {
+    Cyk::new(cfg).recognize(input)
+}
+
+/// Parse `input`, giving one of its parse trees, or `None` if the grammar doesn't derive it.
+pub fn parse<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> Option<ParseTree> {
+    Cyk::new(cfg).parse(input)
+}
+
+/// The variables of the normal form deriving each span of an input.
+trait Spans {
+    fn get(&self, i: uint, j: uint, v: Variable) -> bool;
+}
+
+/// The variables deriving each span of the input, as a flag for each possible start, end and
+/// variable.
+struct Table {
+    n: uint,
+    variables: uint,
+    cells: Vec<bool>
+}
+
+impl Table {
+    fn set(&mut self, i: uint, j: uint, v: Variable) {
+        self.cells[(i * (self.n + 1) + j) * self.variables + v.index()] = true;
+    }
+}
+
+impl Spans for Table {
+    fn get(&self, i: uint, j: uint, v: Variable) -> bool {
+        self.cells[(i * (self.n + 1) + j) * self.variables + v.index()]
+    }
+}
+
+/// The variables deriving each span of the input, as a bitset of ends for each variable and
+/// start, and of starts for each variable and end.
+struct BitTable {
+    n: uint,
+    /// The number of words in each bitset.
+    words: uint,
+    ends: Vec<u64>,
+    starts: Vec<u64>
+}
+
+impl BitTable {
+    /// The offset of the bitset for a variable and position.
+    fn row(&self, v: Variable, position: uint) -> uint {
+        (v.index() * (self.n + 1) + position) * self.words
+    }
+
+    fn set(&mut self, i: uint, j: uint, v: Variable) {
+        let (from, to) = (self.row(v, i), self.row(v, j));
+        self.ends[from + j / 64] |= 1 << (j % 64);
+        self.starts[to + i / 64] |= 1 << (i % 64);
+    }
+
+    /// Whether some position between `i` and `j` ends a span of `b` from `i` and starts a span
+    /// of `c` to `j`.
+    fn meet(&self, b: Variable, i: uint, c: Variable, j: uint) -> bool {
+        let (from, to) = (self.row(b, i), self.row(c, j));
+        range((i + 1) / 64, (j - 1) / 64 + 1).any(|w| {
+            self.ends[from + w] & self.starts[to + w] != 0
+        })
+    }
+}
+
+impl Spans for BitTable {
+    fn get(&self, i: uint, j: uint, v: Variable) -> bool {
+        self.ends[self.row(v, i) + j / 64] & (1 << (j % 64)) != 0
+    }
+}
+
+/// Finds derivations in the original grammar, guided by the table.
+///
+/// The original variables keep their numbering in the normal form and derive the same nonempty
+/// strings, so the table tells which of them derive each nonempty span.
+struct Builder<'a, 'b, T: 'a, S: 'b> {
+    cyk: &'b Cyk<'a, T>,
+    input: &'b [Token],
+    table: &'b S,
+    /// For each nonempty span looked at, the rule and the bounds of the children of a derivation
+    /// for each variable deriving it.
+    witnesses: HashMap<(uint, uint), Vec<Option<(uint, Vec<uint>)>>>
+}
+
+impl<'a, 'b, T: Eq + Hash + Clone, S: Spans> Builder<'a, 'b, T, S> {
+    fn tree(&mut self, variable: Variable, i: uint, j: uint) -> ParseTree {
+        if i == j {
+            return self.empty_tree(variable)
+        }
+        if !self.witnesses.contains_key(&(i, j)) {
+            let witnesses = self.witnesses(i, j);
+            self.witnesses.insert((i, j), witnesses);
+        }
+        let (rule, bounds) = self.witnesses.get(&(i, j)).unwrap()[variable.index()].clone()
+                                 .unwrap();
+        let body = rules(self.cyk.cfg, variable)[rule].clone();
+        let mut children = Vec::with_capacity(body.len());
+        for (k, &symbol) in body.iter().enumerate() {
+            children.push(match symbol {
+                Symbol::Tok(t) => ParseTree::Leaf(t),
+                Symbol::Var(v) => self.tree(v, bounds[k], bounds[k + 1])
+            });
+        }
+        ParseTree::Node(variable, rule, children)
+    }
+
+    fn empty_tree(&self, variable: Variable) -> ParseTree {
+        let rule = self.cyk.empty[variable.index()].unwrap();
+        let children = rules(self.cyk.cfg, variable)[rule].iter().map(|&s| match s {
+            Symbol::Var(v) => self.empty_tree(v),
+            Symbol::Tok(t) => ParseTree::Leaf(t)
+        }).collect();
+        ParseTree::Node(variable, rule, children)
+    }
+
+    /// Find a derivation of a nonempty span for each variable deriving it.
+    ///
+    /// A variable can derive the span through another that derives the same span, so this goes
+    /// on until no more are found, only using those already found to avoid cycles.
+    fn witnesses(&self, i: uint, j: uint) -> Vec<Option<(uint, Vec<uint>)>> {
+        let cfg = self.cyk.cfg;
+        let mut found: Vec<Option<(uint, Vec<uint>)>> = Vec::from_elem(cfg.num_variables(), None);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, found.len()) {
+                if found[v].is_some() || !self.table.get(i, j, Variable(v as u32)) {
+                    continue
+                }
+                for (rule, body) in rules(cfg, Variable(v as u32)).iter().enumerate() {
+                    match self.split(body.as_slice(), i, j, found.as_slice()) {
+                        Some(bounds) => {
+                            found[v] = Some((rule, bounds));
+                            changed = true;
+                            break
+                        },
+                        None => { }
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Divide the span from `i` to `j` among the symbols of `body`, giving the bounds of each.
+    fn split(&self, body: &[Symbol], i: uint, j: uint, found: &[Option<(uint, Vec<uint>)>])
+             -> Option<Vec<uint>> {
+        let width = j - i + 1;
+        // The start of the last symbol, for each number of symbols and position they reach.
+        let mut from: Vec<Option<uint>> = Vec::from_elem((body.len() + 1) * width, None);
+        from[0] = Some(i);
+        for (k, &symbol) in body.iter().enumerate() {
+            for a in range(i, j + 1) {
+                if from[k * width + a - i].is_none() {
+                    continue
+                }
+                for b in range(a, j + 1) {
+                    if from[(k + 1) * width + b - i].is_none() &&
+                       self.derives(symbol, a, b, i, j, found) {
+                        from[(k + 1) * width + b - i] = Some(a);
+                    }
+                }
+            }
+        }
+
+        if from[body.len() * width + width - 1].is_none() {
+            return None
+        }
+        let mut bounds = Vec::from_elem(body.len() + 1, j);
+        for k in range(0, body.len()).rev() {
+            bounds[k] = from[(k + 1) * width + bounds[k + 1] - i].unwrap();
+        }
+        Some(bounds)
+    }
+
+    /// Whether `symbol` derives the span from `a` to `b`, within the span from `i` to `j`.
+    fn derives(&self, symbol: Symbol, a: uint, b: uint, i: uint, j: uint,
+               found: &[Option<(uint, Vec<uint>)>]) -> bool {
+        match symbol {
+            Symbol::Tok(t) => b == a + 1 && self.input[a] == t,
+            Symbol::Var(v) if a == b => self.cyk.empty[v.index()].is_some(),
+            Symbol::Var(v) if a == i && b == j => found[v.index()].is_some(),
+            Symbol::Var(v) => self.table.get(a, b, v)
+        }
+    }
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cyk, parse, recognize};
+    use earley;
+    use testing::{EXPRESSIONS, SMALL, check_tree, grammar, strings, tokens};
+
+    #[test]
+    fn agrees_with_earley() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let cyk = Cyk::new(&cfg);
+            for input in strings(&cfg, 4).iter() {
+                let recognized = earley::recognize(&cfg, input.as_slice());
+                assert_eq!(cyk.recognize(input.as_slice()), recognized);
+                match cyk.parse(input.as_slice()) {
+                    Some(tree) => {
+                        assert!(recognized);
+                        check_tree(&cfg, &tree, input.as_slice());
+                    },
+                    None => assert!(!recognized)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn expressions() {
+        let cfg = grammar(EXPRESSIONS);
+        let input = tokens(&cfg, "( id + id ) * id");
+        assert!(recognize(&cfg, input.as_slice()));
+        check_tree(&cfg, &parse(&cfg, input.as_slice()).unwrap(), input.as_slice());
+        assert!(parse(&cfg, tokens(&cfg, "( id + id ) *").as_slice()).is_none());
+    }
+
+    #[test]
+    fn bitwise() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let cyk = Cyk::new(&cfg);
+            for input in strings(&cfg, 4).iter() {
+                let input = input.as_slice();
+                assert_eq!(cyk.recognize_bitwise(input), cyk.recognize(input));
+                match cyk.parse_bitwise(input.as_slice()) {
+                    Some(tree) => check_tree(&cfg, &tree, input.as_slice()),
+                    None => assert!(!cyk.recognize(input.as_slice()))
+                }
+            }
+        }
+
+        // Long enough for the spans of a variable to take more than one word.
+        let cfg = grammar("<s> ::= <s> <s> | \"(\" <s> \")\" | \"\"");
+        let cyk = Cyk::new(&cfg);
+        let mut input = Vec::new();
+        for _ in range(0, 50u) {
+            input.push_all(tokens(&cfg, "( ( ) )").as_slice());
+        }
+        assert!(cyk.recognize_bitwise(input.as_slice()));
+        check_tree(&cfg, &cyk.parse_bitwise(input.as_slice()).unwrap(), input.as_slice());
+        input.pop();
+        assert!(!cyk.recognize_bitwise(input.as_slice()));
+    }
+}
@@ -0,0 +1,460 @@
+//! Comparing the rules of two grammars, by the names of their symbols or up to renaming them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, Rule, Symbol, SymbolMap, Token, Variable};
+
+/// The differences between an old and a new grammar, found by `Cfg::diff`.
+///
+/// Variables are matched by name and tokens by terminal, so the report doesn't depend on how the
+/// symbols are numbered. Symbols and rules of the old grammar are given as they are numbered in
+/// it, and those of the new one as they are in the new one.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Diff {
+    /// Whether the start symbols have different names.
+    pub start_changed: bool,
+    /// The variables of the old grammar with no variable of the same name in the new one.
+    pub removed_variables: Vec<Variable>,
+    /// The variables of the new grammar with no variable of the same name in the old one.
+    pub added_variables: Vec<Variable>,
+    /// The tokens of the old grammar whose terminal isn't in the new one.
+    pub removed_tokens: Vec<Token>,
+    /// The tokens of the new grammar whose terminal isn't in the old one.
+    pub added_tokens: Vec<Token>,
+    /// The variables in both grammars whose rules differ, as the old and the new variable.
+    pub changed_variables: Vec<(Variable, Variable)>,
+    /// The rules of the old grammar without the same rule in the new one, each given as a
+    /// variable and the index of the rule in `get_rules`.
+    pub removed_rules: Vec<(Variable, uint)>,
+    /// The rules of the new grammar without the same rule in the old one.
+    pub added_rules: Vec<(Variable, uint)>
+}
+
+impl Diff {
+    /// Whether the grammars are the same up to the numbering of their symbols and the order of
+    /// their rules.
+    pub fn is_empty(&self) -> bool {
+        !self.start_changed && self.removed_variables.is_empty() &&
+            self.added_variables.is_empty() && self.removed_tokens.is_empty() &&
+            self.added_tokens.is_empty() && self.removed_rules.is_empty() &&
+            self.added_rules.is_empty()
+    }
+
+    /// Render the differences for reading, given the grammars compared: a line for a changed
+    /// start symbol, then each removed rule prefixed by `-` and each added rule prefixed by `+`,
+    /// written as in BNF. The rules are grouped by variable, in the order of the old grammar's
+    /// variables followed by the new variables.
+    pub fn render<T: fmt::Show + Eq + Hash + Clone>(&self, old: &Cfg<T>, new: &Cfg<T>)
+                                                    -> String {
+        let mut out = String::new();
+        if self.start_changed {
+            out.push_str(format!("start: {} -> {}\n", var(old, old.get_start()),
+                                 var(new, new.get_start())).as_slice());
+        }
+        let new_names = names(new);
+        for v in range(0, old.num_variables()).map(|v| Variable(v as u32)) {
+            for &(_, i) in self.removed_rules.iter().filter(|&&(w, _)| w == v) {
+                out.push_str(format!("- {}\n", rule(old, v, i)).as_slice());
+            }
+            match new_names.get(&var(old, v)) {
+                Some(&w) => for &(_, i) in self.added_rules.iter().filter(|&&(x, _)| x == w) {
+                    out.push_str(format!("+ {}\n", rule(new, w, i)).as_slice());
+                },
+                None => { }
+            }
+        }
+        for &v in self.added_variables.iter() {
+            for &(_, i) in self.added_rules.iter().filter(|&&(w, _)| w == v) {
+                out.push_str(format!("+ {}\n", rule(new, v, i)).as_slice());
+            }
+        }
+        out
+    }
+}
+
+impl<T: fmt::Show + Eq + Hash + Clone> Cfg<T> {
+    /// Compare this grammar, as the old one, with `other`, matching variables by their names and
+    /// tokens by their terminals, as written in BNF.
+    ///
+    /// Rules are compared as written in BNF too, so a rule of the old grammar is removed unless
+    /// the variable of the same name in the new grammar has the same rule. A variable with the
+    /// same rule more than once keeps as many copies as there are in the other grammar.
+    pub fn diff(&self, other: &Cfg<T>) -> Diff {
+        let old_names = names(self);
+        let new_names = names(other);
+        let mut diff = Diff {
+            start_changed: var(self, self.get_start()) != var(other, other.get_start()),
+            removed_variables: Vec::new(),
+            added_variables: Vec::new(),
+            removed_tokens: Vec::new(),
+            added_tokens: Vec::new(),
+            changed_variables: Vec::new(),
+            removed_rules: Vec::new(),
+            added_rules: Vec::new()
+        };
+
+        let old_terminals = terminals(self);
+        let new_terminals = terminals(other);
+        diff.removed_tokens = range(0, self.num_tokens()).map(|t| Token(t as u32)).filter(|&t| {
+            !new_terminals.contains_key(&tok(self, t))
+        }).collect();
+        diff.added_tokens = range(0, other.num_tokens()).map(|t| Token(t as u32)).filter(|&t| {
+            !old_terminals.contains_key(&tok(other, t))
+        }).collect();
+
+        for v in range(0, self.num_variables()).map(|v| Variable(v as u32)) {
+            match new_names.get(&var(self, v)) {
+                Some(&w) => {
+                    let (removed, added) = unmatched(self, v, other, w);
+                    if !removed.is_empty() || !added.is_empty() {
+                        diff.changed_variables.push((v, w));
+                    }
+                    diff.removed_rules.extend(removed.into_iter().map(|i| (v, i)));
+                    diff.added_rules.extend(added.into_iter().map(|i| (w, i)));
+                },
+                None => {
+                    diff.removed_variables.push(v);
+                    let n = self.get_rules(v).map_or(0, |rules| rules.len());
+                    diff.removed_rules.extend(range(0, n).map(|i| (v, i)));
+                }
+            }
+        }
+        for w in range(0, other.num_variables()).map(|v| Variable(v as u32)) {
+            if !old_names.contains_key(&var(other, w)) {
+                diff.added_variables.push(w);
+                let n = other.get_rules(w).map_or(0, |rules| rules.len());
+                diff.added_rules.extend(range(0, n).map(|i| (w, i)));
+            }
+        }
+        diff
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Whether this grammar and `other` are the same up to renaming their variables: see
+    /// `isomorphism`.
+    pub fn is_isomorphic(&self, other: &Cfg<T>) -> bool {
+        self.isomorphism(other).is_some()
+    }
+
+    /// Find a way to renumber the variables of this grammar, and its tokens to those of `other`
+    /// with the same terminals, so that the start symbol and the rules of each variable become
+    /// those of `other`, if there is one. The names of the variables don't matter, and the order
+    /// of each variable's rules doesn't either.
+    ///
+    /// The variables are first told apart by their rules, refined by how the variables they use
+    /// are told apart until nothing changes, and then matched up by backtracking among those
+    /// alike.
+    pub fn isomorphism(&self, other: &Cfg<T>) -> Option<SymbolMap> {
+        let n = self.num_variables();
+        if n != other.num_variables() || self.num_tokens() != other.num_tokens() {
+            return None
+        }
+        let mut tokens = Vec::new();
+        for t in range(0, self.num_tokens()).map(|t| Token(t as u32)) {
+            match other.token(self.terminal(t).unwrap()) {
+                Some(u) => tokens.push(u),
+                None => return None
+            }
+        }
+        let own: Vec<Token> = range(0, other.num_tokens()).map(|t| Token(t as u32)).collect();
+
+        // Colour the variables of both grammars alike, starting with the start symbols apart
+        // from the others, and recolour each by its rules with the variables replaced by their
+        // colours until the number of colours stops growing.
+        let mut ours = Vec::from_elem(n, 0u);
+        let mut theirs = Vec::from_elem(n, 0u);
+        ours[self.get_start().index()] = 1;
+        theirs[other.get_start().index()] = 1;
+        let mut count = 2;
+        loop {
+            let mut ids = HashMap::new();
+            let (old_ours, old_theirs) = (ours.clone(), theirs.clone());
+            for v in range(0, n) {
+                let rules = signature(self, Variable(v as u32), tokens.as_slice(),
+                                      old_ours.as_slice());
+                ours[v] = colour(&mut ids, (old_ours[v], rules));
+            }
+            for v in range(0, n) {
+                let rules = signature(other, Variable(v as u32), own.as_slice(),
+                                      old_theirs.as_slice());
+                theirs[v] = colour(&mut ids, (old_theirs[v], rules));
+            }
+            if ids.len() <= count {
+                break
+            }
+            count = ids.len();
+        }
+        let (mut sorted_ours, mut sorted_theirs) = (ours.clone(), theirs.clone());
+        sorted_ours.sort();
+        sorted_theirs.sort();
+        if sorted_ours != sorted_theirs {
+            return None
+        }
+
+        let mut matcher = Matcher {
+            cfg: self,
+            other: other,
+            tokens: tokens.as_slice(),
+            ours: ours.as_slice(),
+            theirs: theirs.as_slice(),
+            to: Vec::from_elem(n, None),
+            taken: Vec::from_elem(n, false)
+        };
+        if !matcher.match_from(0) {
+            return None
+        }
+        let mut map = SymbolMap::new();
+        for t in range(0, tokens.len()) {
+            map.insert_token(Token(t as u32), tokens[t]);
+        }
+        let to = matcher.to;
+        for v in range(0, n) {
+            map.insert_variable(Variable(v as u32), to[v].unwrap());
+        }
+        Some(map)
+    }
+}
+
+/// The rules of `v`, with each token replaced by the one it stands for in `tokens` and each
+/// variable by its colour, in order.
+fn signature<T: Eq + Hash + Clone>(cfg: &Cfg<T>, v: Variable, tokens: &[Token],
+                                   colours: &[uint]) -> Vec<Rule> {
+    let mut rules: Vec<Rule> = rules(cfg, v).iter().map(|rule| {
+        rule.iter().map(|&s| match s {
+            Symbol::Tok(t) => Symbol::Tok(tokens[t.index()]),
+            Symbol::Var(w) => Symbol::Var(Variable(colours[w.index()] as u32))
+        }).collect()
+    }).collect();
+    rules.sort();
+    rules
+}
+
+/// The colour numbered for `key`, numbering it next if it's new.
+fn colour(ids: &mut HashMap<(uint, Vec<Rule>), uint>, key: (uint, Vec<Rule>)) -> uint {
+    match ids.get(&key) {
+        Some(&id) => return id,
+        None => { }
+    }
+    let id = ids.len();
+    ids.insert(key, id);
+    id
+}
+
+/// The search for a matching of the variables of one grammar with those of another.
+struct Matcher<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    other: &'a Cfg<T>,
+    /// The token of `other` for each token of `cfg`.
+    tokens: &'a [Token],
+    /// The colour of each variable of `cfg`, and of `other`, which their matches must share.
+    ours: &'a [uint],
+    theirs: &'a [uint],
+    /// The variable of `other` each variable of `cfg` is matched to so far.
+    to: Vec<Option<Variable>>,
+    /// Whether each variable of `other` has been matched.
+    taken: Vec<bool>
+}
+
+impl<'a, T: Eq + Hash + Clone> Matcher<'a, T> {
+    /// Match up the variables from `v` on with those not yet taken, so that every matched
+    /// variable whose rules only use matched variables has the rules of its match.
+    fn match_from(&mut self, v: uint) -> bool {
+        let n = self.to.len();
+        if v == n {
+            return true
+        }
+        for w in range(0, n) {
+            if self.taken[w] || self.ours[v] != self.theirs[w] {
+                continue
+            }
+            self.to[v] = Some(Variable(w as u32));
+            self.taken[w] = true;
+            if range(0, v + 1).all(|u| self.same_rules(Variable(u as u32))) &&
+               self.match_from(v + 1) {
+                return true
+            }
+            self.to[v] = None;
+            self.taken[w] = false;
+        }
+        false
+    }
+
+    /// Whether `v` has the rules of its match, or uses a variable not matched yet.
+    fn same_rules(&self, v: Variable) -> bool {
+        let mut mapped = Vec::new();
+        for rule in rules(self.cfg, v).iter() {
+            let mut body = Vec::new();
+            for &s in rule.iter() {
+                body.push(match s {
+                    Symbol::Tok(t) => Symbol::Tok(self.tokens[t.index()]),
+                    Symbol::Var(w) => match self.to[w.index()] {
+                        Some(x) => Symbol::Var(x),
+                        None => return true
+                    }
+                });
+            }
+            mapped.push(body);
+        }
+        let mut theirs = rules(self.other, self.to[v.index()].unwrap()).to_vec();
+        mapped.sort();
+        theirs.sort();
+        mapped == theirs
+    }
+}
+
+/// The indices of the rules of `v` in `old` without a copy among those of `w` in `new`, and the
+/// other way around, pairing equal rules off in order.
+fn unmatched<T: fmt::Show + Eq + Hash + Clone>(old: &Cfg<T>, v: Variable,
+                                               new: &Cfg<T>, w: Variable)
+                                               -> (Vec<uint>, Vec<uint>) {
+    let old_rules: Vec<Vec<String>> = rules(old, v).iter().map(|r| written(old, r)).collect();
+    let new_rules: Vec<Vec<String>> = rules(new, w).iter().map(|r| written(new, r)).collect();
+    let mut paired = Vec::from_elem(new_rules.len(), false);
+    let mut removed = Vec::new();
+    for (i, rule) in old_rules.iter().enumerate() {
+        match range(0, new_rules.len()).find(|&j| !paired[j] && new_rules[j] == *rule) {
+            Some(j) => paired[j] = true,
+            None => removed.push(i)
+        }
+    }
+    let added = range(0, new_rules.len()).filter(|&j| !paired[j]).collect();
+    (removed, added)
+}
+
+/// Each variable of a grammar, by its name as written in BNF.
+fn names<T: fmt::Show + Eq + Hash + Clone>(cfg: &Cfg<T>) -> HashMap<String, Variable> {
+    let mut names = HashMap::new();
+    for v in range(0, cfg.num_variables()).map(|v| Variable(v as u32)) {
+        names.insert(var(cfg, v), v);
+    }
+    names
+}
+
+/// Each token of a grammar, by its terminal as written in BNF.
+fn terminals<T: fmt::Show + Eq + Hash + Clone>(cfg: &Cfg<T>) -> HashMap<String, Token> {
+    let mut terminals = HashMap::new();
+    for t in range(0, cfg.num_tokens()).map(|t| Token(t as u32)) {
+        terminals.insert(tok(cfg, t), t);
+    }
+    terminals
+}
+
+fn var<T: fmt::Show + Eq + Hash + Clone>(cfg: &Cfg<T>, v: Variable) -> String {
+    cfg.symbol_to_bnf(Symbol::Var(v))
+}
+
+fn tok<T: fmt::Show + Eq + Hash + Clone>(cfg: &Cfg<T>, t: Token) -> String {
+    cfg.symbol_to_bnf(Symbol::Tok(t))
+}
+
+fn written<T: fmt::Show + Eq + Hash + Clone>(cfg: &Cfg<T>, rule: &Rule) -> Vec<String> {
+    rule.iter().map(|&s| cfg.symbol_to_bnf(s)).collect()
+}
+
+/// A rule written as in BNF.
+fn rule<T: fmt::Show + Eq + Hash + Clone>(cfg: &Cfg<T>, v: Variable, i: uint) -> String {
+    let body = &rules(cfg, v)[i];
+    if body.is_empty() {
+        return format!("{} ::= \"\"", var(cfg, v))
+    }
+    format!("{} ::= {}", var(cfg, v), written(cfg, body).connect(" "))
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Token;
+    use testing::{EXPRESSIONS, grammar, variable};
+
+    const OLD: &'static str = "
+    <s> ::= <a> \"x\" | \"y\"
+    <a> ::= \"a\"
+    <b> ::= \"b\"
+    ";
+
+    #[test]
+    fn diff() {
+        let old = grammar(OLD);
+        let new = grammar("
+            <s> ::= \"y\" | <a> \"z\"
+            <a> ::= \"a\"
+            <c> ::= \"c\"
+        ");
+        let diff = old.diff(&new);
+        let (s, b) = (variable(&old, "s"), variable(&old, "b"));
+        let (t, c) = (variable(&new, "s"), variable(&new, "c"));
+        assert!(!diff.is_empty());
+        assert!(!diff.start_changed);
+        assert_eq!(diff.removed_variables, vec![b]);
+        assert_eq!(diff.added_variables, vec![c]);
+        assert_eq!(diff.removed_tokens, vec![Token(0), Token(3)]);
+        assert_eq!(diff.added_tokens, vec![Token(1), Token(3)]);
+        assert_eq!(diff.changed_variables, vec![(s, t)]);
+        assert_eq!(diff.removed_rules, vec![(s, 0), (b, 0)]);
+        assert_eq!(diff.added_rules, vec![(t, 1), (c, 0)]);
+        assert_eq!(diff.render(&old, &new).as_slice(), "\
+    - <s> ::= <a> \"x\"
+    + <s> ::= <a> \"z\"
+    - <b> ::= \"b\"
+    + <c> ::= \"c\"
+    ");
+    }
+
+    #[test]
+    fn same_grammar() {
+        let old = grammar(OLD);
+        let reordered = grammar("
+            <s> ::= \"y\" | <a> \"x\"
+            <b> ::= \"b\"
+            <a> ::= \"a\"
+        ");
+        assert!(old.diff(&reordered).is_empty());
+        assert!(old.diff(&old).render(&old, &old).is_empty());
+
+        let start = grammar("<a> ::= \"a\"\n<s> ::= <a> \"x\" | \"y\"\n<b> ::= \"b\"");
+        let diff = old.diff(&start);
+        assert!(diff.start_changed && diff.removed_rules.is_empty() && diff.added_rules.is_empty());
+        assert_eq!(diff.render(&old, &start).as_slice(), "start: <s> -> <a>\n");
+
+        // Each copy of a rule is matched by one copy in the other grammar.
+        let twice = grammar("<s> ::= <a> \"x\" | \"y\" | \"y\"\n<a> ::= \"a\"\n<b> ::= \"b\"");
+        let diff = old.diff(&twice);
+        assert_eq!(diff.added_rules, vec![(variable(&twice, "s"), 2)]);
+    }
+
+    #[test]
+    fn isomorphism() {
+        let cfg = grammar(EXPRESSIONS);
+        let renamed = grammar("
+            <sum> ::= <product> | <sum> \"+\" <product>
+            <product> ::= <product> \"*\" <atom> | <atom>
+            <atom> ::= \"id\" | \"(\" <sum> \")\"
+        ");
+        let map = cfg.isomorphism(&renamed).unwrap();
+        assert_eq!(map.variable(variable(&cfg, "e")), Some(variable(&renamed, "sum")));
+        assert_eq!(map.variable(variable(&cfg, "t")), Some(variable(&renamed, "product")));
+        assert_eq!(map.variable(variable(&cfg, "f")), Some(variable(&renamed, "atom")));
+        let plus = cfg.token(&"+".to_string()).unwrap();
+        assert_eq!(map.token(plus), renamed.token(&"+".to_string()));
+        assert!(renamed.is_isomorphic(&cfg));
+
+        // Swapping the operators keeps the shape but not the terminals.
+        let swapped = grammar("
+            <e> ::= <e> \"*\" <t> | <t>
+            <t> ::= <t> \"+\" <f> | <f>
+            <f> ::= \"(\" <e> \")\" | \"id\"
+        ");
+        assert!(!cfg.is_isomorphic(&swapped));
+        // The start symbol has to map to the start symbol.
+        let mut started = grammar(EXPRESSIONS);
+        started.set_start(variable(&started, "t")).unwrap();
+        assert!(!cfg.is_isomorphic(&started));
+        assert!(!cfg.is_isomorphic(&grammar("<e> ::= \"id\"")));
+    }
+}
@@ -0,0 +1,891 @@
+//! Earley recognition and parsing, for any grammar.
+
+use std::cmp;
+use std::collections::{BTreeSet, HashMap, RingBuf};
+use std::hash::Hash;
+use std::mem;
+
+use super::{Cfg, ParseError, Rule, Symbol, Token, TokenSet, Variable};
+use actions::Reducer;
+use forest::{Forest, Label, Node, Packed};
+use source::{TokenSource, TokenStream};
+
+/// A rule recognized up to a position, having started at the input position `origin`.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct Item {
+    pub variable: Variable,
+    /// The index of the rule in `get_rules`.
+    pub rule: uint,
+    /// The number of symbols of the rule recognized so far.
+    pub dot: uint,
+    pub origin: uint
+}
+
+impl Item {
+    fn advance(self) -> Item {
+        Item { dot: self.dot + 1, ..self }
+    }
+}
+
+/// The topmost item completed by a deterministic chain of completions, for Leo's optimization.
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+struct Transitive {
+    top: Item,
+    /// The only item waiting for the variable, which is the last symbol of its rule.
+    waiting: Item
+}
+
+/// The Earley sets built while recognizing an input, one for each position including the end.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Chart {
+    sets: Vec<Vec<Item>>,
+    /// The positions where the last symbol recognized by each item of each set began.
+    pivots: Vec<HashMap<Item, Vec<uint>>>,
+    /// The transitive item, if any, for each variable completed from each position.
+    leo: Vec<HashMap<Variable, Option<Transitive>>>,
+    /// The completions, by origin and variable, that each topmost item was added in place of.
+    links: Vec<HashMap<Item, Vec<(uint, Variable)>>>,
+    start: Variable,
+    accepted: bool
+}
+
+impl Chart {
+    /// The items in the set at an input position, in the order they were added.
+    pub fn get(&self, position: uint) -> &[Item] {
+        self.sets[position].as_slice()
+    }
+
+    /// The number of sets, one more than the length of the input.
+    pub fn len(&self) -> uint {
+        self.sets.len()
+    }
+
+    /// Whether the whole input was recognized as the start symbol.
+    pub fn accepts(&self) -> bool {
+        self.accepted
+    }
+
+    /// Where `input`, which the chart was built for, stops matching the grammar, or `None` if
+    /// it's accepted. The error is at the last nonempty set, and expects the tokens its items
+    /// can move past next, and the end of the input if the start symbol was recognized there.
+    pub fn error<T: Eq + Hash + Clone>(&self, cfg: &Cfg<T>, input: &[Token])
+                                       -> Option<ParseError> {
+        if self.accepted {
+            return None
+        }
+        let position = range(0, self.sets.len()).rev()
+            .find(|&i| !self.sets[i].is_empty())
+            .unwrap_or(0);
+        let mut tokens = TokenSet::new();
+        for &item in self.sets[position].iter() {
+            match next_symbol(cfg, item) {
+                Some(Symbol::Tok(t)) => { tokens.insert(t); },
+                _ => { }
+            }
+        }
+        let mut expected: Vec<Option<Token>> = tokens.iter().map(|t| Some(t)).collect();
+        if self.completes_start(cfg, position) {
+            expected.push(None);
+        }
+        Some(ParseError {
+            position: position,
+            found: input.get(position).map(|&t| t),
+            expected: expected
+        })
+    }
+
+    /// Update the chart for an edit of the input it was built for, given the input after the
+    /// edit, whose first `prefix` and last `suffix` tokens are the same as before.
+    ///
+    /// Each set depends only on the input before it, so the sets up to the one at `prefix` are
+    /// kept as they are. Those after it are built again until one has the same items as the
+    /// set the same distance from the end before the edit, each having started before the
+    /// edit or at that set. Nothing after that set can then tell the edit happened, so the
+    /// rest of the old sets are reused, their positions shifted by the change in length.
+    pub fn update<T: Eq + Hash + Clone>(&mut self, cfg: &Cfg<T>, input: &[Token],
+                                        prefix: uint, suffix: uint) {
+        let (n, old_n) = (input.len(), self.sets.len() - 1);
+        let kept = cmp::min(prefix, cmp::min(old_n, n)) + 1;
+        let suffix = cmp::min(suffix, cmp::min(old_n, n) + 1 - kept);
+        let mut sets = Sets {
+            sets: mem::replace(&mut self.sets, Vec::new()),
+            pivots: mem::replace(&mut self.pivots, Vec::new()),
+            leo: mem::replace(&mut self.leo, Vec::new()),
+            links: mem::replace(&mut self.links, Vec::new())
+        };
+        let mut old = sets.pop_from(kept);
+        for _ in range(kept, n + 1) {
+            sets.push_empty();
+        }
+        let nullable = cfg.nullable();
+        // The last set kept is processed again to scan its items over the new input; what it
+        // adds to itself is already there.
+        for i in range(kept - 1, n + 1) {
+            process(cfg, &nullable, &mut sets, input, i);
+            if i < kept || i + suffix < n || i + old_n < n + kept {
+                continue
+            }
+            let shift = Shift { from: i + old_n - n, to: i };
+            if sets.matches(i, &old, old_n - shift.from, kept - 1, shift) {
+                // The transitive items found for the old set are needed to follow the links
+                // of the sets reused after it.
+                for (&v, &t) in old.leo[old_n - shift.from].iter() {
+                    if !sets.leo[i].contains_key(&v) {
+                        sets.leo[i].insert(v, t.map(|t| shift.transitive(t)));
+                    }
+                }
+                old.truncate(old_n - shift.from);
+                sets.truncate(i + 1);
+                sets.append_shifted(old, shift);
+                break
+            }
+        }
+        self.sets = sets.sets;
+        self.pivots = sets.pivots;
+        self.leo = sets.leo;
+        self.links = sets.links;
+        self.accepted = self.completes_start(cfg, n);
+    }
+
+    /// The forest of all the parses of the input the chart was built for, or `None` if the
+    /// grammar doesn't derive it.
+    pub fn forest<T: Eq + Hash + Clone>(&self, cfg: &Cfg<T>) -> Option<Forest> {
+        if !self.accepts() {
+            return None
+        }
+        let mut builder = Builder { chart: self, nodes: Vec::new(), ids: HashMap::new(),
+                                    queue: RingBuf::new(), skipped: HashMap::new() };
+        let root = builder.node(Label::Symbol(Symbol::Var(self.start)), 0, self.sets.len() - 1);
+        loop {
+            let id = match builder.queue.pop_front() {
+                Some(id) => id,
+                None => break
+            };
+            let (label, start, end) = {
+                let node = &builder.nodes[id];
+                (node.label, node.start, node.end)
+            };
+            let items = match label {
+                Label::Symbol(Symbol::Tok(_)) => continue,
+                Label::Symbol(Symbol::Var(v)) => {
+                    let mut items = completions(cfg, v, start);
+                    items.retain(|item| {
+                        self.pivots[end].contains_key(item)
+                            || builder.skipped(end).contains_key(item)
+                    });
+                    items
+                },
+                Label::Prefix(v, rule, dot) => {
+                    vec![Item { variable: v, rule: rule, dot: dot, origin: start }]
+                }
+            };
+            let mut packed = Vec::new();
+            for &item in items.iter() {
+                packed.push_all(builder.derivations(cfg, item, end).as_slice());
+            }
+            builder.nodes[id].packed = packed;
+        }
+        Some(Forest { nodes: builder.nodes, root: root })
+    }
+
+    /// Whether the start symbol was recognized from the start of the input up to `end`.
+    fn completes_start<T: Eq + Hash + Clone>(&self, cfg: &Cfg<T>, end: uint) -> bool {
+        let skipped = self.skipped(end);
+        completions(cfg, self.start, 0).iter().any(|item| {
+            self.pivots[end].contains_key(item) || skipped.contains_key(item)
+        })
+    }
+
+    /// The completed items left out of the set at `end` by Leo's optimization, each with the
+    /// positions where its last symbol began.
+    fn skipped(&self, end: uint) -> HashMap<Item, Vec<uint>> {
+        let mut skipped = HashMap::new();
+        for (&top, links) in self.links[end].iter() {
+            for &(k, v) in links.iter() {
+                let (mut k, mut v) = (k, v);
+                loop {
+                    let waiting = match self.leo[k].get(&v) {
+                        Some(&Some(t)) => t.waiting,
+                        _ => break
+                    };
+                    let item = waiting.advance();
+                    if !skipped.contains_key(&item) {
+                        skipped.insert(item, Vec::new());
+                    }
+                    let pivots = skipped.get_mut(&item).unwrap();
+                    if !pivots.contains(&k) {
+                        pivots.push(k);
+                    }
+                    if item == top {
+                        break
+                    }
+                    k = waiting.origin;
+                    v = waiting.variable;
+                }
+            }
+        }
+        skipped
+    }
+}
+
+/// Whether the grammar derives `input`.
+pub fn recognize<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> bool {
+    chart(cfg, input).accepts()
+}
+
+/// Build the Earley sets of `input`.
+///
+/// Nullable variables are handled by moving past them as they're predicted, as described by
+/// Aycock and Horspool, so the grammar can be used as it is, with empty rules and ambiguity.
+/// Chains of completions that are bound to happen, as in right recursion, are cut short with
+/// Leo's optimization, making every LR-regular grammar take linear time.
+pub fn chart<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> Chart {
+    let n = input.len();
+    let mut sets = Sets {
+        sets: Vec::from_fn(n + 1, |_| Vec::new()),
+        pivots: Vec::from_fn(n + 1, |_| HashMap::new()),
+        leo: Vec::from_fn(n + 1, |_| HashMap::new()),
+        links: Vec::from_fn(n + 1, |_| HashMap::new())
+    };
+    let start = cfg.get_start();
+    for rule in range(0, rules(cfg, start).len()) {
+        sets.add(0, Item { variable: start, rule: rule, dot: 0, origin: 0 }, None);
+    }
+    fill(cfg, &mut sets, input, 0);
+
+    let mut chart = Chart {
+        sets: sets.sets,
+        pivots: sets.pivots,
+        leo: sets.leo,
+        links: sets.links,
+        start: start,
+        accepted: false
+    };
+    chart.accepted = chart.completes_start(cfg, n);
+    chart
+}
+
+/// Process the sets from the one at `from` on, adding the items each leads to in it and the
+/// next.
+fn fill<T: Eq + Hash + Clone>(cfg: &Cfg<T>, sets: &mut Sets, input: &[Token], from: uint) {
+    let nullable = cfg.nullable();
+    for i in range(from, input.len() + 1) {
+        process(cfg, &nullable, sets, input, i);
+    }
+}
+
+/// Process the set at `i`, adding the items it leads to in it and the next.
+fn process<T: Eq + Hash + Clone>(cfg: &Cfg<T>, nullable: &BTreeSet<Variable>, sets: &mut Sets,
+                                 input: &[Token], i: uint) {
+    let n = input.len();
+    let mut j = 0;
+    while j < sets.sets[i].len() {
+        let item = sets.sets[i][j];
+        j += 1;
+        match next_symbol(cfg, item) {
+            Some(Symbol::Var(v)) => predict(cfg, nullable, sets, i, item, v),
+            Some(Symbol::Tok(t)) => {
+                if i < n && input[i] == t {
+                    sets.add(i + 1, item.advance(), Some(i));
+                }
+            },
+            None => {
+                // The set at the current position is still growing, so only earlier ones can
+                // have a transitive item.
+                let transitive = if item.origin < i {
+                    sets.transitive(cfg, item.origin, item.variable)
+                } else {
+                    None
+                };
+                match transitive {
+                    Some(t) => {
+                        sets.link(i, t.top, item.origin, item.variable);
+                        continue
+                    },
+                    None => { }
+                }
+                let waiting: Vec<Item> = sets.sets[item.origin].iter()
+                    .filter(|w| next_symbol(cfg, **w) == Some(Symbol::Var(item.variable)))
+                    .map(|w| w.advance())
+                    .collect();
+                for w in waiting.into_iter() {
+                    sets.add(i, w, Some(item.origin));
+                }
+            }
+        }
+    }
+}
+
+/// An input kept with its chart, to be edited and parsed again without building the chart from
+/// scratch: after an edit, the sets before it, and those after it once the parse is back in
+/// step, are reused as `Chart::update` describes. Input read a piece at a time can be added to
+/// the end as it arrives, and parsed once it ends.
+pub struct Incremental<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    input: Vec<Token>,
+    chart: Chart
+}
+
+impl<'a, T: Eq + Hash + Clone> Incremental<'a, T> {
+    pub fn new(cfg: &'a Cfg<T>, input: Vec<Token>) -> Incremental<'a, T> {
+        let chart = chart(cfg, input.as_slice());
+        Incremental { cfg: cfg, input: input, chart: chart }
+    }
+
+    pub fn input(&self) -> &[Token] {
+        self.input.as_slice()
+    }
+
+    pub fn chart(&self) -> &Chart {
+        &self.chart
+    }
+
+    /// Replace the tokens from `start` up to `end` with `replacement`, and update the chart.
+    ///
+    /// Panics if `start` is after `end` or `end` is past the end of the input.
+    pub fn splice(&mut self, start: uint, end: uint, replacement: &[Token]) {
+        assert!(start <= end && end <= self.input.len());
+        let suffix = self.input.len() - end;
+        let mut input = Vec::with_capacity(start + replacement.len() + suffix);
+        input.push_all(self.input.slice_to(start));
+        input.push_all(replacement);
+        input.push_all(self.input.slice_from(end));
+        self.input = input;
+        self.chart.update(self.cfg, self.input.as_slice(), start, suffix);
+    }
+
+    /// Add tokens to the end of the input, as they arrive when it's read a piece at a time.
+    pub fn extend(&mut self, tokens: &[Token]) {
+        let len = self.input.len();
+        self.splice(len, len, tokens);
+    }
+
+    /// Whether the last set of the chart has items, which, unless the grammar has nonproductive
+    /// variables, is whether more input could make the input so far a sentence.
+    pub fn is_viable(&self) -> bool {
+        !self.chart.get(self.input.len()).is_empty()
+    }
+
+    /// Whether the grammar derives the input.
+    pub fn recognize(&self) -> bool {
+        self.chart.accepts()
+    }
+
+    /// The forest of all the parses of the input, or `None` if the grammar doesn't derive it.
+    pub fn parse(&self) -> Option<Forest> {
+        self.chart.forest(self.cfg)
+    }
+
+    /// Where the input stops matching the grammar, or `None` if it's accepted.
+    pub fn error(&self) -> Option<ParseError> {
+        self.chart.error(self.cfg, self.input.as_slice())
+    }
+}
+
+/// Parse `input`, giving the forest of all its parses, or `None` if the grammar doesn't derive it.
+pub fn parse<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> Option<Forest> {
+    chart(cfg, input).forest(cfg)
+}
+
+/// Parse the tokens read from a lexer, giving the forest of all their parses. To refer to their
+/// spans and payloads from the forest, read them with `TokenStream::read` and parse its `tokens`
+/// instead.
+pub fn parse_source<T: Eq + Hash + Clone, P, S: TokenSource<P>>(cfg: &Cfg<T>, source: &mut S)
+                                                                -> Option<Forest> {
+    parse(cfg, TokenStream::read(source).tokens.as_slice())
+}
+
+/// Parse `input`, building a value for one of its parse trees with `reducer` as
+/// `Forest::fold` does, or `None` if the grammar doesn't derive it.
+pub fn parse_with<T: Eq + Hash + Clone, V, R: Reducer<V>>(cfg: &Cfg<T>, input: &[Token],
+                                                          reducer: &mut R) -> Option<V> {
+    parse(cfg, input).map(|forest| forest.fold(reducer))
+}
+
+/// The number of parse trees the grammar gives `input`, or `None` if there are infinitely many.
+///
+/// The trees are counted over the forest, without enumerating them, saturating at `u64::MAX`.
+pub fn count_parses<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> Option<u64> {
+    match parse(cfg, input) {
+        Some(forest) => forest.count_trees(),
+        None => Some(0)
+    }
+}
+
+struct Builder<'a> {
+    chart: &'a Chart,
+    nodes: Vec<Node>,
+    ids: HashMap<(Label, uint, uint), uint>,
+    /// The nodes whose derivations are yet to be found.
+    queue: RingBuf<uint>,
+    /// The items skipped by Leo's optimization in each set looked at so far.
+    skipped: HashMap<uint, HashMap<Item, Vec<uint>>>
+}
+
+impl<'a> Builder<'a> {
+    /// The node for a label over a span, added if it's new.
+    fn node(&mut self, label: Label, start: uint, end: uint) -> uint {
+        match self.ids.get(&(label, start, end)) {
+            Some(&id) => return id,
+            None => { }
+        }
+        let id = self.nodes.len();
+        self.nodes.push(Node { label: label, start: start, end: end, packed: Vec::new() });
+        self.ids.insert((label, start, end), id);
+        self.queue.push_back(id);
+        id
+    }
+
+    /// The items skipped by Leo's optimization in the set at `end`, found the first time
+    /// they're needed.
+    fn skipped(&mut self, end: uint) -> &HashMap<Item, Vec<uint>> {
+        if !self.skipped.contains_key(&end) {
+            self.skipped.insert(end, self.chart.skipped(end));
+        }
+        self.skipped.get(&end).unwrap()
+    }
+
+    /// The derivations of the item, found in the set at `end`.
+    fn derivations<T: Eq + Hash + Clone>(&mut self, cfg: &Cfg<T>, item: Item, end: uint)
+                                         -> Vec<Packed> {
+        if item.dot == 0 {
+            return vec![Packed { variable: item.variable, rule: item.rule, pivot: end,
+                                 left: None, right: None }]
+        }
+        let last = rules(cfg, item.variable)[item.rule][item.dot - 1];
+        let mut pivots = self.chart.pivots[end].get(&item).map_or(Vec::new(), |p| p.clone());
+        if item.dot == rules(cfg, item.variable)[item.rule].len() {
+            match self.skipped(end).get(&item) {
+                Some(skipped) => {
+                    for &k in skipped.iter() {
+                        if !pivots.contains(&k) {
+                            pivots.push(k);
+                        }
+                    }
+                },
+                None => { }
+            }
+        }
+        let mut packed = Vec::with_capacity(pivots.len());
+        for k in pivots.into_iter() {
+            let left = if item.dot == 1 {
+                None
+            } else {
+                Some(self.node(Label::Prefix(item.variable, item.rule, item.dot - 1),
+                               item.origin, k))
+            };
+            packed.push(Packed {
+                variable: item.variable,
+                rule: item.rule,
+                pivot: k,
+                left: left,
+                right: Some(self.node(Label::Symbol(last), k, end))
+            });
+        }
+        packed
+    }
+}
+
+struct Sets {
+    sets: Vec<Vec<Item>>,
+    pivots: Vec<HashMap<Item, Vec<uint>>>,
+    leo: Vec<HashMap<Variable, Option<Transitive>>>,
+    links: Vec<HashMap<Item, Vec<(uint, Variable)>>>
+}
+
+impl Sets {
+    /// Add an item to a set, if it isn't already there, along with the position where the
+    /// symbol it just moved past began.
+    fn add(&mut self, position: uint, item: Item, pivot: Option<uint>) {
+        if !self.pivots[position].contains_key(&item) {
+            self.sets[position].push(item);
+            self.pivots[position].insert(item, Vec::new());
+        }
+        match pivot {
+            Some(k) => {
+                let pivots = self.pivots[position].get_mut(&item).unwrap();
+                if !pivots.contains(&k) {
+                    pivots.push(k);
+                }
+            },
+            None => { }
+        }
+    }
+
+    fn push_empty(&mut self) {
+        self.sets.push(Vec::new());
+        self.pivots.push(HashMap::new());
+        self.leo.push(HashMap::new());
+        self.links.push(HashMap::new());
+    }
+
+    fn truncate(&mut self, len: uint) {
+        self.sets.truncate(len);
+        self.pivots.truncate(len);
+        self.leo.truncate(len);
+        self.links.truncate(len);
+    }
+
+    /// Take off the sets from the one at `from` on, giving them last first.
+    fn pop_from(&mut self, from: uint) -> Sets {
+        let mut popped = Sets { sets: Vec::new(), pivots: Vec::new(), leo: Vec::new(),
+                                links: Vec::new() };
+        while self.sets.len() > from {
+            popped.sets.extend(self.sets.pop().into_iter());
+            popped.pivots.extend(self.pivots.pop().into_iter());
+            popped.leo.extend(self.leo.pop().into_iter());
+            popped.links.extend(self.links.pop().into_iter());
+        }
+        popped
+    }
+
+    /// Whether the set at `i` has the items of the set at `index` of `old`, as taken off by
+    /// `pop_from`, with its positions mapped by `shift`, and each of its items started at or
+    /// before `last_kept` or at `i`.
+    fn matches(&self, i: uint, old: &Sets, index: uint, last_kept: uint, shift: Shift) -> bool {
+        let pivots = &old.pivots[index];
+        self.sets[i].len() == old.sets[index].len() && self.sets[i].iter().all(|&item| {
+            let origin = if item.origin == i { shift.from } else { item.origin };
+            (item.origin <= last_kept || item.origin == i)
+                && pivots.contains_key(&Item { origin: origin, ..item })
+        })
+    }
+
+    /// Add the sets of `old`, as taken off by `pop_from`, to the end in order, with their
+    /// positions mapped by `shift`.
+    fn append_shifted(&mut self, mut old: Sets, shift: Shift) {
+        loop {
+            match (old.sets.pop(), old.pivots.pop(), old.leo.pop(), old.links.pop()) {
+                (Some(set), Some(pivots), Some(leo), Some(links)) => {
+                    if shift.from == shift.to {
+                        self.sets.push(set);
+                        self.pivots.push(pivots);
+                        self.leo.push(leo);
+                        self.links.push(links);
+                        continue
+                    }
+                    self.sets.push(set.into_iter().map(|item| shift.item(item)).collect());
+                    self.pivots.push(pivots.into_iter().map(|(item, pivots)| {
+                        (shift.item(item), pivots.into_iter().map(|k| shift.position(k)).collect())
+                    }).collect());
+                    self.leo.push(leo.into_iter().map(|(v, t)| {
+                        (v, t.map(|t| shift.transitive(t)))
+                    }).collect());
+                    self.links.push(links.into_iter().map(|(top, links)| {
+                        (shift.item(top), links.into_iter().map(|(k, v)| (shift.position(k), v))
+                                               .collect())
+                    }).collect());
+                },
+                _ => return
+            }
+        }
+    }
+
+    /// Add the topmost item of a chain of completions to a set in place of completing
+    /// `variable` from `origin`.
+    fn link(&mut self, position: uint, top: Item, origin: uint, variable: Variable) {
+        self.add(position, top, None);
+        if !self.links[position].contains_key(&top) {
+            self.links[position].insert(top, Vec::new());
+        }
+        let links = self.links[position].get_mut(&top).unwrap();
+        if !links.contains(&(origin, variable)) {
+            links.push((origin, variable));
+        }
+    }
+
+    /// The transitive item for completing `variable` from `position`, if the only item of the
+    /// set waiting for it has it as the last symbol of its rule.
+    fn transitive<T: Eq + Hash + Clone>(&mut self, cfg: &Cfg<T>, position: uint,
+                                        variable: Variable) -> Option<Transitive> {
+        match self.leo[position].get(&variable) {
+            Some(&t) => return t,
+            None => { }
+        }
+        let only = {
+            let mut waiting = self.sets[position].iter()
+                .filter(|w| next_symbol(cfg, **w) == Some(Symbol::Var(variable)));
+            match (waiting.next(), waiting.next()) {
+                (Some(&w), None) => Some(w),
+                _ => None
+            }
+        };
+        // A chain of unit rules can lead back here, and is cut short where it does.
+        self.leo[position].insert(variable, None);
+        let transitive = match only {
+            Some(w) if w.dot + 1 == rules(cfg, w.variable)[w.rule].len() => {
+                let top = match self.transitive(cfg, w.origin, w.variable) {
+                    Some(t) => t.top,
+                    None => w.advance()
+                };
+                Some(Transitive { top: top, waiting: w })
+            },
+            _ => None
+        };
+        self.leo[position].insert(variable, transitive);
+        transitive
+    }
+}
+
+/// Where the positions of the sets reused after an edit move to: those from `from` on, in the
+/// chart before the edit, start at `to` after it, and those before the edit stay where they are.
+#[deriving(Copy)]
+struct Shift {
+    from: uint,
+    to: uint
+}
+
+impl Shift {
+    fn position(self, position: uint) -> uint {
+        if position < self.from { position } else { position - self.from + self.to }
+    }
+
+    fn item(self, item: Item) -> Item {
+        Item { origin: self.position(item.origin), ..item }
+    }
+
+    fn transitive(self, t: Transitive) -> Transitive {
+        Transitive { top: self.item(t.top), waiting: self.item(t.waiting) }
+    }
+}
+
+fn predict<T: Eq + Hash + Clone>(cfg: &Cfg<T>, nullable: &BTreeSet<Variable>, sets: &mut Sets,
+                                 position: uint, item: Item, variable: Variable) {
+    for rule in range(0, rules(cfg, variable).len()) {
+        let predicted = Item { variable: variable, rule: rule, dot: 0, origin: position };
+        sets.add(position, predicted, None);
+    }
+    if nullable.contains(&variable) {
+        sets.add(position, item.advance(), Some(position));
+    }
+}
+
+/// The completed items of every rule of a variable, starting from `origin`.
+fn completions<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable, origin: uint)
+                                     -> Vec<Item> {
+    rules(cfg, variable).iter().enumerate().map(|(rule, body)| {
+        Item { variable: variable, rule: rule, dot: body.len(), origin: origin }
+    }).collect()
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+/// The symbol right after the position of an item, if any.
+fn next_symbol<T: Eq + Hash + Clone>(cfg: &Cfg<T>, item: Item) -> Option<Symbol> {
+    rules(cfg, item.variable)[item.rule].get(item.dot).map(|&s| s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Incremental, chart, count_parses, parse, recognize};
+    use super::super::{Symbol, Token};
+    use forest::Label;
+    use testing::{EXPRESSIONS, grammar, strings, tokens, variable};
+
+    #[test]
+    fn expressions() {
+        let cfg = grammar(EXPRESSIONS);
+        assert!(recognize(&cfg, tokens(&cfg, "id + id * ( id )").as_slice()));
+        assert!(!recognize(&cfg, tokens(&cfg, "id + * id").as_slice()));
+        assert!(!recognize(&cfg, tokens(&cfg, "( id").as_slice()));
+        let empty: &[Token] = &[];
+        assert!(!recognize(&cfg, empty));
+
+        let input = tokens(&cfg, "( id )");
+        let chart = chart(&cfg, input.as_slice());
+        assert!(chart.accepts());
+        assert_eq!(chart.len(), 4);
+        for i in range(0, chart.len()) {
+            assert!(!chart.get(i).is_empty());
+            assert!(chart.get(i).iter().all(|item| item.origin <= i));
+        }
+    }
+
+    #[test]
+    fn balanced_parentheses() {
+        let cfg = grammar("<s> ::= <s> <s> | \"(\" <s> \")\" | \"\"");
+        let recognized: Vec<uint> = range(0, 9u).map(|len| {
+            strings(&cfg, len).iter()
+                .filter(|input| input.len() == len && recognize(&cfg, input.as_slice()))
+                .count()
+        }).collect();
+        assert_eq!(recognized, vec![1, 0, 1, 0, 2, 0, 5, 0, 14]);
+    }
+
+    #[test]
+    fn empty_rules() {
+        // `a` is nullable only through a cycle of unit rules, which a single pass over each set
+        // would miss without moving past nullable variables as they're predicted.
+        let cfg = grammar("
+            <s> ::= <a> <a> \"x\" <a>
+            <a> ::= <b> | \"\"
+            <b> ::= <a>
+        ");
+        assert!(recognize(&cfg, tokens(&cfg, "x").as_slice()));
+        assert!(!recognize(&cfg, tokens(&cfg, "x x").as_slice()));
+    }
+
+    /// The size of the largest set in the chart of `n` tokens `a`.
+    fn largest_set(n: uint) -> uint {
+        let cfg = grammar("<s> ::= \"a\" <s> | \"\"");
+        let input = Vec::from_elem(n, tokens(&cfg, "a")[0]);
+        let chart = chart(&cfg, input.as_slice());
+        assert!(chart.accepts());
+        range(0, chart.len()).map(|i| chart.get(i).len()).max().unwrap()
+    }
+
+    #[test]
+    fn leo_right_recursion() {
+        // Without Leo's optimization each set would hold a completed item for every earlier
+        // position; with it, the sets stay the same size however long the input.
+        assert_eq!(largest_set(10), largest_set(100));
+        let cfg = grammar("<s> ::= \"a\" <s> | \"\"");
+        let input = Vec::from_elem(20, tokens(&cfg, "a")[0]);
+        assert!(parse(&cfg, input.as_slice()).is_some());
+        assert!(recognize(&cfg, input.slice_to(19)));
+    }
+
+    #[test]
+    fn forest() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"id\"");
+        let e = variable(&cfg, "e");
+        let forest = parse(&cfg, tokens(&cfg, "id + id").as_slice()).unwrap();
+        assert!(!forest.is_ambiguous());
+        let root = forest.node(forest.root);
+        assert_eq!((root.label, root.start, root.end), (Label::Symbol(Symbol::Var(e)), 0, 3));
+        assert_eq!(root.packed.len(), 1);
+        let packed = &root.packed[0];
+        assert_eq!((packed.variable, packed.rule, packed.pivot), (e, 0, 2));
+        let left = forest.node(packed.left.unwrap());
+        assert_eq!((left.label, left.start, left.end), (Label::Prefix(e, 0, 2), 0, 2));
+        let right = forest.node(packed.right.unwrap());
+        assert_eq!((right.label, right.start, right.end), (Label::Symbol(Symbol::Var(e)), 2, 3));
+
+        let forest = parse(&cfg, tokens(&cfg, "id + id + id").as_slice()).unwrap();
+        assert!(forest.is_ambiguous());
+        assert_eq!(forest.node(forest.root).packed.len(), 2);
+        assert!(parse(&cfg, tokens(&cfg, "id +").as_slice()).is_none());
+    }
+
+    #[test]
+    fn count_parses_over_forest() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"id\"");
+        let counts: Vec<Option<u64>> = ["id", "id + id", "id + id + id", "id + id + id + id"]
+            .iter()
+            .map(|text| count_parses(&cfg, tokens(&cfg, *text).as_slice()))
+            .collect();
+        assert_eq!(counts, vec![Some(1), Some(1), Some(2), Some(5)]);
+        assert_eq!(count_parses(&cfg, tokens(&cfg, "id +").as_slice()), Some(0));
+
+        // `s s` with `s` nullable can be pumped forever.
+        let cfg = grammar("<s> ::= <s> <s> | \"(\" <s> \")\" | \"\"");
+        assert_eq!(count_parses(&cfg, tokens(&cfg, "( )").as_slice()), None);
+    }
+
+    #[test]
+    fn error_position() {
+        let cfg = grammar(EXPRESSIONS);
+        let token = |name: &str| cfg.token(&name.to_string());
+        let input = tokens(&cfg, "id + ) id");
+        let error = chart(&cfg, input.as_slice()).error(&cfg, input.as_slice()).unwrap();
+        assert_eq!(error.position, 2);
+        assert_eq!(error.found, token(")"));
+        assert_eq!(error.expected, vec![token("("), token("id")]);
+
+        let input = tokens(&cfg, "id +");
+        let error = chart(&cfg, input.as_slice()).error(&cfg, input.as_slice()).unwrap();
+        assert_eq!((error.position, error.found), (2, None));
+
+        let input = tokens(&cfg, "id id");
+        let error = chart(&cfg, input.as_slice()).error(&cfg, input.as_slice()).unwrap();
+        assert_eq!(error.position, 1);
+        assert_eq!(error.expected, vec![token("+"), token("*"), None]);
+
+        let input = tokens(&cfg, "( id )");
+        assert_eq!(chart(&cfg, input.as_slice()).error(&cfg, input.as_slice()), None);
+    }
+
+    #[test]
+    fn incremental() {
+        let cfg = grammar(EXPRESSIONS);
+        let mut incremental = Incremental::new(&cfg, tokens(&cfg, "id + id * id"));
+        assert!(incremental.recognize());
+        let edits: &[(uint, uint, &str)] = &[
+            (2, 3, "( id + id )"),
+            (0, 0, "id *"),
+            (4, 9, ""),
+            (3, 5, "+"),
+            (0, 1, "( id"),
+            (6, 6, ")")
+        ];
+        for &(start, end, replacement) in edits.iter() {
+            incremental.splice(start, end, tokens(&cfg, replacement).as_slice());
+            let fresh = chart(&cfg, incremental.input());
+            assert_eq!(incremental.recognize(), fresh.accepts());
+            assert_eq!(incremental.error(), fresh.error(&cfg, incremental.input()));
+            assert_eq!(incremental.chart().len(), fresh.len());
+            for i in range(0, fresh.len()) {
+                let mut kept = incremental.chart().get(i).to_vec();
+                let mut built = fresh.get(i).to_vec();
+                kept.sort();
+                built.sort();
+                assert_eq!(kept, built);
+            }
+            let tree = fresh.forest(&cfg).map(|forest| forest.tree());
+            assert_eq!(incremental.parse().map(|forest| forest.tree()), tree);
+        }
+        assert_eq!(incremental.input(), tokens(&cfg, "( id * id + id )").as_slice());
+        let expected = parse(&cfg, incremental.input()).unwrap().tree();
+        assert_eq!(incremental.parse().unwrap().tree(), expected);
+    }
+
+    #[test]
+    fn edit_statements() {
+        // Right recursion, so the chains of completions at the end are cut short by Leo's
+        // optimization, and the sets after an edit are reused with their transitive items.
+        let cfg = grammar("
+        <list> ::= <stmt> <list> | <stmt>
+        <stmt> ::= \"id\" \"=\" <expr> \";\"
+        <expr> ::= \"id\" | \"(\" <expr> \")\"
+        ");
+        let input = tokens(&cfg, "id = id ; id = id ; id = id ;");
+        let mut incremental = Incremental::new(&cfg, input);
+        let edits: &[(uint, uint, &str)] = &[
+            (2, 3, "( ( id ) )"),
+            (3, 3, "("),
+            (3, 4, ""),
+            (2, 7, "id"),
+            (4, 4, "id = ( id ) ;"),
+            (12, 16, "")
+        ];
+        for &(start, end, replacement) in edits.iter() {
+            incremental.splice(start, end, tokens(&cfg, replacement).as_slice());
+            let fresh = chart(&cfg, incremental.input());
+            assert_eq!(incremental.recognize(), fresh.accepts());
+            assert_eq!(incremental.error(), fresh.error(&cfg, incremental.input()));
+            assert_eq!(incremental.chart().len(), fresh.len());
+            let tree = fresh.forest(&cfg).map(|forest| forest.tree());
+            assert_eq!(incremental.parse().map(|forest| forest.tree()), tree);
+        }
+        let expected = tokens(&cfg, "id = id ; id = ( id ) ; id = id ;");
+        assert_eq!(incremental.input(), expected.as_slice());
+    }
+
+    #[test]
+    fn extend() {
+        let cfg = grammar(EXPRESSIONS);
+        let mut growing = Incremental::new(&cfg, Vec::new());
+        assert!(growing.is_viable());
+        for piece in ["id", "+ (", "id", "* id )"].iter() {
+            growing.extend(tokens(&cfg, *piece).as_slice());
+            assert!(growing.is_viable());
+        }
+        assert!(growing.recognize());
+        assert_eq!(growing.input(), tokens(&cfg, "id + ( id * id )").as_slice());
+        growing.extend(tokens(&cfg, "id").as_slice());
+        assert!(!growing.is_viable());
+        assert_eq!(growing.error().unwrap().position, 7);
+    }
+}
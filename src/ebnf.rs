@@ -0,0 +1,258 @@
+//! Reading grammars written in EBNF.
+
+use super::{Cfg, Rule, SourceMap, Span, Symbol, SyntaxError};
+use import::{Scanner, Symbols, body_span, is_space, span};
+
+impl Cfg<String> {
+    /// Parse a grammar written in EBNF.
+    ///
+    /// Each rule has the form `name ::= alternatives` (or `name = alternatives`), optionally
+    /// terminated by `;`. Names are identifiers, terminals are quoted with `"` or `'`, and
+    /// alternatives are separated by `|`. Within a rule, `( ... )` groups, and a suffix `?`, `*`
+    /// or `+` makes the preceding item optional, repeated zero or more times, or repeated one or
+    /// more times. The ISO forms `[ ... ]` and `{ ... }` are accepted for optional and repeated
+    /// items, the items of a sequence may be separated by `,` as in ISO EBNF, and `(* ... *)` is
+    /// a comment. The variable of the first rule is the start symbol.
+    ///
+    /// Groups, optional and repeated items are lowered to fresh variables named after the rule
+    /// they appear in, with repetition expressed by right recursion.
+    pub fn parse_ebnf(src: &str) -> Result<Cfg<String>, SyntaxError> {
+        Cfg::parse_ebnf_with_spans(src).map(|(cfg, _)| cfg)
+    }
+
+    /// Parse a grammar written in EBNF as for `parse_ebnf`, also giving where each variable was
+    /// first defined and each rule written. Rules of the variables made for groups, optional and
+    /// repeated items have no spans.
+    pub fn parse_ebnf_with_spans(src: &str) -> Result<(Cfg<String>, SourceMap), SyntaxError> {
+        let mut s = Scanner::new(src);
+        let mut syms = Symbols::new();
+        try!(skip_space(&mut s));
+        while !s.at_end() {
+            let at = s.mark();
+            let name = s.take_while(in_ident);
+            if name.is_empty() {
+                return Err(s.error("expected a rule"))
+            }
+            let lhs = syms.define_at(name.as_slice(), span(at, s.mark()));
+            try!(skip_space(&mut s));
+            if !s.eat("::=") && !s.eat("=") {
+                return Err(s.error("expected `::=` or `=`"))
+            }
+            let bodies = try!(alternatives(&mut s, &mut syms, name.as_slice()));
+            for (body, rule_span) in bodies.into_iter() {
+                syms.add_rule_at(lhs, body, rule_span);
+            }
+            s.eat(";");
+            try!(skip_space(&mut s));
+        }
+        syms.finish_with_spans()
+    }
+}
+
+fn in_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Skip whitespace and `(* ... *)` comments.
+fn skip_space(s: &mut Scanner) -> Result<(), SyntaxError> {
+    loop {
+        s.skip_while(is_space);
+        if !s.looking_at("(*") {
+            return Ok(())
+        }
+        let start = s.mark();
+        s.eat("(*");
+        while !s.eat("*)") {
+            if s.bump().is_none() {
+                s.reset(start);
+                return Err(s.error("unterminated comment"))
+            }
+        }
+    }
+}
+
+/// Whether the cursor is at the `name ::=` beginning the next rule.
+fn starts_rule(s: &mut Scanner) -> bool {
+    let mark = s.mark();
+    let starts = !s.take_while(in_ident).is_empty() && {
+        skip_space(s).is_ok() && (s.looking_at("::=") || (s.looking_at("=") && !s.looking_at("==")))
+    };
+    s.reset(mark);
+    starts
+}
+
+fn alternatives(s: &mut Scanner, syms: &mut Symbols<String>, rule: &str)
+                -> Result<Vec<(Rule, Span)>, SyntaxError> {
+    let mut bodies = Vec::new();
+    loop {
+        bodies.push(try!(sequence(s, syms, rule)));
+        try!(skip_space(s));
+        if !s.eat("|") {
+            return Ok(bodies)
+        }
+    }
+}
+
+/// Read the body of a rule, along with where it's written.
+fn sequence(s: &mut Scanner, syms: &mut Symbols<String>, rule: &str)
+            -> Result<(Rule, Span), SyntaxError> {
+    let before = s.mark();
+    try!(skip_space(s));
+    let start = s.mark();
+    let mut end = start;
+    let mut body = Vec::new();
+    loop {
+        try!(skip_space(s));
+        let mut item = match s.peek() {
+            Some('"') | Some('\'') => {
+                let terminal = try!(s.quoted());
+                if terminal.is_empty() {
+                    vec![]
+                } else {
+                    vec![syms.token(terminal)]
+                }
+            },
+            Some('(') => try!(group(s, syms, rule, ")")),
+            Some('[') => {
+                let inner = try!(group(s, syms, rule, "]"));
+                vec![syms.optional(rule, inner)]
+            },
+            Some('{') => {
+                let inner = try!(group(s, syms, rule, "}"));
+                vec![syms.star(rule, inner)]
+            },
+            Some(c) if in_ident(c) => {
+                if starts_rule(s) {
+                    return Ok((body, body_span(before, start, end)))
+                }
+                let at = s.mark();
+                let name = s.take_while(in_ident);
+                vec![Symbol::Var(syms.variable(name.as_slice(), at))]
+            },
+            _ => return Ok((body, body_span(before, start, end)))
+        };
+        loop {
+            end = s.mark();
+            try!(skip_space(s));
+            if s.eat("?") {
+                item = vec![syms.optional(rule, item)];
+            } else if s.eat("*") {
+                item = vec![syms.star(rule, item)];
+            } else if s.eat("+") {
+                let rest = syms.star(rule, item.clone());
+                item.push(rest);
+            } else {
+                break
+            }
+        }
+        body.extend(item.into_iter());
+        if s.eat(",") {
+            try!(skip_space(s));
+            if !s.peek().map_or(false, |c| starts_item(c)) || starts_rule(s) {
+                return Err(s.error("expected an item after `,`"))
+            }
+        }
+    }
+}
+
+/// Whether an item of a sequence can start with `c`.
+fn starts_item(c: char) -> bool {
+    c == '"' || c == '\'' || c == '(' || c == '[' || c == '{' || in_ident(c)
+}
+
+/// Parse a bracketed group, giving the sequence of symbols it stands for.
+fn group(s: &mut Scanner, syms: &mut Symbols<String>, rule: &str, close: &str)
+         -> Result<Vec<Symbol>, SyntaxError> {
+    let open = s.mark();
+    s.bump();
+    let bodies = try!(alternatives(s, syms, rule));
+    if !s.eat(close) {
+        s.reset(open);
+        return Err(s.error(format!("unclosed group, expected `{}`", close).as_slice()))
+    }
+    Ok(syms.choice(rule, bodies.into_iter().map(|(body, _)| body).collect()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Cfg, Symbol};
+    use testing::{rules, variable};
+
+    fn token(cfg: &Cfg<String>, terminal: &str) -> Symbol {
+        Symbol::Tok(cfg.token(&terminal.to_string()).unwrap())
+    }
+
+    #[test]
+    fn repetition() {
+        let cfg = Cfg::parse_ebnf("list = \"[\" { item } \"]\" ;\nitem = \"x\" ;").unwrap();
+        let item = Symbol::Var(variable(&cfg, "item"));
+        let list = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].len(), 3);
+        assert_eq!((list[0][0], list[0][2]), (token(&cfg, "["), token(&cfg, "]")));
+        let star = list[0][1];
+        assert_eq!(rules(&cfg, star), [vec![item, star], vec![]].as_slice());
+
+        let cfg = Cfg::parse_ebnf("a ::= 'x'+").unwrap();
+        let a = cfg.get_rules(cfg.get_start()).unwrap();
+        let x = token(&cfg, "x");
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0][0], x);
+        let star = a[0][1];
+        assert_eq!(rules(&cfg, star), [vec![x, star], vec![]].as_slice());
+    }
+
+    #[test]
+    fn options_and_groups() {
+        let cfg = Cfg::parse_ebnf("a = ('x' | 'y') 'z'? ; b = ['x' 'y'] ('z') ;").unwrap();
+        let (x, y, z) = (token(&cfg, "x"), token(&cfg, "y"), token(&cfg, "z"));
+        let a = cfg.get_rules(variable(&cfg, "a")).unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(rules(&cfg, a[0][0]), [vec![x], vec![y]].as_slice());
+        assert_eq!(rules(&cfg, a[0][1]), [vec![z], vec![]].as_slice());
+        let b = cfg.get_rules(variable(&cfg, "b")).unwrap();
+        assert_eq!(b.len(), 1);
+        assert_eq!(b[0].len(), 2);
+        assert_eq!(rules(&cfg, b[0][0]), [vec![x, y], vec![]].as_slice());
+        assert_eq!(b[0][1], z);
+    }
+
+    #[test]
+    fn iso_concatenation() {
+        let cfg = Cfg::parse_ebnf("a = 'x', b, ('y' | 'z'), ['x'] ; b = 'y' ;").unwrap();
+        let a = cfg.get_rules(variable(&cfg, "a")).unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].len(), 4);
+        assert_eq!((a[0][0], a[0][1]), (token(&cfg, "x"), Symbol::Var(variable(&cfg, "b"))));
+        let error = Cfg::parse_ebnf("a = 'x', ; b = 'y' ;").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 10));
+        assert_eq!(error.message.as_slice(), "expected an item after `,`");
+        assert!(Cfg::parse_ebnf("a = 'x', | 'y'").is_err());
+        assert!(Cfg::parse_ebnf("a = 'x',\nb = 'y'").is_err());
+    }
+
+    #[test]
+    fn rules_and_comments() {
+        let cfg = Cfg::parse_ebnf("
+            (* a comment *)
+            s = a | b ''
+            a = 'a' (* another *)
+            b = 'b'
+        ").unwrap();
+        assert_eq!(cfg.get_start(), variable(&cfg, "s"));
+        let a = variable(&cfg, "a");
+        assert_eq!(cfg.get_rules(cfg.get_start()).unwrap(),
+                   [vec![Symbol::Var(a)], vec![Symbol::Var(variable(&cfg, "b"))]].as_slice());
+        assert_eq!(cfg.get_rules(a).unwrap(), [vec![token(&cfg, "a")]].as_slice());
+    }
+
+    #[test]
+    fn errors() {
+        let error = Cfg::parse_ebnf("s = 'a' ('b' | 'c'").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 9));
+        let error = Cfg::parse_ebnf("s = 'a'\n(* open").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 1));
+        assert!(Cfg::parse_ebnf("s 'a'").is_err());
+        assert!(Cfg::parse_ebnf("s = t").is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! A C interface to grammars, their analyses and LR parsing, built with the `ffi` feature.
+//!
+//! Grammars, parsers and parse trees are handed to C as opaque pointers, to be declared there as
+//! `typedef struct cfg cfg_t;`, `typedef struct cfg_parser cfg_parser_t;` and
+//! `typedef struct cfg_tree cfg_tree_t;`, and freed with `cfg_free`, `cfg_parser_free` and
+//! `cfg_tree_free`. Tokens and variables are passed as their `uint32_t` indices, and symbols as
+//! `cfg_symbol_t`, laid out as `CSymbol`. Functions that can fail return 0 on success and -1 on
+//! failure, or a null pointer on failure if they return a pointer.
+//!
+//! Strings are NUL-terminated, with any invalid UTF-8 replaced. Every pointer passed must be
+//! valid, except that the free functions accept null.
+
+use std::c_str::CString;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use libc::{c_char, c_int, size_t};
+
+use super::{Cfg, Symbol, Token, Variable};
+use earley;
+use lr::LrParser;
+use tree::ParseTree;
+
+/// A symbol as `cfg_symbol_t`: a variable if `is_variable` is nonzero, and a token otherwise,
+/// with its index.
+#[repr(C)]
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+pub struct CSymbol {
+    pub is_variable: c_int,
+    pub index: u32
+}
+
+impl CSymbol {
+    fn from_symbol(symbol: Symbol) -> CSymbol {
+        match symbol {
+            Symbol::Tok(t) => CSymbol { is_variable: 0, index: t.index() as u32 },
+            Symbol::Var(v) => CSymbol { is_variable: 1, index: v.index() as u32 }
+        }
+    }
+
+    fn to_symbol(self) -> Symbol {
+        if self.is_variable != 0 {
+            Symbol::Var(Variable(self.index))
+        } else {
+            Symbol::Tok(Token(self.index))
+        }
+    }
+}
+
+unsafe fn to_string(s: *const c_char) -> String {
+    let s = CString::new(s, false);
+    String::from_utf8_lossy(s.as_bytes_no_nul()).into_owned()
+}
+
+unsafe fn to_tokens(tokens: *const u32, len: size_t) -> Vec<Token> {
+    if len == 0 {
+        return Vec::new()
+    }
+    slice::from_raw_buf(&tokens, len as uint).iter().map(|&t| Token(t)).collect()
+}
+
+/// Write the tokens of a set to `out`, up to `capacity` of them, returning how many there are.
+unsafe fn write_tokens<I: Iterator<Token>>(tokens: I, out: *mut u32, capacity: size_t)
+                                           -> size_t {
+    let mut n = 0;
+    for t in tokens {
+        if n < capacity {
+            *out.offset(n as int) = t.index() as u32;
+        }
+        n += 1;
+    }
+    n
+}
+
+/// A new grammar, with no symbols.
+#[no_mangle]
+pub extern "C" fn cfg_new() -> *mut Cfg<String> {
+    unsafe { mem::transmute(box Cfg::<String>::new()) }
+}
+
+/// Read a grammar written in BNF, as `Cfg::parse_bnf` does. On failure, returns null and sets
+/// `line` and `column` to where the error was found, if they're not null.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_parse_bnf(text: *const c_char, line: *mut size_t,
+                                       column: *mut size_t) -> *mut Cfg<String> {
+    match Cfg::parse_bnf(to_string(text).as_slice()) {
+        Ok(cfg) => mem::transmute(box cfg),
+        Err(error) => {
+            if !line.is_null() {
+                *line = error.line as size_t;
+            }
+            if !column.is_null() {
+                *column = error.column as size_t;
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cfg_free(cfg: *mut Cfg<String>) {
+    if !cfg.is_null() {
+        let _: Box<Cfg<String>> = mem::transmute(cfg);
+    }
+}
+
+/// Get the token standing for `terminal`, allocating a new one if there is none yet.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_add_token(cfg: *mut Cfg<String>, terminal: *const c_char) -> u32 {
+    (*cfg).add_token(to_string(terminal)).index() as u32
+}
+
+/// The token standing for `terminal`, or -1 if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_token(cfg: *const Cfg<String>, terminal: *const c_char) -> i64 {
+    (*cfg).token(&to_string(terminal)).map_or(-1, |t| t.index() as i64)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cfg_add_variable(cfg: *mut Cfg<String>, name: *const c_char) -> u32 {
+    (*cfg).add_variable(to_string(name).as_slice()).index() as u32
+}
+
+/// Add a rule with the `len` symbols at `symbols` as its body. Fails if any symbol hasn't been
+/// allocated.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_add_rule(cfg: *mut Cfg<String>, variable: u32,
+                                      symbols: *const CSymbol, len: size_t) -> c_int {
+    let body = if len == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_buf(&symbols, len as uint).iter().map(|s| s.to_symbol()).collect()
+    };
+    match (*cfg).add_rule(Variable(variable), body) {
+        Ok(()) => 0,
+        Err(_) => -1
+    }
+}
+
+/// Fails if the variable hasn't been allocated.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_set_start(cfg: *mut Cfg<String>, variable: u32) -> c_int {
+    match (*cfg).set_start(Variable(variable)) {
+        Ok(()) => 0,
+        Err(_) => -1
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cfg_num_tokens(cfg: *const Cfg<String>) -> size_t {
+    (*cfg).num_tokens() as size_t
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cfg_num_variables(cfg: *const Cfg<String>) -> size_t {
+    (*cfg).num_variables() as size_t
+}
+
+/// Check the grammar as `Cfg::validate` does.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_validate(cfg: *const Cfg<String>) -> c_int {
+    match (*cfg).validate() {
+        Ok(()) => 0,
+        Err(_) => -1
+    }
+}
+
+/// Write the FIRST set of `variable` to `tokens`, up to `capacity` tokens, and set `epsilon` to
+/// whether it holds the empty string, if it's not null. Returns the number of tokens in the set,
+/// which may be more than were written, or 0 if the variable hasn't been allocated.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_first(cfg: *const Cfg<String>, variable: u32, tokens: *mut u32,
+                                   capacity: size_t, epsilon: *mut c_int) -> size_t {
+    if variable as uint >= (*cfg).num_variables() {
+        if !epsilon.is_null() {
+            *epsilon = 0;
+        }
+        return 0
+    }
+    let sets = (*cfg).first_sets();
+    let set = sets.get(Variable(variable));
+    if !epsilon.is_null() {
+        *epsilon = set.epsilon as c_int;
+    }
+    write_tokens(set.tokens.iter(), tokens, capacity)
+}
+
+/// Write the FOLLOW set of `variable` to `tokens` as `cfg_first` does, setting `end` to whether
+/// the end of the input can follow it, if it's not null.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_follow(cfg: *const Cfg<String>, variable: u32, tokens: *mut u32,
+                                    capacity: size_t, end: *mut c_int) -> size_t {
+    if variable as uint >= (*cfg).num_variables() {
+        if !end.is_null() {
+            *end = 0;
+        }
+        return 0
+    }
+    let sets = (*cfg).follow_sets();
+    let set = sets.get(Variable(variable));
+    if !end.is_null() {
+        *end = set.end as c_int;
+    }
+    write_tokens(set.tokens.iter(), tokens, capacity)
+}
+
+/// 1 if the grammar is LL(1), and 0 otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_is_ll1(cfg: *const Cfg<String>) -> c_int {
+    (*cfg).ll1_table().is_ok() as c_int
+}
+
+/// 1 if the grammar is LALR(1), and 0 otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_is_lalr1(cfg: *const Cfg<String>) -> c_int {
+    (*cfg).lalr_table().is_ok() as c_int
+}
+
+/// 1 if the grammar derives the `len` tokens at `tokens`, and 0 otherwise. Works for any
+/// grammar, by Earley's algorithm.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_recognize(cfg: *const Cfg<String>, tokens: *const u32,
+                                       len: size_t) -> c_int {
+    earley::recognize(&*cfg, to_tokens(tokens, len).as_slice()) as c_int
+}
+
+/// An LR parser for the grammar, or null if it isn't LALR(1). The grammar must not be changed
+/// or freed while the parser is in use.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_parser_new(cfg: *const Cfg<String>)
+                                        -> *mut LrParser<'static, String> {
+    match LrParser::new(&*cfg) {
+        Ok(parser) => mem::transmute(box parser),
+        Err(_) => ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cfg_parser_free(parser: *mut LrParser<'static, String>) {
+    if !parser.is_null() {
+        let _: Box<LrParser<'static, String>> = mem::transmute(parser);
+    }
+}
+
+/// Parse the `len` tokens at `tokens`. On failure, returns null and sets `error_position` to the
+/// position of the token that couldn't be parsed, or `len` if the input ended too soon, if it's
+/// not null.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_parser_parse(parser: *const LrParser<'static, String>,
+                                          tokens: *const u32, len: size_t,
+                                          error_position: *mut size_t) -> *mut ParseTree {
+    match (*parser).parse(to_tokens(tokens, len).as_slice()) {
+        Ok(tree) => mem::transmute(box tree),
+        Err(error) => {
+            if !error_position.is_null() {
+                *error_position = error.position as size_t;
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Free a tree returned by `cfg_parser_parse`, with all its subtrees.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_tree_free(tree: *mut ParseTree) {
+    if !tree.is_null() {
+        let _: Box<ParseTree> = mem::transmute(tree);
+    }
+}
+
+/// The symbol at the root of the tree.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_tree_symbol(tree: *const ParseTree) -> CSymbol {
+    CSymbol::from_symbol((*tree).symbol())
+}
+
+/// The index of the rule the root variable was expanded by, or 0 for a leaf.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_tree_rule(tree: *const ParseTree) -> size_t {
+    match *tree {
+        ParseTree::Node(_, rule, _) => rule as size_t,
+        ParseTree::Leaf(_) => 0
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cfg_tree_num_children(tree: *const ParseTree) -> size_t {
+    (*tree).children().len() as size_t
+}
+
+/// The subtree for the `i`th symbol of the root's rule, or null if there isn't one. It belongs
+/// to the tree and is freed with it.
+#[no_mangle]
+pub unsafe extern "C" fn cfg_tree_child(tree: *const ParseTree, i: size_t) -> *const ParseTree {
+    match (*tree).children().get(i as uint) {
+        Some(child) => child as *const ParseTree,
+        None => ptr::null()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::c_str::ToCStr;
+
+    use super::{CSymbol, cfg_add_rule, cfg_add_token, cfg_add_variable, cfg_first, cfg_follow,
+                cfg_free, cfg_is_lalr1, cfg_is_ll1, cfg_new, cfg_num_tokens, cfg_parse_bnf,
+                cfg_parser_free, cfg_parser_new, cfg_parser_parse, cfg_recognize, cfg_set_start,
+                cfg_token, cfg_tree_child, cfg_tree_free, cfg_tree_num_children, cfg_tree_rule,
+                cfg_tree_symbol, cfg_validate};
+    use std::ptr;
+
+    #[test]
+    fn grammar() {
+        unsafe {
+            // s ::= "a" s | "b"
+            let cfg = cfg_new();
+            let s = cfg_add_variable(cfg, "s".to_c_str().as_ptr());
+            let a = cfg_add_token(cfg, "a".to_c_str().as_ptr());
+            let b = cfg_add_token(cfg, "b".to_c_str().as_ptr());
+            let rule = [CSymbol { is_variable: 0, index: a }, CSymbol { is_variable: 1, index: s }];
+            assert_eq!(cfg_add_rule(cfg, s, rule.as_ptr(), 2), 0);
+            assert_eq!(cfg_add_rule(cfg, s, [CSymbol { is_variable: 0, index: b }].as_ptr(), 1), 0);
+            let undefined = [CSymbol { is_variable: 1, index: 7 }];
+            assert_eq!(cfg_add_rule(cfg, s, undefined.as_ptr(), 1), -1);
+            assert_eq!(cfg_set_start(cfg, s), 0);
+            assert_eq!(cfg_set_start(cfg, 7), -1);
+            assert_eq!(cfg_validate(cfg), 0);
+            assert_eq!(cfg_num_tokens(cfg), 2);
+            assert_eq!(cfg_token(cfg, "b".to_c_str().as_ptr()), b as i64);
+            assert_eq!(cfg_token(cfg, "c".to_c_str().as_ptr()), -1);
+
+            let mut tokens = vec![9u32, 9, 9];
+            let mut flag = 1;
+            assert_eq!(cfg_first(cfg, s, tokens.as_mut_ptr(), 1, &mut flag), 2);
+            assert_eq!((tokens[0], tokens[1], flag), (a, 9, 0));
+            assert_eq!(cfg_follow(cfg, s, tokens.as_mut_ptr(), 3, &mut flag), 0);
+            assert_eq!(flag, 1);
+            assert_eq!(cfg_first(cfg, 7, tokens.as_mut_ptr(), 3, &mut flag), 0);
+            assert_eq!((cfg_is_ll1(cfg), cfg_is_lalr1(cfg)), (1, 1));
+            assert_eq!(cfg_recognize(cfg, [a, a, b].as_ptr(), 3), 1);
+            assert_eq!(cfg_recognize(cfg, [a].as_ptr(), 1), 0);
+            assert_eq!(cfg_recognize(cfg, ptr::null(), 0), 0);
+
+            let parser = cfg_parser_new(cfg);
+            assert!(!parser.is_null());
+            let mut position = 0;
+            let tree = cfg_parser_parse(parser, [a, b].as_ptr(), 2, &mut position);
+            assert_eq!(cfg_tree_symbol(tree), CSymbol { is_variable: 1, index: s });
+            assert_eq!((cfg_tree_rule(tree), cfg_tree_num_children(tree)), (0, 2));
+            let leaf = cfg_tree_child(tree, 0);
+            assert_eq!(cfg_tree_symbol(leaf), CSymbol { is_variable: 0, index: a });
+            assert_eq!(cfg_tree_num_children(leaf), 0);
+            assert_eq!(cfg_tree_rule(cfg_tree_child(tree, 1)), 1);
+            assert!(cfg_tree_child(tree, 2).is_null());
+            cfg_tree_free(tree);
+            assert!(cfg_parser_parse(parser, [a].as_ptr(), 1, &mut position).is_null());
+            assert_eq!(position, 1);
+            cfg_parser_free(parser);
+            cfg_free(cfg);
+        }
+    }
+
+    #[test]
+    fn parse_bnf() {
+        unsafe {
+            let (mut line, mut column) = (0, 0);
+            let text = "<s> ::= \"a\" <s> | \"b\"\n<t> ::= \"c".to_c_str();
+            assert!(cfg_parse_bnf(text.as_ptr(), &mut line, &mut column).is_null());
+            assert_eq!(line, 2);
+            assert!(column > 0);
+            let cfg = cfg_parse_bnf("<s> ::= \"a\" <s> | \"\"".to_c_str().as_ptr(), &mut line,
+                                    &mut column);
+            assert!(!cfg.is_null());
+            assert_eq!(cfg_is_ll1(cfg), 1);
+            let parser = cfg_parser_new(cfg);
+            let tree = cfg_parser_parse(parser, ptr::null(), 0, ptr::null_mut());
+            assert_eq!(cfg_tree_num_children(tree), 0);
+            cfg_tree_free(tree);
+            cfg_parser_free(parser);
+            cfg_free(cfg);
+            cfg_free(ptr::null_mut());
+            cfg_parser_free(ptr::null_mut());
+            cfg_tree_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn null_flags() {
+        unsafe {
+            let text = "<s> ::= \"a\" <s> | \"b\"".to_c_str();
+            let cfg = cfg_parse_bnf(text.as_ptr(), ptr::null_mut(), ptr::null_mut());
+            let mut tokens = vec![0u32, 0];
+            assert_eq!(cfg_first(cfg, 0, tokens.as_mut_ptr(), 2, ptr::null_mut()), 2);
+            assert_eq!(cfg_follow(cfg, 0, tokens.as_mut_ptr(), 2, ptr::null_mut()), 0);
+            assert_eq!(cfg_first(cfg, 5, tokens.as_mut_ptr(), 2, ptr::null_mut()), 0);
+            assert_eq!(cfg_follow(cfg, 5, tokens.as_mut_ptr(), 2, ptr::null_mut()), 0);
+            cfg_free(cfg);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! FIRST and FOLLOW sets, for one token of lookahead and for `k`.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use std::collections::BTreeSet;
+
+use super::{Cfg, Symbol, Terminal, Token, TokenSet, Variable};
+
+/// The tokens that can begin the strings derived from some symbols, and whether the empty string
+/// is among them.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct FirstSet {
+    pub tokens: TokenSet,
+    pub epsilon: bool
+}
+
+impl FirstSet {
+    pub fn new() -> FirstSet {
+        FirstSet { tokens: TokenSet::new(), epsilon: false }
+    }
+
+    /// Add everything in `other` to the set, returning whether anything was added.
+    pub fn union_with(&mut self, other: &FirstSet) -> bool {
+        let changed = self.tokens.union_with(&other.tokens) || (other.epsilon && !self.epsilon);
+        self.epsilon = self.epsilon || other.epsilon;
+        changed
+    }
+}
+
+/// The tokens that can follow a variable in a sentential form, and whether the end of the input
+/// can follow it.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct FollowSet {
+    pub tokens: TokenSet,
+    pub end: bool
+}
+
+impl FollowSet {
+    pub fn new() -> FollowSet {
+        FollowSet { tokens: TokenSet::new(), end: false }
+    }
+
+    /// Add everything in `other` to the set, returning whether anything was added.
+    pub fn union_with(&mut self, other: &FollowSet) -> bool {
+        let changed = self.tokens.union_with(&other.tokens) || (other.end && !self.end);
+        self.end = self.end || other.end;
+        changed
+    }
+}
+
+/// The FIRST set of every variable in a grammar.
+pub struct FirstSets {
+    sets: Vec<FirstSet>
+}
+
+impl FirstSets {
+    /// The FIRST set of a variable.
+    pub fn get(&self, variable: Variable) -> &FirstSet {
+        &self.sets[variable.index()]
+    }
+
+    /// The FIRST set of a string of symbols.
+    pub fn of(&self, symbols: &[Symbol]) -> FirstSet {
+        let mut first = FirstSet::new();
+        for &symbol in symbols.iter() {
+            match symbol {
+                Symbol::Tok(t) => {
+                    first.tokens.insert(t);
+                    return first
+                },
+                Symbol::Var(v) => {
+                    let set = &self.sets[v.index()];
+                    first.tokens.union_with(&set.tokens);
+                    if !set.epsilon {
+                        return first
+                    }
+                }
+            }
+        }
+        first.epsilon = true;
+        first
+    }
+}
+
+/// The FOLLOW set of every variable in a grammar.
+pub struct FollowSets {
+    sets: Vec<FollowSet>
+}
+
+impl FollowSets {
+    /// The FOLLOW set of a variable.
+    pub fn get(&self, variable: Variable) -> &FollowSet {
+        &self.sets[variable.index()]
+    }
+}
+
+/// The FIRST_k set of every variable in a grammar: the strings of up to `k` tokens that can begin
+/// a string it derives. A string shorter than `k` is a whole derived string.
+pub struct FirstKSets {
+    k: uint,
+    sets: Vec<BTreeSet<Vec<Token>>>
+}
+
+impl FirstKSets {
+    pub fn k(&self) -> uint {
+        self.k
+    }
+
+    /// The FIRST_k set of a variable.
+    pub fn get(&self, variable: Variable) -> &BTreeSet<Vec<Token>> {
+        &self.sets[variable.index()]
+    }
+
+    /// The FIRST_k set of a string of symbols.
+    pub fn of(&self, symbols: &[Symbol]) -> BTreeSet<Vec<Token>> {
+        let mut first = BTreeSet::new();
+        first.insert(Vec::new());
+        for &symbol in symbols.iter() {
+            if first.is_empty() || first.iter().all(|s| s.len() >= self.k) {
+                break
+            }
+            first = match symbol {
+                Symbol::Tok(t) => {
+                    let mut single = BTreeSet::new();
+                    single.insert(vec![t]);
+                    concat_k(&first, &single, self.k)
+                },
+                Symbol::Var(v) => concat_k(&first, &self.sets[v.index()], self.k)
+            };
+        }
+        first
+    }
+}
+
+/// The FOLLOW_k set of every variable in a grammar: the strings of up to `k` tokens that can come
+/// right after it in a sentential form. A string shorter than `k` is followed by the end of the
+/// input.
+pub struct FollowKSets {
+    k: uint,
+    sets: Vec<BTreeSet<Vec<Token>>>
+}
+
+impl FollowKSets {
+    pub fn k(&self) -> uint {
+        self.k
+    }
+
+    /// The FOLLOW_k set of a variable.
+    pub fn get(&self, variable: Variable) -> &BTreeSet<Vec<Token>> {
+        &self.sets[variable.index()]
+    }
+}
+
+/// Every concatenation of a string from `a` with one from `b`, truncated to `k` tokens.
+fn concat_k(a: &BTreeSet<Vec<Token>>, b: &BTreeSet<Vec<Token>>, k: uint)
+            -> BTreeSet<Vec<Token>> {
+    let mut result = BTreeSet::new();
+    for x in a.iter() {
+        if x.len() >= k {
+            result.insert(x.clone());
+            continue
+        }
+        for y in b.iter() {
+            let mut s = x.clone();
+            s.extend(y.iter().take(k - x.len()).map(|&t| t));
+            result.insert(s);
+        }
+    }
+    result
+}
+
+/// Add every string in `from` to `into`, returning whether any were new.
+fn union(into: &mut BTreeSet<Vec<Token>>, from: BTreeSet<Vec<Token>>) -> bool {
+    let mut changed = false;
+    for s in from.into_iter() {
+        changed = into.insert(s) || changed;
+    }
+    changed
+}
+
+impl<T: Terminal> Cfg<T> {
+    /// Compute the FIRST set of every variable: the tokens that can begin a string it derives,
+    /// and whether it derives the empty string.
+    pub fn first_sets(&self) -> FirstSets {
+        let n = self.num_variables();
+        let mut first = FirstSets { sets: Vec::from_elem(n, FirstSet::new()) };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                let rules = match self.get_rules(Variable(v as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                for rule in rules.iter() {
+                    let body = first.of(rule.as_slice());
+                    changed = first.sets[v].union_with(&body) || changed;
+                }
+            }
+        }
+        first
+    }
+
+    /// Compute the FOLLOW set of every variable: the tokens that can come right after it in a
+    /// sentential form derived from the start symbol, and whether the end of the input can.
+    pub fn follow_sets(&self) -> FollowSets {
+        let first = self.first_sets();
+        let n = self.num_variables();
+        let mut follow = FollowSets { sets: Vec::from_elem(n, FollowSet::new()) };
+        if self.get_start().index() < n {
+            follow.sets[self.get_start().index()].end = true;
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for a in range(0, n) {
+                let rules = match self.get_rules(Variable(a as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                for rule in rules.iter() {
+                    for (i, &symbol) in rule.iter().enumerate() {
+                        let b = match symbol {
+                            Symbol::Var(b) => b.index(),
+                            Symbol::Tok(_) => continue
+                        };
+                        let rest = first.of(rule.slice_from(i + 1));
+                        changed = follow.sets[b].tokens.union_with(&rest.tokens) || changed;
+                        if rest.epsilon && a != b {
+                            let from = follow.sets[a].clone();
+                            changed = follow.sets[b].union_with(&from) || changed;
+                        }
+                    }
+                }
+            }
+        }
+        follow
+    }
+
+    /// Compute the FIRST_k set of every variable.
+    pub fn first_k(&self, k: uint) -> FirstKSets {
+        let n = self.num_variables();
+        let mut first = FirstKSets { k: k, sets: Vec::from_elem(n, BTreeSet::new()) };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                let rules = match self.get_rules(Variable(v as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                for rule in rules.iter() {
+                    let body = first.of(rule.as_slice());
+                    changed = union(&mut first.sets[v], body) || changed;
+                }
+            }
+        }
+        first
+    }
+
+    /// Compute the FOLLOW_k set of every variable.
+    pub fn follow_k(&self, k: uint) -> FollowKSets {
+        let first = self.first_k(k);
+        let n = self.num_variables();
+        let mut follow = FollowKSets { k: k, sets: Vec::from_elem(n, BTreeSet::new()) };
+        if self.get_start().index() < n {
+            follow.sets[self.get_start().index()].insert(Vec::new());
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for a in range(0, n) {
+                let rules = match self.get_rules(Variable(a as u32)) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+                for rule in rules.iter() {
+                    for (i, &symbol) in rule.iter().enumerate() {
+                        let b = match symbol {
+                            Symbol::Var(b) => b.index(),
+                            Symbol::Tok(_) => continue
+                        };
+                        let rest = first.of(rule.slice_from(i + 1));
+                        let after = concat_k(&rest, &follow.sets[a], k);
+                        changed = union(&mut follow.sets[b], after) || changed;
+                    }
+                }
+            }
+        }
+        follow
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::super::{Cfg, Symbol, Token, TokenSet, Variable};
+    use testing::{EXPRESSIONS_LL1, grammar, tokens, variable};
+
+    fn set(cfg: &Cfg<String>, text: &str) -> TokenSet {
+        let mut set = TokenSet::new();
+        for &t in tokens(cfg, text).iter() {
+            set.insert(t);
+        }
+        set
+    }
+
+    #[test]
+    fn first_sets() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let first = cfg.first_sets();
+        for &(name, terminals, epsilon) in [("e", "( id", false), ("ep", "+", true),
+                                            ("t", "( id", false), ("tp", "*", true),
+                                            ("f", "( id", false)].iter() {
+            let set = first.get(variable(&cfg, name));
+            assert_eq!(set.tokens, self::set(&cfg, terminals));
+            assert_eq!(set.epsilon, epsilon);
+        }
+
+        let (tp, ep) = (variable(&cfg, "tp"), variable(&cfg, "ep"));
+        let of = first.of(&[Symbol::Var(tp), Symbol::Var(ep)]);
+        assert_eq!((of.tokens, of.epsilon), (set(&cfg, "* +"), true));
+        let plus = Symbol::Tok(tokens(&cfg, "+")[0]);
+        let of = first.of(&[Symbol::Var(tp), plus, Symbol::Var(ep)]);
+        assert_eq!((of.tokens, of.epsilon), (set(&cfg, "* +"), false));
+        let empty: &[Symbol] = &[];
+        assert!(first.of(empty).epsilon);
+    }
+
+    #[test]
+    fn first_of_left_recursion() {
+        let cfg = grammar("<s> ::= <s> \"a\" | <t>\n<t> ::= <t> \"b\" | \"\"");
+        let first = cfg.first_sets();
+        let s = first.get(cfg.get_start());
+        assert_eq!((s.tokens.clone(), s.epsilon), (set(&cfg, "a b"), true));
+    }
+
+    #[test]
+    fn follow_sets() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let follow = cfg.follow_sets();
+        for &(name, terminals) in [("e", ")"), ("ep", ")"), ("t", "+ )"), ("tp", "+ )"),
+                                   ("f", "* + )")].iter() {
+            let set = follow.get(variable(&cfg, name));
+            assert_eq!(set.tokens, self::set(&cfg, terminals));
+            assert!(set.end);
+        }
+
+        let cfg = grammar("<s> ::= <a> \"x\" <s> | \"y\"\n<a> ::= <s> | \"\"");
+        let follow = cfg.follow_sets();
+        let a = follow.get(variable(&cfg, "a"));
+        assert_eq!((a.tokens.clone(), a.end), (set(&cfg, "x"), false));
+    }
+
+    fn token_strings(cfg: &Cfg<String>, strings: &[&str]) -> BTreeSet<Vec<Token>> {
+        strings.iter().map(|s| tokens(cfg, *s)).collect()
+    }
+
+    #[test]
+    fn first_k_and_follow_k() {
+        let cfg = grammar("<s> ::= \"a\" <s> \"b\" | \"\"");
+        let s = cfg.get_start();
+        let first = cfg.first_k(2);
+        assert_eq!(first.k(), 2);
+        assert_eq!(*first.get(s), token_strings(&cfg, &["", "a a", "a b"]));
+        let follow = cfg.follow_k(2);
+        assert_eq!(*follow.get(s), token_strings(&cfg, &["", "b", "b b"]));
+        let b = Symbol::Tok(tokens(&cfg, "b")[0]);
+        assert_eq!(first.of(&[Symbol::Var(s), b]), token_strings(&cfg, &["b", "a a", "a b"]));
+    }
+
+    #[test]
+    fn first_k_of_expressions() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let first = cfg.first_k(2);
+        assert_eq!(*first.get(variable(&cfg, "e")),
+                   token_strings(&cfg, &["id", "id +", "id *", "( (", "( id"]));
+        assert_eq!(*first.get(variable(&cfg, "ep")), token_strings(&cfg, &["", "+ (", "+ id"]));
+
+        // With one token of lookahead, FIRST_k and FOLLOW_k agree with FIRST and FOLLOW.
+        let (first_1, first) = (cfg.first_k(1), cfg.first_sets());
+        let (follow_1, follow) = (cfg.follow_k(1), cfg.follow_sets());
+        for i in range(0, cfg.num_variables()) {
+            let v = Variable(i as u32);
+            let set = first.get(v);
+            let mut expected: BTreeSet<Vec<Token>> = set.tokens.iter().map(|t| vec![t]).collect();
+            if set.epsilon {
+                expected.insert(vec![]);
+            }
+            assert_eq!(*first_1.get(v), expected);
+            let set = follow.get(v);
+            let mut expected: BTreeSet<Vec<Token>> = set.tokens.iter().map(|t| vec![t]).collect();
+            if set.end {
+                expected.insert(vec![]);
+            }
+            assert_eq!(*follow_1.get(v), expected);
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! Shared packed parse forests, as built by the general parsers.
+
+use std::collections::HashMap;
+
+use super::{Symbol, Variable};
+use actions::Reducer;
+use source::{ByteSpan, TokenStream};
+use tree::ParseTree;
+
+/// What a node of a parse forest stands for.
+#[deriving(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub enum Label {
+    /// A symbol derived over the span of the node.
+    Symbol(Symbol),
+    /// The first `dot` symbols of a rule, given by its index in `get_rules`.
+    Prefix(Variable, uint, uint)
+}
+
+/// A node of a parse forest, standing for every derivation of its label over a span of the input.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Node {
+    pub label: Label,
+    pub start: uint,
+    pub end: uint,
+    /// One alternative for each distinct derivation; empty for tokens.
+    pub packed: Vec<Packed>
+}
+
+/// One way of deriving the label of a node: a prefix of a rule followed by its next symbol.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Packed {
+    pub variable: Variable,
+    /// The index of the rule in `get_rules`.
+    pub rule: uint,
+    /// The input position where the last symbol begins.
+    pub pivot: uint,
+    /// The node for the symbols before the last, or `None` if there is at most one symbol.
+    pub left: Option<uint>,
+    /// The node for the last symbol, or `None` if the rule is empty.
+    pub right: Option<uint>
+}
+
+/// A shared packed parse forest: every parse of an input, with common subtrees shared.
+///
+/// The rules are binarized, so a derivation of `A ::= X Y Z` over a span has the prefix
+/// `X Y` as its left child and `Z` as its right. Nodes are referred to by their index, and the
+/// forest may be cyclic if the grammar is.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Forest {
+    pub nodes: Vec<Node>,
+    /// The node for the start symbol over the whole input.
+    pub root: uint
+}
+
+impl Forest {
+    /// The forest of the nodes reachable from `root`, renumbered in the order they're reached, so
+    /// the root is numbered 0.
+    pub fn new(nodes: Vec<Node>, root: uint) -> Forest {
+        let mut ids: HashMap<uint, uint> = HashMap::new();
+        let mut order = vec![root];
+        ids.insert(root, 0);
+        let mut k = 0;
+        while k < order.len() {
+            for packed in nodes[order[k]].packed.iter() {
+                for &child in packed.left.iter().chain(packed.right.iter()) {
+                    if !ids.contains_key(&child) {
+                        ids.insert(child, order.len());
+                        order.push(child);
+                    }
+                }
+            }
+            k += 1;
+        }
+        let renumber = |id: Option<uint>| id.map(|id| *ids.get(&id).unwrap());
+        let nodes = order.iter().map(|&id| {
+            let node = &nodes[id];
+            Node {
+                label: node.label,
+                start: node.start,
+                end: node.end,
+                packed: node.packed.iter().map(|p| Packed {
+                    left: renumber(p.left),
+                    right: renumber(p.right),
+                    ..*p
+                }).collect()
+            }
+        }).collect();
+        Forest { nodes: nodes, root: 0 }
+    }
+
+    pub fn node(&self, id: uint) -> &Node {
+        &self.nodes[id]
+    }
+
+    /// The span of text a node covers, given the tokens the forest was parsed from.
+    pub fn byte_span<P>(&self, id: uint, stream: &TokenStream<P>) -> ByteSpan {
+        let node = &self.nodes[id];
+        stream.span_of(node.start, node.end)
+    }
+
+    /// Whether some node has more than one derivation, making the input ambiguous.
+    pub fn is_ambiguous(&self) -> bool {
+        self.nodes.iter().any(|node| node.packed.len() > 1)
+    }
+
+    /// One of the parse trees in the forest.
+    ///
+    /// Every node is given the first of its derivations found to be finite, so a cyclic forest
+    /// still gives a tree.
+    pub fn tree(&self) -> ParseTree {
+        self.build(self.choices().as_slice(), self.root)
+    }
+
+    /// Build a value for one of the parse trees in the forest, the one `tree` gives, with
+    /// `reducer`, without building the tree.
+    pub fn fold<V, R: Reducer<V>>(&self, reducer: &mut R) -> V {
+        self.fold_node(self.choices().as_slice(), self.root, reducer)
+    }
+
+    /// Iterate over the distinct parse trees in the forest, finding each only when it's asked for.
+    ///
+    /// An ambiguous input can have exponentially many parses, so take only as many as are
+    /// needed. A cyclic forest has infinitely many, so trees deriving a node within itself are
+    /// skipped, leaving finitely many.
+    pub fn trees(&self) -> Trees {
+        Trees { forest: self, choices: Vec::new(), started: false }
+    }
+
+    /// The number of distinct parse trees in the forest, counted over the shared nodes without
+    /// enumerating them, or `None` if there are infinitely many.
+    ///
+    /// The count saturates at `u64::MAX`.
+    pub fn count_trees(&self) -> Option<u64> {
+        let mut counts = Vec::from_elem(self.nodes.len(), None);
+        let mut visiting = Vec::from_elem(self.nodes.len(), false);
+        self.count(self.root, counts.as_mut_slice(), visiting.as_mut_slice())
+    }
+
+    /// The number of trees for a node, or `None` if it's part of a cycle of nodes, which can be
+    /// pumped to give infinitely many.
+    fn count(&self, id: uint, counts: &mut [Option<u64>], visiting: &mut [bool]) -> Option<u64> {
+        match counts[id] {
+            Some(count) => return Some(count),
+            None if visiting[id] => return None,
+            None => { }
+        }
+        let node = &self.nodes[id];
+        if node.packed.is_empty() {
+            counts[id] = Some(1);
+            return Some(1)
+        }
+        visiting[id] = true;
+        let mut total = 0u64;
+        for packed in node.packed.iter() {
+            let mut product = 1u64;
+            for &child in packed.left.iter().chain(packed.right.iter()) {
+                match self.count(child, counts, visiting) {
+                    Some(count) => product = product.saturating_mul(count),
+                    None => return None
+                }
+            }
+            total = total.saturating_add(product);
+        }
+        visiting[id] = false;
+        counts[id] = Some(total);
+        Some(total)
+    }
+
+    /// For each node, the index of a packed alternative deriving a finite tree, found bottom-up
+    /// from the tokens.
+    fn choices(&self) -> Vec<uint> {
+        let n = self.nodes.len();
+        let mut choice = Vec::from_elem(n, 0u);
+        let mut done = Vec::from_elem(n, false);
+        let mut ready = Vec::new();
+        // The number of children not yet done for each alternative, and the alternatives each
+        // node is a child in.
+        let mut missing: Vec<Vec<uint>> = Vec::with_capacity(n);
+        let mut users: Vec<Vec<(uint, uint)>> = Vec::from_fn(n, |_| Vec::new());
+        for (id, node) in self.nodes.iter().enumerate() {
+            let mut counts = Vec::with_capacity(node.packed.len());
+            for (k, packed) in node.packed.iter().enumerate() {
+                let mut count = 0;
+                for &child in packed.left.iter().chain(packed.right.iter()) {
+                    users[child].push((id, k));
+                    count += 1;
+                }
+                if count == 0 && !done[id] {
+                    done[id] = true;
+                    choice[id] = k;
+                    ready.push(id);
+                }
+                counts.push(count);
+            }
+            missing.push(counts);
+            if node.packed.is_empty() {
+                done[id] = true;
+                ready.push(id);
+            }
+        }
+        loop {
+            let id = match ready.pop() {
+                Some(id) => id,
+                None => break
+            };
+            for &(user, k) in users[id].iter() {
+                missing[user][k] -= 1;
+                if missing[user][k] == 0 && !done[user] {
+                    done[user] = true;
+                    choice[user] = k;
+                    ready.push(user);
+                }
+            }
+        }
+        choice
+    }
+
+    fn build(&self, choice: &[uint], id: uint) -> ParseTree {
+        let node = &self.nodes[id];
+        match node.label {
+            Label::Symbol(Symbol::Tok(t)) => return ParseTree::Leaf(t),
+            _ => { }
+        }
+        let mut packed = &node.packed[choice[id]];
+        let (variable, rule) = (packed.variable, packed.rule);
+        // Walk down the prefixes, collecting the children from the last.
+        let mut children = Vec::new();
+        loop {
+            children.extend(packed.right.iter().map(|&right| self.build(choice, right)));
+            match packed.left {
+                Some(left) => packed = &self.nodes[left].packed[choice[left]],
+                None => break
+            }
+        }
+        children.reverse();
+        ParseTree::Node(variable, rule, children)
+    }
+
+    fn fold_node<V, R: Reducer<V>>(&self, choice: &[uint], id: uint, reducer: &mut R) -> V {
+        let node = &self.nodes[id];
+        match node.label {
+            Label::Symbol(Symbol::Tok(t)) => return reducer.shift(t, node.start),
+            _ => { }
+        }
+        let packed = &node.packed[choice[id]];
+        // The nodes of the symbols, found by walking down the prefixes from the last.
+        let mut symbols = Vec::new();
+        let mut prefix = packed;
+        loop {
+            symbols.extend(prefix.right.iter().map(|&right| right));
+            match prefix.left {
+                Some(left) => prefix = &self.nodes[left].packed[choice[left]],
+                None => break
+            }
+        }
+        let mut children = Vec::with_capacity(symbols.len());
+        for &child in symbols.iter().rev() {
+            children.push(self.fold_node(choice, child, reducer));
+        }
+        reducer.reduce(packed.variable, packed.rule, children)
+    }
+}
+
+/// An iterator over the parse trees of a forest.
+///
+/// A tree is given by the alternative chosen at each node it passes through, in pre-order, and
+/// the trees are found in order of these choices, backtracking from the last.
+pub struct Trees<'a> {
+    forest: &'a Forest,
+    /// The nodes passed through by the last tree tried, with the alternatives taken.
+    choices: Vec<(uint, uint)>,
+    started: bool
+}
+
+impl<'a> Iterator<ParseTree> for Trees<'a> {
+    fn next(&mut self) -> Option<ParseTree> {
+        if self.started && !self.advance() {
+            return None
+        }
+        self.started = true;
+        loop {
+            let mut k = 0;
+            match self.tree(self.forest.root, &mut k, &mut Vec::new()) {
+                Some(tree) => return Some(tree),
+                None => {
+                    self.choices.truncate(k);
+                    if !self.advance() {
+                        return None
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Trees<'a> {
+    /// Take the next alternative at the last node with any left, dropping the choices after it.
+    fn advance(&mut self) -> bool {
+        loop {
+            let (id, alternative) = match self.choices.pop() {
+                Some(choice) => choice,
+                None => return false
+            };
+            if alternative + 1 < self.forest.nodes[id].packed.len() {
+                self.choices.push((id, alternative + 1));
+                return true
+            }
+        }
+    }
+
+    /// The tree for a node following the choices from the `k`th, taking the first alternative
+    /// wherever they run out, or `None` if it would pass through a node in `path` again.
+    fn tree(&mut self, id: uint, k: &mut uint, path: &mut Vec<uint>) -> Option<ParseTree> {
+        match self.forest.nodes[id].label {
+            Label::Symbol(Symbol::Tok(t)) => return Some(ParseTree::Leaf(t)),
+            _ => { }
+        }
+        let at = *k;
+        let mut children = Vec::new();
+        if !self.children(id, k, path, &mut children) {
+            return None
+        }
+        let (_, alternative) = self.choices[at];
+        let packed = &self.forest.nodes[id].packed[alternative];
+        Some(ParseTree::Node(packed.variable, packed.rule, children))
+    }
+
+    /// Add the trees for the symbols a node derives, from the first.
+    fn children(&mut self, id: uint, k: &mut uint, path: &mut Vec<uint>,
+                out: &mut Vec<ParseTree>) -> bool {
+        if path.contains(&id) {
+            return false
+        }
+        if *k == self.choices.len() {
+            self.choices.push((id, 0));
+        }
+        let (_, alternative) = self.choices[*k];
+        *k += 1;
+        let (left, right) = {
+            let packed = &self.forest.nodes[id].packed[alternative];
+            (packed.left, packed.right)
+        };
+        path.push(id);
+        let found = match left {
+            Some(left) => self.children(left, k, path, out),
+            None => true
+        } && match right {
+            Some(right) => match self.tree(right, k, path) {
+                Some(tree) => {
+                    out.push(tree);
+                    true
+                },
+                None => false
+            },
+            None => true
+        };
+        path.pop();
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use earley;
+    use testing::{check_tree, grammar, tokens};
+    use tree::ParseTree;
+
+    #[test]
+    fn cyclic_tree() {
+        // `s` derives itself over the same span, so the forest has a cycle to keep out of the tree.
+        let cfg = grammar("<s> ::= <s> | <s> \"+\" <s> | \"x\"");
+        for text in ["x", "x + x", "x + x + x"].iter() {
+            let input = tokens(&cfg, *text);
+            let forest = earley::parse(&cfg, input.as_slice()).unwrap();
+            check_tree(&cfg, &forest.tree(), input.as_slice());
+        }
+    }
+
+    #[test]
+    fn trees() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"x\"");
+        let input = tokens(&cfg, "x + x + x + x");
+        let forest = earley::parse(&cfg, input.as_slice()).unwrap();
+        let trees: Vec<ParseTree> = forest.trees().collect();
+        assert_eq!(trees.len(), 5);
+        for (i, tree) in trees.iter().enumerate() {
+            check_tree(&cfg, tree, input.as_slice());
+            assert!(trees.slice_to(i).iter().all(|other| other != tree));
+        }
+        assert_eq!(forest.trees().take(2).count(), 2);
+
+        let cfg = grammar("<s> ::= <s> | \"x\"");
+        let input = tokens(&cfg, "x");
+        let forest = earley::parse(&cfg, input.as_slice()).unwrap();
+        let trees: Vec<ParseTree> = forest.trees().collect();
+        assert_eq!(trees.len(), 1);
+        check_tree(&cfg, &trees[0], input.as_slice());
+    }
+
+    #[test]
+    fn count_trees() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"x\"");
+        let texts = ["x", "x + x", "x + x + x", "x + x + x + x"];
+        let counts: Vec<Option<u64>> = texts.iter().map(|text| {
+            earley::parse(&cfg, tokens(&cfg, *text).as_slice()).unwrap().count_trees()
+        }).collect();
+        assert_eq!(counts, vec![Some(1), Some(1), Some(2), Some(5)]);
+
+        let cfg = grammar("<s> ::= <s> | \"x\"");
+        let forest = earley::parse(&cfg, tokens(&cfg, "x").as_slice()).unwrap();
+        assert_eq!(forest.count_trees(), None);
+    }
+}
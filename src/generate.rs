@@ -0,0 +1,519 @@
+//! Generating sentences of a grammar.
+
+use std::cmp::max;
+use std::collections::BTreeSet;
+use std::hash::Hash;
+use std::rand::Rng;
+
+use super::{Cfg, Rule, Symbol, Token, Variable};
+use tree::ParseTree;
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Generate a random sentence by expanding the start symbol with random rules, to a depth of
+    /// at most `max_depth`.
+    ///
+    /// Rules are chosen uniformly among those that can still finish within the depth left, so
+    /// recursive rules can't go on forever. Fails if no derivation is shallow enough.
+    pub fn generate<R: Rng>(&self, rng: &mut R, max_depth: uint) -> Option<Vec<Token>> {
+        self.generate_tree(rng, max_depth).map(|tree| tree.tokens())
+    }
+
+    /// Generate the parse tree of a random sentence, as `generate` does.
+    pub fn generate_tree<R: Rng>(&self, rng: &mut R, max_depth: uint) -> Option<ParseTree> {
+        let heights = self.heights();
+        let start = self.get_start();
+        match heights[start.index()] {
+            Some(height) if height <= max_depth => { }
+            _ => return None
+        }
+        Some(expand(self, heights.as_slice(), rng, start, max_depth))
+    }
+
+    /// The height of the shallowest parse tree of each variable, by index, counting a variable
+    /// expanded only into tokens as 1, or `None` if it doesn't derive any string of tokens.
+    fn heights(&self) -> Vec<Option<uint>> {
+        let n = self.num_variables();
+        let mut heights: Vec<Option<uint>> = Vec::from_elem(n, None);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                let best = rules(self, Variable(v as u32)).iter()
+                    .filter_map(|rule| height(heights.as_slice(), rule.as_slice())).min();
+                if best.is_some() && (heights[v].is_none() || best < heights[v]) {
+                    heights[v] = best;
+                    changed = true;
+                }
+            }
+        }
+        heights
+    }
+}
+
+/// Draws sentences of a given length at random, each with probability proportional to its number
+/// of parse trees.
+///
+/// Expanding rules at random favours short sentences, or gives long ones that are mostly
+/// recursion. Here the parse trees of each variable are counted for each length, so that rules
+/// and the lengths of their parts can be chosen by how many trees they lead to. The trees are
+/// those of an equivalent grammar with no empty or unit rules, so the sampling is uniform among
+/// sentences whenever that grammar is unambiguous. Counts are kept as floating point numbers,
+/// since they grow exponentially.
+pub struct Sampler<T> {
+    cfg: Cfg<T>,
+    /// Whether the grammar derives the empty string.
+    empty: bool,
+    max_len: uint,
+    /// The number of trees of each variable for each length.
+    counts: Vec<Vec<f64>>,
+    /// For each rule of each variable, the number of ways the symbols from each position of its
+    /// body derive each length, indexed by position times `max_len + 1` plus length.
+    suffixes: Vec<Vec<Vec<f64>>>
+}
+
+impl<T: Eq + Hash + Clone> Sampler<T> {
+    /// Count the trees of the grammar for every length up to `max_len`.
+    pub fn new(cfg: &Cfg<T>, max_len: uint) -> Sampler<T> {
+        let empty = cfg.nullable().contains(&cfg.get_start());
+        let cfg = cfg.eliminate_epsilon().eliminate_unit_rules();
+        let n = cfg.num_variables();
+        let width = max_len + 1;
+        let suffixes = range(0, n).map(|v| rules(&cfg, Variable(v as u32)).iter().map(|rule| {
+            let mut table = Vec::from_elem((rule.len() + 1) * width, 0.0f64);
+            table[rule.len() * width] = 1.0;
+            table
+        }).collect()).collect();
+        let mut sampler = Sampler {
+            cfg: cfg,
+            empty: empty,
+            max_len: max_len,
+            counts: Vec::from_fn(n, |_| Vec::from_elem(width, 0.0f64)),
+            suffixes: suffixes
+        };
+        // No symbol derives the empty string, so the counts for a length depend only on those
+        // for shorter lengths, except for the last symbol of a rule, which is counted after.
+        for len in range(1, width) {
+            for v in range(0, n) {
+                let mut count = 0.0;
+                for r in range(0, sampler.suffixes[v].len()) {
+                    count += sampler.fill(v, r, 0, len);
+                }
+                sampler.counts[v][len] = count;
+            }
+            for v in range(0, n) {
+                for r in range(0, sampler.suffixes[v].len()) {
+                    let body = rules(&sampler.cfg, Variable(v as u32))[r].len();
+                    for i in range(1, body).rev() {
+                        sampler.fill(v, r, i, len);
+                    }
+                }
+            }
+        }
+        sampler
+    }
+
+    /// The number of parse trees for sentences of `len` tokens, or 0 if `len` is more than the
+    /// sampler was built for.
+    pub fn count(&self, len: uint) -> f64 {
+        if len == 0 {
+            return if self.empty { 1.0 } else { 0.0 }
+        }
+        if len > self.max_len {
+            return 0.0
+        }
+        self.counts[self.cfg.get_start().index()][len]
+    }
+
+    /// Draw a sentence of `len` tokens, or `None` if there is none.
+    pub fn sample<R: Rng>(&self, rng: &mut R, len: uint) -> Option<Vec<Token>> {
+        if self.count(len) == 0.0 {
+            return None
+        }
+        let mut sentence = Vec::with_capacity(len);
+        if len > 0 {
+            self.expand(rng, self.cfg.get_start(), len, &mut sentence);
+        }
+        Some(sentence)
+    }
+
+    /// Count the ways the symbols of a rule from position `i` derive `len` tokens.
+    fn fill(&mut self, v: uint, r: uint, i: uint, len: uint) -> f64 {
+        let width = self.max_len + 1;
+        let symbol = rules(&self.cfg, Variable(v as u32))[r][i];
+        let mut ways = 0.0;
+        for first in range(1, len + 1) {
+            ways += self.weight(symbol, first) * self.suffixes[v][r][(i + 1) * width + len - first];
+        }
+        self.suffixes[v][r][i * width + len] = ways;
+        ways
+    }
+
+    /// The number of trees of a symbol deriving `len` tokens.
+    fn weight(&self, symbol: Symbol, len: uint) -> f64 {
+        match symbol {
+            Symbol::Tok(_) => if len == 1 { 1.0 } else { 0.0 },
+            Symbol::Var(w) => self.counts[w.index()][len]
+        }
+    }
+
+    fn expand<R: Rng>(&self, rng: &mut R, variable: Variable, len: uint, out: &mut Vec<Token>) {
+        let width = self.max_len + 1;
+        let v = variable.index();
+        let tables = self.suffixes[v].as_slice();
+        let r = choose(rng, range(0, tables.len()).map(|r| tables[r][len]).collect());
+        let body = rules(&self.cfg, variable)[r].as_slice();
+        let mut left = len;
+        for (i, &symbol) in body.iter().enumerate() {
+            let first = 1 + choose(rng, range(1, left + 1).map(|first| {
+                self.weight(symbol, first) * tables[r][(i + 1) * width + left - first]
+            }).collect());
+            match symbol {
+                Symbol::Tok(t) => out.push(t),
+                Symbol::Var(w) => self.expand(rng, w, first, out)
+            }
+            left -= first;
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Draw a sentence of `len` tokens at random, as a `Sampler` does, or `None` if there is
+    /// none.
+    ///
+    /// This counts the trees of the grammar afresh each time; to draw many sentences, build a
+    /// `Sampler` once.
+    pub fn sample<R: Rng>(&self, rng: &mut R, len: uint) -> Option<Vec<Token>> {
+        Sampler::new(self, len).sample(rng, len)
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Iterate over every sentence of at most `max_len` tokens, shortest first, and in order of
+    /// their tokens among those of the same length.
+    ///
+    /// The sentences of each length are found as they're reached, from those of every variable
+    /// for the shorter lengths.
+    pub fn sentences(&self, max_len: uint) -> Sentences<T> {
+        let cfg = self.eliminate_epsilon().eliminate_unit_rules();
+        let n = cfg.num_variables();
+        let mut current = Vec::new();
+        if self.nullable().contains(&self.get_start()) {
+            current.push(Vec::new());
+        }
+        Sentences {
+            cfg: cfg,
+            max_len: max_len,
+            len: 0,
+            by_len: Vec::from_fn(n, |_| vec![BTreeSet::new()]),
+            current: current
+        }
+    }
+}
+
+/// An iterator over the sentences of a grammar up to a length.
+pub struct Sentences<T> {
+    /// An equivalent grammar with no empty or unit rules, apart from the empty string.
+    cfg: Cfg<T>,
+    max_len: uint,
+    /// The length of the sentences in `current`.
+    len: uint,
+    /// The sentences each variable derives of each length found so far.
+    by_len: Vec<Vec<BTreeSet<Vec<Token>>>>,
+    /// The sentences of the current length yet to be given, last first.
+    current: Vec<Vec<Token>>
+}
+
+impl<T: Eq + Hash + Clone> Iterator<Vec<Token>> for Sentences<T> {
+    fn next(&mut self) -> Option<Vec<Token>> {
+        loop {
+            match self.current.pop() {
+                Some(sentence) => return Some(sentence),
+                None => { }
+            }
+            if self.len == self.max_len {
+                return None
+            }
+            self.len += 1;
+            let len = self.len;
+            // Every rule body has at least two symbols or is a single token, so each part of a
+            // sentence is shorter, and its sentences are all known already.
+            for v in range(0, self.cfg.num_variables()) {
+                let mut found = BTreeSet::new();
+                for rule in rules(&self.cfg, Variable(v as u32)).iter() {
+                    found.extend(self.concatenations(rule.as_slice(), len).into_iter());
+                }
+                self.by_len[v].push(found);
+            }
+            let start = self.cfg.get_start().index();
+            self.current = self.by_len[start][len].iter().rev().map(|s| s.clone()).collect();
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Sentences<T> {
+    /// Every sentence of `len` tokens that `body` derives.
+    fn concatenations(&self, body: &[Symbol], len: uint) -> Vec<Vec<Token>> {
+        if body.is_empty() {
+            return if len == 0 { vec![Vec::new()] } else { Vec::new() }
+        }
+        let mut found = Vec::new();
+        if body.len() > len {
+            return found
+        }
+        for first in range(1, len + 2 - body.len()) {
+            let heads = match body[0] {
+                Symbol::Tok(t) if first == 1 => vec![vec![t]],
+                Symbol::Tok(_) => continue,
+                Symbol::Var(w) => self.by_len[w.index()][first].iter().map(|s| s.clone()).collect()
+            };
+            let tails = self.concatenations(body.slice_from(1), len - first);
+            for head in heads.iter() {
+                for tail in tails.iter() {
+                    let mut sentence = head.clone();
+                    sentence.push_all(tail.as_slice());
+                    found.push(sentence);
+                }
+            }
+        }
+        found
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Compare the sentences of at most `max_len` tokens of this grammar and `other`, matching
+    /// tokens by terminal, and give the shortest sentence found in only one of them, if any.
+    ///
+    /// Equivalence of the languages of two grammars can't be decided in general, but this finds
+    /// where a transformation that should preserve the language went wrong, up to a length.
+    pub fn equiv_up_to(&self, other: &Cfg<T>, max_len: uint) -> Option<Vec<T>> {
+        let ours: BTreeSet<Vec<Token>> = self.sentences(max_len).collect();
+        let mut theirs = BTreeSet::new();
+        // The shortest sentence of `other` using a terminal this grammar doesn't have.
+        let mut foreign: Option<Vec<T>> = None;
+        for sentence in other.sentences(max_len) {
+            let terminals: Vec<T> = sentence.iter().map(|&t| {
+                other.terminal(t).unwrap().clone()
+            }).collect();
+            match self.tokenize(terminals.as_slice()) {
+                Some(tokens) => { theirs.insert(tokens); },
+                None => if foreign.is_none() { foreign = Some(terminals) }
+            }
+        }
+        let mut shortest: Option<&Vec<Token>> = None;
+        for sentence in ours.symmetric_difference(&theirs) {
+            if shortest.map_or(true, |s| sentence.len() < s.len()) {
+                shortest = Some(sentence);
+            }
+        }
+        match (shortest, foreign) {
+            (Some(s), Some(f)) if f.len() < s.len() => Some(f),
+            (Some(s), _) => Some(s.iter().map(|&t| self.terminal(t).unwrap().clone()).collect()),
+            (None, f) => f
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// The number of sentences of exactly `len` tokens.
+    ///
+    /// Sentences are counted once for each of their parse trees in an equivalent grammar with no
+    /// empty or unit rules, so the count is exact when that grammar is unambiguous, and counts
+    /// derivations otherwise. The count saturates at `u64::MAX`.
+    pub fn count_sentences(&self, len: uint) -> u64 {
+        if len == 0 {
+            return if self.nullable().contains(&self.get_start()) { 1 } else { 0 }
+        }
+        let cfg = self.eliminate_epsilon().eliminate_unit_rules();
+        let n = cfg.num_variables();
+        let mut counts: Vec<Vec<u64>> = Vec::from_fn(n, |_| Vec::from_elem(len + 1, 0u64));
+        for l in range(1, len + 1) {
+            for v in range(0, n) {
+                let mut count = 0u64;
+                for rule in rules(&cfg, Variable(v as u32)).iter() {
+                    count = count.saturating_add(ways(counts.as_slice(), rule.as_slice(), l));
+                }
+                counts[v][l] = count;
+            }
+        }
+        counts[cfg.get_start().index()][len]
+    }
+}
+
+/// The number of ways `body` derives `len` tokens, given the counts of each variable for shorter
+/// lengths.
+///
+/// Every symbol derives at least one token, so a variable only needs to be counted for `len` if
+/// it makes up the whole body, which can't happen without unit rules.
+fn ways(counts: &[Vec<u64>], body: &[Symbol], len: uint) -> u64 {
+    // The ways the symbols after a position derive each length, from the end of the body.
+    let mut suffix = Vec::from_elem(len + 1, 0u64);
+    suffix[0] = 1;
+    for &symbol in body.iter().rev() {
+        let mut next = Vec::from_elem(len + 1, 0u64);
+        for m in range(1, len + 1) {
+            let mut total = 0u64;
+            for first in range(1, m + 1) {
+                let weight = match symbol {
+                    Symbol::Tok(_) => if first == 1 { 1 } else { 0 },
+                    Symbol::Var(w) => counts[w.index()][first]
+                };
+                total = total.saturating_add(weight.saturating_mul(suffix[m - first]));
+            }
+            next[m] = total;
+        }
+        suffix = next;
+    }
+    suffix[len]
+}
+
+/// Choose an index at random, with probability proportional to its weight.
+fn choose<R: Rng>(rng: &mut R, weights: Vec<f64>) -> uint {
+    let total = weights.iter().fold(0.0, |sum, &w| sum + w);
+    let mut target = rng.gen::<f64>() * total;
+    for (i, &w) in weights.iter().enumerate() {
+        if target < w {
+            return i
+        }
+        target -= w;
+    }
+    // Rounding can leave a little over; take the last choice with any weight.
+    weights.iter().rposition(|&w| w > 0.0).unwrap()
+}
+
+/// Expand `variable` by a random rule whose tree can be at most `depth` high.
+fn expand<T: Eq + Hash + Clone, R: Rng>(cfg: &Cfg<T>, heights: &[Option<uint>], rng: &mut R,
+                                        variable: Variable, depth: uint) -> ParseTree {
+    let rules = rules(cfg, variable);
+    let fitting: Vec<uint> = range(0, rules.len()).filter(|&i| {
+        height(heights, rules[i].as_slice()).map_or(false, |h| h <= depth)
+    }).collect();
+    let rule = fitting[rng.gen_range(0, fitting.len())];
+    let children = rules[rule].iter().map(|&symbol| match symbol {
+        Symbol::Tok(t) => ParseTree::Leaf(t),
+        Symbol::Var(w) => expand(cfg, heights, rng, w, depth - 1)
+    }).collect();
+    ParseTree::Node(variable, rule, children)
+}
+
+/// The height of the shallowest parse tree using `rule` at its root.
+fn height(heights: &[Option<uint>], rule: &[Symbol]) -> Option<uint> {
+    let mut height = 1;
+    for &symbol in rule.iter() {
+        match symbol {
+            Symbol::Var(w) => match heights[w.index()] {
+                Some(h) => height = max(height, h + 1),
+                None => return None
+            },
+            Symbol::Tok(_) => { }
+        }
+    }
+    Some(height)
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::rand::{SeedableRng, XorShiftRng};
+
+    use super::Sampler;
+    use super::super::Token;
+    use earley;
+    use testing::{EXPRESSIONS, EXPRESSIONS_LL1, SMALL, check_tree, grammar, sentences, tokens};
+
+    fn rng() -> XorShiftRng {
+        SeedableRng::from_seed([1, 2, 3, 4])
+    }
+
+    #[test]
+    fn generate() {
+        let cfg = grammar(EXPRESSIONS);
+        let mut rng = rng();
+        for _ in range(0, 100u) {
+            let sentence = cfg.generate(&mut rng, 6).unwrap();
+            assert!(earley::recognize(&cfg, sentence.as_slice()));
+            let tree = cfg.generate_tree(&mut rng, 6).unwrap();
+            check_tree(&cfg, &tree, tree.tokens().as_slice());
+        }
+        // `e` needs `t` and `f` below it before it reaches a token.
+        assert!(cfg.generate(&mut rng, 2).is_none());
+        assert!(cfg.generate(&mut rng, 3).is_some());
+        assert!(grammar("<s> ::= <s> \"x\"").generate(&mut rng, 10).is_none());
+    }
+
+    /// Balanced parentheses, unambiguously.
+    const DYCK: &'static str = "<s> ::= \"(\" <s> \")\" <s> | \"\"";
+
+    #[test]
+    fn sample() {
+        let cfg = grammar(DYCK);
+        let sampler = Sampler::new(&cfg, 8);
+        let counts: Vec<f64> = range(0, 10u).map(|len| sampler.count(len)).collect();
+        assert_eq!(counts, vec![1.0, 0.0, 1.0, 0.0, 2.0, 0.0, 5.0, 0.0, 14.0, 0.0]);
+
+        let mut rng = rng();
+        let mut seen = Vec::new();
+        for _ in range(0, 200u) {
+            let sentence = sampler.sample(&mut rng, 6).unwrap();
+            assert_eq!(sentence.len(), 6);
+            assert!(earley::recognize(&cfg, sentence.as_slice()));
+            if !seen.contains(&sentence) {
+                seen.push(sentence);
+            }
+        }
+        assert_eq!(seen.len(), 5);
+        assert!(sampler.sample(&mut rng, 5).is_none());
+        assert!(sampler.sample(&mut rng, 10).is_none());
+        assert_eq!(cfg.sample(&mut rng, 0), Some(Vec::new()));
+        assert_eq!(cfg.sample(&mut rng, 2), Some(tokens(&cfg, "( )")));
+    }
+
+    #[test]
+    fn sentences_in_order() {
+        let cfg = grammar(EXPRESSIONS);
+        let found: Vec<Vec<Token>> = cfg.sentences(3).collect();
+        assert_eq!(found, vec![tokens(&cfg, "id"), tokens(&cfg, "( id )"), tokens(&cfg, "id + id"),
+                               tokens(&cfg, "id * id")]);
+
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let found: Vec<Vec<Token>> = cfg.sentences(4).collect();
+            let terminals: BTreeSet<Vec<String>> = found.iter().map(|sentence| {
+                sentence.iter().map(|&t| cfg.terminal(t).unwrap().clone()).collect()
+            }).collect();
+            assert_eq!(terminals.len(), found.len());
+            assert_eq!(terminals, sentences(&cfg, 4));
+            for (i, sentence) in found.iter().enumerate().skip(1) {
+                let previous = &found[i - 1];
+                assert!(previous.len() < sentence.len() ||
+                        (previous.len() == sentence.len() && previous < sentence));
+            }
+        }
+    }
+
+    #[test]
+    fn count_sentences() {
+        let cfg = grammar(DYCK);
+        let counts: Vec<u64> = range(0, 9u).map(|len| cfg.count_sentences(len)).collect();
+        assert_eq!(counts, vec![1, 0, 1, 0, 2, 0, 5, 0, 14]);
+        let cfg = grammar(EXPRESSIONS);
+        for len in range(0, 6u) {
+            let found = cfg.sentences(len).filter(|sentence| sentence.len() == len).count();
+            assert_eq!(cfg.count_sentences(len), found as u64);
+        }
+    }
+
+    #[test]
+    fn equiv_up_to() {
+        let cfg = grammar(EXPRESSIONS);
+        assert_eq!(cfg.equiv_up_to(&grammar(EXPRESSIONS_LL1), 5), None);
+        let flat = grammar("<e> ::= <e> \"+\" <e> | <e> \"*\" <e> | \"id\"");
+        let parens = vec!["(".to_string(), "id".to_string(), ")".to_string()];
+        assert_eq!(cfg.equiv_up_to(&flat, 5), Some(parens.clone()));
+        assert_eq!(flat.equiv_up_to(&cfg, 5), Some(parens));
+        assert_eq!(flat.equiv_up_to(&cfg, 2), None);
+    }
+}
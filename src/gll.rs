@@ -0,0 +1,265 @@
+//! Generalized LL parsing, for any grammar.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use super::{Cfg, Rule, Symbol, Token, Variable};
+use forest::{Forest, Label, Node, Packed};
+
+/// Whether the grammar derives `input`.
+pub fn recognize<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> bool {
+    parse(cfg, input).is_some()
+}
+
+/// Parse `input`, giving the forest of all its parses, or `None` if the grammar doesn't derive it.
+///
+/// This is the GLL algorithm of Scott and Johnstone, interpreting the grammar directly: each
+/// rule is followed like a recursive descent parser would, but the calls are kept on a
+/// graph-structured stack so that every alternative can be tried, and left recursion loops back
+/// to an existing call instead of going on forever.
+pub fn parse<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> Option<Forest> {
+    let mut parse = Parse {
+        cfg: cfg,
+        input: input,
+        stack: Vec::new(),
+        calls: HashMap::new(),
+        edges: Vec::new(),
+        popped: Vec::new(),
+        pending: Vec::new(),
+        seen: HashSet::new(),
+        nodes: Vec::new(),
+        ids: HashMap::new()
+    };
+    let root = parse.call(None, 0);
+    let start = cfg.get_start();
+    for rule in range(0, rules(cfg, start).len()) {
+        parse.add(Slot { variable: start, rule: rule, dot: 0 }, root, 0, None);
+    }
+    loop {
+        let (slot, call, position, node) = match parse.pending.pop() {
+            Some(descriptor) => descriptor,
+            None => break
+        };
+        parse.process(slot, call, position, node);
+    }
+    let root = parse.ids.get(&(Label::Symbol(Symbol::Var(start)), 0, input.len())).map(|&r| r);
+    root.map(|root| Forest::new(parse.nodes, root))
+}
+
+/// A position in a rule.
+#[deriving(Copy, Clone, PartialEq, Eq, Hash, Show)]
+struct Slot {
+    variable: Variable,
+    rule: uint,
+    dot: uint
+}
+
+/// What to do next: carry on from a slot, having been called from a stack node, at an input
+/// position, with the forest node for what the rule has recognized so far.
+type Descriptor = (Slot, uint, uint, Option<uint>);
+
+struct Parse<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    input: &'a [Token],
+    /// The slot to return to and the input position of each call on the stack; the bottom of
+    /// the stack has no slot.
+    stack: Vec<(Option<Slot>, uint)>,
+    calls: HashMap<(Option<Slot>, uint), uint>,
+    /// The edges from each stack node to the callers, with the forest node for what the caller
+    /// had recognized.
+    edges: Vec<Vec<(uint, Option<uint>)>>,
+    /// The forest nodes for what each call has recognized so far.
+    popped: Vec<Vec<uint>>,
+    pending: Vec<Descriptor>,
+    seen: HashSet<Descriptor>,
+    nodes: Vec<Node>,
+    ids: HashMap<(Label, uint, uint), uint>
+}
+
+impl<'a, T: Eq + Hash + Clone> Parse<'a, T> {
+    fn add(&mut self, slot: Slot, call: uint, position: uint, node: Option<uint>) {
+        let descriptor = (slot, call, position, node);
+        if self.seen.insert(descriptor) {
+            self.pending.push(descriptor);
+        }
+    }
+
+    /// Follow a rule from a slot until it calls a variable, fails to match a token or ends.
+    fn process(&mut self, slot: Slot, call: uint, mut position: uint, mut node: Option<uint>) {
+        let body = rules(self.cfg, slot.variable)[slot.rule].clone();
+        let mut dot = slot.dot;
+        loop {
+            if dot == body.len() {
+                let node = match node {
+                    Some(node) => node,
+                    None => {
+                        let empty = self.node(Label::Symbol(Symbol::Var(slot.variable)),
+                                              position, position);
+                        self.pack(empty, Packed {
+                            variable: slot.variable,
+                            rule: slot.rule,
+                            pivot: position,
+                            left: None,
+                            right: None
+                        });
+                        empty
+                    }
+                };
+                self.pop(call, position, node);
+                return
+            }
+            let next = Slot { dot: dot + 1, ..slot };
+            match body[dot] {
+                Symbol::Tok(t) => {
+                    if position >= self.input.len() || self.input[position] != t {
+                        return
+                    }
+                    let leaf = self.node(Label::Symbol(Symbol::Tok(t)), position, position + 1);
+                    node = Some(self.extend(next, node, leaf));
+                    position += 1;
+                    dot += 1;
+                },
+                Symbol::Var(v) => {
+                    let callee = self.create(next, call, position, node);
+                    for rule in range(0, rules(self.cfg, v).len()) {
+                        self.add(Slot { variable: v, rule: rule, dot: 0 }, callee, position, None);
+                    }
+                    return
+                }
+            }
+        }
+    }
+
+    /// The stack node for a call returning to `slot` at an input position, adding an edge to
+    /// the caller, and returning at once with whatever the call already recognized.
+    fn create(&mut self, slot: Slot, caller: uint, position: uint, node: Option<uint>) -> uint {
+        let callee = self.call(Some(slot), position);
+        if !self.edges[callee].contains(&(caller, node)) {
+            self.edges[callee].push((caller, node));
+            let popped = self.popped[callee].clone();
+            for &z in popped.iter() {
+                let end = self.nodes[z].end;
+                let extended = self.extend(slot, node, z);
+                self.add(slot, caller, end, Some(extended));
+            }
+        }
+        callee
+    }
+
+    /// Return from a call having recognized `node`, ending at `position`.
+    fn pop(&mut self, call: uint, position: uint, node: uint) {
+        let slot = match self.stack[call] {
+            (Some(slot), _) => slot,
+            (None, _) => return
+        };
+        if self.popped[call].contains(&node) {
+            return
+        }
+        self.popped[call].push(node);
+        let edges = self.edges[call].clone();
+        for &(caller, left) in edges.iter() {
+            let extended = self.extend(slot, left, node);
+            self.add(slot, caller, position, Some(extended));
+        }
+    }
+
+    fn call(&mut self, slot: Option<Slot>, position: uint) -> uint {
+        match self.calls.get(&(slot, position)) {
+            Some(&call) => return call,
+            None => { }
+        }
+        let call = self.stack.len();
+        self.stack.push((slot, position));
+        self.edges.push(Vec::new());
+        self.popped.push(Vec::new());
+        self.calls.insert((slot, position), call);
+        call
+    }
+
+    /// The forest node for a rule recognized up to `slot`, from the node for the symbols before
+    /// the last one, if any, and the node for the last one.
+    fn extend(&mut self, slot: Slot, left: Option<uint>, right: uint) -> uint {
+        let complete = slot.dot == rules(self.cfg, slot.variable)[slot.rule].len();
+        let label = if complete {
+            Label::Symbol(Symbol::Var(slot.variable))
+        } else {
+            Label::Prefix(slot.variable, slot.rule, slot.dot)
+        };
+        let start = self.nodes[left.unwrap_or(right)].start;
+        let (pivot, end) = (self.nodes[right].start, self.nodes[right].end);
+        let node = self.node(label, start, end);
+        self.pack(node, Packed {
+            variable: slot.variable,
+            rule: slot.rule,
+            pivot: pivot,
+            left: left,
+            right: Some(right)
+        });
+        node
+    }
+
+    /// The forest node for a label over a span, added if it's new.
+    fn node(&mut self, label: Label, start: uint, end: uint) -> uint {
+        match self.ids.get(&(label, start, end)) {
+            Some(&id) => return id,
+            None => { }
+        }
+        let id = self.nodes.len();
+        self.nodes.push(Node { label: label, start: start, end: end, packed: Vec::new() });
+        self.ids.insert((label, start, end), id);
+        id
+    }
+
+    fn pack(&mut self, node: uint, packed: Packed) {
+        if !self.nodes[node].packed.contains(&packed) {
+            self.nodes[node].packed.push(packed);
+        }
+    }
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, recognize};
+    use earley;
+    use testing::{SMALL, check_tree, grammar, strings, tokens};
+
+    #[test]
+    fn agrees_with_earley() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            for input in strings(&cfg, 4).iter() {
+                let recognized = earley::recognize(&cfg, input.as_slice());
+                assert_eq!(recognize(&cfg, input.as_slice()), recognized);
+                assert_eq!(parse(&cfg, input.as_slice()).is_some(), recognized);
+            }
+        }
+    }
+
+    #[test]
+    fn ambiguity() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"id\"");
+        let forest = parse(&cfg, tokens(&cfg, "id + id").as_slice()).unwrap();
+        assert!(!forest.is_ambiguous());
+        let root = forest.node(forest.root);
+        assert_eq!((root.start, root.end), (0, 3));
+        let forest = parse(&cfg, tokens(&cfg, "id + id + id").as_slice()).unwrap();
+        assert!(forest.is_ambiguous());
+        assert!(parse(&cfg, tokens(&cfg, "id + + id").as_slice()).is_none());
+    }
+
+    #[test]
+    fn counts_parses() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | <e> \"*\" <e> | \"id\"");
+        for text in ["id", "id + id", "id + id * id", "id * id + id * id"].iter() {
+            let input = tokens(&cfg, *text);
+            let forest = parse(&cfg, input.as_slice()).unwrap();
+            check_tree(&cfg, &forest.tree(), input.as_slice());
+            assert_eq!(forest.count_trees(), earley::count_parses(&cfg, input.as_slice()));
+        }
+    }
+}
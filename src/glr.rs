@@ -0,0 +1,300 @@
+//! Generalized LR parsing, for any grammar.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::{Cfg, Rule, Symbol, Token, Variable};
+use forest::{Forest, Label, Node, Packed};
+use lr::{Action, GlrTable};
+use source::{TokenSource, TokenStream};
+
+/// A Tomita-style GLR parser for a grammar, holding its table.
+///
+/// The parser follows every action in conflict, sharing what the alternatives have in common on
+/// a graph-structured stack, and gives every parse of the input as a forest.
+pub struct Glr<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    table: GlrTable
+}
+
+impl<'a, T: Eq + Hash + Clone> Glr<'a, T> {
+    pub fn new(cfg: &'a Cfg<T>) -> Glr<'a, T> {
+        Glr { cfg: cfg, table: cfg.glr_table() }
+    }
+
+    pub fn table(&self) -> &GlrTable {
+        &self.table
+    }
+
+    /// Whether the grammar derives `input`.
+    pub fn recognize(&self, input: &[Token]) -> bool {
+        self.parse(input).is_some()
+    }
+
+    /// Parse `input`, giving the forest of all its parses, or `None` if the grammar doesn't
+    /// derive it.
+    pub fn parse(&self, input: &[Token]) -> Option<Forest> {
+        let mut parse = Parse {
+            glr: self,
+            stack: Vec::new(),
+            edges: Vec::new(),
+            nodes: Vec::new(),
+            ids: HashMap::new()
+        };
+        let mut frontier = vec![parse.push(0, 0)];
+        for i in range(0, input.len() + 1) {
+            frontier = parse.reduce(frontier, i, input.get(i).map(|&t| t));
+            if i == input.len() {
+                break
+            }
+
+            let leaf = parse.node(Label::Symbol(Symbol::Tok(input[i])), i, i + 1);
+            let mut next: HashMap<uint, uint> = HashMap::new();
+            for &v in frontier.iter() {
+                let (state, _) = parse.stack[v];
+                for &action in self.table.actions(state, Some(input[i])).iter() {
+                    let target = match action {
+                        Action::Shift(target) => target,
+                        _ => continue
+                    };
+                    let existing = next.get(&target).map(|&w| w);
+                    let w = match existing {
+                        Some(w) => w,
+                        None => {
+                            let w = parse.push(target, i + 1);
+                            next.insert(target, w);
+                            w
+                        }
+                    };
+                    parse.link(w, v, leaf);
+                }
+            }
+            if next.is_empty() {
+                return None
+            }
+            frontier = next.into_iter().map(|(_, w)| w).collect();
+            frontier.sort();
+        }
+
+        let accepted = frontier.iter().any(|&v| {
+            let (state, _) = parse.stack[v];
+            self.table.actions(state, None).contains(&Action::Accept)
+        });
+        let root = parse.ids.get(&(Label::Symbol(Symbol::Var(self.cfg.get_start())), 0,
+                                   input.len())).map(|&root| root);
+        match root {
+            Some(root) if accepted => Some(Forest::new(parse.nodes, root)),
+            _ => None
+        }
+    }
+
+    /// Parse the tokens read from a lexer, giving the forest of all their parses. To refer to
+    /// their spans and payloads from the forest, read them with `TokenStream::read` and parse its
+    /// `tokens` instead.
+    pub fn parse_source<P, S: TokenSource<P>>(&self, source: &mut S) -> Option<Forest> {
+        self.parse(TokenStream::read(source).tokens.as_slice())
+    }
+}
+
+/// Whether the grammar derives `input`.
+pub fn recognize<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> bool {
+    Glr::new(cfg).recognize(input)
+}
+
+/// Parse `input`, giving the forest of all its parses, or `None` if the grammar doesn't derive it.
+pub fn parse<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> Option<Forest> {
+    Glr::new(cfg).parse(input)
+}
+
+struct Parse<'a, 'b, T: 'a> {
+    glr: &'b Glr<'a, T>,
+    /// The state and input position of each node of the stack.
+    stack: Vec<(uint, uint)>,
+    /// The edges from each node of the stack to those below it, with the node of the forest for
+    /// the symbol between them.
+    edges: Vec<Vec<(uint, uint)>>,
+    nodes: Vec<Node>,
+    ids: HashMap<(Label, uint, uint), uint>
+}
+
+impl<'a, 'b, T: Eq + Hash + Clone> Parse<'a, 'b, T> {
+    fn push(&mut self, state: uint, position: uint) -> uint {
+        self.stack.push((state, position));
+        self.edges.push(Vec::new());
+        self.stack.len() - 1
+    }
+
+    /// Add an edge from `from` down to `to`, returning whether it's new.
+    fn link(&mut self, from: uint, to: uint, node: uint) -> bool {
+        if self.edges[from].iter().any(|&(v, _)| v == to) {
+            return false
+        }
+        self.edges[from].push((to, node));
+        true
+    }
+
+    /// Do every reduction possible on the stack nodes at `position`, giving them along with
+    /// those added.
+    ///
+    /// A new edge to an existing node gives new paths to reduce along for the nodes above it, so
+    /// the reductions are done again until no edge is added.
+    fn reduce(&mut self, mut frontier: Vec<uint>, position: uint, lookahead: Option<Token>)
+              -> Vec<uint> {
+        let mut by_state: HashMap<uint, uint> = frontier.iter().map(|&v| {
+            let (state, _) = self.stack[v];
+            (state, v)
+        }).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let mut k = 0;
+            while k < frontier.len() {
+                let v = frontier[k];
+                k += 1;
+                let (state, _) = self.stack[v];
+                let actions = self.glr.table.actions(state, lookahead).to_vec();
+                for &action in actions.iter() {
+                    let (variable, rule) = match action {
+                        Action::Reduce(variable, rule) => (variable, rule),
+                        _ => continue
+                    };
+                    let len = rules(self.glr.cfg, variable)[rule].len();
+                    for (u, children) in self.paths(v, len).into_iter() {
+                        let (below, start) = self.stack[u];
+                        let node = self.node(Label::Symbol(Symbol::Var(variable)), start,
+                                             position);
+                        self.pack(node, variable, rule, children.as_slice());
+                        let target = match self.glr.table.goto(below, variable) {
+                            Some(target) => target,
+                            None => continue
+                        };
+                        let existing = by_state.get(&target).map(|&w| w);
+                        match existing {
+                            Some(w) => {
+                                if self.link(w, u, node) {
+                                    changed = true;
+                                }
+                            },
+                            None => {
+                                let w = self.push(target, position);
+                                self.link(w, u, node);
+                                by_state.insert(target, w);
+                                frontier.push(w);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        frontier
+    }
+
+    /// Every path of `len` edges down from a stack node, as the node reached and the forest
+    /// nodes along the way, from the bottom.
+    fn paths(&self, from: uint, len: uint) -> Vec<(uint, Vec<uint>)> {
+        if len == 0 {
+            return vec![(from, Vec::new())]
+        }
+        let mut paths = Vec::new();
+        for &(to, node) in self.edges[from].iter() {
+            for (bottom, mut nodes) in self.paths(to, len - 1).into_iter() {
+                nodes.push(node);
+                paths.push((bottom, nodes));
+            }
+        }
+        paths
+    }
+
+    /// The forest node for a label over a span, added if it's new.
+    fn node(&mut self, label: Label, start: uint, end: uint) -> uint {
+        match self.ids.get(&(label, start, end)) {
+            Some(&id) => return id,
+            None => { }
+        }
+        let id = self.nodes.len();
+        self.nodes.push(Node { label: label, start: start, end: end, packed: Vec::new() });
+        self.ids.insert((label, start, end), id);
+        id
+    }
+
+    /// Record a derivation of `node` by a rule, with the forest nodes for the symbols of its
+    /// body, binarizing it through a node for each of its prefixes.
+    fn pack(&mut self, node: uint, variable: Variable, rule: uint, children: &[uint]) {
+        let start = self.nodes[node].start;
+        let mut left = None;
+        let mut pivot = start;
+        for (k, &child) in children.iter().enumerate() {
+            pivot = self.nodes[child].start;
+            let packed = Packed {
+                variable: variable,
+                rule: rule,
+                pivot: pivot,
+                left: left,
+                right: Some(child)
+            };
+            let prefix = if k + 1 == children.len() {
+                node
+            } else {
+                let end = self.nodes[child].end;
+                self.node(Label::Prefix(variable, rule, k + 1), start, end)
+            };
+            if !self.nodes[prefix].packed.contains(&packed) {
+                self.nodes[prefix].packed.push(packed);
+            }
+            left = Some(prefix);
+        }
+        if children.is_empty() {
+            let packed = Packed { variable: variable, rule: rule, pivot: pivot, left: None,
+                                  right: None };
+            if !self.nodes[node].packed.contains(&packed) {
+                self.nodes[node].packed.push(packed);
+            }
+        }
+    }
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, recognize};
+    use earley;
+    use testing::{SMALL, check_tree, grammar, strings, tokens};
+
+    #[test]
+    fn agrees_with_earley() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            for input in strings(&cfg, 4).iter() {
+                let recognized = earley::recognize(&cfg, input.as_slice());
+                assert_eq!(recognize(&cfg, input.as_slice()), recognized);
+                assert_eq!(parse(&cfg, input.as_slice()).is_some(), recognized);
+            }
+        }
+    }
+
+    #[test]
+    fn ambiguity() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"id\"");
+        let forest = parse(&cfg, tokens(&cfg, "id + id").as_slice()).unwrap();
+        assert!(!forest.is_ambiguous());
+        let root = forest.node(forest.root);
+        assert_eq!((root.start, root.end), (0, 3));
+        let forest = parse(&cfg, tokens(&cfg, "id + id + id").as_slice()).unwrap();
+        assert!(forest.is_ambiguous());
+        assert!(parse(&cfg, tokens(&cfg, "id + + id").as_slice()).is_none());
+    }
+
+    #[test]
+    fn counts_parses() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | <e> \"*\" <e> | \"id\"");
+        for text in ["id", "id + id", "id + id * id", "id * id + id * id"].iter() {
+            let input = tokens(&cfg, *text);
+            let forest = parse(&cfg, input.as_slice()).unwrap();
+            check_tree(&cfg, &forest.tree(), input.as_slice());
+            assert_eq!(forest.count_trees(), earley::count_parses(&cfg, input.as_slice()));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Graphs over the variables of a grammar.
+
+use std::cmp::min;
+
+use super::Variable;
+
+/// The strongly connected components of the graph with `edges[v]` the variables that `v` has an
+/// edge to. Each component comes after every component it has an edge to.
+pub fn components(edges: &[Vec<Variable>]) -> Vec<Vec<Variable>> {
+    let n = edges.len();
+    let mut scc = Tarjan {
+        edges: edges,
+        index: Vec::from_elem(n, None),
+        low: Vec::from_elem(n, 0),
+        stack: Vec::new(),
+        on_stack: Vec::from_elem(n, false),
+        next: 0,
+        components: Vec::new()
+    };
+    for v in range(0, n) {
+        if scc.index[v].is_none() {
+            scc.visit(v);
+        }
+    }
+    scc.components
+}
+
+/// The state of Tarjan's strongly connected components algorithm.
+struct Tarjan<'a> {
+    edges: &'a [Vec<Variable>],
+    index: Vec<Option<uint>>,
+    low: Vec<uint>,
+    stack: Vec<Variable>,
+    on_stack: Vec<bool>,
+    next: uint,
+    components: Vec<Vec<Variable>>
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, v: uint) {
+        self.index[v] = Some(self.next);
+        self.low[v] = self.next;
+        self.next += 1;
+        self.stack.push(Variable(v as u32));
+        self.on_stack[v] = true;
+        let edges = self.edges;
+        for &w in edges[v].iter() {
+            let w = w.index();
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.low[v] = min(self.low[v], self.low[w]);
+                },
+                Some(i) => {
+                    if self.on_stack[w] {
+                        self.low[v] = min(self.low[v], i);
+                    }
+                }
+            }
+        }
+        if Some(self.low[v]) == self.index[v] {
+            let mut component = Vec::new();
+            loop {
+                let w = self.stack.pop().unwrap();
+                self.on_stack[w.index()] = false;
+                component.push(w);
+                if w.index() == v {
+                    break
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::components;
+    use super::super::Variable;
+
+    #[test]
+    fn strongly_connected_components() {
+        let edges = vec![vec![Variable(1)],
+                         vec![Variable(0), Variable(2)],
+                         vec![Variable(2)],
+                         vec![]];
+        assert_eq!(components(edges.as_slice()),
+                   vec![vec![Variable(2)], vec![Variable(1), Variable(0)], vec![Variable(3)]]);
+        assert!(components(&[]).is_empty());
+    }
+}
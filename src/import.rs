@@ -0,0 +1,425 @@
+//! Pieces shared by the readers for the various grammar notations.
+
+use std::collections::{HashMap, VecMap};
+use std::hash::Hash;
+
+use super::{Cfg, Rule, SourceMap, Span, Symbol, SyntaxError, Variable};
+
+/// A position in the source text.
+#[deriving(Copy, Clone)]
+pub struct Mark {
+    pos: uint,
+    pub line: uint,
+    pub column: uint
+}
+
+/// A cursor over the characters of a grammar's source text, tracking lines and columns.
+pub struct Scanner {
+    chars: Vec<char>,
+    pos: uint,
+    line: uint,
+    column: uint
+}
+
+impl Scanner {
+    pub fn new(src: &str) -> Scanner {
+        Scanner {
+            chars: src.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1
+        }
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    pub fn peek_at(&self, n: uint) -> Option<char> {
+        self.chars.get(self.pos + n).map(|&c| c)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    pub fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        match c {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            },
+            Some(_) => self.column += 1,
+            None => return None
+        }
+        self.pos += 1;
+        c
+    }
+
+    /// Whether the text at the cursor starts with `s`.
+    pub fn looking_at(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    /// Consume `s` if the text at the cursor starts with it.
+    pub fn eat(&mut self, s: &str) -> bool {
+        if !self.looking_at(s) {
+            return false
+        }
+        for _ in s.chars() {
+            self.bump();
+        }
+        true
+    }
+
+    pub fn skip_while(&mut self, pred: fn(char) -> bool) {
+        loop {
+            match self.peek() {
+                Some(c) if pred(c) => { self.bump(); },
+                _ => return
+            }
+        }
+    }
+
+    pub fn take_while(&mut self, pred: fn(char) -> bool) -> String {
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some(c) if pred(c) => {
+                    s.push(c);
+                    self.bump();
+                },
+                _ => return s
+            }
+        }
+    }
+
+    /// Skip to the start of the next line.
+    pub fn skip_line(&mut self) {
+        loop {
+            match self.bump() {
+                Some('\n') | None => return,
+                Some(_) => { }
+            }
+        }
+    }
+
+    /// Read a string literal delimited by the quote character at the cursor, handling backslash
+    /// escapes.
+    pub fn quoted(&mut self) -> Result<String, SyntaxError> {
+        let start = self.mark();
+        let quote = match self.bump() {
+            Some(q) => q,
+            None => return Err(self.error("expected a string literal"))
+        };
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => break
+                },
+                Some(c) if c == quote => return Ok(s),
+                Some(c) => s.push(c),
+                None => break
+            }
+        }
+        Err(error_at(start, "unterminated string literal"))
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark { pos: self.pos, line: self.line, column: self.column }
+    }
+
+    /// Move the cursor back to a mark previously taken.
+    pub fn reset(&mut self, mark: Mark) {
+        self.pos = mark.pos;
+        self.line = mark.line;
+        self.column = mark.column;
+    }
+
+    /// An error at the cursor.
+    pub fn error(&self, message: &str) -> SyntaxError {
+        error_at(self.mark(), message)
+    }
+}
+
+pub fn error_at(mark: Mark, message: &str) -> SyntaxError {
+    SyntaxError {
+        line: mark.line,
+        column: mark.column,
+        message: message.to_string()
+    }
+}
+
+/// The span from one mark to another.
+pub fn span(from: Mark, to: Mark) -> Span {
+    Span {
+        line: from.line,
+        column: from.column,
+        end_line: to.line,
+        end_column: to.column
+    }
+}
+
+/// The span of a rule body read from `start` to `end`, where `start` follows any space skipped
+/// from `before`. An empty body is placed at `before` rather than where the next thing starts.
+pub fn body_span(before: Mark, start: Mark, end: Mark) -> Span {
+    if end.pos == start.pos {
+        span(before, before)
+    } else {
+        span(start, end)
+    }
+}
+
+pub fn is_space(c: char) -> bool {
+    c.is_whitespace()
+}
+
+/// Builds up a `Cfg` from named symbols as they're read.
+///
+/// The first variable defined becomes the start symbol unless `set_start` is called. When
+/// finished, every variable that was used must have been given at least one rule. The spans
+/// given for definitions and rules are collected into a `SourceMap`.
+pub struct Symbols<T> {
+    cfg: Cfg<T>,
+    variables: HashMap<String, Variable>,
+    /// The number of fresh variables named after each base.
+    fresh: HashMap<String, uint>,
+    used: VecMap<Mark>,
+    start: Option<Variable>,
+    spans: SourceMap
+}
+
+impl<T: Eq + Hash + Clone> Symbols<T> {
+    pub fn new() -> Symbols<T> {
+        Symbols {
+            cfg: Cfg::new(),
+            variables: HashMap::new(),
+            fresh: HashMap::new(),
+            used: VecMap::new(),
+            start: None,
+            spans: SourceMap::new()
+        }
+    }
+
+    /// The variable with the given name, without recording a use of it.
+    pub fn lookup(&mut self, name: &str) -> Variable {
+        match self.variables.get(name) {
+            Some(&v) => return v,
+            None => { }
+        }
+        let v = self.cfg.add_variable(name);
+        self.variables.insert(name.to_string(), v);
+        v
+    }
+
+    /// The variable with the given name, used at `at`.
+    pub fn variable(&mut self, name: &str, at: Mark) -> Variable {
+        let v = self.lookup(name);
+        if !self.used.contains_key(&v.index()) {
+            self.used.insert(v.index(), at);
+        }
+        v
+    }
+
+    /// The variable with the given name, about to be given rules.
+    pub fn define(&mut self, name: &str) -> Variable {
+        let v = self.lookup(name);
+        if self.start.is_none() {
+            self.start = Some(v);
+        }
+        v
+    }
+
+    /// The variable with the given name, about to be given rules by a definition whose name is
+    /// written at `span`.
+    pub fn define_at(&mut self, name: &str, span: Span) -> Variable {
+        let v = self.define(name);
+        if self.spans.variable_span(v).is_none() {
+            self.spans.set_variable_span(v, span);
+        }
+        v
+    }
+
+    /// Whether a variable with the given name has been mentioned.
+    pub fn is_variable(&self, name: &str) -> bool {
+        self.variables.contains_key(name)
+    }
+
+    /// Whether a variable with the given name has been given rules.
+    pub fn is_defined(&self, name: &str) -> bool {
+        match self.variables.get(name) {
+            Some(&v) => self.cfg.get_rules(v).is_some(),
+            None => false
+        }
+    }
+
+    /// A new variable not mentioned in the source, named `base#N` after `base`.
+    ///
+    /// The variable isn't one `lookup` can find, so no name in the source refers to it, even
+    /// one read after it was made, and the `#` keeps its name apart from those of notations
+    /// whose names can't contain one.
+    pub fn fresh(&mut self, base: &str) -> Variable {
+        let n = match self.fresh.get(base) {
+            Some(&n) => n + 1,
+            None => 1
+        };
+        self.fresh.insert(base.to_string(), n);
+        self.cfg.add_variable(format!("{}#{}", base, n).as_slice())
+    }
+
+    /// The number of tokens allocated so far.
+    pub fn num_tokens(&self) -> uint {
+        self.cfg.num_tokens()
+    }
+
+    /// The sequence of symbols standing for a choice between `bodies`, using a fresh variable
+    /// named after `base` if there is more than one.
+    pub fn choice(&mut self, base: &str, mut bodies: Vec<Rule>) -> Vec<Symbol> {
+        if bodies.len() == 1 {
+            return bodies.pop().unwrap()
+        }
+        let v = self.fresh(format!("{}_group", base).as_slice());
+        for body in bodies.into_iter() {
+            self.add_rule(v, body);
+        }
+        vec![Symbol::Var(v)]
+    }
+
+    /// A fresh variable, named after `base`, deriving either `item` or the empty string.
+    pub fn optional(&mut self, base: &str, item: Vec<Symbol>) -> Symbol {
+        let v = self.fresh(format!("{}_opt", base).as_slice());
+        self.add_rule(v, item);
+        self.add_rule(v, vec![]);
+        Symbol::Var(v)
+    }
+
+    /// A fresh variable, named after `base`, deriving zero or more repetitions of `item`.
+    pub fn star(&mut self, base: &str, mut item: Vec<Symbol>) -> Symbol {
+        let v = self.fresh(format!("{}_star", base).as_slice());
+        item.push(Symbol::Var(v));
+        self.add_rule(v, item);
+        self.add_rule(v, vec![]);
+        Symbol::Var(v)
+    }
+
+    pub fn token(&mut self, terminal: T) -> Symbol {
+        Symbol::Tok(self.cfg.add_token(terminal))
+    }
+
+    pub fn add_rule(&mut self, variable: Variable, body: Rule) {
+        self.cfg.add_rule(variable, body).unwrap();
+    }
+
+    /// Add a rule whose body is written at `span`.
+    pub fn add_rule_at(&mut self, variable: Variable, body: Rule, span: Span) {
+        self.add_rule(variable, body);
+        let id = self.cfg.rule_id(variable, self.num_rules(variable) - 1).unwrap();
+        self.spans.set_rule_span(id, span);
+    }
+
+    /// The number of rules `variable` has been given so far.
+    pub fn num_rules(&self, variable: Variable) -> uint {
+        self.cfg.get_rules(variable).map_or(0, |rules| rules.len())
+    }
+
+    pub fn set_start(&mut self, variable: Variable) {
+        self.start = Some(variable);
+    }
+
+    /// Finish reading, checking that every variable used has rules.
+    pub fn finish(self) -> Result<Cfg<T>, SyntaxError> {
+        self.finish_with_spans().map(|(cfg, _)| cfg)
+    }
+
+    /// Finish reading as for `finish`, also giving the spans collected.
+    pub fn finish_with_spans(mut self) -> Result<(Cfg<T>, SourceMap), SyntaxError> {
+        for (v, &at) in self.used.iter() {
+            if self.cfg.get_rules(Variable(v as u32)).is_none() {
+                let name = self.cfg.name(Variable(v as u32)).unwrap_or("");
+                return Err(error_at(at, format!("`{}` has no rules", name).as_slice()))
+            }
+        }
+        let start = match self.start {
+            Some(start) => start,
+            None => return Err(SyntaxError {
+                line: 1,
+                column: 1,
+                message: "grammar has no rules".to_string()
+            })
+        };
+        if self.cfg.get_rules(start).is_none() {
+            let name = self.cfg.name(start).unwrap_or("").to_string();
+            return Err(SyntaxError {
+                line: 1,
+                column: 1,
+                message: format!("start symbol `{}` has no rules", name)
+            })
+        }
+        self.cfg.set_start(start).unwrap();
+        Ok((self.cfg, self.spans))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Scanner, Symbols, is_space};
+    use super::super::Symbol;
+
+    fn is_letter(c: char) -> bool {
+        c.is_alphabetic()
+    }
+
+    #[test]
+    fn scanner() {
+        let mut s = Scanner::new("ab \"c\\\"d\"\n  xy");
+        assert_eq!(s.take_while(is_letter).as_slice(), "ab");
+        s.skip_while(is_space);
+        let at = s.mark();
+        assert_eq!(s.quoted().unwrap().as_slice(), "c\"d");
+        assert!(s.looking_at("\n  x"));
+        s.reset(at);
+        assert_eq!((s.mark().line, s.mark().column), (1, 4));
+        assert!(s.quoted().is_ok());
+        s.skip_line();
+        assert_eq!((s.mark().line, s.mark().column), (2, 1));
+        assert!(!s.eat("xy"));
+        s.skip_while(is_space);
+        assert_eq!(s.peek_at(1), Some('y'));
+        assert!(s.eat("xy"));
+        assert!(s.at_end());
+        assert_eq!(s.bump(), None);
+        let error = s.error("here");
+        assert_eq!((error.line, error.column), (2, 5));
+
+        let error = Scanner::new("'abc").quoted().unwrap_err();
+        assert_eq!((error.line, error.column), (1, 1));
+        assert_eq!(error.message.as_slice(), "unterminated string literal");
+    }
+
+    #[test]
+    fn fresh() {
+        let mut syms: Symbols<char> = Symbols::new();
+        let s = syms.define("s");
+        let first = syms.fresh("s_opt");
+        let second = syms.fresh("s_opt");
+        // The source can't refer to a fresh variable, even by its name.
+        let named = syms.define("s_opt#1");
+        assert!(first != second && named != first);
+        assert!(!syms.is_variable("s_opt#2"));
+        let a = syms.token('a');
+        syms.add_rule(s, vec![Symbol::Var(first), Symbol::Var(second), Symbol::Var(named)]);
+        for &v in [first, second, named].iter() {
+            syms.add_rule(v, vec![a]);
+        }
+        let cfg = syms.finish().unwrap();
+        assert_eq!(cfg.name(first), Some("s_opt#1"));
+        assert_eq!(cfg.name(second), Some("s_opt#2"));
+        assert_eq!(cfg.get_rules(first).unwrap().len(), 1);
+    }
+}
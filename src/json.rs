@@ -0,0 +1,241 @@
+//! A minimal JSON reader, keeping the order of object members.
+
+use std::char;
+
+use super::SyntaxError;
+use import::{Mark, Scanner, error_at, is_space};
+
+pub enum Value {
+    Null,
+    Bool(bool),
+    /// A number, kept as written.
+    Number(String),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+/// A JSON value, along with where it appeared in the source.
+pub struct Json {
+    pub at: Mark,
+    pub value: Value
+}
+
+impl Json {
+    /// The member of an object with the given key.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self.value {
+            Value::Object(ref members) => {
+                members.iter().find(|&&(ref k, _)| k.as_slice() == key).map(|&(_, ref v)| v)
+            },
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self.value {
+            Value::Str(ref s) => Some(s.as_slice()),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self.value {
+            Value::Array(ref items) => Some(items.as_slice()),
+            _ => None
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self.value {
+            Value::Object(ref members) => Some(members.as_slice()),
+            _ => None
+        }
+    }
+
+    /// An error located at this value.
+    pub fn error(&self, message: &str) -> SyntaxError {
+        error_at(self.at, message)
+    }
+}
+
+pub fn parse(src: &str) -> Result<Json, SyntaxError> {
+    let mut s = Scanner::new(src);
+    let json = try!(value(&mut s));
+    s.skip_while(is_space);
+    if !s.at_end() {
+        return Err(s.error("expected the end of input"))
+    }
+    Ok(json)
+}
+
+fn value(s: &mut Scanner) -> Result<Json, SyntaxError> {
+    s.skip_while(is_space);
+    let at = s.mark();
+    let value = match s.peek() {
+        Some('{') => {
+            s.bump();
+            let mut members = Vec::new();
+            s.skip_while(is_space);
+            if !s.eat("}") {
+                loop {
+                    s.skip_while(is_space);
+                    if s.peek() != Some('"') {
+                        return Err(s.error("expected a string key"))
+                    }
+                    let key = try!(string(s));
+                    s.skip_while(is_space);
+                    if !s.eat(":") {
+                        return Err(s.error("expected `:`"))
+                    }
+                    members.push((key, try!(value(s))));
+                    s.skip_while(is_space);
+                    if s.eat("}") {
+                        break
+                    }
+                    if !s.eat(",") {
+                        return Err(s.error("expected `,` or `}`"))
+                    }
+                }
+            }
+            Value::Object(members)
+        },
+        Some('[') => {
+            s.bump();
+            let mut items = Vec::new();
+            s.skip_while(is_space);
+            if !s.eat("]") {
+                loop {
+                    items.push(try!(value(s)));
+                    s.skip_while(is_space);
+                    if s.eat("]") {
+                        break
+                    }
+                    if !s.eat(",") {
+                        return Err(s.error("expected `,` or `]`"))
+                    }
+                }
+            }
+            Value::Array(items)
+        },
+        Some('"') => Value::Str(try!(string(s))),
+        Some(c) if c == '-' || c.is_digit(10) => Value::Number(s.take_while(in_number)),
+        _ => {
+            if s.eat("null") {
+                Value::Null
+            } else if s.eat("true") {
+                Value::Bool(true)
+            } else if s.eat("false") {
+                Value::Bool(false)
+            } else {
+                return Err(s.error("expected a value"))
+            }
+        }
+    };
+    Ok(Json { at: at, value: value })
+}
+
+fn in_number(c: char) -> bool {
+    c.is_digit(10) || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E'
+}
+
+fn string(s: &mut Scanner) -> Result<String, SyntaxError> {
+    let start = s.mark();
+    s.bump();
+    let mut string = String::new();
+    loop {
+        match s.bump() {
+            Some('"') => return Ok(string),
+            Some('\\') => {
+                let c = match s.bump() {
+                    Some('b') => '\x08',
+                    Some('f') => '\x0c',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let mut code = try!(hex4(s));
+                        if 0xD800 <= code && code < 0xDC00 && s.eat("\\u") {
+                            let low = try!(hex4(s));
+                            if low < 0xDC00 || 0xE000 <= low {
+                                return Err(s.error("invalid unicode escape"))
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        match char::from_u32(code) {
+                            Some(c) => c,
+                            None => return Err(s.error("invalid unicode escape"))
+                        }
+                    },
+                    Some(c) => c,
+                    None => break
+                };
+                string.push(c);
+            },
+            Some(c) => string.push(c),
+            None => break
+        }
+    }
+    Err(error_at(start, "unterminated string"))
+}
+
+fn hex4(s: &mut Scanner) -> Result<u32, SyntaxError> {
+    let mut n = 0;
+    for _ in range(0u, 4) {
+        let d = match s.bump() {
+            Some(c @ '0'...'9') => c as u32 - '0' as u32,
+            Some(c @ 'a'...'f') => c as u32 - 'a' as u32 + 10,
+            Some(c @ 'A'...'F') => c as u32 - 'A' as u32 + 10,
+            _ => return Err(s.error("expected four hex digits"))
+        };
+        n = n * 16 + d;
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Value, parse};
+
+    #[test]
+    fn values() {
+        let json = parse(r#" {"a": [1, -2.5e3, true, false, null], "b": {},
+                             "c": "x\ty\u00e9\uD83D\uDE00\"\\"} "#).unwrap();
+        let object = json.as_object().unwrap();
+        let keys: Vec<&str> = object.iter().map(|&(ref k, _)| k.as_slice()).collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        let a = json.get("a").unwrap().as_array().unwrap();
+        assert_eq!(a.len(), 5);
+        match a[1].value {
+            Value::Number(ref n) => assert_eq!(n.as_slice(), "-2.5e3"),
+            _ => panic!("expected a number")
+        }
+        match (&a[2].value, &a[3].value, &a[4].value) {
+            (&Value::Bool(true), &Value::Bool(false), &Value::Null) => { },
+            _ => panic!("expected true, false and null")
+        }
+        assert_eq!((a[1].at.line, a[1].at.column), (1, 12));
+        assert_eq!(json.get("b").unwrap().as_object().map(|m| m.len()), Some(0));
+        assert_eq!(json.get("c").and_then(|c| c.as_str()), Some("x\tyé😀\"\\"));
+        assert!(json.get("d").is_none() && a[0].get("a").is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse("[1, 2").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 6));
+        let error = parse("{\"a\" 1}").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 6));
+        let error = parse("[\"abc]").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 2));
+        let error = parse("[1]\nx").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 1));
+        assert!(parse("\"\\u12G4\"").is_err());
+        let error = parse("\"\\uDBFF\\u0041\"").unwrap_err();
+        assert_eq!(error.message.as_slice(), "invalid unicode escape");
+        assert!(parse("\"\\uD83D\"").is_err());
+        assert!(parse("\"\\uDE00\"").is_err());
+        assert!(parse("{1: 2}").is_err());
+        assert!(parse("nul").is_err());
+    }
+}
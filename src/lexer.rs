@@ -0,0 +1,680 @@
+//! Lexers for the tokens of a grammar, each token given by a regular expression over characters.
+//!
+//! The patterns are compiled together into a single deterministic automaton. At each position
+//! of the text the lexer takes the longest match of any pattern and, of the patterns matching
+//! that much, the one added first.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Show;
+use std::hash::Hash;
+
+use super::{Cfg, SyntaxError, Token};
+use class::CharClass;
+use import::Scanner;
+use source::{ByteSpan, TokenStream};
+
+/// The last character, as a number.
+const MAX_CHAR: u32 = 0x10FFFF;
+
+/// A regular expression over characters, as read.
+enum Pattern {
+    Class(CharClass),
+    Sequence(Vec<Pattern>),
+    Alternatives(Vec<Pattern>),
+    /// An item repeated at least, and at most, the given number of times.
+    Repeat(Box<Pattern>, uint, Option<uint>)
+}
+
+/// Gathers the patterns of a lexer in order of priority, to be compiled by `build`.
+pub struct LexerBuilder {
+    patterns: Vec<Pattern>,
+    /// The token each pattern gives, or `None` for text to skip.
+    tokens: Vec<Option<Token>>
+}
+
+impl LexerBuilder {
+    pub fn new() -> LexerBuilder {
+        LexerBuilder { patterns: Vec::new(), tokens: Vec::new() }
+    }
+
+    /// Give `token` for text matching `pattern`, with lower priority than the patterns added
+    /// before.
+    ///
+    /// Patterns are written as usual for regular expressions: a character matches itself,
+    /// `.` any character but a newline, `[a-z_]` any of those listed, and `[^a-z_]` any but
+    /// those. A `\` escapes the next character, and `\n`, `\r`, `\t`, `\d`, `\w` and `\s` and
+    /// their uppercase negations mean what they do in Perl. Alternatives are separated by `|`,
+    /// `( ... )` groups, and a suffix `?`, `*`, `+`, `{n}`, `{n,}` or `{n,m}` repeats the
+    /// preceding item.
+    pub fn token(&mut self, token: Token, pattern: &str) -> Result<(), SyntaxError> {
+        let pattern = try!(read(pattern));
+        self.patterns.push(pattern);
+        self.tokens.push(Some(token));
+        Ok(())
+    }
+
+    /// Skip text matching `pattern`, such as whitespace or comments, with lower priority than
+    /// the patterns added before.
+    pub fn skip(&mut self, pattern: &str) -> Result<(), SyntaxError> {
+        let pattern = try!(read(pattern));
+        self.patterns.push(pattern);
+        self.tokens.push(None);
+        Ok(())
+    }
+
+    /// Compile the patterns into a lexer.
+    pub fn build(&self) -> Lexer {
+        let mut nfa = Nfa { edges: vec![Vec::new()], accepting: vec![None] };
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            let start = nfa.add_state();
+            nfa.edges[0].push((None, start));
+            let end = nfa.compile(pattern, start);
+            nfa.accepting[end] = Some(i);
+        }
+        nfa.to_lexer(self.tokens.clone())
+    }
+}
+
+impl<T: Eq + Hash + Clone + Show> Cfg<T> {
+    /// A lexer for the tokens of this grammar, each given by the pattern paired with its
+    /// terminal, as `LexerBuilder::token` reads them, and skipping text matching any of `skip`.
+    /// Patterns are in order of priority, with those to skip after all the tokens.
+    pub fn lexer(&self, patterns: &[(T, &str)], skip: &[&str]) -> Result<Lexer, SyntaxError> {
+        let mut builder = LexerBuilder::new();
+        for &(ref terminal, pattern) in patterns.iter() {
+            let token = match self.token(terminal) {
+                Some(token) => token,
+                None => return Err(SyntaxError {
+                    line: 1,
+                    column: 1,
+                    message: format!("`{}` is not a terminal of the grammar", terminal)
+                })
+            };
+            try!(builder.token(token, pattern));
+        }
+        for &pattern in skip.iter() {
+            try!(builder.skip(pattern));
+        }
+        Ok(builder.build())
+    }
+}
+
+/// A state of a lexer's automaton.
+#[deriving(Clone, PartialEq, Eq, Show)]
+struct State {
+    /// Inclusive ranges of characters, as numbers, sorted and disjoint, with the state each goes
+    /// to.
+    transitions: Vec<(u32, u32, uint)>,
+    /// The pattern matched on reaching the state, if any.
+    accept: Option<uint>
+}
+
+impl State {
+    fn next(&self, c: char) -> Option<uint> {
+        let c = c as u32;
+        let (mut lo, mut hi) = (0, self.transitions.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let (first, last, to) = self.transitions[mid];
+            if last < c {
+                lo = mid + 1;
+            } else if first > c {
+                hi = mid;
+            } else {
+                return Some(to)
+            }
+        }
+        None
+    }
+}
+
+/// A lexer compiled from patterns by a `LexerBuilder`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Lexer {
+    /// The states of the automaton, starting from 0.
+    states: Vec<State>,
+    tokens: Vec<Option<Token>>
+}
+
+impl Lexer {
+    /// The tokens of `text`, each with its span and text, as a source for a parser.
+    pub fn tokens<'a>(&'a self, text: &'a str) -> Tokens<'a> {
+        Tokens { lexer: self, text: text, position: 0, error: None }
+    }
+
+    /// Every token of `text`, or the byte offset at which no pattern matches.
+    pub fn tokenize<'a>(&self, text: &'a str) -> Result<TokenStream<&'a str>, uint> {
+        let mut tokens = self.tokens(text);
+        let stream = TokenStream::read(&mut tokens);
+        match tokens.error() {
+            Some(position) => Err(position),
+            None => Ok(stream)
+        }
+    }
+
+    /// The longest non-empty match at the start of `text`, as its length and pattern.
+    fn longest_match(&self, text: &str) -> Option<(uint, uint)> {
+        let mut state = 0;
+        let mut longest = None;
+        for (i, c) in text.char_indices() {
+            state = match self.states[state].next(c) {
+                Some(state) => state,
+                None => break
+            };
+            match self.states[state].accept {
+                Some(pattern) => longest = Some((i + c.len_utf8(), pattern)),
+                None => { }
+            }
+        }
+        longest
+    }
+}
+
+/// The tokens of a text, read by a lexer. It stops at the end of the text or at the first
+/// position no pattern matches, which `error` gives.
+pub struct Tokens<'a> {
+    lexer: &'a Lexer,
+    text: &'a str,
+    position: uint,
+    error: Option<uint>
+}
+
+impl<'a> Tokens<'a> {
+    /// The byte offset at which no pattern matched, if reading stopped there.
+    pub fn error(&self) -> Option<uint> {
+        self.error
+    }
+}
+
+impl<'a> Iterator<(Token, ByteSpan, &'a str)> for Tokens<'a> {
+    fn next(&mut self) -> Option<(Token, ByteSpan, &'a str)> {
+        while self.error.is_none() && self.position < self.text.len() {
+            let start = self.position;
+            match self.lexer.longest_match(self.text.slice_from(start)) {
+                Some((len, pattern)) => {
+                    self.position += len;
+                    match self.lexer.tokens[pattern] {
+                        Some(token) => {
+                            let span = ByteSpan { start: start, end: self.position };
+                            return Some((token, span, self.text.slice(start, self.position)))
+                        },
+                        None => { }
+                    }
+                },
+                None => self.error = Some(start)
+            }
+        }
+        None
+    }
+}
+
+/// A nondeterministic automaton over ranges of characters, built from the patterns by
+/// Thompson's construction.
+struct Nfa {
+    /// The transitions from each state, on any character in one of a list of ranges, or on
+    /// nothing.
+    edges: Vec<Vec<(Option<Vec<(u32, u32)>>, uint)>>,
+    /// The pattern each state accepts, if any.
+    accepting: Vec<Option<uint>>
+}
+
+impl Nfa {
+    fn add_state(&mut self) -> uint {
+        self.edges.push(Vec::new());
+        self.accepting.push(None);
+        self.edges.len() - 1
+    }
+
+    /// Add states matching `pattern` from `from`, returning the state it ends in.
+    fn compile(&mut self, pattern: &Pattern, from: uint) -> uint {
+        match *pattern {
+            Pattern::Class(ref class) => {
+                let to = self.add_state();
+                self.edges[from].push((Some(ranges(class)), to));
+                to
+            },
+            Pattern::Sequence(ref items) => {
+                items.iter().fold(from, |at, item| self.compile(item, at))
+            },
+            Pattern::Alternatives(ref alternatives) => {
+                let to = self.add_state();
+                for alternative in alternatives.iter() {
+                    let end = self.compile(alternative, from);
+                    self.edges[end].push((None, to));
+                }
+                to
+            },
+            Pattern::Repeat(ref item, min, max) => {
+                let mut at = from;
+                for _ in range(0, min) {
+                    at = self.compile(&**item, at);
+                }
+                match max {
+                    Some(max) => {
+                        let to = self.add_state();
+                        self.edges[at].push((None, to));
+                        for _ in range(min, max) {
+                            at = self.compile(&**item, at);
+                            self.edges[at].push((None, to));
+                        }
+                        to
+                    },
+                    // A fresh state to loop on, so the loop doesn't take in other transitions
+                    // from `at`.
+                    None => {
+                        let loop_ = self.add_state();
+                        self.edges[at].push((None, loop_));
+                        let end = self.compile(&**item, loop_);
+                        self.edges[end].push((None, loop_));
+                        loop_
+                    }
+                }
+            }
+        }
+    }
+
+    /// The states reachable from `states` on nothing, sorted.
+    fn closure(&self, states: Vec<uint>) -> Vec<uint> {
+        let mut seen: BTreeSet<uint> = states.iter().map(|&q| q).collect();
+        let mut stack = states;
+        loop {
+            let q = match stack.pop() {
+                Some(q) => q,
+                None => break
+            };
+            for &(ref on, r) in self.edges[q].iter() {
+                if on.is_none() && seen.insert(r) {
+                    stack.push(r);
+                }
+            }
+        }
+        seen.into_iter().collect()
+    }
+
+    /// The lexer for the automaton, by the subset construction over the intervals of
+    /// characters no range of a transition splits.
+    fn to_lexer(&self, tokens: Vec<Option<Token>>) -> Lexer {
+        let mut bounds = BTreeSet::new();
+        for edges in self.edges.iter() {
+            for &(ref on, _) in edges.iter() {
+                for &(first, last) in on.iter().flat_map(|ranges| ranges.iter()) {
+                    bounds.insert(first);
+                    bounds.insert(last + 1);
+                }
+            }
+        }
+        let bounds: Vec<u32> = bounds.into_iter().collect();
+
+        let start = self.closure(vec![0]);
+        let mut ids = HashMap::new();
+        ids.insert(start.clone(), 0u);
+        let mut subsets = vec![start];
+        let mut states = Vec::new();
+        let mut next = 0;
+        while next < subsets.len() {
+            let mut state = State {
+                transitions: Vec::new(),
+                accept: subsets[next].iter().filter_map(|&q| self.accepting[q]).min()
+            };
+            for window in bounds.windows(2) {
+                let (first, last) = (window[0], window[1] - 1);
+                let mut to = Vec::new();
+                for &q in subsets[next].iter() {
+                    for &(ref on, r) in self.edges[q].iter() {
+                        let matches = on.as_ref().map_or(false, |ranges| {
+                            ranges.iter().any(|&(lo, hi)| lo <= first && first <= hi)
+                        });
+                        if matches && !to.contains(&r) {
+                            to.push(r);
+                        }
+                    }
+                }
+                if to.is_empty() {
+                    continue
+                }
+                let to = self.closure(to);
+                let id = match ids.get(&to) {
+                    Some(&id) => id,
+                    None => {
+                        let id = subsets.len();
+                        ids.insert(to.clone(), id);
+                        subsets.push(to);
+                        id
+                    }
+                };
+                // Adjacent intervals going to the same state make one range.
+                let adjacent = match state.transitions.last() {
+                    Some(&(_, end, target)) => target == id && end + 1 == first,
+                    None => false
+                };
+                if adjacent {
+                    state.transitions.last_mut().unwrap().1 = last;
+                } else {
+                    state.transitions.push((first, last, id));
+                }
+            }
+            states.push(state);
+            next += 1;
+        }
+        Lexer { states: states, tokens: tokens }
+    }
+}
+
+/// The characters of a class, as sorted and disjoint inclusive ranges of numbers.
+fn ranges(class: &CharClass) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = class.ranges.iter()
+        .filter(|&&(first, last)| first <= last)
+        .map(|&(first, last)| (first as u32, last as u32))
+        .collect();
+    ranges.sort();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    for &(first, last) in ranges.iter() {
+        match merged.last_mut() {
+            Some(range) if first <= range.1 + 1 => {
+                if last > range.1 {
+                    range.1 = last;
+                }
+                continue
+            },
+            _ => { }
+        }
+        merged.push((first, last));
+    }
+    if !class.negated {
+        return merged
+    }
+
+    let mut complement = Vec::new();
+    let mut next = 0;
+    for &(first, last) in merged.iter() {
+        if first > next {
+            complement.push((next, first - 1));
+        }
+        next = last + 1;
+    }
+    if next <= MAX_CHAR {
+        complement.push((next, MAX_CHAR));
+    }
+    complement
+}
+
+/// Read a whole pattern.
+fn read(pattern: &str) -> Result<Pattern, SyntaxError> {
+    let mut s = Scanner::new(pattern);
+    let pattern = try!(alternatives(&mut s));
+    match s.peek() {
+        Some(c) => Err(s.error(format!("unexpected `{}`", c).as_slice())),
+        None => Ok(pattern)
+    }
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_digit(10)
+}
+
+fn alternatives(s: &mut Scanner) -> Result<Pattern, SyntaxError> {
+    let mut alternatives = Vec::new();
+    loop {
+        alternatives.push(try!(sequence(s)));
+        if !s.eat("|") {
+            break
+        }
+    }
+    if alternatives.len() == 1 {
+        return Ok(alternatives.pop().unwrap())
+    }
+    Ok(Pattern::Alternatives(alternatives))
+}
+
+fn sequence(s: &mut Scanner) -> Result<Pattern, SyntaxError> {
+    let mut items = Vec::new();
+    loop {
+        let mut item = match s.peek() {
+            Some('(') => {
+                let open = s.mark();
+                s.bump();
+                let inner = try!(alternatives(s));
+                if !s.eat(")") {
+                    s.reset(open);
+                    return Err(s.error("unclosed group, expected `)`"))
+                }
+                inner
+            },
+            Some('[') => Pattern::Class(try!(class(s))),
+            Some('.') => {
+                s.bump();
+                Pattern::Class(CharClass::single('\n').negate())
+            },
+            Some('\\') => Pattern::Class(try!(escape(s))),
+            Some('*') | Some('+') | Some('?') | Some('{') => {
+                return Err(s.error("nothing to repeat"))
+            },
+            Some(')') | Some('|') | None => break,
+            Some(c) => {
+                s.bump();
+                Pattern::Class(CharClass::single(c))
+            }
+        };
+        loop {
+            let (min, max) = if s.eat("?") {
+                (0, Some(1))
+            } else if s.eat("*") {
+                (0, None)
+            } else if s.eat("+") {
+                (1, None)
+            } else if s.looking_at("{") {
+                try!(bounds(s))
+            } else {
+                break
+            };
+            item = Pattern::Repeat(box item, min, max);
+        }
+        items.push(item);
+    }
+    if items.len() == 1 {
+        return Ok(items.pop().unwrap())
+    }
+    Ok(Pattern::Sequence(items))
+}
+
+/// Parse an escape, `\` and the character after it.
+fn escape(s: &mut Scanner) -> Result<CharClass, SyntaxError> {
+    s.bump();
+    let digit = CharClass::range('0', '9');
+    let word = CharClass {
+        ranges: vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+        negated: false
+    };
+    let space = CharClass {
+        ranges: vec![(' ', ' '), ('\t', '\r')],
+        negated: false
+    };
+    let class = match s.bump() {
+        Some('n') => CharClass::single('\n'),
+        Some('r') => CharClass::single('\r'),
+        Some('t') => CharClass::single('\t'),
+        Some('d') => digit,
+        Some('D') => digit.negate(),
+        Some('w') => word,
+        Some('W') => word.negate(),
+        Some('s') => space,
+        Some('S') => space.negate(),
+        Some(c) => CharClass::single(c),
+        None => return Err(s.error("expected a character to escape"))
+    };
+    Ok(class)
+}
+
+/// Parse a bracketed class of characters.
+fn class(s: &mut Scanner) -> Result<CharClass, SyntaxError> {
+    let open = s.mark();
+    s.bump();
+    let negated = s.eat("^");
+    let mut ranges = Vec::new();
+    loop {
+        let first = match s.peek() {
+            Some(']') => {
+                s.bump();
+                return Ok(CharClass { ranges: ranges, negated: negated })
+            },
+            Some('\\') => {
+                let class = try!(escape(s));
+                if class.negated {
+                    return Err(s.error("negated classes can't be used inside brackets"))
+                }
+                match class.ranges.as_slice() {
+                    [(c, d)] if c == d => c,
+                    listed => {
+                        ranges.push_all(listed);
+                        continue
+                    }
+                }
+            },
+            Some(c) => {
+                s.bump();
+                c
+            },
+            None => {
+                s.reset(open);
+                return Err(s.error("unclosed class, expected `]`"))
+            }
+        };
+        if s.looking_at("-") && s.peek_at(1).map_or(false, |c| c != ']') {
+            s.bump();
+            let last = match s.peek() {
+                Some('\\') => {
+                    let class = try!(escape(s));
+                    match class.ranges.as_slice() {
+                        [(c, d)] if c == d && !class.negated => c,
+                        _ => return Err(s.error("expected a character to end the range"))
+                    }
+                },
+                _ => s.bump().unwrap()
+            };
+            if last < first {
+                return Err(s.error("range ends before it starts"))
+            }
+            ranges.push((first, last));
+        } else {
+            ranges.push((first, first));
+        }
+    }
+}
+
+/// Parse a bound on repetitions, `{n}`, `{n,}` or `{n,m}`.
+fn bounds(s: &mut Scanner) -> Result<(uint, Option<uint>), SyntaxError> {
+    let open = s.mark();
+    s.bump();
+    let min = try!(number(s));
+    let max = if s.eat(",") {
+        if s.looking_at("}") { None } else { Some(try!(number(s))) }
+    } else {
+        Some(min)
+    };
+    if !s.eat("}") {
+        return Err(s.error("expected `}`"))
+    }
+    match max {
+        Some(max) if max < min => {
+            s.reset(open);
+            Err(s.error("repetition bound is less than its minimum"))
+        },
+        _ => Ok((min, max))
+    }
+}
+
+fn number(s: &mut Scanner) -> Result<uint, SyntaxError> {
+    let at = s.mark();
+    match s.take_while(is_digit).parse() {
+        Some(n) => Ok(n),
+        None => {
+            s.reset(at);
+            Err(s.error("expected a number"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Lexer, LexerBuilder};
+    use super::super::Token;
+    use lr::LrParser;
+    use source::ByteSpan;
+    use testing::{EXPRESSIONS, grammar, tokens};
+
+    fn lexed<'a>(lexer: &'a Lexer, text: &'a str) -> Vec<(Token, &'a str)> {
+        let mut found = vec![];
+        for (token, _, text) in lexer.tokens(text) {
+            found.push((token, text));
+        }
+        found
+    }
+
+    #[test]
+    fn longest_match() {
+        let mut builder = LexerBuilder::new();
+        builder.token(Token(0), "if").unwrap();
+        builder.token(Token(1), "[a-z_][a-z_0-9]*").unwrap();
+        builder.token(Token(2), "\\d+(\\.\\d+)?").unwrap();
+        builder.token(Token(3), "=|==").unwrap();
+        builder.skip("\\s+|#[^\\n]*").unwrap();
+        let lexer = builder.build();
+        let text = "if iffy == 3.25 # x\nx=1.";
+        assert_eq!(lexed(&lexer, text), vec![(Token(0), "if"), (Token(1), "iffy"), (Token(3), "=="),
+                                             (Token(2), "3.25"), (Token(1), "x"), (Token(3), "="),
+                                             (Token(2), "1")]);
+        assert_eq!(lexer.tokenize(text), Err(23));
+        let stream = lexer.tokenize("x = 10").unwrap();
+        assert_eq!(stream.tokens, vec![Token(1), Token(3), Token(2)]);
+        assert_eq!(stream.spans[2], ByteSpan { start: 4, end: 6 });
+        assert_eq!(stream.payloads, vec!["x", "=", "10"]);
+    }
+
+    #[test]
+    fn characters() {
+        let mut builder = LexerBuilder::new();
+        builder.token(Token(0), "a{2,3}").unwrap();
+        builder.token(Token(1), "[^ a]+").unwrap();
+        builder.token(Token(2), "\\w").unwrap();
+        builder.skip(" ").unwrap();
+        let lexer = builder.build();
+        assert_eq!(lexed(&lexer, "aaaaa é√ Z"),
+                   vec![(Token(0), "aaa"), (Token(0), "aa"), (Token(1), "é√"), (Token(1), "Z")]);
+        let stream = lexer.tokenize("é a").unwrap();
+        assert_eq!(stream.tokens, vec![Token(1), Token(2)]);
+        assert_eq!(stream.spans[0], ByteSpan { start: 0, end: 2 });
+        assert_eq!(stream.spans[1], ByteSpan { start: 3, end: 4 });
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [("(a", "unclosed group, expected `)`"),
+                     ("*a", "nothing to repeat"),
+                     ("[b-a]", "range ends before it starts"),
+                     ("[a", "unclosed class, expected `]`"),
+                     ("a{3,2}", "repetition bound is less than its minimum"),
+                     ("a{", "expected a number"),
+                     ("a)", "unexpected `)`"),
+                     ("[\\D]", "negated classes can't be used inside brackets")];
+        for &(pattern, message) in cases.iter() {
+            let error = LexerBuilder::new().token(Token(0), pattern).err().unwrap();
+            assert_eq!(error.message.as_slice(), message);
+        }
+    }
+
+    #[test]
+    fn cfg_lexer() {
+        let cfg = grammar(EXPRESSIONS);
+        let patterns = [("id".to_string(), "[a-z]+"),
+                        ("+".to_string(), "\\+"),
+                        ("*".to_string(), "\\*"),
+                        ("(".to_string(), "\\("),
+                        (")".to_string(), "\\)")];
+        let lexer = cfg.lexer(&patterns, &["\\s+"]).unwrap();
+        let parser = LrParser::new(&cfg).unwrap();
+        let expected = parser.parse(tokens(&cfg, "id + id * ( id )").as_slice());
+        assert_eq!(parser.parse_source(&mut lexer.tokens("a +b*(cd)")), expected);
+        let error = cfg.lexer(&[("-".to_string(), "-")], &[]).err().unwrap();
+        assert_eq!(error.message.as_slice(), "`-` is not a terminal of the grammar");
+    }
+}
@@ -1,4 +1,530 @@
-use std::collections::VecMap;
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "std"), feature(globs, phase))]
+
+#[cfg(not(feature = "std"))]
+#[phase(plugin, link)]
+extern crate core;
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+#[phase(plugin, link)]
+extern crate collections;
+#[cfg(feature = "ffi")]
+extern crate libc;
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use std::collections::{HashMap, VecMap};
+#[cfg(feature = "std")]
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+
+#[cfg(feature = "std")]
+#[macro_escape]
+mod macros;
+#[cfg(not(feature = "std"))]
+#[path = "nostd.rs"]
+mod std;
+
+#[cfg(feature = "std")]
+pub mod abnf;
+pub mod actions;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod antlr;
+#[cfg(feature = "std")]
+pub mod attribute;
+pub mod binary;
+#[cfg(feature = "std")]
+pub mod bnf;
+#[cfg(feature = "std")]
+pub mod class;
+#[cfg(feature = "std")]
+mod codegen;
+#[cfg(feature = "std")]
+pub mod compose;
+#[cfg(feature = "std")]
+pub mod cyk;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod earley;
+#[cfg(feature = "std")]
+pub mod ebnf;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod first;
+#[cfg(feature = "std")]
+pub mod forest;
+#[cfg(feature = "std")]
+pub mod generate;
+#[cfg(feature = "std")]
+pub mod gll;
+#[cfg(feature = "std")]
+pub mod glr;
+#[cfg(feature = "std")]
+mod graph;
+#[cfg(feature = "std")]
+mod import;
+#[cfg(feature = "std")]
+mod json;
+#[cfg(feature = "std")]
+pub mod lexer;
+#[cfg(feature = "std")]
+pub mod lint;
+pub mod ll1;
+#[cfg(feature = "std")]
+pub mod lr;
+#[cfg(feature = "std")]
+pub mod lrgen;
+#[cfg(feature = "std")]
+pub mod pcfg;
+#[cfg(feature = "std")]
+pub mod pda;
+#[cfg(feature = "std")]
+pub mod prefix;
+#[cfg(feature = "std")]
+pub mod rd;
+pub mod recovery;
+#[cfg(feature = "std")]
+pub mod regex;
+#[cfg(feature = "std")]
+pub mod regular;
+#[cfg(feature = "std")]
+pub mod scannerless;
+pub mod source;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(all(test, feature = "std"))]
+mod testing;
+#[cfg(feature = "std")]
+pub mod transform;
+pub mod tree;
+#[cfg(feature = "std")]
+mod tree_sitter;
+#[cfg(feature = "std")]
+pub mod yacc;
+
+/// A terminal symbol, identified by its index among the tokens of a grammar.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct Token(pub u32);
+
+/// A non-terminal symbol, identified by its index among the variables of a grammar.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct Variable(pub u32);
+
+/// A rule of a grammar, identified independently of its position among the rules of its variable.
+/// See `Cfg::rule_id`.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct RuleId(pub u32);
+
+impl Token {
+    pub fn index(self) -> uint {
+        let Token(i) = self;
+        i as uint
+    }
+}
+
+impl Variable {
+    pub fn index(self) -> uint {
+        let Variable(i) = self;
+        i as uint
+    }
+}
+
+/// A symbol is either a token or a variable.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub enum Symbol {
+    Tok(Token),
+    Var(Variable)
+}
+
+/// A set of tokens.
+#[deriving(Clone)]
+pub struct TokenSet {
+    words: Vec<u64>
+}
+
+impl TokenSet {
+    pub fn new() -> TokenSet {
+        TokenSet { words: Vec::new() }
+    }
+
+    /// Add a token to the set, returning whether it wasn't already present.
+    pub fn insert(&mut self, token: Token) -> bool {
+        let (word, bit) = (token.index() / 64, token.index() % 64);
+        while self.words.len() <= word {
+            self.words.push(0);
+        }
+        let present = self.words[word] & (1 << bit) != 0;
+        self.words[word] |= 1 << bit;
+        !present
+    }
+
+    /// Remove a token from the set, returning whether it was present.
+    pub fn remove(&mut self, token: Token) -> bool {
+        let present = self.contains(token);
+        if present {
+            self.words[token.index() / 64] &= !(1 << (token.index() % 64));
+        }
+        present
+    }
+
+    pub fn contains(&self, token: Token) -> bool {
+        match self.words.get(token.index() / 64) {
+            Some(&w) => w & (1 << (token.index() % 64)) != 0,
+            None => false
+        }
+    }
+
+    /// Add all the tokens of `other` to the set, returning whether any weren't already present.
+    pub fn union_with(&mut self, other: &TokenSet) -> bool {
+        while self.words.len() < other.words.len() {
+            self.words.push(0);
+        }
+        let mut changed = false;
+        for (w, &o) in self.words.iter_mut().zip(other.words.iter()) {
+            if *w | o != *w {
+                *w |= o;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Remove every token not in `other` from the set.
+    pub fn intersect_with(&mut self, other: &TokenSet) {
+        for (i, w) in self.words.iter_mut().enumerate() {
+            *w &= other.words.get(i).map_or(0, |&o| o);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    pub fn len(&self) -> uint {
+        self.iter().count()
+    }
+
+    /// Iterate over the tokens in the set, in order.
+    pub fn iter(&self) -> TokenSetIter {
+        TokenSetIter { set: self, next: 0 }
+    }
+}
+
+impl PartialEq for TokenSet {
+    fn eq(&self, other: &TokenSet) -> bool {
+        let n = std::cmp::max(self.words.len(), other.words.len());
+        range(0, n).all(|i| self.words.get(i).map_or(0, |&w| w) ==
+                            other.words.get(i).map_or(0, |&w| w))
+    }
+}
+
+impl Eq for TokenSet { }
+
+impl fmt::Show for TokenSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+        for (i, t) in self.iter().enumerate() {
+            if i > 0 {
+                try!(write!(f, ", "));
+            }
+            try!(write!(f, "{}", t.index()));
+        }
+        write!(f, "}}")
+    }
+}
+
+/// An iterator over the tokens in a `TokenSet`.
+pub struct TokenSetIter<'a> {
+    set: &'a TokenSet,
+    next: uint
+}
+
+impl<'a> Iterator<Token> for TokenSetIter<'a> {
+    fn next(&mut self) -> Option<Token> {
+        while self.next < self.set.words.len() * 64 {
+            let t = Token(self.next as u32);
+            self.next += 1;
+            if self.set.contains(t) {
+                return Some(t)
+            }
+        }
+        None
+    }
+}
+
+/// The body of a production: a possibly empty list of symbols.
+pub type Rule = Vec<Symbol>;
+
+/// Where the symbols of one grammar ended up in another derived from it, for transformations
+/// that renumber or drop symbols.
+#[deriving(Clone)]
+pub struct SymbolMap {
+    variables: VecMap<Variable>,
+    tokens: VecMap<Token>
+}
+
+impl SymbolMap {
+    pub fn new() -> SymbolMap {
+        SymbolMap { variables: VecMap::new(), tokens: VecMap::new() }
+    }
+
+    /// Record that `from` became `to`.
+    pub fn insert_variable(&mut self, from: Variable, to: Variable) {
+        self.variables.insert(from.index(), to);
+    }
+
+    /// Record that `from` became `to`.
+    pub fn insert_token(&mut self, from: Token, to: Token) {
+        self.tokens.insert(from.index(), to);
+    }
+
+    /// The variable `variable` became, or `None` if it was dropped.
+    pub fn variable(&self, variable: Variable) -> Option<Variable> {
+        self.variables.get(&variable.index()).map(|&v| v)
+    }
+
+    /// The token `token` became, or `None` if it was dropped.
+    pub fn token(&self, token: Token) -> Option<Token> {
+        self.tokens.get(&token.index()).map(|&t| t)
+    }
+
+    /// The symbol `symbol` became, or `None` if it was dropped.
+    pub fn symbol(&self, symbol: Symbol) -> Option<Symbol> {
+        match symbol {
+            Symbol::Tok(t) => self.token(t).map(Symbol::Tok),
+            Symbol::Var(v) => self.variable(v).map(Symbol::Var)
+        }
+    }
+}
+
+/// Data attached to rules of a grammar by their ids, such as semantic actions or the source of
+/// each rule.
+#[deriving(Clone)]
+pub struct RuleTable<D> {
+    data: HashMap<RuleId, D>
+}
+
+impl<D> RuleTable<D> {
+    pub fn new() -> RuleTable<D> {
+        RuleTable { data: HashMap::new() }
+    }
+
+    /// Attach `data` to a rule, returning what was attached before, if anything.
+    pub fn set_rule_data(&mut self, id: RuleId, data: D) -> Option<D> {
+        self.data.insert(id, data)
+    }
+
+    pub fn rule_data(&self, id: RuleId) -> Option<&D> {
+        self.data.get(&id)
+    }
+
+    pub fn rule_data_mut(&mut self, id: RuleId) -> Option<&mut D> {
+        self.data.get_mut(&id)
+    }
+
+    /// Detach the data from a rule, returning it.
+    pub fn remove_rule_data(&mut self, id: RuleId) -> Option<D> {
+        self.data.remove(&id)
+    }
+
+    /// The number of rules with data attached.
+    pub fn len(&self) -> uint {
+        self.data.len()
+    }
+}
+
+/// How operators of the same precedence group, for resolving shift/reduce conflicts in LR
+/// parse tables.
+#[deriving(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`: reduce.
+    Left,
+    /// `a = b = c` is `a = (b = c)`: shift.
+    Right,
+    /// `a < b < c` is an error.
+    NonAssoc,
+    /// Only the precedence is given, so a conflict between equal precedences isn't resolved.
+    Unspecified
+}
+
+/// The ways constructing or modifying a grammar can fail.
+#[deriving(Copy, Clone, PartialEq, Eq)]
+pub enum CfgError {
+    /// A variable was used as the start symbol or in a rule body, but has no rules.
+    UndefinedVariable { variable: Variable },
+    /// A symbol was used that hasn't been allocated in the grammar.
+    SymbolOutOfRange { symbol: Symbol },
+    /// The same terminal was given for two different tokens.
+    DuplicateTerminal { token: Token },
+    /// A grammar was built without any variables, so there is no start symbol.
+    NoVariables,
+    /// A variable doesn't have a rule with the given index.
+    RuleOutOfRange { variable: Variable, rule: uint },
+    /// The start symbol was given to an operation that removes the variable.
+    StartVariable { variable: Variable },
+    /// A variable derives a string containing itself, where it must not.
+    RecursiveVariable { variable: Variable },
+    /// A variable to be removed appears in the rules of another.
+    VariableInUse { variable: Variable }
+}
+
+impl fmt::Show for CfgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CfgError::UndefinedVariable { variable } =>
+                write!(f, "variable {} has no rules", variable.index()),
+            CfgError::SymbolOutOfRange { symbol: Symbol::Tok(t) } =>
+                write!(f, "token {} is out of range", t.index()),
+            CfgError::SymbolOutOfRange { symbol: Symbol::Var(v) } =>
+                write!(f, "variable {} is out of range", v.index()),
+            CfgError::DuplicateTerminal { token } =>
+                write!(f, "token {} duplicates the terminal of an earlier token", token.index()),
+            CfgError::NoVariables => write!(f, "grammar has no variables"),
+            CfgError::RuleOutOfRange { variable, rule } =>
+                write!(f, "variable {} has no rule {}", variable.index(), rule),
+            CfgError::StartVariable { variable } =>
+                write!(f, "variable {} is the start symbol", variable.index()),
+            CfgError::RecursiveVariable { variable } =>
+                write!(f, "variable {} derives itself", variable.index()),
+            CfgError::VariableInUse { variable } =>
+                write!(f, "variable {} is used by another variable", variable.index())
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for CfgError {
+    fn description(&self) -> &str {
+        match *self {
+            CfgError::UndefinedVariable { .. } => "undefined variable",
+            CfgError::SymbolOutOfRange { .. } => "symbol out of range",
+            CfgError::DuplicateTerminal { .. } => "duplicate terminal",
+            CfgError::NoVariables => "no variables",
+            CfgError::RuleOutOfRange { .. } => "rule out of range",
+            CfgError::StartVariable { .. } => "start variable",
+            CfgError::RecursiveVariable { .. } => "recursive variable",
+            CfgError::VariableInUse { .. } => "variable in use"
+        }
+    }
+}
+
+/// An error in the text of a grammar being read, such as by `Cfg::parse_bnf`.
+#[deriving(Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// The line the error was found on, starting from 1.
+    pub line: uint,
+    /// The column the error was found at, starting from 1.
+    pub column: uint,
+    pub message: String
+}
+
+impl fmt::Show for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for SyntaxError {
+    fn description(&self) -> &str {
+        self.message.as_slice()
+    }
+}
+
+/// Where the input of a parser stopped matching the grammar.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct ParseError {
+    /// The index of the token that couldn't be parsed, or the length of the input if it ended
+    /// too soon.
+    pub position: uint,
+    /// The token that couldn't be parsed, or `None` if the input ended too soon.
+    pub found: Option<Token>,
+    /// Every token that could have come there instead, in order, with `None` last for the end
+    /// of the input.
+    pub expected: Vec<Option<Token>>
+}
+
+#[cfg(feature = "std")]
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        match self.found {
+            Some(_) => "unexpected token",
+            None => "unexpected end of input"
+        }
+    }
+}
+
+/// A stretch of the text a grammar was read from.
+#[deriving(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The line the span starts on, starting from 1.
+    pub line: uint,
+    /// The column the span starts at, starting from 1.
+    pub column: uint,
+    pub end_line: uint,
+    /// The column just past the end of the span.
+    pub end_column: uint
+}
+
+impl fmt::Show for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}-{}:{}", self.line, self.column, self.end_line, self.end_column)
+    }
+}
+
+/// Where the variables and rules of a grammar read from text were written, such as by
+/// `Cfg::parse_bnf_with_spans`.
+///
+/// Rules are looked up by their ids, so the spans still apply after rules have been added to or
+/// removed from the grammar.
+#[deriving(Clone)]
+pub struct SourceMap {
+    variables: VecMap<Span>,
+    rules: RuleTable<Span>
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { variables: VecMap::new(), rules: RuleTable::new() }
+    }
+
+    /// The span of the name heading the first definition of a variable.
+    pub fn variable_span(&self, variable: Variable) -> Option<Span> {
+        self.variables.get(&variable.index()).map(|&span| span)
+    }
+
+    /// The span of the body of a rule.
+    pub fn rule_span(&self, id: RuleId) -> Option<Span> {
+        self.rules.rule_data(id).map(|&span| span)
+    }
+
+    pub fn set_variable_span(&mut self, variable: Variable, span: Span) {
+        self.variables.insert(variable.index(), span);
+    }
+
+    pub fn set_rule_span(&mut self, id: RuleId, span: Span) {
+        self.rules.set_rule_data(id, span);
+    }
+}
+
+/// What the terminals of a grammar must be: comparable and cloneable, and hashable with the
+/// standard library or ordered without it, so that grammars can look up their tokens. Every
+/// such type is a terminal.
+#[cfg(feature = "std")]
+pub trait Terminal: Eq + Hash + Clone {}
+#[cfg(feature = "std")]
+impl<T: Eq + Hash + Clone> Terminal for T {}
+#[cfg(not(feature = "std"))]
+pub trait Terminal: Ord + Hash + Clone {}
+#[cfg(not(feature = "std"))]
+impl<T: Ord + Hash + Clone> Terminal for T {}
+
 /// A Context-Free Grammar
 ///
 /// A context-free grammar consists of a set of terminals (called tokens), a set of non-terminals
@@ -6,88 +532,875 @@ use std::collections::VecMap;
 /// production is a mapping from a variable to a possibly empty list of symbols (a symbol is either
 /// a token or a variable).
 ///
-/// A symbol is internally represented as a u64, differentiating between tokens and variables by
-/// assuming token values are less than or equal to a fixed value, `last_token`, which represents
-/// the last token. A string "representing" a symbol in some way can be retrieved with
-/// `cfg.name(sym)`.
-
-pub type Rule = Vec<u64>;
-
-pub struct Cfg {
+/// Tokens and variables are numbered independently, and are distinguished by the `Token` and
+/// `Variable` types so one can't be used where the other is expected. Each token stands for a
+/// terminal of type `T`, such as the token kind produced by a lexer; `cfg.token(&t)` and
+/// `cfg.terminal(tok)` convert between the two. A string naming a variable can be retrieved with
+/// `cfg.name(var)`.
+pub struct Cfg<T> {
     rules: VecMap<Vec<Rule>>,
-    symbol_map: VecMap<String>,
-    start: u64,
-    last_token: u64
+    terminals: Vec<T>,
+    token_map: HashMap<T, Token>,
+    variable_names: VecMap<String>,
+    variables: u32,
+    start: Variable,
+    precedence: VecMap<(uint, Associativity)>,
+    rule_precedence: HashMap<RuleId, Token>,
+    /// The id of each rule, in the same layout as `rules`.
+    rule_ids: VecMap<Vec<RuleId>>,
+    next_rule_id: u32
 }
 
-impl Cfg {
-    pub fn new(last_token: u64) -> Cfg {
+impl<T: Terminal> Cfg<T> {
+    /// Create an empty grammar. The first variable added is used as the start symbol.
+    pub fn new() -> Cfg<T> {
         Cfg {
             rules: VecMap::new(),
-            symbol_map: VecMap::new(),
-            start: -1,
-            last_token: last_token
+            terminals: Vec::new(),
+            token_map: HashMap::new(),
+            variable_names: VecMap::new(),
+            variables: 0,
+            start: Variable(0),
+            precedence: VecMap::new(),
+            rule_precedence: HashMap::new(),
+            rule_ids: VecMap::new(),
+            next_rule_id: 0
         }
     }
 
     /// Create a complete `Cfg` from its constituent pieces.
     ///
-    /// Returns `None` if the start symbol is a token, or if there are variables mentioned with no
-    /// corresponding rule.
+    /// `terminals[i]` is the terminal represented by `Token(i)`. Fails if a terminal is listed
+    /// twice, if a rule mentions a token with no terminal, or if the start symbol or any variable
+    /// mentioned in a rule has no corresponding rule.
     pub fn from_pieces(rules: VecMap<Vec<Rule>>,
-                       symbol_map: VecMap<String>,
-                       start: u64, last_token: u64) -> Option<Cfg> {
-        if start <= last_token {
-            return None
+                       terminals: Vec<T>,
+                       variable_names: VecMap<String>,
+                       start: Variable) -> Result<Cfg<T>, CfgError> {
+        if rules.get(&start.index()).is_none() {
+            return Err(CfgError::UndefinedVariable { variable: start })
+        }
+
+        let mut token_map = HashMap::new();
+        for (i, terminal) in terminals.iter().enumerate() {
+            if token_map.insert(terminal.clone(), Token(i as u32)).is_some() {
+                return Err(CfgError::DuplicateTerminal { token: Token(i as u32) })
+            }
         }
 
         for (_, all_rules) in rules.iter() {
             for rule in all_rules.iter() {
                 for &symbol in rule.iter() {
-                    if symbol > last_token && rules.get(&(symbol as uint)).is_none() {
-                        return None
+                    match symbol {
+                        Symbol::Tok(t) if t.index() >= terminals.len() =>
+                            return Err(CfgError::SymbolOutOfRange { symbol: symbol }),
+                        Symbol::Var(v) if rules.get(&v.index()).is_none() =>
+                            return Err(CfgError::UndefinedVariable { variable: v }),
+                        _ => { }
                     }
                 }
             }
         }
 
-        Some(Cfg {
-            rules: rules,
-            symbol_map: symbol_map,
+        let variables = rules.keys().chain(variable_names.keys()).max().map_or(0, |v| v + 1);
+
+        let mut cfg = Cfg {
+            rules: VecMap::new(),
+            terminals: terminals,
+            token_map: token_map,
+            variable_names: variable_names,
+            variables: variables as u32,
             start: start,
-            last_token: last_token
-        })
+            precedence: VecMap::new(),
+            rule_precedence: HashMap::new(),
+            rule_ids: VecMap::new(),
+            next_rule_id: 0
+        };
+        for (v, rules) in rules.into_iter() {
+            cfg.set_rules(Variable(v as u32), rules);
+        }
+        Ok(cfg)
     }
 
-    pub fn get_start(&self) -> u64 {
+    pub fn get_start(&self) -> Variable {
         self.start
     }
 
-    /// Get the name of a symbol.
-    pub fn name(&self, symbol: u64) -> Option<&str> {
-        self.symbol_map.get(&(symbol as uint)).map(|x| x.as_slice())
+    pub fn set_start(&mut self, start: Variable) -> Result<(), CfgError> {
+        try!(self.check_symbol(Symbol::Var(start)));
+        self.start = start;
+        Ok(())
+    }
+
+    /// Get the token standing for `terminal`, allocating a new one if there is none yet.
+    pub fn add_token(&mut self, terminal: T) -> Token {
+        match self.token_map.get(&terminal) {
+            Some(&t) => return t,
+            None => { }
+        }
+        let token = Token(self.terminals.len() as u32);
+        self.terminals.push(terminal.clone());
+        self.token_map.insert(terminal, token);
+        token
+    }
+
+    /// Allocate a new variable with the given name.
+    pub fn add_variable(&mut self, name: &str) -> Variable {
+        let variable = Variable(self.variables);
+        self.variables += 1;
+        self.variable_names.insert(variable.index(), name.to_string());
+        variable
+    }
+
+    /// Get the token standing for a terminal, if any.
+    pub fn token(&self, terminal: &T) -> Option<Token> {
+        self.token_map.get(terminal).map(|&t| t)
+    }
+
+    /// Get the terminal a token stands for.
+    pub fn terminal(&self, token: Token) -> Option<&T> {
+        self.terminals.get(token.index())
+    }
+
+    /// The number of tokens in the grammar.
+    pub fn num_tokens(&self) -> uint {
+        self.terminals.len()
+    }
+
+    /// The number of variables in the grammar.
+    pub fn num_variables(&self) -> uint {
+        self.variables as uint
+    }
+
+    /// Iterate over the variables of the grammar, in order.
+    pub fn variables(&self) -> Variables {
+        Variables { next: 0, end: self.variables }
+    }
+
+    /// Iterate over the tokens of the grammar, in order.
+    pub fn tokens(&self) -> Tokens {
+        Tokens { next: 0, end: self.terminals.len() as u32 }
+    }
+
+    /// Iterate over the rules of the grammar with the variable each belongs to, by variable and
+    /// then in the order of `get_rules`.
+    pub fn rules(&self) -> Rules {
+        Rules { rules: &self.rules, variable: 0, end: self.variables as uint, rule: 0 }
+    }
+
+    /// Map a sequence of terminals to their tokens.
+    ///
+    /// Returns `None` if any terminal isn't part of the grammar.
+    pub fn tokenize(&self, input: &[T]) -> Option<Vec<Token>> {
+        let mut tokens = Vec::with_capacity(input.len());
+        for terminal in input.iter() {
+            match self.token(terminal) {
+                Some(t) => tokens.push(t),
+                None => return None
+            }
+        }
+        Some(tokens)
+    }
+
+    /// Get the name of a variable.
+    pub fn name(&self, variable: Variable) -> Option<&str> {
+        self.variable_names.get(&variable.index()).map(|x| x.as_slice())
     }
 
     /// Add a rule to the grammar.
-    pub fn add_rule(&mut self, variable: u64, body: Rule) {
-        assert!(variable > self.last_token);
-        match self.rules.get_mut(&(variable as uint)) {
+    ///
+    /// Fails if the variable or any symbol in the body hasn't been allocated.
+    pub fn add_rule(&mut self, variable: Variable, body: Rule) -> Result<(), CfgError> {
+        try!(self.check_symbol(Symbol::Var(variable)));
+        for &symbol in body.iter() {
+            try!(self.check_symbol(symbol));
+        }
+        let id = self.new_rule_id();
+        match self.rules.get_mut(&variable.index()) {
             Some(v) => {
                 v.push(body);
-                return;
+                self.rule_ids.get_mut(&variable.index()).unwrap().push(id);
+                return Ok(());
             },
             None => { }
         }
-        self.rules.insert(variable as uint, vec![body]);
+        self.rules.insert(variable.index(), vec![body]);
+        self.rule_ids.insert(variable.index(), vec![id]);
+        Ok(())
+    }
+
+    /// Set the name of a variable, returning the old name if any.
+    pub fn set_name(&mut self, variable: Variable, name: String)
+                    -> Result<Option<String>, CfgError> {
+        try!(self.check_symbol(Symbol::Var(variable)));
+        Ok(self.variable_names.insert(variable.index(), name))
+    }
+
+    pub fn get_rules(&self, variable: Variable) -> Option<&[Rule]> {
+        self.rules.get(&variable.index()).map(|x| x.as_slice())
+    }
+
+    /// Remove a rule, by its index in `get_rules`, returning it. The rules after it move down an
+    /// index, keeping any precedence given them by `set_rule_precedence`.
+    pub fn remove_rule(&mut self, variable: Variable, rule: uint) -> Result<Rule, CfgError> {
+        let removed = match self.rules.get_mut(&variable.index()) {
+            Some(rules) if rule < rules.len() => rules.remove(rule).unwrap(),
+            _ => return Err(CfgError::RuleOutOfRange { variable: variable, rule: rule })
+        };
+        let id = self.rule_ids.get_mut(&variable.index()).unwrap().remove(rule).unwrap();
+        self.rule_precedence.remove(&id);
+        if self.rules.get(&variable.index()).map_or(false, |rules| rules.is_empty()) {
+            self.take_rules(variable);
+        }
+        Ok(removed)
+    }
+
+    /// Replace all the rules of a variable, returning the old ones. Precedences given to the old
+    /// rules by `set_rule_precedence` are dropped.
+    ///
+    /// Fails, leaving the grammar unchanged, if the variable or any symbol in the new rules
+    /// hasn't been allocated.
+    pub fn replace_rules(&mut self, variable: Variable, rules: Vec<Rule>)
+                         -> Result<Vec<Rule>, CfgError> {
+        try!(self.check_symbol(Symbol::Var(variable)));
+        for rule in rules.iter() {
+            for &symbol in rule.iter() {
+                try!(self.check_symbol(symbol));
+            }
+        }
+        let old = self.take_rules(variable);
+        self.set_rules(variable, rules);
+        Ok(old)
     }
 
-    /// Set the name of a symbol, returning the old name if any.
-    pub fn set_name(&mut self, symbol: u64, name: String) -> Option<String> {
-        self.symbol_map.insert(symbol as uint, name)
+    /// Remove a variable and its rules, returning where the other symbols went: the variables
+    /// after it move down an index.
+    ///
+    /// Fails, leaving the grammar unchanged, if the variable is the start symbol or appears in
+    /// the rules of another variable.
+    pub fn remove_variable(&mut self, variable: Variable) -> Result<SymbolMap, CfgError> {
+        try!(self.check_symbol(Symbol::Var(variable)));
+        if variable == self.start {
+            return Err(CfgError::StartVariable { variable: variable })
+        }
+        for (v, rules) in self.rules.iter() {
+            if v != variable.index() && rules.iter().any(|rule| {
+                rule.contains(&Symbol::Var(variable))
+            }) {
+                return Err(CfgError::VariableInUse { variable: variable })
+            }
+        }
+
+        let mut map = SymbolMap::new();
+        for t in range(0, self.num_tokens()) {
+            map.insert_token(Token(t as u32), Token(t as u32));
+        }
+        for v in range(0, self.num_variables()) {
+            if v < variable.index() {
+                map.insert_variable(Variable(v as u32), Variable(v as u32));
+            } else if v > variable.index() {
+                map.insert_variable(Variable(v as u32), Variable(v as u32 - 1));
+            }
+        }
+        self.take_rules(variable);
+        let old = mem::replace(&mut self.rules, VecMap::new());
+        for (v, rules) in old.into_iter() {
+            let rules = rules.into_iter().map(|rule| {
+                rule.into_iter().map(|s| map.symbol(s).unwrap()).collect()
+            }).collect();
+            self.rules.insert(map.variable(Variable(v as u32)).unwrap().index(), rules);
+        }
+        let old = mem::replace(&mut self.rule_ids, VecMap::new());
+        for (v, ids) in old.into_iter() {
+            self.rule_ids.insert(map.variable(Variable(v as u32)).unwrap().index(), ids);
+        }
+        let old = mem::replace(&mut self.variable_names, VecMap::new());
+        for (v, name) in old.into_iter().filter(|&(v, _)| v != variable.index()) {
+            self.variable_names.insert(map.variable(Variable(v as u32)).unwrap().index(), name);
+        }
+        self.variables -= 1;
+        self.start = map.variable(self.start).unwrap();
+        Ok(map)
+    }
+
+    /// Check that the grammar is well-formed as `from_pieces` requires: that the start symbol
+    /// and every variable used in a rule have rules. Editing a grammar can leave it otherwise
+    /// for a while, as when a rule is added before the rules of a variable it uses.
+    pub fn validate(&self) -> Result<(), CfgError> {
+        if self.rules.get(&self.start.index()).is_none() {
+            return Err(CfgError::UndefinedVariable { variable: self.start })
+        }
+        for (_, rules) in self.rules.iter() {
+            for rule in rules.iter() {
+                for &symbol in rule.iter() {
+                    match symbol {
+                        Symbol::Var(v) if self.rules.get(&v.index()).is_none() =>
+                            return Err(CfgError::UndefinedVariable { variable: v }),
+                        _ => { }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Give a token a precedence level and associativity, like the `%left`, `%right` and
+    /// `%nonassoc` declarations of Yacc. Higher levels bind more tightly.
+    pub fn set_precedence(&mut self, token: Token, level: uint, associativity: Associativity)
+                          -> Result<(), CfgError> {
+        try!(self.check_symbol(Symbol::Tok(token)));
+        self.precedence.insert(token.index(), (level, associativity));
+        Ok(())
+    }
+
+    /// The precedence level and associativity of a token, if it has one.
+    pub fn precedence(&self, token: Token) -> Option<(uint, Associativity)> {
+        self.precedence.get(&token.index()).map(|&p| p)
+    }
+
+    /// Give a rule, by its index in `get_rules`, the precedence of `token`, like `%prec` in Yacc.
+    pub fn set_rule_precedence(&mut self, variable: Variable, rule: uint, token: Token)
+                               -> Result<(), CfgError> {
+        try!(self.check_symbol(Symbol::Tok(token)));
+        let id = match self.rule_id(variable, rule) {
+            Some(id) => id,
+            None => return Err(CfgError::RuleOutOfRange { variable: variable, rule: rule })
+        };
+        self.rule_precedence.insert(id, token);
+        Ok(())
+    }
+
+    /// The precedence of a rule: that of the token given by `set_rule_precedence`, or else that
+    /// of the last token in its body with a precedence.
+    pub fn rule_precedence(&self, variable: Variable, rule: uint)
+                           -> Option<(uint, Associativity)> {
+        match self.rule_precedence_token(variable, rule) {
+            Some(t) => return self.precedence(t),
+            None => { }
+        }
+        let body = match self.get_rules(variable) {
+            Some(rules) if rule < rules.len() => rules[rule].as_slice(),
+            _ => return None
+        };
+        body.iter().rev().filter_map(|&s| match s {
+            Symbol::Tok(t) => self.precedence(t),
+            Symbol::Var(_) => None
+        }).next()
+    }
+
+    /// The id of a rule, by its index in `get_rules`.
+    ///
+    /// Each rule added to the grammar gets a new id, which it keeps as other rules are added or
+    /// removed and its index changes, and which no other rule of the grammar gets. Grammars
+    /// produced from this one by transformations give their rules new ids.
+    pub fn rule_id(&self, variable: Variable, rule: uint) -> Option<RuleId> {
+        self.rule_ids.get(&variable.index()).and_then(|ids| ids.get(rule)).map(|&id| id)
+    }
+
+    /// Find the rule with the given id, as its variable and index in `get_rules`.
+    pub fn find_rule(&self, id: RuleId) -> Option<(Variable, uint)> {
+        for (v, ids) in self.rule_ids.iter() {
+            match ids.iter().position(|&other| other == id) {
+                Some(i) => return Some((Variable(v as u32), i)),
+                None => { }
+            }
+        }
+        None
+    }
+
+    /// Set the rules of a variable without checking them, giving each a new id.
+    fn set_rules(&mut self, variable: Variable, rules: Vec<Rule>) {
+        if rules.is_empty() {
+            self.take_rules(variable);
+            return
+        }
+        let ids = range(0, rules.len()).map(|_| self.new_rule_id()).collect();
+        self.rules.insert(variable.index(), rules);
+        self.rule_ids.insert(variable.index(), ids);
+    }
+
+    /// The token given a rule by `set_rule_precedence`, if any.
+    fn rule_precedence_token(&self, variable: Variable, rule: uint) -> Option<Token> {
+        self.rule_id(variable, rule).and_then(|id| self.rule_precedence.get(&id)).map(|&t| t)
+    }
+
+    /// Remove the rules of a variable, returning them. Their precedences go with them.
+    fn take_rules(&mut self, variable: Variable) -> Vec<Rule> {
+        for id in self.rule_ids.remove(&variable.index()).unwrap_or(Vec::new()).iter() {
+            self.rule_precedence.remove(id);
+        }
+        self.rules.remove(&variable.index()).unwrap_or(Vec::new())
+    }
+
+    fn new_rule_id(&mut self) -> RuleId {
+        self.next_rule_id += 1;
+        RuleId(self.next_rule_id - 1)
+    }
+
+    fn check_symbol(&self, symbol: Symbol) -> Result<(), CfgError> {
+        let in_range = match symbol {
+            Symbol::Tok(t) => t.index() < self.terminals.len(),
+            Symbol::Var(v) => v.index() < self.variables as uint
+        };
+        if in_range {
+            Ok(())
+        } else {
+            Err(CfgError::SymbolOutOfRange { symbol: symbol })
+        }
+    }
+}
+
+/// An iterator over the variables of a grammar.
+pub struct Variables {
+    next: u32,
+    end: u32
+}
+
+impl Iterator<Variable> for Variables {
+    fn next(&mut self) -> Option<Variable> {
+        if self.next == self.end {
+            return None
+        }
+        self.next += 1;
+        Some(Variable(self.next - 1))
+    }
+}
+
+/// An iterator over the tokens of a grammar.
+pub struct Tokens {
+    next: u32,
+    end: u32
+}
+
+impl Iterator<Token> for Tokens {
+    fn next(&mut self) -> Option<Token> {
+        if self.next == self.end {
+            return None
+        }
+        self.next += 1;
+        Some(Token(self.next - 1))
+    }
+}
+
+/// An iterator over the rules of a grammar, with the variable each belongs to.
+pub struct Rules<'a> {
+    rules: &'a VecMap<Vec<Rule>>,
+    variable: uint,
+    end: uint,
+    /// The index of the next rule of the current variable.
+    rule: uint
+}
+
+impl<'a> Iterator<(Variable, &'a Rule)> for Rules<'a> {
+    fn next(&mut self) -> Option<(Variable, &'a Rule)> {
+        while self.variable < self.end {
+            match self.rules.get(&self.variable) {
+                Some(rules) if self.rule < rules.len() => {
+                    self.rule += 1;
+                    return Some((Variable(self.variable as u32), &rules[self.rule - 1]))
+                },
+                _ => { }
+            }
+            self.variable += 1;
+            self.rule = 0;
+        }
+        None
+    }
+}
+
+/// Incrementally builds a `Cfg`, allocating symbol ids as symbols are added.
+///
+/// The first variable added is used as the start symbol unless `set_start` is called.
+pub struct CfgBuilder<T> {
+    rules: VecMap<Vec<Rule>>,
+    terminals: Vec<T>,
+    variable_names: VecMap<String>,
+    next_variable: u32,
+    start: Option<Variable>
+}
+
+impl<T: Terminal> CfgBuilder<T> {
+    pub fn new() -> CfgBuilder<T> {
+        CfgBuilder {
+            rules: VecMap::new(),
+            terminals: Vec::new(),
+            variable_names: VecMap::new(),
+            next_variable: 0,
+            start: None
+        }
+    }
+
+    /// Get the token standing for `terminal`, allocating a new one if it hasn't been added yet.
+    pub fn add_token(&mut self, terminal: T) -> Token {
+        match self.terminals.iter().position(|t| *t == terminal) {
+            Some(i) => return Token(i as u32),
+            None => { }
+        }
+        self.terminals.push(terminal);
+        Token((self.terminals.len() - 1) as u32)
+    }
+
+    /// Allocate a new variable with the given name.
+    pub fn add_variable(&mut self, name: &str) -> Variable {
+        let variable = Variable(self.next_variable);
+        self.next_variable += 1;
+        if self.start.is_none() {
+            self.start = Some(variable);
+        }
+        self.variable_names.insert(variable.index(), name.to_string());
+        variable
+    }
+
+    /// Add a rule to the grammar being built.
+    ///
+    /// Fails if the variable or any symbol in the body hasn't been allocated.
+    pub fn add_rule(&mut self, variable: Variable, body: Rule) -> Result<(), CfgError> {
+        try!(self.check_symbol(Symbol::Var(variable)));
+        for &symbol in body.iter() {
+            try!(self.check_symbol(symbol));
+        }
+        match self.rules.get_mut(&variable.index()) {
+            Some(v) => {
+                v.push(body);
+                return Ok(());
+            },
+            None => { }
+        }
+        self.rules.insert(variable.index(), vec![body]);
+        Ok(())
+    }
+
+    /// Use `variable` as the start symbol instead of the first variable added.
+    pub fn set_start(&mut self, variable: Variable) -> Result<(), CfgError> {
+        try!(self.check_symbol(Symbol::Var(variable)));
+        self.start = Some(variable);
+        Ok(())
+    }
+
+    /// Finish building, validating the grammar as `Cfg::from_pieces` does.
+    pub fn build(self) -> Result<Cfg<T>, CfgError> {
+        let start = match self.start {
+            Some(start) => start,
+            None => return Err(CfgError::NoVariables)
+        };
+        let mut cfg = try!(Cfg::from_pieces(self.rules, self.terminals,
+                                            self.variable_names, start));
+        cfg.variables = self.next_variable;
+        Ok(cfg)
+    }
+
+    fn check_symbol(&self, symbol: Symbol) -> Result<(), CfgError> {
+        let in_range = match symbol {
+            Symbol::Tok(t) => t.index() < self.terminals.len(),
+            Symbol::Var(Variable(v)) => v < self.next_variable
+        };
+        if in_range {
+            Ok(())
+        } else {
+            Err(CfgError::SymbolOutOfRange { symbol: symbol })
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::collections::VecMap;
+
+    use super::{Associativity, Cfg, CfgBuilder, CfgError, RuleId, RuleTable, Symbol, Token,
+                TokenSet, Variable};
+
+    #[test]
+    fn builder() {
+        let mut builder = CfgBuilder::new();
+        let e = builder.add_variable("e");
+        let plus = builder.add_token('+');
+        let t = builder.add_variable("t");
+        let x = builder.add_token('x');
+        builder.add_rule(e, vec![Symbol::Var(e), Symbol::Tok(plus), Symbol::Var(t)]).unwrap();
+        builder.add_rule(e, vec![Symbol::Var(t)]).unwrap();
+        builder.add_rule(t, vec![Symbol::Tok(x)]).unwrap();
+        let cfg = builder.build().unwrap();
+        // Tokens and variables are numbered separately, however they're interleaved.
+        assert_eq!((plus, x), (Token(0), Token(1)));
+        assert_eq!((e, t), (Variable(0), Variable(1)));
+        assert_eq!(cfg.get_start(), e);
+        assert_eq!(cfg.name(t), Some("t"));
+        assert_eq!(cfg.num_variables(), 2);
+        let rules = cfg.get_rules(e).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0], vec![Symbol::Var(e), Symbol::Tok(plus), Symbol::Var(t)]);
+        assert_eq!(rules[1], vec![Symbol::Var(t)]);
+    }
+
+    #[test]
+    fn builder_start() {
+        let mut builder = CfgBuilder::new();
+        let s = builder.add_variable("s");
+        let t = builder.add_variable("t");
+        let x = builder.add_token('x');
+        builder.add_rule(s, vec![Symbol::Var(t)]).unwrap();
+        builder.add_rule(t, vec![Symbol::Tok(x)]).unwrap();
+        builder.set_start(t).unwrap();
+        assert_eq!(builder.build().unwrap().get_start(), t);
+    }
+
+    #[test]
+    fn builder_errors() {
+        assert_eq!(CfgBuilder::<char>::new().build().err(), Some(CfgError::NoVariables));
+
+        let mut builder = CfgBuilder::<char>::new();
+        let s = builder.add_variable("s");
+        let t = builder.add_variable("t");
+        builder.add_rule(s, vec![Symbol::Var(t)]).unwrap();
+        assert_eq!(builder.add_rule(s, vec![Symbol::Tok(Token(0))]),
+                   Err(CfgError::SymbolOutOfRange { symbol: Symbol::Tok(Token(0)) }));
+        assert_eq!(builder.set_start(Variable(2)),
+                   Err(CfgError::SymbolOutOfRange { symbol: Symbol::Var(Variable(2)) }));
+        assert_eq!(builder.build().err(), Some(CfgError::UndefinedVariable { variable: t }));
+    }
+
+    #[test]
+    fn terminals() {
+        let mut cfg = Cfg::new();
+        let a = cfg.add_token("a".to_string());
+        let b = cfg.add_token("b".to_string());
+        assert_eq!(cfg.add_token("a".to_string()), a);
+        assert_eq!(cfg.num_tokens(), 2);
+        assert_eq!(cfg.token(&"b".to_string()), Some(b));
+        assert_eq!(cfg.token(&"c".to_string()), None);
+        assert_eq!(cfg.terminal(a).map(|t| t.as_slice()), Some("a"));
+        assert_eq!(cfg.terminal(Token(2)), None);
+        let input = vec!["b".to_string(), "a".to_string(), "b".to_string()];
+        assert_eq!(cfg.tokenize(input.as_slice()), Some(vec![b, a, b]));
+        assert_eq!(cfg.tokenize(&["c".to_string()]), None);
+
+        let mut builder = CfgBuilder::new();
+        assert_eq!(builder.add_token(1u), builder.add_token(1u));
+    }
+
+    #[test]
+    fn modify() {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let x = cfg.add_token('x');
+        cfg.add_rule(s, vec![Symbol::Tok(x), Symbol::Var(s)]).unwrap();
+        cfg.add_rule(s, vec![]).unwrap();
+        assert_eq!(cfg.get_start(), s);
+        assert_eq!(cfg.set_name(s, "start".to_string()), Ok(Some("s".to_string())));
+        assert_eq!(cfg.name(s), Some("start"));
+        assert_eq!(cfg.get_rules(s).map(|r| r.len()), Some(2));
+        assert_eq!(cfg.add_rule(Variable(1), vec![]),
+                   Err(CfgError::SymbolOutOfRange { symbol: Symbol::Var(Variable(1)) }));
+        assert_eq!(cfg.set_start(Variable(1)),
+                   Err(CfgError::SymbolOutOfRange { symbol: Symbol::Var(Variable(1)) }));
+    }
+
+    #[test]
+    fn from_pieces() {
+        let mut rules = VecMap::new();
+        rules.insert(0, vec![vec![Symbol::Tok(Token(0))]]);
+        let duplicate = vec!['a', 'a'];
+        assert_eq!(Cfg::from_pieces(rules.clone(), duplicate, VecMap::new(), Variable(0)).err(),
+                   Some(CfgError::DuplicateTerminal { token: Token(1) }));
+        assert_eq!(Cfg::from_pieces(rules.clone(), vec![], VecMap::new(), Variable(0)).err(),
+                   Some(CfgError::SymbolOutOfRange { symbol: Symbol::Tok(Token(0)) }));
+        assert_eq!(Cfg::from_pieces(rules.clone(), vec!['a'], VecMap::new(), Variable(1)).err(),
+                   Some(CfgError::UndefinedVariable { variable: Variable(1) }));
+        let cfg = Cfg::from_pieces(rules, vec!['a'], VecMap::new(), Variable(0)).unwrap();
+        assert_eq!(cfg.num_variables(), 1);
+        assert_eq!(cfg.token(&'a'), Some(Token(0)));
+    }
+
+    #[test]
+    fn token_set() {
+        let mut set = TokenSet::new();
+        assert!(set.is_empty());
+        assert!(set.insert(Token(3)) && set.insert(Token(70)));
+        assert!(!set.insert(Token(3)));
+        assert!(set.contains(Token(70)) && !set.contains(Token(4)) && !set.contains(Token(200)));
+        assert_eq!(set.iter().collect::<Vec<Token>>(), vec![Token(3), Token(70)]);
+        assert_eq!(set.len(), 2);
+
+        let mut other = TokenSet::new();
+        other.insert(Token(3));
+        other.insert(Token(5));
+        assert!(set.union_with(&other));
+        assert!(!set.union_with(&other));
+        assert_eq!(set.len(), 3);
+        set.intersect_with(&other);
+        assert_eq!(set, other);
+        assert!(set.remove(Token(5)) && !set.remove(Token(5)));
+        // Sets are equal whatever capacity they were left with.
+        other.remove(Token(5));
+        other.remove(Token(3));
+        assert_eq!(other, TokenSet::new());
+    }
+
+    #[test]
+    fn remove_rule() {
+        let mut cfg = Cfg::new();
+        let e = cfg.add_variable("e");
+        let minus = cfg.add_token('-');
+        let n = cfg.add_token('n');
+        let uminus = cfg.add_token('u');
+        cfg.add_rule(e, vec![Symbol::Var(e), Symbol::Tok(minus), Symbol::Var(e)]).unwrap();
+        cfg.add_rule(e, vec![Symbol::Tok(minus), Symbol::Var(e)]).unwrap();
+        cfg.add_rule(e, vec![Symbol::Tok(n)]).unwrap();
+        cfg.set_precedence(uminus, 2, Associativity::Right).unwrap();
+        cfg.set_rule_precedence(e, 1, uminus).unwrap();
+
+        assert_eq!(cfg.remove_rule(e, 0),
+                   Ok(vec![Symbol::Var(e), Symbol::Tok(minus), Symbol::Var(e)]));
+        assert_eq!(cfg.rule_precedence(e, 0), Some((2, Associativity::Right)));
+        assert_eq!(cfg.rule_precedence(e, 1), None);
+        assert_eq!(cfg.remove_rule(e, 2), Err(CfgError::RuleOutOfRange { variable: e, rule: 2 }));
+        assert_eq!(cfg.remove_rule(e, 1), Ok(vec![Symbol::Tok(n)]));
+        assert_eq!(cfg.remove_rule(e, 0), Ok(vec![Symbol::Tok(minus), Symbol::Var(e)]));
+        assert_eq!(cfg.get_rules(e), None);
+        assert_eq!(cfg.validate(), Err(CfgError::UndefinedVariable { variable: e }));
+    }
+
+    #[test]
+    fn replace_rules() {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let x = cfg.add_token('x');
+        cfg.add_rule(s, vec![Symbol::Tok(x), Symbol::Tok(x)]).unwrap();
+        cfg.set_precedence(x, 1, Associativity::Left).unwrap();
+        cfg.set_rule_precedence(s, 0, x).unwrap();
+
+        let rules = vec![vec![Symbol::Tok(x)], vec![]];
+        assert_eq!(cfg.replace_rules(s, vec![vec![Symbol::Tok(Token(1))]]),
+                   Err(CfgError::SymbolOutOfRange { symbol: Symbol::Tok(Token(1)) }));
+        assert_eq!(cfg.get_rules(s).unwrap().len(), 1);
+        assert_eq!(cfg.replace_rules(s, rules.clone()),
+                   Ok(vec![vec![Symbol::Tok(x), Symbol::Tok(x)]]));
+        assert_eq!(cfg.get_rules(s), Some(rules.as_slice()));
+        // The new first rule takes its precedence from its body, not from the one it replaced.
+        cfg.set_precedence(x, 3, Associativity::Left).unwrap();
+        assert_eq!(cfg.rule_precedence(s, 0), Some((3, Associativity::Left)));
+        assert_eq!(cfg.replace_rules(s, Vec::new()), Ok(rules));
+        assert_eq!(cfg.get_rules(s), None);
+    }
+
+    #[test]
+    fn remove_variable() {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let a = cfg.add_variable("a");
+        let b = cfg.add_variable("b");
+        let c = cfg.add_variable("c");
+        let x = cfg.add_token('x');
+        cfg.add_rule(s, vec![Symbol::Var(a), Symbol::Tok(x)]).unwrap();
+        cfg.add_rule(s, vec![Symbol::Var(c)]).unwrap();
+        cfg.add_rule(a, vec![Symbol::Tok(x)]).unwrap();
+        cfg.add_rule(b, vec![Symbol::Var(a)]).unwrap();
+        cfg.add_rule(c, vec![Symbol::Var(c), Symbol::Tok(x)]).unwrap();
+        cfg.add_rule(c, vec![]).unwrap();
+
+        assert_eq!(cfg.remove_variable(s).err(), Some(CfgError::StartVariable { variable: s }));
+        assert_eq!(cfg.remove_variable(a).err(), Some(CfgError::VariableInUse { variable: a }));
+        assert_eq!(cfg.num_variables(), 4);
+
+        let map = cfg.remove_variable(b).unwrap();
+        assert_eq!(map.variable(b), None);
+        assert_eq!(map.variable(a), Some(a));
+        let c2 = map.variable(c).unwrap();
+        assert_eq!(c2, Variable(2));
+        assert_eq!(map.token(x), Some(x));
+        assert_eq!(cfg.num_variables(), 3);
+        assert_eq!(cfg.name(c2), Some("c"));
+        assert_eq!(cfg.get_rules(s).unwrap()[1], vec![Symbol::Var(c2)]);
+        assert_eq!(cfg.get_rules(c2).unwrap()[0], vec![Symbol::Var(c2), Symbol::Tok(x)]);
+        assert_eq!(cfg.get_start(), s);
+        assert_eq!(cfg.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate() {
+        let mut cfg = Cfg::<char>::new();
+        let s = cfg.add_variable("s");
+        assert_eq!(cfg.validate(), Err(CfgError::UndefinedVariable { variable: s }));
+        let t = cfg.add_variable("t");
+        cfg.add_rule(s, vec![Symbol::Var(t)]).unwrap();
+        assert_eq!(cfg.validate(), Err(CfgError::UndefinedVariable { variable: t }));
+        cfg.add_rule(t, vec![]).unwrap();
+        assert_eq!(cfg.validate(), Ok(()));
+    }
+
+    #[test]
+    fn iterators() {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let a = cfg.add_variable("a");
+        let b = cfg.add_variable("b");
+        let x = cfg.add_token('x');
+        let y = cfg.add_token('y');
+        cfg.add_rule(s, vec![Symbol::Var(b), Symbol::Tok(x)]).unwrap();
+        cfg.add_rule(s, vec![]).unwrap();
+        cfg.add_rule(b, vec![Symbol::Tok(y)]).unwrap();
+
+        let variables: Vec<Variable> = cfg.variables().collect();
+        assert_eq!(variables, vec![s, a, b]);
+        let tokens: Vec<Token> = cfg.tokens().collect();
+        assert_eq!(tokens, vec![x, y]);
+        let rules: Vec<(Variable, &Vec<Symbol>)> = cfg.rules().collect();
+        assert_eq!(rules.len(), 3);
+        let owners: Vec<Variable> = rules.iter().map(|&(v, _)| v).collect();
+        assert_eq!(owners, vec![s, s, b]);
+        let bodies: Vec<Vec<Symbol>> = rules.iter().map(|&(_, rule)| rule.clone()).collect();
+        assert_eq!(bodies, vec![vec![Symbol::Var(b), Symbol::Tok(x)], vec![],
+                                vec![Symbol::Tok(y)]]);
+
+        let empty = Cfg::<char>::new();
+        assert_eq!(empty.variables().count(), 0);
+        assert_eq!(empty.rules().count(), 0);
+    }
+
+    #[test]
+    fn rule_ids() {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let t = cfg.add_variable("t");
+        let x = cfg.add_token('x');
+        cfg.add_rule(s, vec![Symbol::Var(t)]).unwrap();
+        cfg.add_rule(s, vec![Symbol::Tok(x)]).unwrap();
+        cfg.add_rule(t, vec![]).unwrap();
+        let (first, second, third) = (cfg.rule_id(s, 0).unwrap(), cfg.rule_id(s, 1).unwrap(),
+                                      cfg.rule_id(t, 0).unwrap());
+        assert!(first != second && second != third && first != third);
+        assert_eq!(cfg.rule_id(s, 2), None);
+        assert_eq!(cfg.find_rule(third), Some((t, 0)));
+
+        // Ids follow their rules as others are removed, and aren't reused.
+        cfg.remove_rule(s, 0).unwrap();
+        assert_eq!(cfg.rule_id(s, 0), Some(second));
+        assert_eq!(cfg.find_rule(second), Some((s, 0)));
+        assert_eq!(cfg.find_rule(first), None);
+        cfg.add_rule(s, vec![Symbol::Var(t), Symbol::Tok(x)]).unwrap();
+        let fourth = cfg.rule_id(s, 1).unwrap();
+        assert!(fourth != first && fourth != second && fourth != third);
+        cfg.replace_rules(s, vec![vec![Symbol::Tok(x)]]).unwrap();
+        assert!(cfg.rule_id(s, 0) != Some(second));
+        assert_eq!(cfg.find_rule(second), None);
+
+        let u = cfg.add_variable("u");
+        cfg.add_rule(u, vec![Symbol::Tok(x)]).unwrap();
+        let id = cfg.rule_id(u, 0).unwrap();
+        cfg.remove_variable(t).unwrap();
+        assert_eq!(cfg.find_rule(id), Some((Variable(1), 0)));
     }
 
-    pub fn get_rules(&self, variable: u64) -> Option<&[Rule]> {
-        assert!(variable > self.last_token);
-        self.rules.get(&(variable as uint)).map(|x| x.as_slice())
+    #[test]
+    fn rule_table() {
+        let mut table = RuleTable::new();
+        assert_eq!(table.set_rule_data(RuleId(3), "a"), None);
+        assert_eq!(table.set_rule_data(RuleId(3), "b"), Some("a"));
+        assert_eq!(table.rule_data(RuleId(3)), Some(&"b"));
+        assert_eq!(table.rule_data(RuleId(4)), None);
+        *table.rule_data_mut(RuleId(3)).unwrap() = "c";
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.remove_rule_data(RuleId(3)), Some("c"));
+        assert_eq!(table.len(), 0);
     }
 }
@@ -0,0 +1,295 @@
+//! Checking a grammar for likely mistakes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, Rule, SourceMap, Span, Symbol, Token, Variable};
+
+/// How serious a diagnostic is.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub enum Severity {
+    /// The grammar works, but something in it is likely a mistake or redundant.
+    Warning,
+    /// Part of the grammar can't be used as written.
+    Error
+}
+
+/// A likely mistake in a grammar, found by `Cfg::lint`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum Lint {
+    /// A variable has two identical rules; `rule` is a later copy of `first`.
+    DuplicateRule { variable: Variable, first: uint, rule: uint },
+    /// A variable has no name.
+    UnnamedVariable { variable: Variable },
+    /// A variable is named the same as the terminal of a token.
+    VariableShadowsToken { variable: Variable, token: Token },
+    /// A variable can't be reached from the start symbol.
+    UnreachableVariable { variable: Variable },
+    /// A token doesn't appear in any rule reachable from the start symbol.
+    UnreachableToken { token: Token },
+    /// A variable can't derive any string of tokens.
+    NonproductiveVariable { variable: Variable },
+    /// A variable other than the start symbol has a single rule of a single symbol, and is used
+    /// only once, so could be replaced by that symbol.
+    SingleUseAlias { variable: Variable },
+    /// The variables derive each other without producing anything else, so the sentences
+    /// derived through them have infinitely many parse trees.
+    Cycle { variables: Vec<Variable> }
+}
+
+impl Lint {
+    pub fn severity(&self) -> Severity {
+        match *self {
+            Lint::NonproductiveVariable { .. } | Lint::Cycle { .. } => Severity::Error,
+            _ => Severity::Warning
+        }
+    }
+
+    /// Where the diagnostic is written in the source of a grammar read from text: the rule for
+    /// a duplicate rule, and the definition of the variable for the other lints about a variable.
+    pub fn span<T: Eq + Hash + Clone>(&self, cfg: &Cfg<T>, spans: &SourceMap) -> Option<Span> {
+        match *self {
+            Lint::DuplicateRule { variable, rule, .. } => {
+                cfg.rule_id(variable, rule).and_then(|id| spans.rule_span(id))
+            },
+            Lint::UnnamedVariable { variable } |
+            Lint::VariableShadowsToken { variable, .. } |
+            Lint::UnreachableVariable { variable } |
+            Lint::NonproductiveVariable { variable } |
+            Lint::SingleUseAlias { variable } => spans.variable_span(variable),
+            Lint::Cycle { ref variables } => spans.variable_span(variables[0]),
+            Lint::UnreachableToken { .. } => None
+        }
+    }
+}
+
+/// A lint found in a grammar, with a message naming the symbols involved.
+#[deriving(Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub lint: Lint,
+    pub message: String
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        self.lint.severity()
+    }
+}
+
+impl fmt::Show for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity() {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message)
+        }
+    }
+}
+
+impl<T: fmt::Show + Eq + Hash + Clone> Cfg<T> {
+    /// Check the grammar for likely mistakes, giving a diagnostic for each.
+    ///
+    /// The diagnostics are grouped by lint, in the order the lints are declared in `Lint`, and
+    /// each group is in order of the symbols involved. A grammar read with one of the
+    /// `*_with_spans` readers can locate each diagnostic with `Lint::span`.
+    pub fn lint(&self) -> Vec<Diagnostic> {
+        let mut lints = Vec::new();
+        let n = self.num_variables();
+        let variables = || range(0, n).map(|v| Variable(v as u32));
+
+        for variable in variables() {
+            let rules = rules(self, variable);
+            for i in range(0, rules.len()) {
+                match range(0, i).find(|&j| rules[j] == rules[i]) {
+                    Some(j) => {
+                        lints.push(Lint::DuplicateRule { variable: variable, first: j, rule: i })
+                    },
+                    None => { }
+                }
+            }
+        }
+
+        for variable in variables().filter(|&v| self.name(v).is_none()) {
+            lints.push(Lint::UnnamedVariable { variable: variable });
+        }
+
+        let mut terminals = HashMap::new();
+        for t in range(0, self.num_tokens()) {
+            let token = Token(t as u32);
+            let text = format!("{}", self.terminal(token).unwrap());
+            if !terminals.contains_key(&text) {
+                terminals.insert(text, token);
+            }
+        }
+        for variable in variables() {
+            match self.name(variable).and_then(|name| terminals.get(name)) {
+                Some(&token) => {
+                    lints.push(Lint::VariableShadowsToken { variable: variable, token: token })
+                },
+                None => { }
+            }
+        }
+
+        let unreachable = self.unreachable_symbols();
+        for &symbol in unreachable.iter() {
+            match symbol {
+                Symbol::Var(v) => lints.push(Lint::UnreachableVariable { variable: v }),
+                Symbol::Tok(_) => { }
+            }
+        }
+        for &symbol in unreachable.iter() {
+            match symbol {
+                Symbol::Tok(t) => lints.push(Lint::UnreachableToken { token: t }),
+                Symbol::Var(_) => { }
+            }
+        }
+
+        for &variable in self.nonproductive_symbols().iter() {
+            lints.push(Lint::NonproductiveVariable { variable: variable });
+        }
+
+        let mut uses = Vec::from_elem(n, 0u);
+        for variable in variables() {
+            for rule in rules(self, variable).iter() {
+                for &symbol in rule.iter() {
+                    match symbol {
+                        Symbol::Var(w) => uses[w.index()] += 1,
+                        Symbol::Tok(_) => { }
+                    }
+                }
+            }
+        }
+        for variable in variables() {
+            let rules = rules(self, variable);
+            if variable != self.get_start() && uses[variable.index()] == 1 &&
+               rules.len() == 1 && rules[0].len() == 1 && rules[0][0] != Symbol::Var(variable) {
+                lints.push(Lint::SingleUseAlias { variable: variable });
+            }
+        }
+
+        for component in self.cycles().cyclic_components().into_iter() {
+            lints.push(Lint::Cycle { variables: component.to_vec() });
+        }
+
+        lints.into_iter().map(|lint| {
+            let message = message(self, &lint);
+            Diagnostic { lint: lint, message: message }
+        }).collect()
+    }
+}
+
+fn message<T: fmt::Show + Eq + Hash + Clone>(cfg: &Cfg<T>, lint: &Lint) -> String {
+    let var = |v: Variable| cfg.symbol_to_bnf(Symbol::Var(v));
+    match *lint {
+        Lint::DuplicateRule { variable, first, rule } => {
+            format!("rule {} of {} is the same as rule {}", rule, var(variable), first)
+        },
+        Lint::UnnamedVariable { variable } => format!("{} has no name", var(variable)),
+        Lint::VariableShadowsToken { variable, token } => {
+            format!("{} is named the same as the token {}", var(variable),
+                    cfg.symbol_to_bnf(Symbol::Tok(token)))
+        },
+        Lint::UnreachableVariable { variable } => {
+            format!("{} can't be reached from the start symbol", var(variable))
+        },
+        Lint::UnreachableToken { token } => {
+            format!("the token {} isn't used by any reachable rule",
+                    cfg.symbol_to_bnf(Symbol::Tok(token)))
+        },
+        Lint::NonproductiveVariable { variable } => {
+            format!("{} can't derive any string of tokens", var(variable))
+        },
+        Lint::SingleUseAlias { variable } => {
+            format!("{} stands for a single symbol and is used once", var(variable))
+        },
+        Lint::Cycle { ref variables } if variables.len() == 1 => {
+            format!("{} derives itself without producing anything else", var(variables[0]))
+        },
+        Lint::Cycle { ref variables } => {
+            let names: Vec<String> = variables.iter().map(|&v| var(v)).collect();
+            format!("{} derive each other without producing anything else",
+                    names.connect(", "))
+        }
+    }
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecMap;
+
+    use super::{Lint, Severity};
+    use super::super::{Cfg, Symbol, Token, Variable};
+    use testing::{EXPRESSIONS_LL1, grammar, variable};
+
+    const MISTAKES: &'static str = "
+    <s> ::= <p> \"x\" | <p> \"x\" | <c> | <l>
+    <p> ::= \"a\" | <n>
+    <n> ::= \"b\" <n>
+    <c> ::= \"c\"
+    <l> ::= <m> | \"z\"
+    <m> ::= <l>
+    <u> ::= \"w\"
+    ";
+
+    fn token(cfg: &Cfg<String>, terminal: &str) -> Token {
+        cfg.token(&terminal.to_string()).unwrap()
+    }
+
+    #[test]
+    fn lint() {
+        let cfg = grammar(MISTAKES);
+        let (s, c, l, m) = (variable(&cfg, "s"), variable(&cfg, "c"), variable(&cfg, "l"),
+                            variable(&cfg, "m"));
+        let diagnostics = cfg.lint();
+        let lints: Vec<Lint> = diagnostics.iter().map(|d| d.lint.clone()).collect();
+        let mut cycle = vec![l, m];
+        cycle.sort();
+        assert_eq!(lints, vec![
+            Lint::DuplicateRule { variable: s, first: 0, rule: 1 },
+            Lint::VariableShadowsToken { variable: c, token: token(&cfg, "c") },
+            Lint::UnreachableVariable { variable: variable(&cfg, "u") },
+            Lint::UnreachableToken { token: token(&cfg, "w") },
+            Lint::NonproductiveVariable { variable: variable(&cfg, "n") },
+            Lint::SingleUseAlias { variable: c },
+            Lint::SingleUseAlias { variable: m },
+            Lint::Cycle { variables: cycle }
+        ]);
+        assert_eq!(diagnostics[0].severity(), Severity::Warning);
+        assert_eq!(diagnostics[4].severity(), Severity::Error);
+        assert_eq!(format!("{}", diagnostics[0]).as_slice(),
+                   "warning: rule 1 of <s> is the same as rule 0");
+        assert_eq!(format!("{}", diagnostics[4]).as_slice(),
+                   "error: <n> can't derive any string of tokens");
+        assert_eq!(format!("{}", diagnostics[7]).as_slice(),
+                   "error: <l>, <m> derive each other without producing anything else");
+
+        assert!(grammar(EXPRESSIONS_LL1).lint().is_empty());
+    }
+
+    #[test]
+    fn unnamed_variable() {
+        let mut rules = VecMap::new();
+        rules.insert(0, vec![vec![Symbol::Var(Variable(1)), Symbol::Var(Variable(1))]]);
+        rules.insert(1, vec![vec![Symbol::Tok(Token(0))]]);
+        let mut names = VecMap::new();
+        names.insert(0, "s".to_string());
+        let cfg = Cfg::from_pieces(rules, vec!['x'], names, Variable(0)).unwrap();
+        let lints: Vec<Lint> = cfg.lint().into_iter().map(|d| d.lint).collect();
+        assert_eq!(lints, vec![Lint::UnnamedVariable { variable: Variable(1) }]);
+    }
+
+    #[test]
+    fn spans() {
+        let (cfg, spans) = Cfg::parse_bnf_with_spans(MISTAKES).unwrap();
+        let diagnostics = cfg.lint();
+        assert_eq!(format!("{}", diagnostics[0].lint.span(&cfg, &spans).unwrap()).as_slice(),
+                   "2:19-2:26");
+        assert_eq!(format!("{}", diagnostics[4].lint.span(&cfg, &spans).unwrap()).as_slice(),
+                   "4:1-4:4");
+        assert_eq!(diagnostics[3].lint.span(&cfg, &spans), None);
+    }
+}
@@ -0,0 +1,520 @@
+//! LL(1) predictive parse tables, and a parser driven by them.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use super::{Cfg, ParseError, Symbol, Terminal, Token, Variable};
+use actions::{Reducer, TreeBuilder};
+use binary::{Kind, LoadError, Reader, Writer};
+use recovery::{PartialTreeBuilder, Recover, SyncSets, report};
+use source::{TokenSource, TokenStream};
+use tree::ParseTree;
+
+/// An LL(1) parse table: the rule to expand a variable with, given the next token of input.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Ll1Table {
+    tokens: uint,
+    entries: Vec<Option<uint>>
+}
+
+impl Ll1Table {
+    /// The index, in `get_rules`, of the rule to expand `variable` with when the next token is
+    /// `lookahead`, or the end of the input if `lookahead` is `None`.
+    pub fn get(&self, variable: Variable, lookahead: Option<Token>) -> Option<uint> {
+        match self.entries.get(self.cell(variable, lookahead)) {
+            Some(&entry) => entry,
+            None => None
+        }
+    }
+
+    /// The number of tokens in the grammar the table was built from.
+    pub fn num_tokens(&self) -> uint {
+        self.tokens
+    }
+
+    /// Whether the table could have been built for `cfg`: it has as many tokens and a row for
+    /// each variable, and only expands a variable by rules it has.
+    pub fn fits<T: Terminal>(&self, cfg: &Cfg<T>) -> bool {
+        self.tokens == cfg.num_tokens() &&
+            self.entries.len() == cfg.num_variables() * (self.tokens + 1) &&
+            self.entries.iter().enumerate().all(|(c, &entry)| match entry {
+                Some(rule) => {
+                    let v = Variable((c / (self.tokens + 1)) as u32);
+                    rule < cfg.get_rules(v).map_or(0, |rules| rules.len())
+                },
+                None => true
+            })
+    }
+
+    /// The table in the binary format of the `binary` module.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new(Kind::Ll1Table);
+        w.uint(self.tokens);
+        w.uint(self.entries.len());
+        for &entry in self.entries.iter() {
+            w.uint(entry.map_or(0, |rule| rule + 1));
+        }
+        w.finish()
+    }
+
+    /// Load a table written by `to_bytes`. As with `LrTable::from_bytes`, the table can only be
+    /// checked against its grammar by `fits`, which building a parser with it does.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Ll1Table, LoadError> {
+        let mut r = try!(Reader::new(bytes, Kind::Ll1Table));
+        let tokens = try!(r.uint());
+        let len = try!(r.uint());
+        match tokens.checked_add(1) {
+            Some(columns) if len % columns == 0 => { }
+            _ => return Err(LoadError::Invalid)
+        }
+        let mut entries = Vec::new();
+        for _ in range(0, len) {
+            entries.push(match try!(r.uint()) {
+                0 => None,
+                n => Some(n - 1)
+            });
+        }
+        try!(r.finish());
+        Ok(Ll1Table { tokens: tokens, entries: entries })
+    }
+
+    fn cell(&self, variable: Variable, lookahead: Option<Token>) -> uint {
+        let column = match lookahead {
+            Some(t) if t.index() < self.tokens => t.index(),
+            Some(_) => return self.entries.len(),
+            None => self.tokens
+        };
+        variable.index() * (self.tokens + 1) + column
+    }
+}
+
+/// Several rules that a variable could be expanded with on the same lookahead.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Conflict {
+    pub variable: Variable,
+    /// The next token of input, or `None` for the end of the input.
+    pub lookahead: Option<Token>,
+    /// The indices, in `get_rules`, of the competing rules.
+    pub rules: Vec<uint>
+}
+
+impl<T: Terminal> Cfg<T> {
+    /// Build the LL(1) parse table of the grammar from its FIRST and FOLLOW sets.
+    ///
+    /// Fails with every conflict found if the grammar isn't LL(1).
+    pub fn ll1_table(&self) -> Result<Ll1Table, Vec<Conflict>> {
+        let first = self.first_sets();
+        let follow = self.follow_sets();
+        let (n, tokens) = (self.num_variables(), self.num_tokens());
+        let mut cells: Vec<Vec<uint>> = Vec::from_fn(n * (tokens + 1), |_| Vec::new());
+        for v in range(0, n) {
+            let rules = match self.get_rules(Variable(v as u32)) {
+                Some(rules) => rules,
+                None => continue
+            };
+            let row = v * (tokens + 1);
+            for (i, rule) in rules.iter().enumerate() {
+                let predict = first.of(rule.as_slice());
+                for t in predict.tokens.iter() {
+                    cells[row + t.index()].push(i);
+                }
+                if predict.epsilon {
+                    let after = follow.get(Variable(v as u32));
+                    for t in after.tokens.iter().filter(|t| !predict.tokens.contains(*t)) {
+                        cells[row + t.index()].push(i);
+                    }
+                    if after.end {
+                        cells[row + tokens].push(i);
+                    }
+                }
+            }
+        }
+
+        let mut conflicts = Vec::new();
+        for (c, rules) in cells.iter().enumerate().filter(|&(_, r)| r.len() > 1) {
+            let column = c % (tokens + 1);
+            conflicts.push(Conflict {
+                variable: Variable((c / (tokens + 1)) as u32),
+                lookahead: if column == tokens { None } else { Some(Token(column as u32)) },
+                rules: rules.clone()
+            });
+        }
+        if !conflicts.is_empty() {
+            return Err(conflicts)
+        }
+        Ok(Ll1Table {
+            tokens: tokens,
+            entries: cells.iter().map(|r| r.get(0).map(|&i| i)).collect()
+        })
+    }
+}
+
+/// A predictive parser for an LL(1) grammar, holding its table.
+pub struct Ll1Parser<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    table: Ll1Table
+}
+
+impl<'a, T: Terminal> Ll1Parser<'a, T> {
+    /// Build the parser's table, failing with every conflict found if the grammar isn't LL(1).
+    pub fn new(cfg: &'a Cfg<T>) -> Result<Ll1Parser<'a, T>, Vec<Conflict>> {
+        let table = try!(cfg.ll1_table());
+        Ok(Ll1Parser { cfg: cfg, table: table })
+    }
+
+    /// A parser using a table already built for the grammar, such as one loaded with
+    /// `Ll1Table::from_bytes`, failing with `LoadError::Invalid` if the table doesn't fit the
+    /// grammar, as checked by `Ll1Table::fits`.
+    pub fn with_table(cfg: &'a Cfg<T>, table: Ll1Table) -> Result<Ll1Parser<'a, T>, LoadError> {
+        if !table.fits(cfg) {
+            return Err(LoadError::Invalid)
+        }
+        Ok(Ll1Parser { cfg: cfg, table: table })
+    }
+
+    pub fn table(&self) -> &Ll1Table {
+        &self.table
+    }
+
+    /// Whether the grammar derives `input`.
+    pub fn recognize(&self, input: &[Token]) -> bool {
+        self.parse(input).is_ok()
+    }
+
+    /// Parse `input`, expanding each variable by the rule the table gives for the next token.
+    pub fn parse(&self, input: &[Token]) -> Result<ParseTree, ParseError> {
+        self.parse_with(input, &mut TreeBuilder)
+    }
+
+    /// Parse the tokens read from a lexer. To refer to their spans and payloads from the tree,
+    /// read them with `TokenStream::read` and parse its `tokens` instead.
+    pub fn parse_source<P, S: TokenSource<P>>(&self, source: &mut S)
+                                              -> Result<ParseTree, ParseError> {
+        self.parse(TokenStream::read(source).tokens.as_slice())
+    }
+
+    /// Parse `input` as `parse` does, building a value for each rule with `reducer` once all
+    /// its symbols have been matched.
+    ///
+    /// The stack of rules being matched is kept explicitly, so deep inputs can't overflow the
+    /// call stack.
+    pub fn parse_with<V, R: Reducer<V>>(&self, input: &[Token], reducer: &mut R)
+                                        -> Result<V, ParseError> {
+        let mut position = 0;
+        // The rules being matched, with the values of the symbols matched so far.
+        let mut stack = vec![try!(self.expand(self.cfg.get_start(), input, position))];
+        loop {
+            let (variable, rule, len) = {
+                let &(variable, rule, ref children) = stack.last().unwrap();
+                (variable, rule, children.len())
+            };
+            let body = self.cfg.get_rules(variable).unwrap()[rule].as_slice();
+            if len == body.len() {
+                let (_, _, children) = stack.pop().unwrap();
+                let value = reducer.reduce(variable, rule, children);
+                match stack.last_mut() {
+                    Some(&mut (_, _, ref mut parent)) => parent.push(value),
+                    None if position == input.len() => return Ok(value),
+                    None => return Err(ParseError { position: position,
+                                                    found: input.get(position).map(|&t| t),
+                                                    expected: vec![None] })
+                }
+                continue
+            }
+            match body[len] {
+                Symbol::Tok(t) => {
+                    if input.get(position) != Some(&t) {
+                        return Err(ParseError { position: position,
+                                                found: input.get(position).map(|&t| t),
+                                                expected: vec![Some(t)] })
+                    }
+                    let value = reducer.shift(t, position);
+                    position += 1;
+                    let &mut (_, _, ref mut children) = stack.last_mut().unwrap();
+                    children.push(value);
+                },
+                Symbol::Var(v) => {
+                    let frame = try!(self.expand(v, input, position));
+                    stack.push(frame);
+                }
+            }
+        }
+    }
+
+    /// Parse `input`, recovering from errors to find every one, as `parse_with_recovery` does.
+    pub fn parse_recovering(&self, input: &[Token], sync: &SyncSets)
+                            -> Result<ParseTree, Vec<ParseError>> {
+        match self.parse_with_recovery(input, sync, &mut PartialTreeBuilder) {
+            (Some(Some(tree)), ref errors) if errors.is_empty() => Ok(tree),
+            (_, errors) => Err(errors)
+        }
+    }
+
+    /// Parse `input` as `parse_with` does, but recover from each error to carry on, giving the
+    /// value built with every error found.
+    ///
+    /// When no rule of a variable can start at the next token, the parser skips tokens until
+    /// one that a rule can start with, or one in the variable's synchronizing set, where it
+    /// gives up on the variable and carries on with `reducer`'s error value for it. A token
+    /// that doesn't match the rule being matched is taken to be missing. An error right after
+    /// recovering from another isn't reported, as it's most likely a consequence.
+    pub fn parse_with_recovery<V, R: Recover<V>>(&self, input: &[Token], sync: &SyncSets,
+                                                 reducer: &mut R)
+                                                 -> (Option<V>, Vec<ParseError>) {
+        let mut errors = Vec::new();
+        let mut position = 0;
+        let mut stack: Vec<(Variable, uint, Vec<V>)> = Vec::new();
+        // A variable to start matching next.
+        let mut next = Some(self.cfg.get_start());
+        loop {
+            let done = match next.take() {
+                Some(v) => match self.expand(v, input, position) {
+                    Ok(frame) => {
+                        stack.push(frame);
+                        None
+                    },
+                    Err(error) => {
+                        report(&mut errors, error);
+                        let start = position;
+                        while position < input.len() {
+                            let lookahead = Some(input[position]);
+                            if sync.syncs(v, lookahead) || self.table.get(v, lookahead).is_some() {
+                                break
+                            }
+                            position += 1;
+                        }
+                        if self.table.get(v, input.get(position).map(|&t| t)).is_some() {
+                            next = Some(v);
+                            continue
+                        }
+                        Some(reducer.error(Symbol::Var(v), start, position))
+                    }
+                },
+                None => {
+                    let (variable, rule, len) = {
+                        let &(variable, rule, ref children) = stack.last().unwrap();
+                        (variable, rule, children.len())
+                    };
+                    let body = self.cfg.get_rules(variable).unwrap()[rule].as_slice();
+                    if len == body.len() {
+                        let (_, _, children) = stack.pop().unwrap();
+                        Some(reducer.reduce(variable, rule, children))
+                    } else {
+                        match body[len] {
+                            Symbol::Tok(t) => {
+                                let value = if input.get(position) == Some(&t) {
+                                    position += 1;
+                                    reducer.shift(t, position - 1)
+                                } else {
+                                    let error = ParseError {
+                                        position: position,
+                                        found: input.get(position).map(|&t| t),
+                                        expected: vec![Some(t)]
+                                    };
+                                    report(&mut errors, error);
+                                    reducer.error(Symbol::Tok(t), position, position)
+                                };
+                                let &mut (_, _, ref mut children) = stack.last_mut().unwrap();
+                                children.push(value);
+                            },
+                            Symbol::Var(v) => next = Some(v)
+                        }
+                        None
+                    }
+                }
+            };
+            match done {
+                Some(value) => match stack.last_mut() {
+                    Some(&mut (_, _, ref mut parent)) => parent.push(value),
+                    None => {
+                        if position < input.len() {
+                            report(&mut errors, ParseError {
+                                position: position,
+                                found: input.get(position).map(|&t| t),
+                                expected: vec![None]
+                            });
+                        }
+                        return (Some(value), errors)
+                    }
+                },
+                None => { }
+            }
+        }
+    }
+
+    /// Start matching the rule for `variable` that the table gives at `position`.
+    fn expand<V>(&self, variable: Variable, input: &[Token], position: uint)
+                 -> Result<(Variable, uint, Vec<V>), ParseError> {
+        let lookahead = input.get(position).map(|&t| t);
+        match self.table.get(variable, lookahead) {
+            Some(rule) => Ok((variable, rule, Vec::new())),
+            None => {
+                let mut expected: Vec<Option<Token>> = range(0, self.table.num_tokens())
+                    .map(|t| Some(Token(t as u32)))
+                    .filter(|&t| self.table.get(variable, t).is_some())
+                    .collect();
+                if self.table.get(variable, None).is_some() {
+                    expected.push(None);
+                }
+                Err(ParseError { position: position, found: lookahead, expected: expected })
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{Ll1Parser, Ll1Table};
+    use super::super::{Cfg, Token, Variable};
+    use actions::Reducer;
+    use binary::{Kind, LoadError, Writer};
+    use std::uint;
+    use testing::{EXPRESSIONS, EXPRESSIONS_LL1, check_tree, grammar, tokens, variable};
+    use tree::ParseTree;
+
+    fn token(cfg: &Cfg<String>, terminal: &str) -> Option<Token> {
+        cfg.token(&terminal.to_string())
+    }
+
+    #[test]
+    fn expression_table() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let table = cfg.ll1_table().unwrap();
+        let (e, ep) = (variable(&cfg, "e"), variable(&cfg, "ep"));
+        let (tp, f) = (variable(&cfg, "tp"), variable(&cfg, "f"));
+
+        assert_eq!(table.num_tokens(), cfg.num_tokens());
+        assert_eq!(table.get(e, token(&cfg, "id")), Some(0));
+        assert_eq!(table.get(e, token(&cfg, "(")), Some(0));
+        assert_eq!(table.get(e, token(&cfg, "+")), None);
+        assert_eq!(table.get(e, None), None);
+        assert_eq!(table.get(ep, token(&cfg, "+")), Some(0));
+        assert_eq!(table.get(ep, token(&cfg, ")")), Some(1));
+        assert_eq!(table.get(ep, None), Some(1));
+        assert_eq!(table.get(ep, token(&cfg, "*")), None);
+        assert_eq!(table.get(tp, token(&cfg, "*")), Some(0));
+        assert_eq!(table.get(tp, token(&cfg, "+")), Some(1));
+        assert_eq!(table.get(tp, token(&cfg, ")")), Some(1));
+        assert_eq!(table.get(tp, None), Some(1));
+        assert_eq!(table.get(f, token(&cfg, "(")), Some(0));
+        assert_eq!(table.get(f, token(&cfg, "id")), Some(1));
+        assert_eq!(table.get(f, token(&cfg, "+")), None);
+        assert_eq!(table.get(f, Some(Token(100))), None);
+    }
+
+    #[test]
+    fn left_recursion_conflicts() {
+        let cfg = grammar(EXPRESSIONS);
+        let conflicts = cfg.ll1_table().unwrap_err();
+        let e = variable(&cfg, "e");
+        let id = token(&cfg, "id");
+        assert!(conflicts.iter().any(|c| c.variable == e && c.lookahead == id
+                                         && c.rules == vec![0, 1]));
+    }
+
+    #[test]
+    fn parse_expression() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let parser = Ll1Parser::new(&cfg).unwrap();
+        let input = tokens(&cfg, "id + id * id");
+        let tree = parser.parse(input.as_slice()).unwrap();
+        check_tree(&cfg, &tree, input.as_slice());
+        match tree {
+            ParseTree::Node(v, 0, ref children) => {
+                assert_eq!(v, variable(&cfg, "e"));
+                assert_eq!(children.len(), 2);
+            },
+            _ => panic!("expected the start symbol at the root")
+        }
+        assert!(parser.recognize(tokens(&cfg, "( id ) * id").as_slice()));
+        assert!(!parser.recognize(tokens(&cfg, "( id").as_slice()));
+        assert!(Ll1Parser::new(&grammar(EXPRESSIONS)).is_err());
+
+        let error = parser.parse(tokens(&cfg, "id + * id").as_slice()).unwrap_err();
+        assert_eq!(error.position, 2);
+        assert!(error.expected.contains(&token(&cfg, "id")));
+        assert!(error.expected.contains(&token(&cfg, "(")));
+        let error = parser.parse(tokens(&cfg, "id +").as_slice()).unwrap_err();
+        assert_eq!(error.position, 2);
+    }
+
+    /// Counts the reductions of each variable, and builds the number of tokens under each.
+    struct Counter {
+        reductions: Vec<uint>
+    }
+
+    impl Reducer<uint> for Counter {
+        fn shift(&mut self, _token: Token, _position: uint) -> uint {
+            1
+        }
+
+        fn reduce(&mut self, variable: Variable, _rule: uint, children: Vec<uint>) -> uint {
+            self.reductions[variable.index()] += 1;
+            children.iter().fold(0, |sum, &n| sum + n)
+        }
+    }
+
+    #[test]
+    fn parse_with() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let parser = Ll1Parser::new(&cfg).unwrap();
+        let input = tokens(&cfg, "( id + id ) * id");
+        let mut counter = Counter { reductions: Vec::from_elem(cfg.num_variables(), 0) };
+        assert_eq!(parser.parse_with(input.as_slice(), &mut counter), Ok(7));
+        let (e, ep, f) = (variable(&cfg, "e"), variable(&cfg, "ep"), variable(&cfg, "f"));
+        assert_eq!(counter.reductions[e.index()], 2);
+        assert_eq!(counter.reductions[ep.index()], 3);
+        assert_eq!(counter.reductions[f.index()], 4);
+        let error = parser.parse_with(tokens(&cfg, "id id").as_slice(), &mut counter).unwrap_err();
+        assert_eq!(error.position, 1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let table = cfg.ll1_table().unwrap();
+        assert_eq!(Ll1Table::from_bytes(table.to_bytes().as_slice()), Ok(table));
+    }
+
+    #[test]
+    fn bad_tables() {
+        let mut w = Writer::new(Kind::Ll1Table);
+        w.uint(uint::MAX);
+        w.uint(0);
+        assert_eq!(Ll1Table::from_bytes(w.finish().as_slice()), Err(LoadError::Invalid));
+
+        let mut w = Writer::new(Kind::Ll1Table);
+        w.uint(1);
+        w.uint(3);
+        assert_eq!(Ll1Table::from_bytes(w.finish().as_slice()), Err(LoadError::Invalid));
+
+        // Far more entries than the data holds must fail when it runs out.
+        let mut w = Writer::new(Kind::Ll1Table);
+        w.uint(1);
+        w.uint(uint::MAX - 1);
+        assert_eq!(Ll1Table::from_bytes(w.finish().as_slice()), Err(LoadError::Truncated));
+    }
+
+    #[test]
+    fn with_table() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let table = cfg.ll1_table().unwrap();
+        assert!(table.fits(&cfg));
+        let parser = Ll1Parser::with_table(&cfg, table.clone()).unwrap();
+        assert!(parser.recognize(tokens(&cfg, "id * id").as_slice()));
+        let small = grammar("<s> ::= \"id\"");
+        assert!(!table.fits(&small));
+        assert_eq!(Ll1Parser::with_table(&small, table).err(), Some(LoadError::Invalid));
+
+        // A table of the right size expanding `s` by a second rule it doesn't have.
+        for &(entry, fits) in [(1, true), (2, false)].iter() {
+            let mut w = Writer::new(Kind::Ll1Table);
+            w.uint(1);
+            w.uint(2);
+            w.uint(entry);
+            w.uint(0);
+            let table = Ll1Table::from_bytes(w.finish().as_slice()).unwrap();
+            assert_eq!(Ll1Parser::with_table(&small, table).is_ok(), fits);
+        }
+    }
+}
@@ -0,0 +1,1607 @@
+//! LR automata and parse tables, and a parser driven by them.
+
+use std::collections::{BTreeMap, HashMap, RingBuf};
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Associativity, Cfg, ParseError, Symbol, Token, Variable};
+use super::first::{FirstSets, FollowSet};
+use actions::{Reducer, TreeBuilder};
+use binary::{Kind, LoadError, Reader, Writer};
+use recovery::{PartialTreeBuilder, Recover, SyncSets, report};
+use source::{TokenSource, TokenStream};
+use tree::ParseTree;
+
+/// A rule with a position in its body.
+///
+/// `variable` is `None` for the rule `S' -> S` of the augmented start symbol, which has a single
+/// rule numbered 0.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct Item {
+    pub variable: Option<Variable>,
+    /// The index of the rule in `get_rules`.
+    pub rule: uint,
+    /// The number of symbols of the body before the position.
+    pub dot: uint
+}
+
+/// A state of an LR(0) automaton.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Lr0State {
+    /// The items the state is reached with, in order.
+    pub kernel: Vec<Item>,
+    /// The kernel followed by the items added by taking its closure.
+    pub items: Vec<Item>,
+    /// The state reached by moving past each symbol.
+    pub goto: BTreeMap<Symbol, uint>
+}
+
+/// The LR(0) automaton of a grammar. The initial state is numbered 0.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Lr0Automaton {
+    pub states: Vec<Lr0State>
+}
+
+impl Lr0State {
+    /// The items added by taking the closure of the kernel.
+    pub fn closure(&self) -> &[Item] {
+        self.items.slice_from(self.kernel.len())
+    }
+}
+
+impl Lr0Automaton {
+    /// Every transition of the automaton, as a state, a symbol, and the state reached by moving
+    /// past the symbol, in order.
+    pub fn edges(&self) -> Vec<(uint, Symbol, uint)> {
+        let mut edges = Vec::new();
+        for (s, state) in self.states.iter().enumerate() {
+            edges.extend(state.goto.iter().map(|(&symbol, &target)| (s, symbol, target)));
+        }
+        edges
+    }
+}
+
+/// A state of an LR(1) automaton. Items with the same rule and position are merged, so each has
+/// a set of lookaheads.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Lr1State {
+    /// The items the state is reached with, in order.
+    pub kernel: Vec<(Item, FollowSet)>,
+    /// The kernel followed by the items added by taking its closure.
+    pub items: Vec<(Item, FollowSet)>,
+    /// The state reached by moving past each symbol.
+    pub goto: BTreeMap<Symbol, uint>
+}
+
+/// An automaton whose items have lookaheads: the canonical LR(1) automaton of a grammar, or its
+/// LALR(1) automaton. The initial state is numbered 0.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Lr1Automaton {
+    pub states: Vec<Lr1State>
+}
+
+impl Lr1State {
+    /// The items added by taking the closure of the kernel.
+    pub fn closure(&self) -> &[(Item, FollowSet)] {
+        self.items.slice_from(self.kernel.len())
+    }
+}
+
+impl Lr1Automaton {
+    /// Every transition of the automaton, as a state, a symbol, and the state reached by moving
+    /// past the symbol, in order.
+    pub fn edges(&self) -> Vec<(uint, Symbol, uint)> {
+        let mut edges = Vec::new();
+        for (s, state) in self.states.iter().enumerate() {
+            edges.extend(state.goto.iter().map(|(&symbol, &target)| (s, symbol, target)));
+        }
+        edges
+    }
+
+    /// The automaton without lookaheads.
+    pub fn cores(&self) -> Lr0Automaton {
+        Lr0Automaton {
+            states: self.states.iter().map(|state| {
+                Lr0State {
+                    kernel: state.kernel.iter().map(|&(item, _)| item).collect(),
+                    items: state.items.iter().map(|&(item, _)| item).collect(),
+                    goto: state.goto.clone()
+                }
+            }).collect()
+        }
+    }
+
+    /// The completed items of each state, with their lookaheads.
+    fn reductions<T: Eq + Hash + Clone>(&self, cfg: &Cfg<T>) -> Vec<Vec<(Item, FollowSet)>> {
+        self.states.iter().map(|state| {
+            state.items.iter().filter(|&&(item, _)| cfg.is_complete(item)).map(|&(item, ref l)| {
+                (item, l.clone())
+            }).collect()
+        }).collect()
+    }
+}
+
+/// What an LR parser does on a lookahead.
+#[deriving(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub enum Action {
+    /// Consume the token and push the given state.
+    Shift(uint),
+    /// Pop the body of the rule, given as a variable and the index of the rule in `get_rules`,
+    /// and push the state reached by moving past the variable.
+    Reduce(Variable, uint),
+    /// Finish, having recognized the whole input.
+    Accept
+}
+
+/// An LR parse table, with the action for each state and lookahead and the goto for each state
+/// and variable.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct LrTable {
+    states: uint,
+    tokens: uint,
+    variables: uint,
+    actions: Vec<Option<Action>>,
+    gotos: Vec<Option<uint>>,
+    resolutions: Vec<Resolution>
+}
+
+impl LrTable {
+    /// The action in `state` when the next token is `lookahead`, or the end of the input if
+    /// `lookahead` is `None`. No action means a syntax error.
+    pub fn action(&self, state: uint, lookahead: Option<Token>) -> Option<Action> {
+        let column = match lookahead {
+            Some(t) if t.index() < self.tokens => t.index(),
+            Some(_) => return None,
+            None => self.tokens
+        };
+        match self.actions.get(state * (self.tokens + 1) + column) {
+            Some(&action) => action,
+            None => None
+        }
+    }
+
+    /// The state to go to from `state` after reducing to `variable`.
+    pub fn goto(&self, state: uint, variable: Variable) -> Option<uint> {
+        if variable.index() >= self.variables {
+            return None
+        }
+        match self.gotos.get(state * self.variables + variable.index()) {
+            Some(&goto) => goto,
+            None => None
+        }
+    }
+
+    pub fn num_states(&self) -> uint {
+        self.states
+    }
+
+    /// The conflicts resolved by the precedence and associativity of tokens, in order.
+    pub fn resolutions(&self) -> &[Resolution] {
+        self.resolutions.as_slice()
+    }
+
+    /// Whether the table could have been built for `cfg`: it has as many tokens and variables,
+    /// only reduces by rules the grammar has, to variables some state has a goto on, shifts
+    /// and goes to states it has, and neither shifts at the end of the input nor accepts
+    /// before it.
+    pub fn fits<T: Eq + Hash + Clone>(&self, cfg: &Cfg<T>) -> bool {
+        if self.tokens != cfg.num_tokens() || self.variables != cfg.num_variables() {
+            return false
+        }
+        let columns = self.tokens + 1;
+        self.actions.iter().enumerate().all(|(c, &action)| match action {
+            Some(Action::Shift(state)) => c % columns != self.tokens && state < self.states,
+            Some(Action::Reduce(v, rule)) => {
+                rule < cfg.get_rules(v).map_or(0, |rules| rules.len()) && self.has_goto(v)
+            },
+            Some(Action::Accept) => c % columns == self.tokens,
+            None => true
+        }) && self.gotos.iter().all(|&goto| goto.map_or(true, |state| state < self.states))
+    }
+
+    /// Whether any state has a goto on `variable`.
+    fn has_goto(&self, variable: Variable) -> bool {
+        range(0, self.states).any(|state| self.goto(state, variable).is_some())
+    }
+
+    /// The table in the binary format of the `binary` module. The resolutions of conflicts,
+    /// which only matter while building a table, aren't kept.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new(Kind::LrTable);
+        w.uint(self.states);
+        w.uint(self.tokens);
+        w.uint(self.variables);
+        for &action in self.actions.iter() {
+            match action {
+                None => w.uint(0),
+                Some(Action::Shift(state)) => {
+                    w.uint(1);
+                    w.uint(state);
+                },
+                Some(Action::Reduce(v, rule)) => {
+                    w.uint(2);
+                    w.uint(v.index());
+                    w.uint(rule);
+                },
+                Some(Action::Accept) => w.uint(3)
+            }
+        }
+        for &goto in self.gotos.iter() {
+            w.uint(goto.map_or(0, |state| state + 1));
+        }
+        w.finish()
+    }
+
+    /// Load a table written by `to_bytes`.
+    ///
+    /// The table only refers to the grammar it was built for by the indices of its symbols and
+    /// rules, so it can't be checked against it here; `fits` does that, and building a parser
+    /// with the table checks it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<LrTable, LoadError> {
+        let mut r = try!(Reader::new(bytes, Kind::LrTable));
+        let states = try!(r.uint());
+        let tokens = try!(r.uint());
+        let variables = try!(r.uint());
+        let cells = match tokens.checked_add(1).and_then(|columns| states.checked_mul(columns)) {
+            Some(cells) => cells,
+            None => return Err(LoadError::Invalid)
+        };
+        let mut actions = Vec::new();
+        for _ in range(0, cells) {
+            actions.push(match try!(r.uint()) {
+                0 => None,
+                1 => Some(Action::Shift(try!(r.below(states)))),
+                2 => {
+                    let v = Variable(try!(r.below(variables)) as u32);
+                    Some(Action::Reduce(v, try!(r.uint())))
+                },
+                3 => Some(Action::Accept),
+                _ => return Err(LoadError::Invalid)
+            });
+        }
+        let cells = match states.checked_mul(variables) {
+            Some(cells) => cells,
+            None => return Err(LoadError::Invalid)
+        };
+        let mut gotos = Vec::new();
+        for _ in range(0, cells) {
+            gotos.push(match try!(r.below(states + 1)) {
+                0 => None,
+                state => Some(state - 1)
+            });
+        }
+        try!(r.finish());
+        Ok(LrTable {
+            states: states,
+            tokens: tokens,
+            variables: variables,
+            actions: actions,
+            gotos: gotos,
+            resolutions: Vec::new()
+        })
+    }
+}
+
+/// An LR parse table keeping every action for each state and lookahead, for generalized LR
+/// parsing.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct GlrTable {
+    states: uint,
+    tokens: uint,
+    variables: uint,
+    actions: Vec<Vec<Action>>,
+    gotos: Vec<Option<uint>>
+}
+
+impl GlrTable {
+    /// The actions in `state` when the next token is `lookahead`, or the end of the input if
+    /// `lookahead` is `None`, in order. No actions means a syntax error.
+    pub fn actions(&self, state: uint, lookahead: Option<Token>) -> &[Action] {
+        let column = match lookahead {
+            Some(t) if t.index() < self.tokens => t.index(),
+            Some(_) => return &[],
+            None => self.tokens
+        };
+        match self.actions.get(state * (self.tokens + 1) + column) {
+            Some(actions) => actions.as_slice(),
+            None => &[]
+        }
+    }
+
+    /// The state to go to from `state` after reducing to `variable`.
+    pub fn goto(&self, state: uint, variable: Variable) -> Option<uint> {
+        if variable.index() >= self.variables {
+            return None
+        }
+        match self.gotos.get(state * self.variables + variable.index()) {
+            Some(&goto) => goto,
+            None => None
+        }
+    }
+
+    pub fn num_states(&self) -> uint {
+        self.states
+    }
+}
+
+/// Several actions possible in the same state on the same lookahead.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Conflict {
+    pub state: uint,
+    /// The next token of input, or `None` for the end of the input.
+    pub lookahead: Option<Token>,
+    /// The competing actions, in order.
+    pub actions: Vec<Action>,
+    /// The items of the state calling for each action: those with the lookahead token after the
+    /// position for a shift, and completed ones for a reduction.
+    pub items: Vec<Item>
+}
+
+impl Conflict {
+    /// Whether shifting is one of the competing actions, rather than all being reductions.
+    pub fn is_shift_reduce(&self) -> bool {
+        self.actions.iter().any(|a| match *a {
+            Action::Shift(_) => true,
+            _ => false
+        })
+    }
+}
+
+/// A shift/reduce conflict resolved using the precedence and associativity of tokens.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Resolution {
+    pub conflict: Conflict,
+    /// The action chosen, or `None` if the lookahead was made a syntax error because the rule and
+    /// the token have the same precedence and are nonassociative.
+    pub action: Option<Action>
+}
+
+/// A sentential form showing how a parser gets to one side of a conflict.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Counterexample {
+    /// The item of the conflict the example is for.
+    pub item: Item,
+    /// A sentential form derived from the start symbol using the item's rule.
+    pub form: Vec<Symbol>,
+    /// The number of symbols of `form` the parser has moved past when it reaches the conflict.
+    /// The rest of `form` can begin with the lookahead of the conflict, and for a completed item
+    /// the rule's body ends right before it.
+    pub position: uint
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Build the LR(0) automaton of the grammar, augmented with a new start symbol.
+    pub fn lr0_automaton(&self) -> Lr0Automaton {
+        let start = vec![Symbol::Var(self.get_start())];
+        let initial = vec![Item { variable: None, rule: 0, dot: 0 }];
+        let mut states = Vec::new();
+        let mut numbers = HashMap::new();
+        numbers.insert(initial.clone(), 0u);
+        let mut pending = vec![initial];
+        let mut i = 0;
+        loop {
+            if i == pending.len() {
+                break
+            }
+            let kernel = pending[i].clone();
+            let items = self.closure(start.as_slice(), kernel.as_slice());
+            let mut moves: BTreeMap<Symbol, Vec<Item>> = BTreeMap::new();
+            for &item in items.iter() {
+                let symbol = match self.next_symbol(start.as_slice(), item) {
+                    Some(symbol) => symbol,
+                    None => continue
+                };
+                let advanced = Item { dot: item.dot + 1, ..item };
+                if moves.contains_key(&symbol) {
+                    moves.get_mut(&symbol).unwrap().push(advanced);
+                } else {
+                    moves.insert(symbol, vec![advanced]);
+                }
+            }
+            let mut goto = BTreeMap::new();
+            for (&symbol, target) in moves.iter_mut() {
+                target.sort();
+                let existing = numbers.get(target).map(|&n| n);
+                let n = match existing {
+                    Some(n) => n,
+                    None => {
+                        let n = pending.len();
+                        numbers.insert(target.clone(), n);
+                        pending.push(target.clone());
+                        n
+                    }
+                };
+                goto.insert(symbol, n);
+            }
+            states.push(Lr0State { kernel: kernel, items: items, goto: goto });
+            i += 1;
+        }
+        Lr0Automaton { states: states }
+    }
+
+    /// Build the SLR(1) parse table of the grammar, which reduces by a rule of `A` on the tokens
+    /// in the FOLLOW set of `A`.
+    ///
+    /// Fails with every conflict found if the grammar isn't SLR(1).
+    pub fn slr_table(&self) -> Result<LrTable, Vec<Conflict>> {
+        let automaton = self.lr0_automaton();
+        let follow = self.follow_sets();
+        let mut accept = FollowSet::new();
+        accept.end = true;
+        let reductions = automaton.states.iter().map(|state| {
+            state.items.iter().filter(|&&item| self.is_complete(item)).map(|&item| {
+                match item.variable {
+                    Some(v) => (item, follow.get(v).clone()),
+                    None => (item, accept.clone())
+                }
+            }).collect()
+        }).collect();
+        self.lr_table(&automaton, reductions)
+    }
+
+    /// Build the LALR(1) parse table of the grammar, from the LR(0) automaton with the
+    /// lookaheads of its items computed by propagation.
+    ///
+    /// Fails with every conflict found if the grammar isn't LALR(1).
+    pub fn lalr_table(&self) -> Result<LrTable, Vec<Conflict>> {
+        let automaton = self.lalr_automaton();
+        self.lr_table(&automaton.cores(), automaton.reductions(self))
+    }
+
+    /// Build a table from the LALR(1) automaton of the grammar that keeps every action in
+    /// conflict, for a generalized LR parser to try them all. Conflicts that the precedence of
+    /// tokens resolves are still resolved.
+    pub fn glr_table(&self) -> GlrTable {
+        let automaton = self.lalr_automaton();
+        let cores = automaton.cores();
+        let (cells, gotos) = self.action_cells(&cores, automaton.reductions(self));
+        let mut actions = Vec::with_capacity(cells.len());
+        for (c, cell) in cells.into_iter().enumerate() {
+            let mut distinct: Vec<Action> = cell.iter().map(|&(a, _)| a).collect();
+            distinct.sort();
+            distinct.dedup();
+            if distinct.len() > 1 {
+                let conflict = cell_conflict(c, self.num_tokens(), distinct, cell.as_slice());
+                distinct = match self.resolve(&conflict) {
+                    Some(action) => action.into_iter().collect(),
+                    None => conflict.actions
+                };
+            }
+            actions.push(distinct);
+        }
+        GlrTable {
+            states: cores.states.len(),
+            tokens: self.num_tokens(),
+            variables: self.num_variables(),
+            actions: actions,
+            gotos: gotos
+        }
+    }
+
+    /// Build the LALR(1) automaton of the grammar: the LR(0) automaton, with the lookaheads of its
+    /// items computed by propagation.
+    pub fn lalr_automaton(&self) -> Lr1Automaton {
+        let automaton = self.lr0_automaton();
+        let start = vec![Symbol::Var(self.get_start())];
+        let first = self.first_sets();
+        // A token that isn't in the grammar, standing for the lookaheads of the kernel item the
+        // closure is taken of, to find where they're propagated to.
+        let probe = Token(self.num_tokens() as u32);
+        let mut lookaheads: Vec<Vec<FollowSet>> = automaton.states.iter().map(|state| {
+            Vec::from_elem(state.kernel.len(), FollowSet::new())
+        }).collect();
+        let mut propagate: Vec<Vec<Vec<(uint, uint)>>> = automaton.states.iter().map(|state| {
+            Vec::from_fn(state.kernel.len(), |_| Vec::new())
+        }).collect();
+        lookaheads[0][0].end = true;
+
+        for (s, state) in automaton.states.iter().enumerate() {
+            for (k, &kernel) in state.kernel.iter().enumerate() {
+                let mut own = FollowSet::new();
+                own.tokens.insert(probe);
+                for (item, mut lookahead) in self.closure1(&first, start.as_slice(),
+                                                           vec![(kernel, own)]).into_iter() {
+                    let symbol = match self.next_symbol(start.as_slice(), item) {
+                        Some(symbol) => symbol,
+                        None => continue
+                    };
+                    let target = *state.goto.get(&symbol).unwrap();
+                    let advanced = Item { dot: item.dot + 1, ..item };
+                    let j = automaton.states[target].kernel.iter().position(|&i| i == advanced)
+                                                                  .unwrap();
+                    if lookahead.tokens.remove(probe) && !propagate[s][k].contains(&(target, j)) {
+                        propagate[s][k].push((target, j));
+                    }
+                    lookaheads[target][j].union_with(&lookahead);
+                }
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for s in range(0, propagate.len()) {
+                for k in range(0, propagate[s].len()) {
+                    let from = lookaheads[s][k].clone();
+                    for &(t, j) in propagate[s][k].iter() {
+                        changed = lookaheads[t][j].union_with(&from) || changed;
+                    }
+                }
+            }
+        }
+
+        let states = automaton.states.into_iter().zip(lookaheads.into_iter()).map(|(state, l)| {
+            let kernel: Vec<(Item, FollowSet)> = state.kernel.into_iter().zip(l.into_iter())
+                                                                        .collect();
+            Lr1State {
+                items: self.closure1(&first, start.as_slice(), kernel.clone()),
+                kernel: kernel,
+                goto: state.goto
+            }
+        }).collect();
+        Lr1Automaton { states: states }
+    }
+
+    /// Build the canonical LR(1) automaton of the grammar, augmented with a new start symbol.
+    pub fn lr1_automaton(&self) -> Lr1Automaton {
+        let start = vec![Symbol::Var(self.get_start())];
+        let first = self.first_sets();
+        let mut end = FollowSet::new();
+        end.end = true;
+        let initial = vec![(Item { variable: None, rule: 0, dot: 0 }, end)];
+        let mut states = Vec::new();
+        let mut numbers = HashMap::new();
+        numbers.insert(kernel_key(initial.as_slice()), 0u);
+        let mut pending = vec![initial];
+        let mut i = 0;
+        loop {
+            if i == pending.len() {
+                break
+            }
+            let kernel = pending[i].clone();
+            let items = self.closure1(&first, start.as_slice(), kernel.clone());
+            let mut moves: BTreeMap<Symbol, Vec<(Item, FollowSet)>> = BTreeMap::new();
+            for &(item, ref lookahead) in items.iter() {
+                let symbol = match self.next_symbol(start.as_slice(), item) {
+                    Some(symbol) => symbol,
+                    None => continue
+                };
+                let advanced = (Item { dot: item.dot + 1, ..item }, lookahead.clone());
+                if moves.contains_key(&symbol) {
+                    moves.get_mut(&symbol).unwrap().push(advanced);
+                } else {
+                    moves.insert(symbol, vec![advanced]);
+                }
+            }
+            let mut goto = BTreeMap::new();
+            for (&symbol, target) in moves.iter_mut() {
+                target.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+                let key = kernel_key(target.as_slice());
+                let existing = numbers.get(&key).map(|&n| n);
+                let n = match existing {
+                    Some(n) => n,
+                    None => {
+                        let n = pending.len();
+                        numbers.insert(key, n);
+                        pending.push(target.clone());
+                        n
+                    }
+                };
+                goto.insert(symbol, n);
+            }
+            states.push(Lr1State { kernel: kernel, items: items, goto: goto });
+            i += 1;
+        }
+        Lr1Automaton { states: states }
+    }
+
+    /// Build the canonical LR(1) parse table of the grammar.
+    ///
+    /// This accepts every LR(1) grammar, including those for which merging states with the same
+    /// items in the LALR(1) table causes reduce/reduce conflicts, at the cost of more states.
+    /// Fails with every conflict found if the grammar isn't LR(1).
+    pub fn lr1_table(&self) -> Result<LrTable, Vec<Conflict>> {
+        let automaton = self.lr1_automaton();
+        self.lr_table(&automaton.cores(), automaton.reductions(self))
+    }
+
+    /// The actions for each state and lookahead of a table built from `automaton`, each with the
+    /// item calling for it, and the goto for each state and variable.
+    fn action_cells(&self, automaton: &Lr0Automaton, reductions: Vec<Vec<(Item, FollowSet)>>)
+                    -> (Vec<Vec<(Action, Item)>>, Vec<Option<uint>>) {
+        let start = vec![Symbol::Var(self.get_start())];
+        let (tokens, variables) = (self.num_tokens(), self.num_variables());
+        let columns = tokens + 1;
+        let states = automaton.states.len();
+        let mut cells: Vec<Vec<(Action, Item)>> = Vec::from_fn(states * columns, |_| Vec::new());
+        let mut gotos = Vec::from_elem(states * variables, None);
+        for (s, state) in automaton.states.iter().enumerate() {
+            for &item in state.items.iter() {
+                match self.next_symbol(start.as_slice(), item) {
+                    Some(Symbol::Tok(t)) => {
+                        let action = Action::Shift(*state.goto.get(&Symbol::Tok(t)).unwrap());
+                        cells[s * columns + t.index()].push((action, item));
+                    },
+                    _ => { }
+                }
+            }
+            for (&symbol, &target) in state.goto.iter() {
+                match symbol {
+                    Symbol::Var(v) if v.index() < variables => {
+                        gotos[s * variables + v.index()] = Some(target);
+                    },
+                    _ => { }
+                }
+            }
+            for &(item, ref lookahead) in reductions[s].iter() {
+                let action = match item.variable {
+                    Some(v) => Action::Reduce(v, item.rule),
+                    None => Action::Accept
+                };
+                for t in lookahead.tokens.iter() {
+                    cells[s * columns + t.index()].push((action, item));
+                }
+                if lookahead.end {
+                    cells[s * columns + tokens].push((action, item));
+                }
+            }
+        }
+        (cells, gotos)
+    }
+
+    /// Build a parse table from the states and transitions of `automaton`, and the completed
+    /// items of each state with their lookaheads.
+    fn lr_table(&self, automaton: &Lr0Automaton, reductions: Vec<Vec<(Item, FollowSet)>>)
+                -> Result<LrTable, Vec<Conflict>> {
+        let (cells, gotos) = self.action_cells(automaton, reductions);
+        let (tokens, variables) = (self.num_tokens(), self.num_variables());
+        let states = automaton.states.len();
+        let mut actions = Vec::with_capacity(cells.len());
+        let mut conflicts = Vec::new();
+        let mut resolutions = Vec::new();
+        for (c, cell) in cells.into_iter().enumerate() {
+            let mut distinct: Vec<Action> = cell.iter().map(|&(a, _)| a).collect();
+            distinct.sort();
+            distinct.dedup();
+            if distinct.len() > 1 {
+                let conflict = cell_conflict(c, tokens, distinct, cell.as_slice());
+                match self.resolve(&conflict) {
+                    Some(action) => {
+                        actions.push(action);
+                        resolutions.push(Resolution { conflict: conflict, action: action });
+                    },
+                    None => {
+                        actions.push(None);
+                        conflicts.push(conflict);
+                    }
+                }
+            } else {
+                actions.push(distinct.pop());
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(conflicts)
+        }
+        Ok(LrTable {
+            states: states,
+            tokens: tokens,
+            variables: variables,
+            actions: actions,
+            gotos: gotos,
+            resolutions: resolutions
+        })
+    }
+
+    /// Resolve a conflict between shifting a token and reducing by a rule using their
+    /// precedences, as Yacc does, giving the action to take if it can be resolved.
+    fn resolve(&self, conflict: &Conflict) -> Option<Option<Action>> {
+        let token = match conflict.lookahead {
+            Some(t) => t,
+            None => return None
+        };
+        if conflict.actions.len() != 2 {
+            return None
+        }
+        let (shift, reduce) = (conflict.actions[0], conflict.actions[1]);
+        let rule = match (shift, reduce) {
+            (Action::Shift(_), Action::Reduce(v, r)) => self.rule_precedence(v, r),
+            _ => None
+        };
+        let (rule, (level, associativity)) = match (rule, self.precedence(token)) {
+            (Some((rule, _)), Some(token)) => (rule, token),
+            _ => return None
+        };
+        if rule != level {
+            return Some(Some(if rule > level { reduce } else { shift }))
+        }
+        match associativity {
+            Associativity::Left => Some(Some(reduce)),
+            Associativity::Right => Some(Some(shift)),
+            Associativity::NonAssoc => Some(None),
+            Associativity::Unspecified => None
+        }
+    }
+
+    /// Find an example for each item of a conflict found in a table built from `automaton`,
+    /// which for a canonical LR(1) table is the `cores` of its automaton.
+    ///
+    /// Each example is a shortest sentential form for its item, found separately from the
+    /// others, so the examples may not share a prefix. An item may have no example if it can't be
+    /// reached with the conflict's lookahead.
+    pub fn counterexamples(&self, automaton: &Lr0Automaton, conflict: &Conflict)
+                           -> Vec<Counterexample> {
+        let start = vec![Symbol::Var(self.get_start())];
+        let first = self.first_sets();
+        let mut into = Vec::from_fn(automaton.states.len(), |_| Vec::new());
+        for (s, symbol, t) in automaton.edges().into_iter() {
+            into[t].push((s, symbol));
+        }
+        let mut examples = Vec::new();
+        let mut items = conflict.items.clone();
+        items.sort();
+        items.dedup();
+        for &item in items.iter() {
+            // For a completed item, the lookahead must be derivable right after the rule; once
+            // a context providing it is found, the rest of the search is unconstrained.
+            let need = if self.is_complete(item) { Some(conflict.lookahead) } else { None };
+            let target = (conflict.state, item, need);
+            let root = Item { variable: None, rule: 0, dot: 0 };
+            let mut next = HashMap::new();
+            let mut queue = RingBuf::new();
+            queue.push_back(target);
+            next.insert(target, target);
+            loop {
+                let node = match queue.pop_front() {
+                    Some(node) => node,
+                    None => break
+                };
+                let (state, at, need) = node;
+                let satisfied = match need {
+                    None | Some(None) => true,
+                    Some(Some(_)) => false
+                };
+                if state == 0 && at == root && satisfied {
+                    examples.push(self.counterexample(start.as_slice(), &next, node, target));
+                    break
+                }
+                if at.dot > 0 {
+                    let symbol = self.body(start.as_slice(), at)[at.dot - 1];
+                    let before = Item { dot: at.dot - 1, ..at };
+                    for &(p, _) in into[state].iter().filter(|&&(_, x)| x == symbol) {
+                        let prev = (p, before, need);
+                        if !next.contains_key(&prev) {
+                            next.insert(prev, node);
+                            queue.push_back(prev);
+                        }
+                    }
+                    continue
+                }
+                let v = match at.variable {
+                    Some(v) => v,
+                    None => continue
+                };
+                for &from in automaton.states[state].items.iter() {
+                    if self.next_symbol(start.as_slice(), from) != Some(Symbol::Var(v)) {
+                        continue
+                    }
+                    let rest = first.of(self.body(start.as_slice(), from).slice_from(from.dot + 1));
+                    let need = match need {
+                        Some(Some(t)) if rest.tokens.contains(t) => None,
+                        Some(lookahead) if rest.epsilon => Some(lookahead),
+                        Some(_) => continue,
+                        None => None
+                    };
+                    let prev = (state, from, need);
+                    if !next.contains_key(&prev) {
+                        next.insert(prev, node);
+                        queue.push_back(prev);
+                    }
+                }
+            }
+        }
+        examples
+    }
+
+    /// Build the example given by the path from `root` to `target` found by `counterexamples`,
+    /// where `next` takes each node of the search to the one after it. Moving past a symbol
+    /// adds it to the example, and moving to the start of one of the rules of a variable adds
+    /// what follows the variable, after the rest of the rule.
+    fn counterexample(&self, start: &[Symbol], next: &HashMap<Node, Node>, root: Node,
+                      target: Node) -> Counterexample {
+        let mut form = Vec::new();
+        let mut contexts = Vec::new();
+        let mut at = root;
+        while at != target {
+            let (_, from, _) = at;
+            let after = *next.get(&at).unwrap();
+            let (_, to, _) = after;
+            let body = self.body(start, from);
+            if to.dot == 0 {
+                contexts.push(body.slice_from(from.dot + 1));
+            } else {
+                form.push(body[from.dot]);
+            }
+            at = after;
+        }
+        let (_, item, _) = target;
+        let position = form.len();
+        form.push_all(self.body(start, item).slice_from(item.dot));
+        for &rest in contexts.iter().rev() {
+            form.push_all(rest);
+        }
+        Counterexample { item: item, form: form, position: position }
+    }
+
+    /// The items of the state with the given kernel, where `start` is the body of the rule of the
+    /// augmented start symbol.
+    fn closure(&self, start: &[Symbol], kernel: &[Item]) -> Vec<Item> {
+        let mut items = kernel.to_vec();
+        let mut added = Vec::from_elem(self.num_variables(), false);
+        let mut i = 0;
+        while i < items.len() {
+            match self.next_symbol(start, items[i]) {
+                Some(Symbol::Var(v)) if !added[v.index()] => {
+                    added[v.index()] = true;
+                    let rules = self.get_rules(v).map_or(0, |rules| rules.len());
+                    for rule in range(0, rules) {
+                        items.push(Item { variable: Some(v), rule: rule, dot: 0 });
+                    }
+                },
+                _ => { }
+            }
+            i += 1;
+        }
+        items
+    }
+
+    /// The items, with their lookaheads, of the LR(1) state with the given kernel.
+    fn closure1(&self, first: &FirstSets, start: &[Symbol], kernel: Vec<(Item, FollowSet)>)
+                -> Vec<(Item, FollowSet)> {
+        let mut items = Vec::with_capacity(kernel.len());
+        let mut lookaheads = Vec::with_capacity(kernel.len());
+        for (item, lookahead) in kernel.into_iter() {
+            items.push(item);
+            lookaheads.push(lookahead);
+        }
+        let mut index: HashMap<Item, uint> = items.iter().enumerate().map(|(i, &item)| (item, i))
+                                                                     .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let mut i = 0;
+            while i < items.len() {
+                let body = self.body(start, items[i]);
+                let v = match body.get(items[i].dot) {
+                    Some(&Symbol::Var(v)) => v,
+                    _ => {
+                        i += 1;
+                        continue
+                    }
+                };
+                let rest = first.of(body.slice_from(items[i].dot + 1));
+                let mut lookahead = FollowSet { tokens: rest.tokens, end: false };
+                if rest.epsilon {
+                    lookahead.union_with(&lookaheads[i]);
+                }
+                for rule in range(0, self.get_rules(v).map_or(0, |rules| rules.len())) {
+                    let item = Item { variable: Some(v), rule: rule, dot: 0 };
+                    let existing = index.get(&item).map(|&j| j);
+                    match existing {
+                        Some(j) => changed = lookaheads[j].union_with(&lookahead) || changed,
+                        None => {
+                            index.insert(item, items.len());
+                            items.push(item);
+                            lookaheads.push(lookahead.clone());
+                            changed = true;
+                        }
+                    }
+                }
+                i += 1;
+            }
+        }
+        items.into_iter().zip(lookaheads.into_iter()).collect()
+    }
+
+    /// The body of the rule of an item.
+    fn body<'a>(&'a self, start: &'a [Symbol], item: Item) -> &'a [Symbol] {
+        match item.variable {
+            Some(v) => self.get_rules(v).unwrap()[item.rule].as_slice(),
+            None => start
+        }
+    }
+
+    /// The symbol right after the position of an item, if any.
+    fn next_symbol(&self, start: &[Symbol], item: Item) -> Option<Symbol> {
+        self.body(start, item).get(item.dot).map(|&s| s)
+    }
+
+    /// Whether the position of an item is at the end of its rule.
+    fn is_complete(&self, item: Item) -> bool {
+        match item.variable {
+            Some(v) => item.dot == self.get_rules(v).unwrap()[item.rule].len(),
+            None => item.dot == 1
+        }
+    }
+}
+
+/// A node of the search for a counterexample: a state, an item of it, and the lookahead that
+/// must still be derived after the item's rule, if any, with `None` for the end of the input.
+type Node = (uint, Item, Option<Option<Token>>);
+
+/// The conflict between `actions` in a cell of a table, given by its index, with the items
+/// calling for each action.
+fn cell_conflict(cell: uint, tokens: uint, actions: Vec<Action>, items: &[(Action, Item)])
+                 -> Conflict {
+    let column = cell % (tokens + 1);
+    Conflict {
+        state: cell / (tokens + 1),
+        lookahead: if column == tokens { None } else { Some(Token(column as u32)) },
+        actions: actions,
+        items: items.iter().map(|&(_, item)| item).collect()
+    }
+}
+
+/// A kernel of LR(1) items in a form that can be hashed, to find states already built.
+fn kernel_key(kernel: &[(Item, FollowSet)]) -> Vec<(Item, Vec<Token>, bool)> {
+    kernel.iter().map(|&(item, ref lookahead)| {
+        (item, lookahead.tokens.iter().collect(), lookahead.end)
+    }).collect()
+}
+
+/// A deterministic LR parser for a grammar, holding its table.
+pub struct LrParser<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    table: LrTable
+}
+
+impl<'a, T: Eq + Hash + Clone> LrParser<'a, T> {
+    /// Build the parser's LALR(1) table, failing with every conflict found if the grammar isn't
+    /// LALR(1).
+    pub fn new(cfg: &'a Cfg<T>) -> Result<LrParser<'a, T>, Vec<Conflict>> {
+        let table = try!(cfg.lalr_table());
+        Ok(LrParser { cfg: cfg, table: table })
+    }
+
+    /// A parser using a table already built for the grammar, such as by `slr_table` or
+    /// `lr1_table`, or loaded with `LrTable::from_bytes`, failing with `LoadError::Invalid` if
+    /// the table doesn't fit the grammar, as checked by `LrTable::fits`.
+    pub fn with_table(cfg: &'a Cfg<T>, table: LrTable) -> Result<LrParser<'a, T>, LoadError> {
+        if !table.fits(cfg) {
+            return Err(LoadError::Invalid)
+        }
+        Ok(LrParser { cfg: cfg, table: table })
+    }
+
+    pub fn table(&self) -> &LrTable {
+        &self.table
+    }
+
+    /// Whether the grammar derives `input`.
+    pub fn recognize(&self, input: &[Token]) -> bool {
+        self.parse(input).is_ok()
+    }
+
+    /// Parse `input`, shifting and reducing as the table says.
+    pub fn parse(&self, input: &[Token]) -> Result<ParseTree, ParseError> {
+        self.parse_with(input, &mut TreeBuilder)
+    }
+
+    /// Parse the tokens read from a lexer. To refer to their spans and payloads from the tree,
+    /// read them with `TokenStream::read` and parse its `tokens` instead.
+    pub fn parse_source<P, S: TokenSource<P>>(&self, source: &mut S)
+                                              -> Result<ParseTree, ParseError> {
+        self.parse(TokenStream::read(source).tokens.as_slice())
+    }
+
+    /// Parse `input` as `parse` does, building a value for each rule with `reducer` as it's
+    /// reduced.
+    pub fn parse_with<V, R: Reducer<V>>(&self, input: &[Token], reducer: &mut R)
+                                        -> Result<V, ParseError> {
+        let mut stream = self.stream_with(reducer);
+        try!(stream.feed(input));
+        stream.finish()
+    }
+
+    /// Start a parse to be given its input a piece at a time, building the parse tree.
+    pub fn stream<'p>(&'p self) -> LrStream<'p, 'a, T, ParseTree, TreeBuilder> {
+        self.stream_with(TreeBuilder)
+    }
+
+    /// Start a parse to be given its input a piece at a time, building a value for each rule
+    /// with `reducer` as `parse_with` does.
+    pub fn stream_with<'p, V, R: Reducer<V>>(&'p self, reducer: R) -> LrStream<'p, 'a, T, V, R> {
+        LrStream {
+            parser: self,
+            reducer: reducer,
+            states: vec![0],
+            values: Vec::new(),
+            position: 0,
+            error: None
+        }
+    }
+
+    /// Parse `input`, recovering from errors to find every one, as `parse_with_recovery` does.
+    pub fn parse_recovering(&self, input: &[Token], sync: &SyncSets)
+                            -> Result<ParseTree, Vec<ParseError>> {
+        match self.parse_with_recovery(input, sync, &mut PartialTreeBuilder) {
+            (Some(Some(tree)), ref errors) if errors.is_empty() => Ok(tree),
+            (_, errors) => Err(errors)
+        }
+    }
+
+    /// Parse `input` as `parse_with` does, but recover from each error to carry on, giving the
+    /// value built, if the parser could recover from every error, with every error found.
+    ///
+    /// On an error, the parser pops states off its stack until one with a goto on some
+    /// variable, and skips tokens until one in that variable's synchronizing set which it has
+    /// an action for after the goto. The variable is taken to have matched the text of the
+    /// popped states and the skipped tokens, with `reducer`'s error value. The states nearest
+    /// the top of the stack and the fewest skipped tokens are tried first. An error right after
+    /// recovering from another isn't reported, as it's most likely a consequence.
+    pub fn parse_with_recovery<V, R: Recover<V>>(&self, input: &[Token], sync: &SyncSets,
+                                                 reducer: &mut R)
+                                                 -> (Option<V>, Vec<ParseError>) {
+        let mut errors = Vec::new();
+        let mut position = 0;
+        let mut states = vec![0u];
+        // The value of each symbol on the stack, with the position its text starts at.
+        let mut values: Vec<(V, uint)> = Vec::new();
+        // Where the parser last recovered, if it hasn't shifted since.
+        let mut recovered = None;
+        loop {
+            let state = *states.last().unwrap();
+            let lookahead = input.get(position).map(|&t| t);
+            match (self.table.action(state, lookahead), lookahead) {
+                (Some(Action::Shift(next)), Some(t)) => {
+                    values.push((reducer.shift(t, position), position));
+                    states.push(next);
+                    position += 1;
+                    recovered = None;
+                },
+                (Some(Action::Reduce(v, rule)), _) => {
+                    let len = self.cfg.get_rules(v).unwrap()[rule].len();
+                    let reduced = match pop(&mut values, len) {
+                        Some(children) => {
+                            let at = states.len() - len;
+                            states.truncate(at);
+                            self.table.goto(states[at - 1], v).map(|next| (children, next))
+                        },
+                        None => None
+                    };
+                    let (children, next) = match reduced {
+                        Some(reduced) => reduced,
+                        None => {
+                            errors.push(self.error(state, lookahead, position));
+                            return (None, errors)
+                        }
+                    };
+                    let start = children.get(0).map_or(position, |&(_, start)| start);
+                    let children = children.into_iter().map(|(value, _)| value).collect();
+                    values.push((reducer.reduce(v, rule, children), start));
+                    states.push(next);
+                },
+                (Some(Action::Accept), _) if values.len() == 1 => {
+                    return (values.pop().map(|(value, _)| value), errors)
+                },
+                (Some(_), _) => {
+                    errors.push(self.error(state, lookahead, position));
+                    return (None, errors)
+                },
+                (None, _) => {
+                    report(&mut errors, self.error(state, lookahead, position));
+                    // Recovering again where it last did could go round in circles, so the
+                    // parser must skip a token this time.
+                    let from = if recovered == Some(position) { position + 1 } else { position };
+                    let (depth, v, next, resume) = match self.recover(states.as_slice(), input,
+                                                                      from, sync) {
+                        Some(recovery) => recovery,
+                        None => return (None, errors)
+                    };
+                    let start = values.get(depth - 1).map_or(position, |&(_, start)| start);
+                    states.truncate(depth);
+                    values.truncate(depth - 1);
+                    values.push((reducer.error(Symbol::Var(v), start, resume), start));
+                    states.push(next);
+                    position = resume;
+                    recovered = Some(resume);
+                }
+            }
+        }
+    }
+
+    /// Where to recover from an error, skipping tokens from `from`: the number of states to
+    /// keep on the stack, the variable to go to from the top one and the state it goes to, and
+    /// the position to resume at.
+    fn recover(&self, states: &[uint], input: &[Token], from: uint, sync: &SyncSets)
+               -> Option<(uint, Variable, uint, uint)> {
+        for resume in range(from, input.len() + 1) {
+            let lookahead = input.get(resume).map(|&t| t);
+            for depth in range(1, states.len() + 1).rev() {
+                for v in self.cfg.variables() {
+                    match self.table.goto(states[depth - 1], v) {
+                        Some(next) if sync.syncs(v, lookahead)
+                                      && self.table.action(next, lookahead).is_some() => {
+                            return Some((depth, v, next, resume))
+                        },
+                        _ => { }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// The error on `lookahead` at `position` of the input, in `state`: every lookahead with an
+    /// action there is expected.
+    fn error(&self, state: uint, lookahead: Option<Token>, position: uint) -> ParseError {
+        let mut expected: Vec<Option<Token>> = self.cfg.tokens().map(|t| Some(t))
+            .filter(|&t| self.table.action(state, t).is_some())
+            .collect();
+        if self.table.action(state, None).is_some() {
+            expected.push(None);
+        }
+        ParseError { position: position, found: lookahead, expected: expected }
+    }
+}
+
+/// A parse by an `LrParser` given its input a piece at a time, such as input arriving over a
+/// socket, to be fed tokens as they come and told when the input ends.
+pub struct LrStream<'p, 'a: 'p, T: 'a, V, R> {
+    parser: &'p LrParser<'a, T>,
+    reducer: R,
+    states: Vec<uint>,
+    values: Vec<V>,
+    position: uint,
+    error: Option<ParseError>
+}
+
+impl<'p, 'a, T: Eq + Hash + Clone, V, R: Reducer<V>> LrStream<'p, 'a, T, V, R> {
+    /// The number of tokens read so far.
+    pub fn position(&self) -> uint {
+        self.position
+    }
+
+    /// Read more of the input, failing at the first token that can't be parsed. Once it has
+    /// failed, the parse fails with the same error whatever it's given.
+    pub fn feed(&mut self, tokens: &[Token]) -> Result<(), ParseError> {
+        match self.error {
+            Some(ref error) => return Err(error.clone()),
+            None => { }
+        }
+        for &t in tokens.iter() {
+            match self.step(Some(t)) {
+                Ok(()) => { },
+                Err(error) => {
+                    self.error = Some(error.clone());
+                    return Err(error)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// End the input, giving the value of the whole parse.
+    pub fn finish(mut self) -> Result<V, ParseError> {
+        match self.error.take() {
+            Some(error) => return Err(error),
+            None => { }
+        }
+        try!(self.step(None));
+        Ok(self.values.pop().unwrap())
+    }
+
+    /// Reduce as the table says until `lookahead` is shifted or, at the end of the input,
+    /// accepted. A table that fits the grammar without having been built for it could reduce
+    /// more than is on the stack, or to a state without a goto, which fails as an error in the
+    /// state reduced in.
+    fn step(&mut self, lookahead: Option<Token>) -> Result<(), ParseError> {
+        let parser = self.parser;
+        loop {
+            let state = *self.states.last().unwrap();
+            match (parser.table.action(state, lookahead), lookahead) {
+                (Some(Action::Shift(next)), Some(t)) => {
+                    self.values.push(self.reducer.shift(t, self.position));
+                    self.states.push(next);
+                    self.position += 1;
+                    return Ok(())
+                },
+                (Some(Action::Reduce(v, rule)), _) => {
+                    let len = parser.cfg.get_rules(v).unwrap()[rule].len();
+                    let children = match pop(&mut self.values, len) {
+                        Some(children) => children,
+                        None => return Err(parser.error(state, lookahead, self.position))
+                    };
+                    let at = self.states.len() - len;
+                    self.states.truncate(at);
+                    let next = match parser.table.goto(self.states[at - 1], v) {
+                        Some(next) => next,
+                        None => return Err(parser.error(state, lookahead, self.position))
+                    };
+                    self.values.push(self.reducer.reduce(v, rule, children));
+                    self.states.push(next);
+                },
+                (Some(Action::Accept), _) if self.values.len() == 1 => return Ok(()),
+                _ => return Err(parser.error(state, lookahead, self.position))
+            }
+        }
+    }
+}
+
+/// Take the last `len` values off a parser's stack, in order, or `None` if it holds fewer.
+fn pop<V>(values: &mut Vec<V>, len: uint) -> Option<Vec<V>> {
+    if len > values.len() {
+        return None
+    }
+    let mut popped = Vec::with_capacity(len);
+    for _ in range(0, len) {
+        popped.extend(values.pop().into_iter());
+    }
+    popped.reverse();
+    Some(popped)
+}
+
+impl<T: fmt::Show + Eq + Hash + Clone> Cfg<T> {
+    /// Render an item as a BNF rule with a `.` at its position, such as `<e> ::= <e> . "+" <t>`.
+    /// The augmented start symbol is written as the start symbol followed by `'`.
+    pub fn item_to_string(&self, item: Item) -> String {
+        let start = vec![Symbol::Var(self.get_start())];
+        let mut text = match item.variable {
+            Some(v) => self.symbol_to_bnf(Symbol::Var(v)),
+            None => format!("{}'", self.symbol_to_bnf(Symbol::Var(self.get_start())))
+        };
+        text.push_str(" ::=");
+        let body = self.body(start.as_slice(), item);
+        for (i, &symbol) in body.iter().enumerate() {
+            if i == item.dot {
+                text.push_str(" .");
+            }
+            text.push(' ');
+            text.push_str(self.symbol_to_bnf(symbol).as_slice());
+        }
+        if item.dot == body.len() {
+            text.push_str(" .");
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Action, Item, LrParser, LrTable};
+    use super::super::{Associativity, Cfg, Symbol, Token, Variable};
+    use binary::{Kind, LoadError, Writer};
+    use recovery::SyncSets;
+    use std::uint;
+    use testing::{EXPRESSIONS, check_tree, grammar, tokens, variable};
+    use tree::ParseTree;
+
+    /// A grammar that is LALR(1) but not SLR(1), since `=` is in FOLLOW(r).
+    const ASSIGNMENT: &'static str = "
+    <s> ::= <l> \"=\" <r> | <r>
+    <l> ::= \"*\" <r> | \"id\"
+    <r> ::= <l>
+    ";
+
+    /// The reductions made running `table` on `input`, or `None` if it rejects the input.
+    fn reductions(cfg: &Cfg<String>, table: &LrTable, input: &[Token])
+                  -> Option<Vec<(Variable, uint)>> {
+        let mut stack = vec![0u];
+        let mut position = 0;
+        let mut reductions = Vec::new();
+        loop {
+            let state = *stack.last().unwrap();
+            match table.action(state, input.get(position).map(|&t| t)) {
+                Some(Action::Shift(next)) => {
+                    stack.push(next);
+                    position += 1;
+                },
+                Some(Action::Reduce(v, rule)) => {
+                    let len = stack.len() - cfg.get_rules(v).unwrap()[rule].len();
+                    stack.truncate(len);
+                    match table.goto(*stack.last().unwrap(), v) {
+                        Some(next) => stack.push(next),
+                        None => return None
+                    }
+                    reductions.push((v, rule));
+                },
+                Some(Action::Accept) => return Some(reductions),
+                None => return None
+            }
+        }
+    }
+
+    #[test]
+    fn slr_table() {
+        let cfg = grammar(EXPRESSIONS);
+        let automaton = cfg.lr0_automaton();
+        assert_eq!(automaton.states.len(), 12);
+        let table = cfg.slr_table().unwrap();
+        assert_eq!(table.num_states(), 12);
+        let accepts = range(0, table.num_states())
+            .filter(|&s| table.action(s, None) == Some(Action::Accept))
+            .count();
+        assert_eq!(accepts, 1);
+
+        let (e, t, f) = (variable(&cfg, "e"), variable(&cfg, "t"), variable(&cfg, "f"));
+        let input = tokens(&cfg, "id + id * id");
+        assert_eq!(reductions(&cfg, &table, input.as_slice()),
+                   Some(vec![(f, 1), (t, 1), (e, 1), (f, 1), (t, 1), (f, 1), (t, 0), (e, 0)]));
+        assert!(reductions(&cfg, &table, tokens(&cfg, "( id ) * id").as_slice()).is_some());
+        assert_eq!(reductions(&cfg, &table, tokens(&cfg, "id +").as_slice()), None);
+        assert_eq!(reductions(&cfg, &table, tokens(&cfg, "id id").as_slice()), None);
+    }
+
+    #[test]
+    fn slr_conflicts() {
+        let cfg = grammar(ASSIGNMENT);
+        let conflicts = cfg.slr_table().unwrap_err();
+        let equals = cfg.token(&"=".to_string());
+        assert!(!conflicts.is_empty());
+        assert!(conflicts.iter().all(|c| c.lookahead == equals && c.is_shift_reduce()));
+        let ambiguous = grammar("<e> ::= <e> \"+\" <e> | \"id\"");
+        assert!(ambiguous.slr_table().is_err());
+    }
+
+    #[test]
+    fn lalr_table() {
+        let cfg = grammar(EXPRESSIONS);
+        let table = cfg.lalr_table().unwrap();
+        assert_eq!(table.num_states(), 12);
+        assert!(reductions(&cfg, &table, tokens(&cfg, "id * ( id + id )").as_slice()).is_some());
+
+        let cfg = grammar(ASSIGNMENT);
+        let table = cfg.lalr_table().unwrap();
+        assert_eq!(table.num_states(), cfg.lr0_automaton().states.len());
+        assert!(reductions(&cfg, &table, tokens(&cfg, "* id = id").as_slice()).is_some());
+        assert!(reductions(&cfg, &table, tokens(&cfg, "* * id").as_slice()).is_some());
+        assert_eq!(reductions(&cfg, &table, tokens(&cfg, "id = = id").as_slice()), None);
+    }
+
+    /// A grammar that is LR(1) but not LALR(1): merging the states reached by `a c` and `b c`
+    /// makes reducing `c` to `a` or to `b` conflict.
+    const NOT_LALR: &'static str = "
+    <s> ::= \"a\" <a> \"d\" | \"b\" <b> \"d\" | \"a\" <b> \"e\" | \"b\" <a> \"e\"
+    <a> ::= \"c\"
+    <b> ::= \"c\"
+    ";
+
+    #[test]
+    fn lr1_table() {
+        let cfg = grammar(EXPRESSIONS);
+        let table = cfg.lr1_table().unwrap();
+        assert_eq!(table.num_states(), 22);
+        assert!(reductions(&cfg, &table, tokens(&cfg, "( id + id ) * id").as_slice()).is_some());
+        assert_eq!(reductions(&cfg, &table, tokens(&cfg, "( id").as_slice()), None);
+
+        let cfg = grammar(NOT_LALR);
+        let conflicts = cfg.lalr_table().unwrap_err();
+        assert!(conflicts.iter().all(|c| !c.is_shift_reduce()));
+        let table = cfg.lr1_table().unwrap();
+        let (s, a, b) = (variable(&cfg, "s"), variable(&cfg, "a"), variable(&cfg, "b"));
+        let input = tokens(&cfg, "a c e");
+        assert_eq!(reductions(&cfg, &table, input.as_slice()), Some(vec![(b, 0), (s, 2)]));
+        let input = tokens(&cfg, "b c e");
+        assert_eq!(reductions(&cfg, &table, input.as_slice()), Some(vec![(a, 0), (s, 3)]));
+    }
+
+    #[test]
+    fn automata() {
+        let cfg = grammar(EXPRESSIONS);
+        let (e, f) = (variable(&cfg, "e"), variable(&cfg, "f"));
+        let automaton = cfg.lr0_automaton();
+        let initial = &automaton.states[0];
+        assert_eq!(initial.kernel, vec![Item { variable: None, rule: 0, dot: 0 }]);
+        assert_eq!(initial.closure().len(), 6);
+        let from_initial = automaton.edges().into_iter().filter(|&(s, _, _)| s == 0).count();
+        assert_eq!(from_initial, 5);
+        assert_eq!(automaton.edges().len(),
+                   automaton.states.iter().fold(0, |n, state| n + state.goto.len()));
+
+        assert_eq!(cfg.lalr_automaton().cores().states.len(), 12);
+        let lr1 = cfg.lr1_automaton();
+        assert_eq!(lr1.states.len(), 22);
+        assert_eq!(lr1.states[0].closure().len(), 6);
+        assert_eq!(lr1.cores().edges(), lr1.edges());
+
+        assert_eq!(cfg.item_to_string(Item { variable: None, rule: 0, dot: 0 }).as_slice(),
+                   "<e>' ::= . <e>");
+        assert_eq!(cfg.item_to_string(Item { variable: Some(e), rule: 0, dot: 1 }).as_slice(),
+                   "<e> ::= <e> . \"+\" <t>");
+        assert_eq!(cfg.item_to_string(Item { variable: Some(f), rule: 1, dot: 1 }).as_slice(),
+                   "<f> ::= \"id\" .");
+    }
+
+    #[test]
+    fn counterexamples() {
+        let cfg = grammar("<e> ::= <e> \"+\" <e> | \"id\"");
+        let e = variable(&cfg, "e");
+        let plus = cfg.token(&"+".to_string()).unwrap();
+        let automaton = cfg.lr0_automaton();
+        let conflicts = cfg.slr_table().unwrap_err();
+        assert_eq!(conflicts.len(), 1);
+        let conflict = &conflicts[0];
+        assert!(conflict.is_shift_reduce());
+        let examples = cfg.counterexamples(&automaton, conflict);
+        assert_eq!(examples.len(), 2);
+        let prefix = [Symbol::Var(e), Symbol::Tok(plus), Symbol::Var(e)];
+        for example in examples.iter() {
+            assert!(conflict.items.contains(&example.item));
+            assert_eq!(example.position, 3);
+            assert_eq!(example.form.slice_to(3), prefix.as_slice());
+            assert_eq!(example.form[3], Symbol::Tok(plus));
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let mut cfg = grammar("<e> ::= <e> \"+\" <e> | <e> \"*\" <e> | \"id\"");
+        assert!(cfg.lalr_table().is_err());
+        let e = variable(&cfg, "e");
+        let plus = cfg.token(&"+".to_string()).unwrap();
+        let times = cfg.token(&"*".to_string()).unwrap();
+        cfg.set_precedence(plus, 1, Associativity::Left).unwrap();
+        cfg.set_precedence(times, 2, Associativity::Left).unwrap();
+        let table = cfg.lalr_table().unwrap();
+        assert_eq!(table.resolutions().len(), 4);
+        assert!(table.resolutions().iter().all(|r| r.action.is_some()));
+
+        // The product is reduced first wherever it is, and sums group to the left.
+        let input = tokens(&cfg, "id * id + id");
+        assert_eq!(reductions(&cfg, &table, input.as_slice()),
+                   Some(vec![(e, 2), (e, 2), (e, 1), (e, 2), (e, 0)]));
+        let input = tokens(&cfg, "id + id * id");
+        assert_eq!(reductions(&cfg, &table, input.as_slice()),
+                   Some(vec![(e, 2), (e, 2), (e, 2), (e, 1), (e, 0)]));
+        let input = tokens(&cfg, "id + id + id");
+        assert_eq!(reductions(&cfg, &table, input.as_slice()),
+                   Some(vec![(e, 2), (e, 2), (e, 0), (e, 2), (e, 0)]));
+
+        cfg.set_precedence(plus, 1, Associativity::NonAssoc).unwrap();
+        let table = cfg.lalr_table().unwrap();
+        assert!(table.resolutions().iter().any(|r| r.action.is_none()));
+        assert_eq!(reductions(&cfg, &table, tokens(&cfg, "id + id + id").as_slice()), None);
+    }
+
+    #[test]
+    fn parse_expression() {
+        let cfg = grammar(EXPRESSIONS);
+        let parser = LrParser::new(&cfg).unwrap();
+        let input = tokens(&cfg, "id + id * id");
+        let tree = parser.parse(input.as_slice()).unwrap();
+        check_tree(&cfg, &tree, input.as_slice());
+        let (e, t) = (variable(&cfg, "e"), variable(&cfg, "t"));
+        match tree {
+            ParseTree::Node(v, 0, ref children) => {
+                assert_eq!(v, e);
+                match children[2] {
+                    ParseTree::Node(v, 0, _) => assert_eq!(v, t),
+                    _ => panic!("expected the product on the right of the sum")
+                }
+            },
+            _ => panic!("expected a sum at the root")
+        }
+        assert!(parser.recognize(tokens(&cfg, "( id ) * id").as_slice()));
+
+        let error = parser.parse(tokens(&cfg, "id +").as_slice()).unwrap_err();
+        assert_eq!(error.position, 2);
+        assert!(error.expected.contains(&cfg.token(&"id".to_string())));
+        assert!(!error.expected.contains(&None));
+
+        // The SLR table of the grammar works as well.
+        let parser = LrParser::with_table(&cfg, cfg.slr_table().unwrap()).unwrap();
+        assert_eq!(parser.parse(input.as_slice()), Ok(tree));
+        assert!(LrParser::new(&grammar(NOT_LALR)).is_err());
+    }
+
+    #[test]
+    fn stream() {
+        let cfg = grammar(EXPRESSIONS);
+        let parser = LrParser::new(&cfg).unwrap();
+        let mut stream = parser.stream();
+        for piece in ["id *", "( id", "", "+ id )"].iter() {
+            stream.feed(tokens(&cfg, *piece).as_slice()).unwrap();
+        }
+        assert_eq!(stream.position(), 7);
+        let expected = parser.parse(tokens(&cfg, "id * ( id + id )").as_slice());
+        assert_eq!(stream.finish(), expected);
+
+        let mut stream = parser.stream();
+        stream.feed(tokens(&cfg, "id +").as_slice()).unwrap();
+        let error = stream.feed(tokens(&cfg, "+ id").as_slice()).unwrap_err();
+        assert_eq!((error.position, error.found), (2, cfg.token(&"+".to_string())));
+        assert_eq!(stream.feed(tokens(&cfg, "id").as_slice()), Err(error.clone()));
+        assert_eq!(stream.finish(), Err(error));
+
+        let mut stream = parser.stream();
+        stream.feed(tokens(&cfg, "id +").as_slice()).unwrap();
+        let error = stream.finish().unwrap_err();
+        assert_eq!((error.position, error.found), (2, None));
+    }
+
+    #[test]
+    fn round_trip() {
+        let cfg = grammar(EXPRESSIONS);
+        let table = cfg.lalr_table().unwrap();
+        assert_eq!(LrTable::from_bytes(table.to_bytes().as_slice()), Ok(table));
+    }
+
+    #[test]
+    fn bad_tables() {
+        let mut w = Writer::new(Kind::LrTable);
+        w.uint(2);
+        w.uint(uint::MAX);
+        w.uint(0);
+        assert_eq!(LrTable::from_bytes(w.finish().as_slice()), Err(LoadError::Invalid));
+
+        let mut w = Writer::new(Kind::LrTable);
+        w.uint(uint::MAX / 2);
+        w.uint(1);
+        w.uint(1);
+        assert_eq!(LrTable::from_bytes(w.finish().as_slice()), Err(LoadError::Truncated));
+    }
+
+    #[test]
+    fn fits() {
+        let table = grammar(EXPRESSIONS).lalr_table().unwrap();
+        assert!(table.fits(&grammar(EXPRESSIONS)));
+        assert!(!table.fits(&grammar("<s> ::= \"id\"")));
+        // As many tokens and variables, but `e` has one rule fewer.
+        let fewer_rules = grammar("
+    <e> ::= <t> \"+\" <t>
+    <t> ::= <t> \"*\" <f> | <f>
+    <f> ::= \"(\" <e> \")\" | \"id\"
+    ");
+        assert!(!table.fits(&fewer_rules));
+
+        // Two states for `<s> ::= "id"`, shifting `id` and accepting after the goto on `s`.
+        let cfg = grammar("<s> ::= \"id\"");
+        let s = variable(&cfg, "s");
+        let id = Some(Action::Shift(1));
+        assert!(two_states(vec![id, None, None, Some(Action::Accept)], vec![Some(1), None])
+                .fits(&cfg));
+        // Shifting at the end of the input.
+        assert!(!two_states(vec![None, Some(Action::Shift(1)), None, Some(Action::Accept)],
+                            vec![Some(1), None]).fits(&cfg));
+        // Accepting before the end of the input.
+        assert!(!two_states(vec![id, None, Some(Action::Accept), None], vec![Some(1), None])
+                .fits(&cfg));
+        // Shifting and going to states the table doesn't have.
+        assert!(!two_states(vec![Some(Action::Shift(2)), None, None, Some(Action::Accept)],
+                            vec![Some(1), None]).fits(&cfg));
+        assert!(!two_states(vec![id, None, None, Some(Action::Accept)], vec![Some(2), None])
+                .fits(&cfg));
+        // Reducing to `s`, which no state has a goto on.
+        let reduce = Some(Action::Reduce(s, 0));
+        assert!(!two_states(vec![id, None, None, reduce], vec![None, None]).fits(&cfg));
+    }
+
+    /// A table of two states for a grammar of one token and one variable.
+    fn two_states(actions: Vec<Option<Action>>, gotos: Vec<Option<uint>>) -> LrTable {
+        LrTable {
+            states: 2,
+            tokens: 1,
+            variables: 1,
+            actions: actions,
+            gotos: gotos,
+            resolutions: Vec::new()
+        }
+    }
+
+    #[test]
+    fn table_not_fitting() {
+        let table = grammar(EXPRESSIONS).lalr_table().unwrap();
+        let cfg = grammar("<s> ::= \"id\"");
+        assert_eq!(LrParser::with_table(&cfg, table).err(), Some(LoadError::Invalid));
+
+        // A table that fits, but reduces `s` before shifting anything for it, fails the parse
+        // rather than popping an empty stack.
+        let s = variable(&cfg, "s");
+        let table = two_states(vec![Some(Action::Reduce(s, 0)), None, None, Some(Action::Accept)],
+                               vec![Some(1), None]);
+        let parser = LrParser::with_table(&cfg, table).unwrap();
+        let input = tokens(&cfg, "id");
+        assert_eq!(parser.parse(input.as_slice()).unwrap_err().position, 0);
+        let sync = SyncSets::follow(&cfg);
+        assert_eq!(parser.parse_recovering(input.as_slice(), &sync).unwrap_err().len(), 1);
+    }
+}
@@ -0,0 +1,302 @@
+//! Generated LR parsers, and a helper for generating them from a build script.
+
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{File, IoError};
+
+use super::{Cfg, SyntaxError, Token, Variable};
+use antlr;
+use codegen::{TREE, write_terminals};
+use lr::{Action, Conflict, LrTable};
+use yacc;
+
+impl<T: fmt::Show + Eq + Hash + Clone> Cfg<T> {
+    /// Generate the Rust source of an LR parser for the grammar, driven by its LALR(1) table,
+    /// with the precedence and associativity of tokens resolving conflicts as they do for
+    /// `lalr_table`.
+    ///
+    /// As for `to_recursive_descent`, the source stands alone and gives a `parse` function
+    /// taking the tokens of the input by index and returning the parse tree, or the position of
+    /// the first token it couldn't parse. Fails with every conflict found if the grammar isn't
+    /// LALR(1).
+    pub fn to_lr_parser(&self) -> Result<String, Vec<Conflict>> {
+        let table = try!(self.lalr_table());
+        Ok(self.to_lr_parser_with_table(&table))
+    }
+
+    /// Generate the Rust source of an LR parser as `to_lr_parser` does, driven by a table
+    /// already built for the grammar, such as by `slr_table` or `lr1_table`.
+    ///
+    /// Panics if the table doesn't fit the grammar, as checked by `LrTable::fits`.
+    pub fn to_lr_parser_with_table(&self, table: &LrTable) -> String {
+        assert!(table.fits(self));
+        format!("{}", LrSource { cfg: self, table: table })
+    }
+}
+
+struct LrSource<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    table: &'a LrTable
+}
+
+impl<'a, T: fmt::Show + Eq + Hash + Clone> fmt::Show for LrSource<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (tokens, variables) = (self.cfg.num_tokens(), self.cfg.num_variables());
+        try!(writeln!(f, "// An LR parser generated from a grammar."));
+        try!(writeln!(f, ""));
+        try!(write_terminals(f, self.cfg));
+        try!(write!(f, "{}", TREE));
+        try!(writeln!(f, ""));
+        try!(writeln!(f, "const TOKENS: uint = {};", tokens));
+        try!(writeln!(f, "const VARIABLES: uint = {};", variables));
+
+        // Each rule, by variable and index in its rules, is numbered in order for reductions.
+        let mut rules = Vec::new();
+        let mut first_rule = Vec::with_capacity(variables);
+        for v in range(0, variables) {
+            first_rule.push(rules.len());
+            let bodies = self.cfg.get_rules(Variable(v as u32)).unwrap_or(&[]);
+            for (i, body) in bodies.iter().enumerate() {
+                rules.push((v, i, body.len()));
+            }
+        }
+        try!(writeln!(f, ""));
+        try!(writeln!(f, "/// Each rule of the grammar, as its variable, its index among the \
+                          variable's rules and"));
+        try!(writeln!(f, "/// its length."));
+        try!(write!(f, "static RULES: &'static [(uint, uint, uint)] = &["));
+        for (i, &(v, rule, len)) in rules.iter().enumerate() {
+            try!(write!(f, "{}{}({}, {}, {})", if i > 0 { "," } else { "" },
+                        if i % 6 == 0 { "\n    " } else { " " }, v, rule, len));
+        }
+        try!(writeln!(f, "\n];"));
+
+        let states = self.table.num_states();
+        let mut actions = Vec::with_capacity(states * (tokens + 1));
+        let mut gotos = Vec::with_capacity(states * variables);
+        for state in range(0, states) {
+            for t in range(0, tokens + 1) {
+                let lookahead = if t < tokens { Some(Token(t as u32)) } else { None };
+                actions.push(match self.table.action(state, lookahead) {
+                    None => 0,
+                    Some(Action::Accept) => 1,
+                    Some(Action::Shift(next)) => 2 + 2 * next,
+                    Some(Action::Reduce(v, rule)) => 3 + 2 * (first_rule[v.index()] + rule)
+                });
+            }
+            for v in range(0, variables) {
+                gotos.push(self.table.goto(state, Variable(v as u32)).map_or(0, |next| next + 1));
+            }
+        }
+        try!(writeln!(f, ""));
+        try!(writeln!(f, "/// The action in each state on each token, and on the end of the \
+                          input last: 0 for an"));
+        try!(writeln!(f, "/// error, 1 to accept, `2 + 2 * s` to shift and go to state `s`, \
+                          and `3 + 2 * r` to reduce"));
+        try!(writeln!(f, "/// by rule `r` of `RULES`."));
+        try!(write_array(f, "ACTIONS", actions.as_slice()));
+        try!(writeln!(f, ""));
+        try!(writeln!(f, "/// The state to go to from each state after reducing to each \
+                          variable, plus one, or 0 for"));
+        try!(writeln!(f, "/// none."));
+        try!(write_array(f, "GOTOS", gotos.as_slice()));
+        write!(f, "{}", DRIVER)
+    }
+}
+
+/// Write a static array of numbers, a line at a time.
+fn write_array(f: &mut fmt::Formatter, name: &str, values: &[uint]) -> fmt::Result {
+    try!(write!(f, "static {}: &'static [uint] = &[", name));
+    for (i, value) in values.iter().enumerate() {
+        try!(write!(f, "{}{}{}", if i > 0 { "," } else { "" },
+                    if i % 16 == 0 { "\n    " } else { " " }, value));
+    }
+    writeln!(f, "\n];")
+}
+
+static DRIVER: &'static str = r#"
+/// Parse a sequence of tokens, given by their index in `TERMINALS`.
+///
+/// Fails with the position of the first token that can't be parsed, or the length of the input if
+/// it ends too soon.
+pub fn parse(input: &[uint]) -> Result<Tree, uint> {
+    let mut states = vec![0u];
+    let mut trees: Vec<Tree> = Vec::new();
+    let mut position = 0;
+    loop {
+        let state = *states.last().unwrap();
+        let column = match input.get(position) {
+            Some(&t) if t < TOKENS => t,
+            Some(_) => return Err(position),
+            None => TOKENS
+        };
+        let action = ACTIONS[state * (TOKENS + 1) + column];
+        if action == 0 {
+            return Err(position)
+        } else if action == 1 {
+            return Ok(trees.pop().unwrap())
+        } else if action % 2 == 0 {
+            trees.push(Tree::Leaf(column));
+            states.push(action / 2 - 1);
+            position += 1;
+        } else {
+            let (variable, rule, len) = RULES[action / 2 - 1];
+            let mut children = Vec::with_capacity(len);
+            for _ in range(0, len) {
+                children.push(trees.pop().unwrap());
+                states.pop();
+            }
+            children.reverse();
+            let next = GOTOS[*states.last().unwrap() * VARIABLES + variable];
+            trees.push(Tree::Node(variable, rule, children));
+            states.push(next - 1);
+        }
+    }
+}
+"#;
+
+/// The ways generating a parser from a grammar file can fail.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum BuildError {
+    /// The grammar couldn't be read, or the parser written.
+    Io(IoError),
+    /// The grammar file isn't a valid grammar.
+    Syntax(SyntaxError),
+    /// The grammar isn't LALR(1).
+    Conflicts(Vec<Conflict>)
+}
+
+impl Error for BuildError {
+    fn description(&self) -> &str {
+        match *self {
+            BuildError::Io(ref error) => error.description(),
+            BuildError::Syntax(ref error) => error.description(),
+            BuildError::Conflicts(_) => "grammar conflicts"
+        }
+    }
+}
+
+/// Generate the source of an LR parser, as `to_lr_parser` does, from the grammar in the file at
+/// `grammar`, and write it to `out`. This is meant for build scripts, with `out` in the
+/// directory Cargo gives in `OUT_DIR`, so the crate can `include!` the parser.
+///
+/// The grammar is read as `read_grammar` reads it.
+pub fn cfg_build(grammar: &Path, out: &Path) -> Result<(), BuildError> {
+    let cfg = try!(read_grammar(grammar));
+    let parser = try!(cfg.to_lr_parser().map_err(BuildError::Conflicts));
+    File::create(out).write_str(parser.as_slice()).map_err(BuildError::Io)
+}
+
+/// Read the grammar in the file at `path`: as Yacc if its file ends in `.y`, as ANTLR if `.g4`,
+/// as EBNF if `.ebnf`, and as BNF otherwise.
+pub fn read_grammar(path: &Path) -> Result<Cfg<String>, BuildError> {
+    let src = try!(File::open(path).read_to_string().map_err(BuildError::Io));
+    match path.extension_str() {
+        Some("y") => yacc::parse(src.as_slice()),
+        Some("g4") => antlr::parse(src.as_slice()),
+        Some("ebnf") => Cfg::parse_ebnf(src.as_slice()),
+        _ => Cfg::parse_bnf(src.as_slice())
+    }.map_err(BuildError::Syntax)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{File, TempDir};
+
+    use super::{BuildError, cfg_build};
+    use super::super::Token;
+    use earley;
+    use testing::{EXPRESSIONS, grammar, strings};
+
+    /// The numbers in the array called `name` in generated source.
+    fn numbers(source: &str, name: &str) -> Vec<uint> {
+        let at = source.find_str(format!("static {}:", name).as_slice()).unwrap();
+        let from = at + source.slice_from(at).find_str("&[").unwrap() + 2;
+        let end = from + source.slice_from(from).find_str("];").unwrap();
+        source.slice(from, end).split(|c: char| !c.is_digit(10))
+                               .filter(|n| !n.is_empty())
+                               .map(|n| n.parse().unwrap())
+                               .collect()
+    }
+
+    /// Whether the tables of a generated parser accept `input`, running them as its `parse` does.
+    fn run(source: &str, tokens: uint, variables: uint, input: &[Token]) -> bool {
+        let actions = numbers(source, "ACTIONS");
+        let gotos = numbers(source, "GOTOS");
+        let rules = numbers(source, "RULES");
+        let mut states = vec![0u];
+        let mut position = 0;
+        loop {
+            let state = *states.last().unwrap();
+            let column = input.get(position).map_or(tokens, |t| t.index());
+            let action = actions[state * (tokens + 1) + column];
+            if action == 0 {
+                return false
+            } else if action == 1 {
+                return true
+            } else if action % 2 == 0 {
+                states.push(action / 2 - 1);
+                position += 1;
+            } else {
+                let rule = action / 2 - 1;
+                let (variable, len) = (rules[3 * rule], rules[3 * rule + 2]);
+                let depth = states.len() - len;
+                states.truncate(depth);
+                states.push(gotos[*states.last().unwrap() * variables + variable] - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn lr_parser() {
+        let cfg = grammar(EXPRESSIONS);
+        let table = cfg.lalr_table().unwrap();
+        let source = cfg.to_lr_parser().unwrap();
+        assert_eq!(source, cfg.to_lr_parser_with_table(&table));
+        let source = source.as_slice();
+        assert!(source.contains("const TOKENS: uint = 5;\nconst VARIABLES: uint = 3;\n"));
+        assert!(source.contains("pub fn parse(input: &[uint]) -> Result<Tree, uint> {"));
+        let rules = vec![0, 0, 3, 0, 1, 1, 1, 0, 3, 1, 1, 1, 2, 0, 3, 2, 1, 1];
+        assert_eq!(numbers(source, "RULES"), rules);
+        assert_eq!(numbers(source, "ACTIONS").len(), table.num_states() * 6);
+        assert_eq!(numbers(source, "GOTOS").len(), table.num_states() * 3);
+        for input in strings(&cfg, 5).iter() {
+            let expected = earley::recognize(&cfg, input.as_slice());
+            assert_eq!(run(source, 5, 3, input.as_slice()), expected);
+        }
+
+        let slr = cfg.to_lr_parser_with_table(&cfg.slr_table().unwrap());
+        let input = [Token(4), Token(1), Token(2), Token(4), Token(3)];
+        assert!(run(slr.as_slice(), 5, 3, &input));
+        assert!(grammar("<e> ::= <e> \"+\" <e> | \"id\"").to_lr_parser().is_err());
+    }
+
+    #[test]
+    fn build() {
+        let dir = TempDir::new("cfg_build").unwrap();
+        let path = dir.path().join("expressions.bnf");
+        let out = dir.path().join("parser.rs");
+        File::create(&path).write_str(EXPRESSIONS).unwrap();
+        cfg_build(&path, &out).unwrap();
+        let written = File::open(&out).read_to_string().unwrap();
+        assert_eq!(written, grammar(EXPRESSIONS).to_lr_parser().unwrap());
+
+        let ambiguous = dir.path().join("ambiguous.bnf");
+        File::create(&ambiguous).write_str("<e> ::= <e> \"+\" <e> | \"id\"").unwrap();
+        match cfg_build(&ambiguous, &out) {
+            Err(BuildError::Conflicts(ref conflicts)) if !conflicts.is_empty() => { },
+            other => panic!("expected conflicts, not {}", other)
+        }
+        let bad = dir.path().join("bad.ebnf");
+        File::create(&bad).write_str("s = \"a").unwrap();
+        match cfg_build(&bad, &out) {
+            Err(BuildError::Syntax(_)) => { },
+            other => panic!("expected a syntax error, not {}", other)
+        }
+        match cfg_build(&dir.path().join("missing.y"), &out) {
+            Err(BuildError::Io(_)) => { },
+            other => panic!("expected an I/O error, not {}", other)
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Macros for writing grammars in Rust source.
+
+/// Build a `Cfg<String>` from its rules, written one variable at a time as
+/// `E => [E, plus, T] | [T];`, with the rules of the variable between brackets and separated by
+/// `|`, and `[]` for the empty rule.
+///
+/// Every name on the left of a `=>` is a variable, named as written; any other name is a token
+/// whose terminal is the name. Variables and tokens are numbered in the order they first appear,
+/// and the first variable is the start symbol. Evaluates to the `Result` of `CfgBuilder::build`.
+#[macro_export]
+macro_rules! grammar {
+    ($($variable:ident => $([$($symbol:ident),*])|+;)+) => ({
+        let mut builder = $crate::CfgBuilder::new();
+        let mut variables = ::std::collections::HashMap::new();
+        $(
+            if !variables.contains_key(&stringify!($variable)) {
+                let variable = builder.add_variable(stringify!($variable));
+                variables.insert(stringify!($variable), variable);
+            }
+        )+
+        $($(
+            let variable = *variables.get(&stringify!($variable)).unwrap();
+            let rule = vec![$(
+                match variables.get(&stringify!($symbol)) {
+                    Some(&v) => $crate::Symbol::Var(v),
+                    None => $crate::Symbol::Tok(builder.add_token(stringify!($symbol).to_string()))
+                }
+            ),*];
+            // Every symbol was just allocated, so this can't fail.
+            builder.add_rule(variable, rule).unwrap();
+        )*)+
+        builder.build()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Symbol;
+    use testing::{grammar, variable};
+
+    #[test]
+    fn grammar_macro() {
+        let cfg = grammar!(
+            e => [e, plus, t] | [t];
+            t => [lparen, e, rparen] | [id] | [];
+        ).unwrap();
+        let expected = grammar("
+    <e> ::= <e> \"plus\" <t> | <t>
+    <t> ::= \"lparen\" <e> \"rparen\" | \"id\" | \"\"
+    ");
+        assert_eq!(cfg.to_bnf(), expected.to_bnf());
+        assert_eq!(cfg.num_tokens(), 4);
+        assert_eq!(cfg.get_start(), variable(&cfg, "e"));
+
+        // A variable used before its rules is still a variable.
+        let cfg = grammar!(
+            a => [b, b];
+            b => [x];
+        ).unwrap();
+        let b = variable(&cfg, "b");
+        let rules = cfg.get_rules(variable(&cfg, "a")).unwrap();
+        assert_eq!(rules[0], vec![Symbol::Var(b), Symbol::Var(b)]);
+        assert_eq!(cfg.num_tokens(), 1);
+    }
+}
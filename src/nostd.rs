@@ -0,0 +1,27 @@
+//! The parts of the standard library the crate uses, gathered from `core`, `alloc` and
+//! `collections` for building without it. Without the `std` feature this module stands in for
+//! `std`, so the same paths work either way, including those that `deriving` and the formatting
+//! macros expand to.
+
+pub use core::{clone, cmp, default, fmt, hash, iter, kinds, mem, option, result, uint};
+pub use alloc::boxed;
+pub use collections::{slice, str, string, vec};
+
+/// The names every module of the crate uses without importing them.
+pub mod prelude {
+    pub use core::prelude::*;
+    pub use core::iter::range;
+    pub use alloc::boxed::Box;
+    pub use collections::slice::{CloneSliceExt, OrdSliceExt};
+    pub use collections::string::{String, ToString};
+    pub use collections::vec::Vec;
+}
+
+pub mod collections {
+    pub use collections::{BTreeMap, BTreeSet, RingBuf, VecMap};
+
+    /// The map the crate uses where the standard library's `HashMap` would be, which keys
+    /// only need to be ordered for rather than hashable, as without the standard library
+    /// there is no randomness to seed a hash with.
+    pub use collections::BTreeMap as HashMap;
+}
@@ -0,0 +1,929 @@
+//! Grammars with a weight on each rule, such as probabilistic grammars.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::num::Float;
+
+use super::{Cfg, CfgError, Rule, RuleTable, Symbol, Token, Variable};
+use earley;
+use forest::{Forest, Label};
+use tree::ParseTree;
+
+/// The ways the weights of a probabilistic grammar can be wrong, found by `Pcfg::validate`.
+#[deriving(Copy, Clone, PartialEq)]
+pub enum WeightError {
+    /// A rule's weight is negative or not a number.
+    InvalidWeight { variable: Variable, rule: uint, weight: f64 },
+    /// The weights of a variable's rules don't sum to 1.
+    Unnormalized { variable: Variable, total: f64 }
+}
+
+impl fmt::Show for WeightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WeightError::InvalidWeight { variable, rule, weight } =>
+                write!(f, "rule {} of variable {} has weight {}", rule, variable.index(), weight),
+            WeightError::Unnormalized { variable, total } =>
+                write!(f, "the weights of variable {} sum to {}", variable.index(), total)
+        }
+    }
+}
+
+impl Error for WeightError {
+    fn description(&self) -> &str {
+        match *self {
+            WeightError::InvalidWeight { .. } => "invalid weight",
+            WeightError::Unnormalized { .. } => "unnormalized weights"
+        }
+    }
+}
+
+/// A grammar with a weight on each rule: the probability of choosing the rule to expand its
+/// variable, or a cost.
+///
+/// Weights are kept by rule id, so they stay with their rules as the grammar is edited through
+/// `cfg_mut`. A rule without a weight, such as one added since, counts as having weight 0.
+pub struct Pcfg<T> {
+    cfg: Cfg<T>,
+    weights: RuleTable<f64>
+}
+
+impl<T: Eq + Hash + Clone> Pcfg<T> {
+    /// Weigh the rules of each variable of `cfg` equally, so that they sum to 1.
+    pub fn new(cfg: Cfg<T>) -> Pcfg<T> {
+        let mut pcfg = Pcfg { cfg: cfg, weights: RuleTable::new() };
+        for v in pcfg.cfg.variables() {
+            let count = rules(&pcfg.cfg, v).len();
+            for i in range(0, count) {
+                pcfg.set_weight(v, i, 1.0 / count as f64).unwrap();
+            }
+        }
+        pcfg
+    }
+
+    pub fn cfg(&self) -> &Cfg<T> {
+        &self.cfg
+    }
+
+    /// The grammar, to edit. Rules added get no weight until given one by `set_weight`.
+    pub fn cfg_mut(&mut self) -> &mut Cfg<T> {
+        &mut self.cfg
+    }
+
+    pub fn into_cfg(self) -> Cfg<T> {
+        self.cfg
+    }
+
+    /// The weights of the rules, by id.
+    pub fn weights(&self) -> &RuleTable<f64> {
+        &self.weights
+    }
+
+    /// The weight of a rule, by its index in `get_rules`, or `None` if there's no such rule.
+    pub fn weight(&self, variable: Variable, rule: uint) -> Option<f64> {
+        self.cfg.rule_id(variable, rule).map(|id| {
+            self.weights.rule_data(id).map_or(0.0, |&w| w)
+        })
+    }
+
+    /// Give a rule, by its index in `get_rules`, a weight.
+    pub fn set_weight(&mut self, variable: Variable, rule: uint, weight: f64)
+                      -> Result<(), CfgError> {
+        match self.cfg.rule_id(variable, rule) {
+            Some(id) => {
+                self.weights.set_rule_data(id, weight);
+                Ok(())
+            },
+            None => Err(CfgError::RuleOutOfRange { variable: variable, rule: rule })
+        }
+    }
+
+    /// The total weight of the rules of a variable.
+    pub fn total(&self, variable: Variable) -> f64 {
+        range(0, rules(&self.cfg, variable).len()).fold(0.0, |sum, i| {
+            sum + self.weight(variable, i).unwrap()
+        })
+    }
+
+    /// Scale the weights of each variable's rules to sum to 1, making them probabilities.
+    /// Variables whose rules all have weight 0 are left alone.
+    pub fn normalize(&mut self) {
+        for v in self.cfg.variables() {
+            let total = self.total(v);
+            if total <= 0.0 {
+                continue
+            }
+            for i in range(0, rules(&self.cfg, v).len()) {
+                let weight = self.weight(v, i).unwrap();
+                self.set_weight(v, i, weight / total).unwrap();
+            }
+        }
+    }
+
+    /// Check that the weights are probabilities: that none is negative and that those of each
+    /// variable with rules sum to 1, to within `1e-9`.
+    pub fn validate(&self) -> Result<(), WeightError> {
+        for v in self.cfg.variables() {
+            let count = rules(&self.cfg, v).len();
+            for i in range(0, count) {
+                let weight = self.weight(v, i).unwrap();
+                if !(weight >= 0.0) {
+                    return Err(WeightError::InvalidWeight { variable: v, rule: i, weight: weight })
+                }
+            }
+            let total = self.total(v);
+            if count > 0 && (total - 1.0).abs() > 1e-9 {
+                return Err(WeightError::Unnormalized { variable: v, total: total })
+            }
+        }
+        Ok(())
+    }
+
+    /// The product of the weights of the rules used in a parse tree: its probability, when the
+    /// weights are probabilities.
+    pub fn probability(&self, tree: &ParseTree) -> f64 {
+        tree.pre_order().fold(1.0, |p, tree| match *tree {
+            ParseTree::Node(v, i, _) => p * self.weight(v, i).unwrap_or(0.0),
+            ParseTree::Leaf(_) => p
+        })
+    }
+}
+
+impl<T: Eq + Hash + Clone> Pcfg<T> {
+    /// Find the most probable parse tree of `input`, with its probability, or `None` if the
+    /// grammar doesn't derive it.
+    ///
+    /// This is CYK generalized to rules of any length, empty rules and unit rules: for each span
+    /// of the input, shortest first, the best derivation of each variable is found from those of
+    /// shorter spans, repeating until none improves so that chains of unit rules and variables
+    /// deriving the empty string are followed. The weights should be probabilities, at most 1,
+    /// so that going around such a chain never improves a derivation.
+    pub fn viterbi(&self, input: &[Token]) -> Option<(ParseTree, f64)> {
+        let start = self.cfg.get_start();
+        if start.index() >= self.cfg.num_variables() {
+            return None
+        }
+        let chart = Viterbi::new(self, input);
+        match chart.best(0, input.len(), start) {
+            Some(&(p, _, _)) => Some((chart.tree(start, 0, input.len()), p)),
+            None => None
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Pcfg<T> {
+    /// The probability that the grammar derives `input`: the sum of the probabilities of its
+    /// parse trees.
+    pub fn inside(&self, input: &[Token]) -> f64 {
+        let start = self.cfg.get_start();
+        if start.index() >= self.cfg.num_variables() {
+            return 0.0
+        }
+        let inside = Inside::new(self, input);
+        inside.get(0, input.len(), start)
+    }
+
+    /// Re-estimate the weights from a corpus of sentences by the inside-outside algorithm, for
+    /// `iterations` rounds of expectation-maximization, giving the log-likelihood of the corpus
+    /// before each round.
+    ///
+    /// Each round finds how many times each rule is expected to be used in parsing the corpus
+    /// under the current weights, and makes the weights of each variable's rules proportional to
+    /// those counts. The likelihood never decreases from one round to the next. Sentences the
+    /// grammar doesn't derive are left out, as are variables not expected to be used at all,
+    /// whose weights are kept. The sums over chains of unit rules and variables deriving the
+    /// empty string are cut off after as many steps as there are variables.
+    pub fn train(&mut self, corpus: &[Vec<Token>], iterations: uint) -> Vec<f64> {
+        let start = self.cfg.get_start();
+        let mut likelihoods = Vec::with_capacity(iterations);
+        if start.index() >= self.cfg.num_variables() {
+            return likelihoods
+        }
+        for _ in range(0, iterations) {
+            let mut counts: Vec<Vec<f64>> = self.cfg.variables().map(|v| {
+                Vec::from_elem(rules(&self.cfg, v).len(), 0.0)
+            }).collect();
+            let mut likelihood = 0.0;
+            for sentence in corpus.iter() {
+                let inside = Inside::new(self, sentence.as_slice());
+                let total = inside.get(0, sentence.len(), start);
+                if total > 0.0 {
+                    likelihood += total.ln();
+                    inside.expected_counts(start, total, counts.as_mut_slice());
+                }
+            }
+            likelihoods.push(likelihood);
+            for v in self.cfg.variables() {
+                let total = counts[v.index()].iter().fold(0.0, |sum, &c| sum + c);
+                if total <= 0.0 {
+                    continue
+                }
+                for (r, &count) in counts[v.index()].iter().enumerate() {
+                    self.set_weight(v, r, count / total).unwrap();
+                }
+            }
+        }
+        likelihoods
+    }
+}
+
+impl<T: Eq + Hash + Clone> Pcfg<T> {
+    /// The `k` most probable parse trees in a forest of parses of the grammar, most probable
+    /// first, with their probabilities. There are fewer if the forest doesn't have `k` trees.
+    ///
+    /// This is the lazy algorithm of Huang and Chiang, "Better k-best Parsing" (2005): the
+    /// derivations of each node are found best first, only as far as they're asked for by the
+    /// nodes above, from the next best derivations of its children. Derivations passing through
+    /// a node again within itself, as in the forest of a cyclic grammar, are skipped.
+    pub fn k_best(&self, forest: &Forest, k: uint) -> Vec<(ParseTree, f64)> {
+        let n = forest.nodes.len();
+        let mut search = KBest {
+            pcfg: self,
+            forest: forest,
+            derivations: Vec::from_fn(n, |_| Vec::new()),
+            candidates: Vec::from_fn(n, |_| Vec::new()),
+            seen: Vec::from_fn(n, |_| HashSet::new()),
+            started: Vec::from_elem(n, false),
+            visiting: Vec::from_elem(n, false)
+        };
+        let mut found = Vec::with_capacity(k);
+        for i in range(0, k) {
+            match search.get(forest.root, i) {
+                Some(p) => found.push((search.tree(forest.root, i), p)),
+                None => break
+            }
+        }
+        found
+    }
+
+    /// Parse `input` with an Earley parser, giving its `k` most probable parse trees as
+    /// `k_best` does.
+    pub fn parse_k_best(&self, input: &[Token], k: uint) -> Vec<(ParseTree, f64)> {
+        match earley::parse(&self.cfg, input) {
+            Some(forest) => self.k_best(&forest, k),
+            None => Vec::new()
+        }
+    }
+}
+
+/// A derivation of a node of a forest: its probability, the index of the packed alternative
+/// taken, and which of the derivations of the left and right children it uses, best first.
+type Derivation = (f64, uint, (uint, uint));
+
+/// The state of a k-best search over a forest.
+struct KBest<'a, 'b, T: 'a> {
+    pcfg: &'a Pcfg<T>,
+    forest: &'b Forest,
+    /// The derivations of each node found so far, best first.
+    derivations: Vec<Vec<Derivation>>,
+    /// The derivations of each node that could come next.
+    candidates: Vec<Vec<Derivation>>,
+    /// The alternatives and choices of children of each node that have been candidates.
+    seen: Vec<HashSet<(uint, (uint, uint))>>,
+    /// Whether the candidates of each node have been started with the best of each alternative.
+    started: Vec<bool>,
+    /// Whether each node is being searched, so reaching it again would go around a cycle.
+    visiting: Vec<bool>
+}
+
+impl<'a, 'b, T: Eq + Hash + Clone> KBest<'a, 'b, T> {
+    /// The probability of the `k`th best derivation of a node, counting from 0, finding it if
+    /// need be.
+    fn get(&mut self, id: uint, k: uint) -> Option<f64> {
+        match self.forest.nodes[id].label {
+            Label::Symbol(Symbol::Tok(_)) => return if k == 0 { Some(1.0) } else { None },
+            _ => { }
+        }
+        if self.visiting[id] {
+            return None
+        }
+        self.visiting[id] = true;
+        if !self.started[id] {
+            self.started[id] = true;
+            for e in range(0, self.forest.nodes[id].packed.len()) {
+                self.push(id, e, (0, 0));
+            }
+        }
+        while self.derivations[id].len() <= k {
+            // The next best derivation is either a candidate already, or uses the next
+            // derivation of a child of the last one found.
+            match self.derivations[id].last().map(|&d| d) {
+                Some((_, e, (a, b))) => {
+                    self.push(id, e, (a + 1, b));
+                    self.push(id, e, (a, b + 1));
+                },
+                None => { }
+            }
+            let candidates = &mut self.candidates[id];
+            if candidates.is_empty() {
+                break
+            }
+            let mut best = 0;
+            for (i, &(p, _, _)) in candidates.iter().enumerate() {
+                if p > candidates[best].0 {
+                    best = i;
+                }
+            }
+            let derivation = candidates.swap_remove(best).unwrap();
+            self.derivations[id].push(derivation);
+        }
+        self.visiting[id] = false;
+        self.derivations[id].get(k).map(|&(p, _, _)| p)
+    }
+
+    /// Add the derivation of a node by an alternative and derivations of its children as a
+    /// candidate, if they exist and it hasn't been one already.
+    fn push(&mut self, id: uint, e: uint, ranks: (uint, uint)) {
+        if self.seen[id].contains(&(e, ranks)) {
+            return
+        }
+        let (left, right, weight) = {
+            let node = &self.forest.nodes[id];
+            let packed = &node.packed[e];
+            let weight = match node.label {
+                Label::Symbol(Symbol::Var(_)) => {
+                    self.pcfg.weight(packed.variable, packed.rule).unwrap_or(0.0)
+                },
+                _ => 1.0
+            };
+            (packed.left, packed.right, weight)
+        };
+        if (left.is_none() && ranks.0 > 0) || (right.is_none() && ranks.1 > 0) {
+            return
+        }
+        let mut p = weight;
+        for &(child, rank) in left.map(|l| (l, ranks.0)).iter()
+                                  .chain(right.map(|r| (r, ranks.1)).iter()) {
+            match self.get(child, rank) {
+                Some(q) => p *= q,
+                None => return
+            }
+        }
+        self.seen[id].insert((e, ranks));
+        self.candidates[id].push((p, e, ranks));
+    }
+
+    /// The tree of the `k`th best derivation of a node, which must have been found.
+    fn tree(&self, id: uint, k: uint) -> ParseTree {
+        let node = &self.forest.nodes[id];
+        match node.label {
+            Label::Symbol(Symbol::Tok(t)) => return ParseTree::Leaf(t),
+            _ => { }
+        }
+        let (_, e, _) = self.derivations[id][k];
+        let mut children = Vec::new();
+        self.children(id, k, &mut children);
+        ParseTree::Node(node.packed[e].variable, node.packed[e].rule, children)
+    }
+
+    /// Add the trees of the symbols the `k`th best derivation of a node derives, from the first.
+    fn children(&self, id: uint, k: uint, out: &mut Vec<ParseTree>) {
+        let (_, e, (a, b)) = self.derivations[id][k];
+        let packed = &self.forest.nodes[id].packed[e];
+        match packed.left {
+            Some(left) => self.children(left, a, out),
+            None => { }
+        }
+        match packed.right {
+            Some(right) => out.push(self.tree(right, b)),
+            None => { }
+        }
+    }
+}
+
+/// The total probability of the derivations of each variable for each span of an input.
+struct Inside<'a, T: 'a> {
+    pcfg: &'a Pcfg<T>,
+    input: &'a [Token],
+    cells: Vec<f64>
+}
+
+impl<'a, T: Eq + Hash + Clone> Inside<'a, T> {
+    fn new(pcfg: &'a Pcfg<T>, input: &'a [Token]) -> Inside<'a, T> {
+        let n = input.len();
+        let variables = pcfg.cfg.num_variables();
+        let mut inside = Inside {
+            pcfg: pcfg,
+            input: input,
+            cells: Vec::from_elem((n + 1) * (n + 1) * variables, 0.0)
+        };
+        for len in range(0, n + 1) {
+            for i in range(0, n + 1 - len) {
+                let j = i + len;
+                // Variables deriving the whole span through each other are summed over one
+                // more step of the chain each round.
+                for _ in range(0, variables + 1) {
+                    let mut changed = false;
+                    for v in pcfg.cfg.variables() {
+                        let mut total = 0.0;
+                        for (r, body) in rules(&pcfg.cfg, v).iter().enumerate() {
+                            let (forward, _) = inside.tables(body.as_slice(), i, j);
+                            total += pcfg.weight(v, r).unwrap() * forward[forward.len() - 1];
+                        }
+                        let index = inside.index(i, j, v);
+                        if inside.cells[index] != total {
+                            inside.cells[index] = total;
+                            changed = true;
+                        }
+                    }
+                    if !changed {
+                        break
+                    }
+                }
+            }
+        }
+        inside
+    }
+
+    fn index(&self, i: uint, j: uint, v: Variable) -> uint {
+        let n = self.input.len();
+        (i * (n + 1) + j) * self.pcfg.cfg.num_variables() + v.index()
+    }
+
+    fn get(&self, i: uint, j: uint, v: Variable) -> f64 {
+        self.cells[self.index(i, j, v)]
+    }
+
+    /// The probability of `symbol` deriving the span from `a` to `b`.
+    fn symbol(&self, symbol: Symbol, a: uint, b: uint) -> f64 {
+        match symbol {
+            Symbol::Tok(t) => if b == a + 1 && self.input[a] == t { 1.0 } else { 0.0 },
+            Symbol::Var(w) => self.get(a, b, w)
+        }
+    }
+
+    /// The probabilities of the symbols of `body` before each position in it deriving the span
+    /// from `i` to each position, and of those after each position deriving the span from each
+    /// position to `j`, indexed by position in the body times `j - i + 1` plus position in the
+    /// input less `i`.
+    fn tables(&self, body: &[Symbol], i: uint, j: uint) -> (Vec<f64>, Vec<f64>) {
+        let width = j - i + 1;
+        let m = body.len();
+        let mut forward = Vec::from_elem((m + 1) * width, 0.0f64);
+        let mut backward = Vec::from_elem((m + 1) * width, 0.0f64);
+        forward[0] = 1.0;
+        backward[m * width + width - 1] = 1.0;
+        for (k, &symbol) in body.iter().enumerate() {
+            for a in range(i, j + 1) {
+                let p = forward[k * width + a - i];
+                if p == 0.0 {
+                    continue
+                }
+                for b in range(a, j + 1) {
+                    forward[(k + 1) * width + b - i] += p * self.symbol(symbol, a, b);
+                }
+            }
+        }
+        for (k, &symbol) in body.iter().enumerate().rev() {
+            for b in range(i, j + 1) {
+                let p = backward[(k + 1) * width + b - i];
+                if p == 0.0 {
+                    continue
+                }
+                for a in range(i, b + 1) {
+                    backward[k * width + a - i] += p * self.symbol(symbol, a, b);
+                }
+            }
+        }
+        (forward, backward)
+    }
+
+    /// Add the number of times each rule is expected to be used in the derivations of the input
+    /// from `start`, whose total probability is `total`, to `counts`.
+    ///
+    /// The outside probability of each variable for each span, of deriving the rest of the input
+    /// around it from the start symbol, is found from the longest spans down, and each rule's
+    /// share of the derivations of a span is that times its weight and inside probability.
+    fn expected_counts(&self, start: Variable, total: f64, counts: &mut [Vec<f64>]) {
+        let pcfg = self.pcfg;
+        let n = self.input.len();
+        let variables = pcfg.cfg.num_variables();
+        let mut outside = Vec::from_elem(self.cells.len(), 0.0f64);
+        outside[self.index(0, n, start)] = 1.0;
+        for len in range(0, n + 1).rev() {
+            for i in range(0, n + 1 - len) {
+                let j = i + len;
+                let width = j - i + 1;
+                let tables: Vec<Vec<(Vec<f64>, Vec<f64>)>> = pcfg.cfg.variables().map(|v| {
+                    rules(&pcfg.cfg, v).iter().map(|body| {
+                        self.tables(body.as_slice(), i, j)
+                    }).collect()
+                }).collect();
+
+                // What comes from longer spans is already in `outside`; add what comes from
+                // variables deriving this whole span, one more step of the chain each round.
+                let base: Vec<f64> = pcfg.cfg.variables().map(|v| {
+                    outside[self.index(i, j, v)]
+                }).collect();
+                for _ in range(0, variables + 1) {
+                    let mut next = base.clone();
+                    for v in pcfg.cfg.variables() {
+                        let out = outside[self.index(i, j, v)];
+                        if out == 0.0 {
+                            continue
+                        }
+                        for (r, body) in rules(&pcfg.cfg, v).iter().enumerate() {
+                            let weight = pcfg.weight(v, r).unwrap();
+                            let (ref forward, ref backward) = tables[v.index()][r];
+                            for (k, &symbol) in body.iter().enumerate() {
+                                match symbol {
+                                    Symbol::Var(w) => {
+                                        next[w.index()] += out * weight * forward[k * width] *
+                                                           backward[(k + 1) * width + width - 1];
+                                    },
+                                    Symbol::Tok(_) => { }
+                                }
+                            }
+                        }
+                    }
+                    let mut changed = false;
+                    for v in pcfg.cfg.variables() {
+                        let index = self.index(i, j, v);
+                        if outside[index] != next[v.index()] {
+                            outside[index] = next[v.index()];
+                            changed = true;
+                        }
+                    }
+                    if !changed {
+                        break
+                    }
+                }
+
+                // Count the rules used for this span, and pass the outside probabilities down to
+                // the shorter spans of their symbols.
+                for v in pcfg.cfg.variables() {
+                    let out = outside[self.index(i, j, v)];
+                    if out == 0.0 {
+                        continue
+                    }
+                    for (r, body) in rules(&pcfg.cfg, v).iter().enumerate() {
+                        let weight = pcfg.weight(v, r).unwrap();
+                        let (ref forward, ref backward) = tables[v.index()][r];
+                        counts[v.index()][r] +=
+                            out * weight * forward[body.len() * width + width - 1] / total;
+                        for (k, &symbol) in body.iter().enumerate() {
+                            let w = match symbol {
+                                Symbol::Var(w) => w,
+                                Symbol::Tok(_) => continue
+                            };
+                            for a in range(i, j + 1) {
+                                let before = forward[k * width + a - i];
+                                if before == 0.0 {
+                                    continue
+                                }
+                                for b in range(a, j + 1) {
+                                    if a == i && b == j {
+                                        continue
+                                    }
+                                    let index = self.index(a, b, w);
+                                    outside[index] += out * weight * before *
+                                                      backward[(k + 1) * width + b - i];
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The best derivation of each variable for each span of an input: its probability, its rule,
+/// and the bounds of the children of the rule.
+struct Viterbi<'a, T: 'a> {
+    pcfg: &'a Pcfg<T>,
+    input: &'a [Token],
+    cells: Vec<Option<(f64, uint, Vec<uint>)>>
+}
+
+impl<'a, T: Eq + Hash + Clone> Viterbi<'a, T> {
+    fn new(pcfg: &'a Pcfg<T>, input: &'a [Token]) -> Viterbi<'a, T> {
+        let n = input.len();
+        let variables = pcfg.cfg.num_variables();
+        let mut chart = Viterbi {
+            pcfg: pcfg,
+            input: input,
+            cells: Vec::from_elem((n + 1) * (n + 1) * variables, None)
+        };
+        for len in range(0, n + 1) {
+            for i in range(0, n + 1 - len) {
+                let j = i + len;
+                // Each round can only lengthen the chains of variables deriving the whole span by
+                // one, so there are at most as many rounds as variables.
+                for _ in range(0, variables + 1) {
+                    let mut changed = false;
+                    for v in pcfg.cfg.variables() {
+                        for (r, body) in rules(&pcfg.cfg, v).iter().enumerate() {
+                            let weight = pcfg.weight(v, r).unwrap();
+                            let (p, bounds) = match chart.split(body.as_slice(), i, j) {
+                                Some(found) => found,
+                                None => continue
+                            };
+                            let p = p * weight;
+                            let index = chart.index(i, j, v);
+                            if chart.cells[index].as_ref().map_or(true, |&(q, _, _)| p > q) {
+                                chart.cells[index] = Some((p, r, bounds));
+                                changed = true;
+                            }
+                        }
+                    }
+                    if !changed {
+                        break
+                    }
+                }
+            }
+        }
+        chart
+    }
+
+    fn index(&self, i: uint, j: uint, v: Variable) -> uint {
+        let n = self.input.len();
+        (i * (n + 1) + j) * self.pcfg.cfg.num_variables() + v.index()
+    }
+
+    fn best(&self, i: uint, j: uint, v: Variable) -> Option<&(f64, uint, Vec<uint>)> {
+        self.cells[self.index(i, j, v)].as_ref()
+    }
+
+    /// The most probable way to divide the span from `i` to `j` among the symbols of `body`, as
+    /// its probability and the bounds of each symbol.
+    fn split(&self, body: &[Symbol], i: uint, j: uint) -> Option<(f64, Vec<uint>)> {
+        let width = j - i + 1;
+        // The best probability of the symbols so far reaching each position, with the start of
+        // the last of them, for each number of symbols.
+        let mut from: Vec<Option<(f64, uint)>> = Vec::from_elem((body.len() + 1) * width, None);
+        from[0] = Some((1.0, i));
+        for (k, &symbol) in body.iter().enumerate() {
+            for a in range(i, j + 1) {
+                let p = match from[k * width + a - i] {
+                    Some((p, _)) => p,
+                    None => continue
+                };
+                for b in range(a, j + 1) {
+                    let q = match symbol {
+                        Symbol::Tok(t) if b == a + 1 && self.input[a] == t => 1.0,
+                        Symbol::Tok(_) => continue,
+                        Symbol::Var(w) => match self.best(a, b, w) {
+                            Some(&(q, _, _)) => q,
+                            None => continue
+                        }
+                    };
+                    let cell = &mut from[(k + 1) * width + b - i];
+                    if cell.map_or(true, |(r, _)| p * q > r) {
+                        *cell = Some((p * q, a));
+                    }
+                }
+            }
+        }
+
+        let p = match from[body.len() * width + width - 1] {
+            Some((p, _)) => p,
+            None => return None
+        };
+        let mut bounds = Vec::from_elem(body.len() + 1, j);
+        for k in range(0, body.len()).rev() {
+            bounds[k] = from[(k + 1) * width + bounds[k + 1] - i].unwrap().1;
+        }
+        Some((p, bounds))
+    }
+
+    fn tree(&self, variable: Variable, i: uint, j: uint) -> ParseTree {
+        let &(_, rule, ref bounds) = self.best(i, j, variable).unwrap();
+        let body = rules(&self.pcfg.cfg, variable)[rule].as_slice();
+        let children = body.iter().enumerate().map(|(k, &symbol)| match symbol {
+            Symbol::Tok(t) => ParseTree::Leaf(t),
+            Symbol::Var(w) => self.tree(w, bounds[k], bounds[k + 1])
+        }).collect();
+        ParseTree::Node(variable, rule, children)
+    }
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::Float;
+
+    use super::{Pcfg, WeightError};
+    use super::super::{Cfg, CfgError, Symbol, Token};
+    use earley;
+    use testing::{check_tree, grammar, tokens, variable};
+    use tree::ParseTree;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn weights() {
+        let mut pcfg = Pcfg::new(grammar("<s> ::= <s> <s> | \"a\" | \"b\""));
+        let s = pcfg.cfg().get_start();
+        assert!(close(pcfg.weight(s, 1).unwrap(), 1.0 / 3.0));
+        assert_eq!(pcfg.weight(s, 3), None);
+        assert_eq!(pcfg.validate(), Ok(()));
+
+        pcfg.set_weight(s, 0, 2.0).unwrap();
+        pcfg.set_weight(s, 1, 1.0).unwrap();
+        pcfg.set_weight(s, 2, 1.0).unwrap();
+        assert_eq!(pcfg.set_weight(s, 3, 1.0),
+                   Err(CfgError::RuleOutOfRange { variable: s, rule: 3 }));
+        assert_eq!(pcfg.total(s), 4.0);
+        assert_eq!(pcfg.validate(), Err(WeightError::Unnormalized { variable: s, total: 4.0 }));
+        pcfg.normalize();
+        assert_eq!((pcfg.weight(s, 0), pcfg.weight(s, 1)), (Some(0.5), Some(0.25)));
+        assert_eq!(pcfg.validate(), Ok(()));
+
+        // (a b) has two rules of weight 1/4 under one of weight 1/2.
+        let (a, b) = (Token(0), Token(1));
+        let tree = ParseTree::Node(s, 0, vec![ParseTree::Node(s, 1, vec![ParseTree::Leaf(a)]),
+                                              ParseTree::Node(s, 2, vec![ParseTree::Leaf(b)])]);
+        assert_eq!(pcfg.probability(&tree), 0.03125);
+
+        // Weights follow their rules through edits, and new rules have none.
+        pcfg.cfg_mut().remove_rule(s, 1).unwrap();
+        assert_eq!(pcfg.weight(s, 1), Some(0.25));
+        pcfg.cfg_mut().add_rule(s, vec![Symbol::Tok(a), Symbol::Tok(b)]).unwrap();
+        assert_eq!(pcfg.weight(s, 2), Some(0.0));
+        assert_eq!(pcfg.weights().len(), 3);
+
+        pcfg.set_weight(s, 0, -1.0).unwrap();
+        assert_eq!(pcfg.validate(),
+                   Err(WeightError::InvalidWeight { variable: s, rule: 0, weight: -1.0 }));
+    }
+
+    /// Sentences and verb phrases with a prepositional phrase attached to either the verb or its
+    /// object, so that "n v n p n" has two trees.
+    const ATTACHMENT: &'static str = "
+    <s> ::= <np> <vp>
+    <vp> ::= \"v\" <np> | \"v\" <np> <pp>
+    <np> ::= \"n\" | \"n\" <pp>
+    <pp> ::= \"p\" <np>
+    ";
+
+    /// The attachment grammar with the given weights for the rules of `vp` and `np`.
+    fn attachment(vp: &[f64], np: &[f64]) -> Pcfg<String> {
+        let mut pcfg = Pcfg::new(grammar(ATTACHMENT));
+        let (v, n) = (variable(pcfg.cfg(), "vp"), variable(pcfg.cfg(), "np"));
+        for i in range(0, 2) {
+            pcfg.set_weight(v, i, vp[i]).unwrap();
+            pcfg.set_weight(n, i, np[i]).unwrap();
+        }
+        pcfg
+    }
+
+    fn leaf(cfg: &Cfg<String>, terminal: &str) -> ParseTree {
+        ParseTree::Leaf(cfg.token(&terminal.to_string()).unwrap())
+    }
+
+    fn noun(cfg: &Cfg<String>) -> ParseTree {
+        ParseTree::Node(variable(cfg, "np"), 0, vec![leaf(cfg, "n")])
+    }
+
+    /// The tree of "n v n p n" with the prepositional phrase attached to the verb if `to_verb`, or
+    /// else to its object.
+    fn attached(cfg: &Cfg<String>, to_verb: bool) -> ParseTree {
+        let (s, vp, np) = (variable(cfg, "s"), variable(cfg, "vp"), variable(cfg, "np"));
+        let phrase = ParseTree::Node(variable(cfg, "pp"), 0, vec![leaf(cfg, "p"), noun(cfg)]);
+        let verb = if to_verb {
+            ParseTree::Node(vp, 1, vec![leaf(cfg, "v"), noun(cfg), phrase])
+        } else {
+            let object = ParseTree::Node(np, 1, vec![leaf(cfg, "n"), phrase]);
+            ParseTree::Node(vp, 0, vec![leaf(cfg, "v"), object])
+        };
+        ParseTree::Node(s, 0, vec![noun(cfg), verb])
+    }
+
+    #[test]
+    fn viterbi() {
+        // Attached to the verb: 0.4 * 0.7^3 = 0.1372; to the object: 0.6 * 0.3 * 0.7^2 = 0.0882.
+        let pcfg = attachment(&[0.6, 0.4], &[0.7, 0.3]);
+        let input = tokens(pcfg.cfg(), "n v n p n");
+        let (tree, p) = pcfg.viterbi(input.as_slice()).unwrap();
+        assert_eq!(tree, attached(pcfg.cfg(), true));
+        assert!(close(p, 0.1372));
+        assert!(close(pcfg.probability(&tree), p));
+
+        // Attached to the verb: 0.2 * 0.7^3 = 0.0686; to the object: 0.8 * 0.3 * 0.7^2 = 0.1176.
+        let pcfg = attachment(&[0.8, 0.2], &[0.7, 0.3]);
+        let (tree, p) = pcfg.viterbi(input.as_slice()).unwrap();
+        assert_eq!(tree, attached(pcfg.cfg(), false));
+        assert!(close(p, 0.1176));
+
+        assert!(pcfg.viterbi(tokens(pcfg.cfg(), "n v p").as_slice()).is_none());
+    }
+
+    #[test]
+    fn viterbi_unit_and_empty_rules() {
+        // The best tree of "x" uses the empty rule, 0.5 * 0.9 * 0.8 = 0.36, against 0.3 directly
+        // and 0.5 * 0.1 * 0.3 * 0.8 through the unit rule.
+        let mut pcfg = Pcfg::new(grammar("
+            <s> ::= <a> <e> | \"x\" | <s> <s>
+            <a> ::= \"x\" | <s>
+            <e> ::= \"\" | \"x\"
+        "));
+        let (s, a, e) = (variable(pcfg.cfg(), "s"), variable(pcfg.cfg(), "a"),
+                         variable(pcfg.cfg(), "e"));
+        for &(v, i, w) in [(s, 0, 0.5), (s, 1, 0.3), (s, 2, 0.2), (a, 0, 0.9), (a, 1, 0.1),
+                           (e, 0, 0.8), (e, 1, 0.2)].iter() {
+            pcfg.set_weight(v, i, w).unwrap();
+        }
+        let x = Token(0);
+        let (tree, p) = pcfg.viterbi(&[x]).unwrap();
+        let expected = ParseTree::Node(s, 0, vec![ParseTree::Node(a, 0, vec![ParseTree::Leaf(x)]),
+                                                  ParseTree::Node(e, 0, vec![])]);
+        assert_eq!(tree, expected);
+        assert!(close(p, 0.36));
+    }
+
+    #[test]
+    fn inside() {
+        let pcfg = attachment(&[0.6, 0.4], &[0.7, 0.3]);
+        assert!(close(pcfg.inside(tokens(pcfg.cfg(), "n v n p n").as_slice()), 0.1372 + 0.0882));
+        assert!(close(pcfg.inside(tokens(pcfg.cfg(), "n v n").as_slice()), 0.7 * 0.6 * 0.7));
+        assert_eq!(pcfg.inside(tokens(pcfg.cfg(), "n v p").as_slice()), 0.0);
+    }
+
+    #[test]
+    fn train_unambiguous() {
+        // Relative frequencies: "a" is chosen 4 times and "b" twice.
+        let mut pcfg = Pcfg::new(grammar("<s> ::= \"a\" <s> | \"b\""));
+        let s = pcfg.cfg().get_start();
+        let corpus = vec![tokens(pcfg.cfg(), "a b"), tokens(pcfg.cfg(), "a a a b"),
+                          tokens(pcfg.cfg(), "b a")];
+        let likelihoods = pcfg.train(corpus.as_slice(), 2);
+        assert!(close(pcfg.weight(s, 0).unwrap(), 2.0 / 3.0));
+        assert!(close(pcfg.weight(s, 1).unwrap(), 1.0 / 3.0));
+        assert_eq!(likelihoods.len(), 2);
+        assert!(close(likelihoods[0], 6.0 * 0.5f64.ln()));
+        assert!(close(likelihoods[1], 4.0 * (2.0f64 / 3.0).ln() + 2.0 * (1.0f64 / 3.0).ln()));
+    }
+
+    #[test]
+    fn train_ambiguous() {
+        // Each tree of the sentence is counted in proportion to its probability.
+        let mut pcfg = attachment(&[0.6, 0.4], &[0.7, 0.3]);
+        let (vp, np) = (variable(pcfg.cfg(), "vp"), variable(pcfg.cfg(), "np"));
+        let corpus = vec![tokens(pcfg.cfg(), "n v n p n")];
+        let (to_verb, to_object) = (0.1372 / 0.2254, 0.0882 / 0.2254);
+        let likelihoods = pcfg.train(corpus.as_slice(), 1);
+        assert!(close(likelihoods[0], 0.2254f64.ln()));
+        assert!(close(pcfg.weight(vp, 1).unwrap(), to_verb));
+        assert!(close(pcfg.weight(np, 1).unwrap(), to_object / 3.0));
+        assert!(pcfg.inside(corpus[0].as_slice()) > 0.2254);
+        assert_eq!(pcfg.validate(), Ok(()));
+    }
+
+    #[test]
+    fn k_best() {
+        let pcfg = attachment(&[0.6, 0.4], &[0.7, 0.3]);
+        let input = tokens(pcfg.cfg(), "n v n p n");
+        let best = pcfg.parse_k_best(input.as_slice(), 3);
+        assert_eq!(best.len(), 2);
+        let (ref first, p) = best[0];
+        assert_eq!(*first, attached(pcfg.cfg(), true));
+        assert!(close(p, 0.1372));
+        let (ref second, q) = best[1];
+        assert_eq!(*second, attached(pcfg.cfg(), false));
+        assert!(close(q, 0.0882));
+        assert!(pcfg.parse_k_best(input.as_slice(), 0).is_empty());
+        assert!(pcfg.parse_k_best(tokens(pcfg.cfg(), "n v p").as_slice(), 2).is_empty());
+    }
+
+    #[test]
+    fn k_best_ties() {
+        // The five trees of "a a a a" are equally likely, so any three distinct ones will do.
+        let mut pcfg = Pcfg::new(grammar("<s> ::= <s> <s> | \"a\""));
+        let s = pcfg.cfg().get_start();
+        pcfg.set_weight(s, 0, 0.25).unwrap();
+        pcfg.set_weight(s, 1, 0.75).unwrap();
+        let input = tokens(pcfg.cfg(), "a a a a");
+        let forest = earley::parse(pcfg.cfg(), input.as_slice()).unwrap();
+        let best = pcfg.k_best(&forest, 3);
+        assert_eq!(best.len(), 3);
+        for (i, &(ref tree, p)) in best.iter().enumerate() {
+            check_tree(pcfg.cfg(), tree, input.as_slice());
+            assert!(close(p, 0.25 * 0.25 * 0.25 * 0.75 * 0.75 * 0.75 * 0.75));
+            for &(ref other, _) in best.slice_to(i).iter() {
+                assert!(other != tree);
+            }
+        }
+        assert_eq!(pcfg.k_best(&forest, 10).len(), 5);
+    }
+
+    #[test]
+    fn k_best_cycle() {
+        // Going around the cycle only makes a tree less likely, and is skipped.
+        let pcfg = Pcfg::new(grammar("<s> ::= <s> | \"a\""));
+        let input = tokens(pcfg.cfg(), "a");
+        let best = pcfg.parse_k_best(input.as_slice(), 3);
+        assert_eq!(best.len(), 1);
+        let (ref tree, p) = best[0];
+        let expected = ParseTree::Node(pcfg.cfg().get_start(), 1, vec![ParseTree::Leaf(input[0])]);
+        assert_eq!(*tree, expected);
+        assert_eq!(p, 0.5);
+    }
+}
@@ -0,0 +1,324 @@
+//! Pushdown automata, and the constructions between them and grammars.
+
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+
+use super::{Cfg, Symbol, Token, Variable};
+use earley;
+
+/// A move of a pushdown automaton.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Transition {
+    pub from: uint,
+    /// The token read, or `None` to move without reading.
+    pub input: Option<Token>,
+    /// The stack symbol popped.
+    pub pop: uint,
+    pub to: uint,
+    /// The stack symbols pushed in place of the one popped, the first ending up on top.
+    pub push: Vec<uint>
+}
+
+/// A nondeterministic pushdown automaton reading tokens, given by their index in a grammar, and
+/// accepting by empty stack.
+///
+/// States and stack symbols are numbered from 0. The automaton starts in its start state with
+/// only its start symbol on the stack, and each move pops the symbol on top. It accepts an input
+/// if some sequence of moves reads all of it and leaves the stack empty, in any state.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Pda {
+    tokens: uint,
+    states: uint,
+    stack_symbols: uint,
+    start: uint,
+    start_symbol: uint,
+    transitions: Vec<Transition>
+}
+
+impl Pda {
+    /// An automaton over `tokens` tokens with a single state and a single stack symbol, and no
+    /// transitions.
+    pub fn new(tokens: uint) -> Pda {
+        Pda {
+            tokens: tokens,
+            states: 1,
+            stack_symbols: 1,
+            start: 0,
+            start_symbol: 0,
+            transitions: Vec::new()
+        }
+    }
+
+    /// The automaton of the standard construction from a grammar, which simulates leftmost
+    /// derivations with a single state.
+    ///
+    /// Stack symbol `v` stands for `Variable(v)`, and the one after all the variables for each
+    /// token. A variable on top of the stack is replaced by the body of one of its rules without
+    /// reading anything, and a token on top is popped by reading it, so the stack holds what is
+    /// left to derive of the rest of the input.
+    pub fn from_cfg<T: Eq + Hash + Clone>(cfg: &Cfg<T>) -> Pda {
+        let n = cfg.num_variables();
+        let stack = |symbol: &Symbol| match *symbol {
+            Symbol::Var(v) => v.index(),
+            Symbol::Tok(t) => n + t.index()
+        };
+        let mut transitions = Vec::new();
+        for v in range(0, n) {
+            for rule in cfg.get_rules(Variable(v as u32)).unwrap_or(&[]).iter() {
+                transitions.push(Transition {
+                    from: 0,
+                    input: None,
+                    pop: v,
+                    to: 0,
+                    push: rule.iter().map(|s| stack(s)).collect()
+                });
+            }
+        }
+        for t in range(0, cfg.num_tokens()) {
+            transitions.push(Transition {
+                from: 0,
+                input: Some(Token(t as u32)),
+                pop: n + t,
+                to: 0,
+                push: Vec::new()
+            });
+        }
+        Pda {
+            tokens: cfg.num_tokens(),
+            states: 1,
+            stack_symbols: n + cfg.num_tokens(),
+            start: 0,
+            start_symbol: cfg.get_start().index(),
+            transitions: transitions
+        }
+    }
+
+    /// Add a state, returning its number.
+    pub fn add_state(&mut self) -> uint {
+        self.states += 1;
+        self.states - 1
+    }
+
+    /// Add a stack symbol, returning its number.
+    pub fn add_stack_symbol(&mut self) -> uint {
+        self.stack_symbols += 1;
+        self.stack_symbols - 1
+    }
+
+    /// Add a transition, if there isn't already the same one.
+    ///
+    /// Panics if any state, stack symbol or token is out of range.
+    pub fn add_transition(&mut self, transition: Transition) {
+        assert!(transition.from < self.states && transition.to < self.states);
+        assert!(transition.input.map_or(true, |t| t.index() < self.tokens));
+        assert!(transition.pop < self.stack_symbols);
+        assert!(transition.push.iter().all(|&x| x < self.stack_symbols));
+        if !self.transitions.contains(&transition) {
+            self.transitions.push(transition);
+        }
+    }
+
+    pub fn set_start(&mut self, state: uint) {
+        assert!(state < self.states);
+        self.start = state;
+    }
+
+    pub fn start(&self) -> uint {
+        self.start
+    }
+
+    pub fn set_start_symbol(&mut self, symbol: uint) {
+        assert!(symbol < self.stack_symbols);
+        self.start_symbol = symbol;
+    }
+
+    pub fn start_symbol(&self) -> uint {
+        self.start_symbol
+    }
+
+    /// The transitions, in the order they were added.
+    pub fn transitions(&self) -> &[Transition] {
+        self.transitions.as_slice()
+    }
+
+    pub fn num_states(&self) -> uint {
+        self.states
+    }
+
+    pub fn num_stack_symbols(&self) -> uint {
+        self.stack_symbols
+    }
+
+    pub fn num_tokens(&self) -> uint {
+        self.tokens
+    }
+
+    /// Whether the automaton accepts `input`.
+    ///
+    /// The automaton may have runs that never halt, so this is decided by parsing the input
+    /// with the grammar from `to_cfg`.
+    pub fn accepts(&self, input: &[Token]) -> bool {
+        let terminals: Vec<Token> = range(0, self.tokens).map(|t| Token(t as u32)).collect();
+        earley::recognize(&self.to_cfg(terminals.as_slice()), input)
+    }
+
+    /// A grammar for the language the automaton accepts, by the standard construction, with
+    /// `terminals[i]` the terminal for `Token(i)`.
+    ///
+    /// Each variable stands for a state `p`, stack symbol `X` and state `q` such that the
+    /// automaton can go from `p` to `q` by a run that pops `X` from the top of the stack and
+    /// otherwise leaves the stack as it was, and is named `[p,X,q]`. Only those with such a run
+    /// are made. The start symbol is named `start`.
+    ///
+    /// Panics if there isn't a distinct terminal for every token.
+    pub fn to_cfg<T: Eq + Hash + Clone>(&self, terminals: &[T]) -> Cfg<T> {
+        assert!(terminals.len() >= self.tokens);
+        let states = self.states;
+
+        // The states each run popping a stack symbol from each state can end in, found
+        // bottom-up.
+        let mut ends: HashMap<(uint, uint), BTreeSet<uint>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for transition in self.transitions.iter() {
+                let key = (transition.from, transition.pop);
+                for q in run(&ends, transition.push.as_slice(), transition.to).into_iter() {
+                    if !ends.contains_key(&key) {
+                        ends.insert(key, BTreeSet::new());
+                    }
+                    if ends.get_mut(&key).unwrap().insert(q) {
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut cfg = Cfg::new();
+        let start = cfg.add_variable("start");
+        for t in range(0, self.tokens) {
+            assert!(cfg.add_token(terminals[t].clone()) == Token(t as u32));
+        }
+        let mut triples: Vec<(uint, uint, uint)> = Vec::new();
+        for (&(p, x), qs) in ends.iter() {
+            triples.extend(qs.iter().map(|&q| (p, x, q)));
+        }
+        triples.sort();
+        let mut ids = HashMap::new();
+        for &(p, x, q) in triples.iter() {
+            let name = format!("[{},{},{}]", p, x, q);
+            ids.insert((p, x, q), cfg.add_variable(name.as_slice()));
+        }
+
+        for q in range(0, states) {
+            match ids.get(&(self.start, self.start_symbol, q)) {
+                Some(&v) => cfg.add_rule(start, vec![Symbol::Var(v)]).unwrap(),
+                None => { }
+            }
+        }
+        for transition in self.transitions.iter() {
+            let mut body = Vec::new();
+            match transition.input {
+                Some(t) => body.push(Symbol::Tok(t)),
+                None => { }
+            }
+            // The states reachable after popping each prefix of what's pushed, with the rule
+            // so far.
+            let mut partial = vec![(transition.to, body)];
+            for &x in transition.push.iter() {
+                let mut next = Vec::new();
+                for (state, body) in partial.into_iter() {
+                    match ends.get(&(state, x)) {
+                        Some(tos) => for &to in tos.iter() {
+                            let mut body = body.clone();
+                            body.push(Symbol::Var(*ids.get(&(state, x, to)).unwrap()));
+                            next.push((to, body));
+                        },
+                        None => { }
+                    }
+                }
+                partial = next;
+            }
+            for (q, body) in partial.into_iter() {
+                let v = *ids.get(&(transition.from, transition.pop, q)).unwrap();
+                if !cfg.get_rules(v).unwrap_or(&[]).contains(&body) {
+                    cfg.add_rule(v, body).unwrap();
+                }
+            }
+        }
+        cfg
+    }
+}
+
+/// The states a run popping each of `push` in turn can end in, starting from `from`, given the
+/// states a run popping each stack symbol can.
+fn run(ends: &HashMap<(uint, uint), BTreeSet<uint>>, push: &[uint], from: uint)
+       -> BTreeSet<uint> {
+    let mut states = BTreeSet::new();
+    states.insert(from);
+    for &x in push.iter() {
+        let mut next = BTreeSet::new();
+        for &state in states.iter() {
+            match ends.get(&(state, x)) {
+                Some(tos) => next.extend(tos.iter().map(|&to| to)),
+                None => { }
+            }
+        }
+        states = next;
+    }
+    states
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Pda, Transition};
+    use super::super::Token;
+    use earley;
+    use testing::{SMALL, grammar, strings};
+
+    fn transition(from: uint, input: Option<Token>, pop: uint, to: uint, push: Vec<uint>)
+                  -> Transition {
+        Transition { from: from, input: input, pop: pop, to: to, push: push }
+    }
+
+    #[test]
+    fn from_cfg() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let pda = Pda::from_cfg(&cfg);
+            assert_eq!(pda.num_states(), 1);
+            assert_eq!(pda.num_stack_symbols(), cfg.num_variables() + cfg.num_tokens());
+            assert_eq!(pda.start_symbol(), cfg.get_start().index());
+            for input in strings(&cfg, 4).iter() {
+                let input = input.as_slice();
+                assert_eq!(pda.accepts(input), earley::recognize(&cfg, input));
+            }
+        }
+    }
+
+    #[test]
+    fn to_cfg() {
+        // a^n b^n for n at least 1: the first a replaces the bottom of the stack by a counter, and
+        // each b pops one.
+        let (a, b) = (Token(0), Token(1));
+        let mut pda = Pda::new(2);
+        let popping = pda.add_state();
+        let counter = pda.add_stack_symbol();
+        pda.add_transition(transition(0, Some(a), 0, 0, vec![counter]));
+        pda.add_transition(transition(0, Some(a), counter, 0, vec![counter, counter]));
+        pda.add_transition(transition(0, Some(b), counter, popping, vec![]));
+        pda.add_transition(transition(popping, Some(b), counter, popping, vec![]));
+        pda.add_transition(transition(popping, Some(b), counter, popping, vec![]));
+        assert_eq!(pda.transitions().len(), 4);
+
+        let cfg = pda.to_cfg(&['a', 'b']);
+        assert_eq!(cfg.token(&'a'), Some(a));
+        for input in strings(&cfg, 6).iter() {
+            let n = input.len() / 2;
+            let expected = n > 0 && input.len() == 2 * n &&
+                           input.iter().enumerate().all(|(i, &t)| t == if i < n { a } else { b });
+            assert_eq!(pda.accepts(input.as_slice()), expected);
+            assert_eq!(earley::recognize(&cfg, input.as_slice()), expected);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Prefixes of sentences: whether some input can still be completed, and what can come next
+//! after it, for completion.
+
+use std::hash::Hash;
+
+use super::{Cfg, Symbol, Token, TokenSet};
+use earley;
+
+/// The tokens that can come next after `prefix` in a sentence of the grammar: those `t` for
+/// which `prefix` followed by `t` is itself a prefix of a sentence. It's empty if `prefix` isn't
+/// a prefix of any sentence, or if it's a sentence that can't go on.
+///
+/// The tokens are read off the Earley set at the end of `prefix`, in a copy of the grammar with
+/// no rules using nonproductive variables, so every token found leads on to a whole sentence.
+pub fn suggest_next<T: Eq + Hash + Clone>(cfg: &Cfg<T>, prefix: &[Token]) -> TokenSet {
+    let productive = productive_part(cfg);
+    let chart = earley::chart(&productive, prefix);
+    let mut tokens = TokenSet::new();
+    for item in chart.get(prefix.len()).iter() {
+        let body = productive.get_rules(item.variable).unwrap()[item.rule].as_slice();
+        match body.get(item.dot) {
+            Some(&Symbol::Tok(t)) => { tokens.insert(t); },
+            _ => { }
+        }
+    }
+    tokens
+}
+
+/// Whether `prefix` is the start of some sentence of the grammar, including a whole sentence.
+/// Unlike recognizing it, this tells whether reading more input could make a sentence of it, so
+/// input that can't can be reported as soon as it's read.
+pub fn is_viable_prefix<T: Eq + Hash + Clone>(cfg: &Cfg<T>, prefix: &[Token]) -> bool {
+    let productive = productive_part(cfg);
+    !earley::chart(&productive, prefix).get(prefix.len()).is_empty()
+}
+
+/// The length of the longest viable prefix of `input`: the position of the first token after
+/// which the input can no longer be completed to a sentence, or its length if there isn't one.
+pub fn viable_prefix_len<T: Eq + Hash + Clone>(cfg: &Cfg<T>, input: &[Token]) -> uint {
+    let productive = productive_part(cfg);
+    let chart = earley::chart(&productive, input);
+    if chart.get(0).is_empty() {
+        return 0
+    }
+    range(0, input.len()).find(|&i| chart.get(i + 1).is_empty()).unwrap_or(input.len())
+}
+
+/// The grammar with only the rules that don't use nonproductive variables, keeping the numbering
+/// of its symbols.
+fn productive_part<T: Eq + Hash + Clone>(cfg: &Cfg<T>) -> Cfg<T> {
+    let nonproductive = cfg.nonproductive_symbols();
+    let mut part = Cfg::new();
+    for t in cfg.tokens() {
+        part.add_token(cfg.terminal(t).unwrap().clone());
+    }
+    for v in cfg.variables() {
+        part.add_variable(cfg.name(v).unwrap_or(""));
+    }
+    part.set_start(cfg.get_start()).unwrap();
+    for (v, rule) in cfg.rules() {
+        let productive = rule.iter().all(|&s| match s {
+            Symbol::Var(w) => !nonproductive.contains(&w),
+            Symbol::Tok(_) => true
+        });
+        if productive && !nonproductive.contains(&v) {
+            part.add_rule(v, rule.clone()).unwrap();
+        }
+    }
+    part
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_viable_prefix, suggest_next, viable_prefix_len};
+    use super::super::Cfg;
+    use earley;
+    use testing::{EXPRESSIONS, grammar, tokens};
+
+    /// The terminals of the tokens that can come after `prefix`.
+    fn next<'a>(cfg: &'a Cfg<String>, prefix: &str) -> Vec<&'a str> {
+        let tokens = suggest_next(cfg, tokens(cfg, prefix).as_slice());
+        tokens.iter().map(|t| cfg.terminal(t).unwrap().as_slice()).collect()
+    }
+
+    #[test]
+    fn suggest() {
+        let cfg = grammar(EXPRESSIONS);
+        assert_eq!(next(&cfg, ""), vec!["(", "id"]);
+        assert_eq!(next(&cfg, "id +"), vec!["(", "id"]);
+        assert_eq!(next(&cfg, "( id"), vec!["+", "*", ")"]);
+        assert_eq!(next(&cfg, "( id )"), vec!["+", "*"]);
+        assert!(next(&cfg, ")").is_empty());
+        assert!(next(&cfg, "id id").is_empty());
+    }
+
+    #[test]
+    fn nonproductive() {
+        let cfg = grammar("
+    <s> ::= \"a\" <x> | \"a\" \"b\" | \"d\"
+    <x> ::= \"c\" <x>
+    ");
+        assert_eq!(next(&cfg, "a"), vec!["b"]);
+        assert!(next(&cfg, "d").is_empty());
+        assert!(next(&cfg, "a c").is_empty());
+    }
+
+    #[test]
+    fn viable_prefix() {
+        let cfg = grammar(EXPRESSIONS);
+        let viable = |text: &str| is_viable_prefix(&cfg, tokens(&cfg, text).as_slice());
+        assert!(viable(""));
+        assert!(viable("( id +"));
+        assert!(viable("id * id"));
+        assert!(!viable("id ("));
+        assert!(!viable(")"));
+        let input = tokens(&cfg, "( id +");
+        assert!(!earley::recognize(&cfg, input.as_slice()));
+
+        let len = |text: &str| viable_prefix_len(&cfg, tokens(&cfg, text).as_slice());
+        assert_eq!(len("id + ( id"), 4);
+        assert_eq!(len("id + ) id"), 2);
+        assert_eq!(len(") id"), 0);
+
+        let cfg = grammar("
+    <s> ::= \"a\" <x> | \"a\" \"b\"
+    <x> ::= \"c\" <x>
+    ");
+        assert!(is_viable_prefix(&cfg, tokens(&cfg, "a").as_slice()));
+        assert!(!is_viable_prefix(&cfg, tokens(&cfg, "a c").as_slice()));
+        assert_eq!(viable_prefix_len(&cfg, tokens(&cfg, "a c").as_slice()), 1);
+    }
+}
@@ -0,0 +1,203 @@
+//! Generated recursive-descent parsers.
+
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, Symbol, Token, Variable};
+use codegen::{TREE, write_terminals};
+use ll1::{Conflict, Ll1Table};
+
+impl<T: fmt::Show + Eq + Hash + Clone> Cfg<T> {
+    /// Generate the Rust source of a recursive-descent parser for the grammar, with a function
+    /// for each variable choosing its rule by the next token, as the LL(1) table does.
+    ///
+    /// The source stands alone, so it can be written out by a build script and included in a
+    /// crate without depending on this one. It gives a `parse` function taking the tokens of the
+    /// input by index and returning the parse tree, or the position of the first token it
+    /// couldn't parse. Fails with every conflict found if the grammar isn't LL(1).
+    pub fn to_recursive_descent(&self) -> Result<String, Vec<Conflict>> {
+        let table = try!(self.ll1_table());
+        Ok(format!("{}", RecursiveDescent { cfg: self, table: table, names: names(self) }))
+    }
+}
+
+struct RecursiveDescent<'a, T: 'a> {
+    cfg: &'a Cfg<T>,
+    table: Ll1Table,
+    /// The name of the function parsing each variable.
+    names: Vec<String>
+}
+
+impl<'a, T: fmt::Show + Eq + Hash + Clone> fmt::Show for RecursiveDescent<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "// A recursive-descent parser generated from a grammar."));
+        try!(writeln!(f, ""));
+        try!(write_terminals(f, self.cfg));
+        try!(write!(f, "{}", TREE));
+        try!(write!(f, "{}", PRELUDE));
+        try!(writeln!(f, "    let tree = try!(parser.{}());",
+                      self.names[self.cfg.get_start().index()]));
+        try!(write!(f, "{}", PARSER));
+        for v in range(0, self.cfg.num_variables()) {
+            try!(self.write_variable(f, Variable(v as u32)));
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl<'a, T: fmt::Show + Eq + Hash + Clone> RecursiveDescent<'a, T> {
+    fn write_variable(&self, f: &mut fmt::Formatter, v: Variable) -> fmt::Result {
+        let rules = self.cfg.get_rules(v).unwrap_or(&[]);
+        try!(writeln!(f, ""));
+        match self.cfg.name(v) {
+            Some(name) => try!(writeln!(f, "    /// Parse `{}`.", name)),
+            None => try!(writeln!(f, "    /// Parse variable {}.", v.index()))
+        }
+        try!(writeln!(f, "    fn {}(&mut self) -> Result<Tree, uint> {{", self.names[v.index()]));
+        try!(writeln!(f, "        match self.peek() {{"));
+        for (i, rule) in rules.iter().enumerate() {
+            let mut lookaheads: Vec<String> = range(0, self.cfg.num_tokens()).filter(|&t| {
+                self.table.get(v, Some(Token(t as u32))) == Some(i)
+            }).map(|t| format!("Some({})", t)).collect();
+            if self.table.get(v, None) == Some(i) {
+                lookaheads.push("None".to_string());
+            }
+            if lookaheads.is_empty() {
+                continue
+            }
+            try!(writeln!(f, "            {} => {{", lookaheads.connect(" | ")));
+            if rule.is_empty() {
+                try!(writeln!(f, "                Ok(Tree::Node({}, {}, Vec::new()))",
+                              v.index(), i));
+                try!(writeln!(f, "            }},"));
+                continue
+            }
+            try!(writeln!(f, "                let mut children = Vec::with_capacity({});",
+                          rule.len()));
+            for &symbol in rule.iter() {
+                let call = match symbol {
+                    Symbol::Tok(t) => format!("expect({})", t.index()),
+                    Symbol::Var(w) => format!("{}()", self.names[w.index()])
+                };
+                try!(writeln!(f, "                children.push(try!(self.{}));", call));
+            }
+            try!(writeln!(f, "                Ok(Tree::Node({}, {}, children))", v.index(), i));
+            try!(writeln!(f, "            }},"));
+        }
+        try!(writeln!(f, "            _ => Err(self.position)"));
+        try!(writeln!(f, "        }}"));
+        writeln!(f, "    }}")
+    }
+}
+
+static PRELUDE: &'static str = r#"
+/// Parse a sequence of tokens, given by their index in `TERMINALS`.
+///
+/// Fails with the position of the first token that can't be parsed, or the length of the input if
+/// it ends too soon.
+pub fn parse(input: &[uint]) -> Result<Tree, uint> {
+    let mut parser = Parser { input: input, position: 0 };
+"#;
+
+static PARSER: &'static str = r#"    if parser.position < input.len() {
+        return Err(parser.position)
+    }
+    Ok(tree)
+}
+
+struct Parser<'a> {
+    input: &'a [uint],
+    position: uint
+}
+
+#[allow(non_snake_case)]
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<uint> {
+        self.input.get(self.position).map(|&t| t)
+    }
+
+    #[allow(dead_code)]
+    fn expect(&mut self, token: uint) -> Result<Tree, uint> {
+        if self.peek() != Some(token) {
+            return Err(self.position)
+        }
+        self.position += 1;
+        Ok(Tree::Leaf(token))
+    }
+"#;
+
+/// A distinct function name for each variable, from its name where it has one.
+fn names<T: Eq + Hash + Clone>(cfg: &Cfg<T>) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(cfg.num_variables());
+    for v in range(0, cfg.num_variables()) {
+        let base = match cfg.name(Variable(v as u32)) {
+            Some(name) => {
+                let name: String = name.chars().map(|c| {
+                    if c.is_alphanumeric() && (c as u32) < 128 { c } else { '_' }
+                }).collect();
+                format!("parse_{}", name)
+            },
+            None => format!("parse_{}", v)
+        };
+        let mut name = base.clone();
+        let mut suffix = 1u;
+        while names.contains(&name) {
+            name = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        names.push(name);
+    }
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use testing::{EXPRESSIONS, EXPRESSIONS_LL1, grammar, variable};
+
+    #[test]
+    fn expressions() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let source = cfg.to_recursive_descent().unwrap();
+        let terminals = "pub static TERMINALS: &'static [&'static str] = \
+                         &[\"+\", \"*\", \"(\", \")\", \"id\"];";
+        assert!(source.as_slice().contains(terminals));
+        assert!(source.as_slice().contains("    let tree = try!(parser.parse_e());\n"));
+
+        // `ep` goes on after `+`, and is empty before `)` or at the end of the input.
+        let ep = variable(&cfg, "ep").index();
+        let plus = cfg.token(&"+".to_string()).unwrap().index();
+        let close = cfg.token(&")".to_string()).unwrap().index();
+        let expected = format!("    fn parse_ep(&mut self) -> Result<Tree, uint> {{
+            match self.peek() {{
+                Some({}) => {{
+                    let mut children = Vec::with_capacity(3);
+                    children.push(try!(self.expect({})));
+                    children.push(try!(self.parse_t()));
+                    children.push(try!(self.parse_ep()));
+                    Ok(Tree::Node({}, 0, children))
+                }},
+                Some({}) | None => {{
+                    Ok(Tree::Node({}, 1, Vec::new()))
+                }},
+                _ => Err(self.position)
+            }}
+        }}
+    ", plus, plus, ep, close, ep);
+        assert!(source.as_slice().contains(expected.as_slice()));
+
+        assert!(grammar(EXPRESSIONS).to_recursive_descent().is_err());
+    }
+
+    #[test]
+    fn function_names() {
+        let cfg = grammar("
+            <a-b> ::= <a_b> <é>
+            <a_b> ::= \"x\"
+            <é> ::= \"y\"
+        ");
+        let source = cfg.to_recursive_descent().unwrap();
+        assert!(source.as_slice().contains("fn parse_a_b(&mut self)"));
+        assert!(source.as_slice().contains("fn parse_a_b_1(&mut self)"));
+        assert!(source.as_slice().contains("fn parse__(&mut self)"));
+    }
+}
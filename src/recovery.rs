@@ -0,0 +1,197 @@
+//! Recovering from syntax errors in the deterministic parsers, to report more than the first.
+//!
+//! The LL(1) and LR parsers recover in panic mode: on an error, they give up on the variable
+//! being parsed, skip tokens until one in its synchronizing set, and carry on as if the variable
+//! had been matched by what they skipped.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use super::{Cfg, ParseError, Symbol, Terminal, Token, TokenSet, Variable};
+use actions::Reducer;
+use tree::ParseTree;
+
+/// The tokens each variable resynchronizes on after an error: those that may come after the
+/// text it was meant to match. The end of the input is always one.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct SyncSets {
+    sets: Vec<TokenSet>
+}
+
+impl SyncSets {
+    /// No tokens for any of `variables` variables, so recovery skips to the end of the input.
+    pub fn new(variables: uint) -> SyncSets {
+        SyncSets { sets: Vec::from_fn(variables, |_| TokenSet::new()) }
+    }
+
+    /// Each variable's FOLLOW set, the usual choice.
+    pub fn follow<T: Terminal>(cfg: &Cfg<T>) -> SyncSets {
+        let follow = cfg.follow_sets();
+        SyncSets {
+            sets: cfg.variables().map(|v| follow.get(v).tokens.clone()).collect()
+        }
+    }
+
+    pub fn get(&self, variable: Variable) -> &TokenSet {
+        &self.sets[variable.index()]
+    }
+
+    /// Add a token to a variable's set, such as a `;` or `}` that ends the statement or block
+    /// it's in.
+    pub fn insert(&mut self, variable: Variable, token: Token) -> bool {
+        self.sets[variable.index()].insert(token)
+    }
+
+    /// Remove a token from a variable's set.
+    pub fn remove(&mut self, variable: Variable, token: Token) -> bool {
+        self.sets[variable.index()].remove(token)
+    }
+
+    /// Whether parsing `variable` resynchronizes on `lookahead`, with `None` for the end of the
+    /// input.
+    pub fn syncs(&self, variable: Variable, lookahead: Option<Token>) -> bool {
+        match lookahead {
+            Some(t) => self.sets.get(variable.index()).map_or(false, |set| set.contains(t)),
+            None => true
+        }
+    }
+}
+
+/// A `Reducer` that can also stand in a value for a symbol the parser recovered from an error
+/// in.
+pub trait Recover<V>: Reducer<V> {
+    /// The value of a symbol that wasn't matched: the tokens from `start` up to `end` were
+    /// skipped in its place, or none if they're equal, as for a missing token.
+    fn error(&mut self, symbol: Symbol, start: uint, end: uint) -> V;
+}
+
+/// A `Recover` building the parse tree of each symbol that was parsed without an error in it,
+/// and `None` for the others.
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+pub struct PartialTreeBuilder;
+
+impl Reducer<Option<ParseTree>> for PartialTreeBuilder {
+    fn shift(&mut self, token: Token, _position: uint) -> Option<ParseTree> {
+        Some(ParseTree::Leaf(token))
+    }
+
+    fn reduce(&mut self, variable: Variable, rule: uint, children: Vec<Option<ParseTree>>)
+              -> Option<ParseTree> {
+        let children: Option<Vec<ParseTree>> = children.into_iter().collect();
+        children.map(|children| ParseTree::Node(variable, rule, children))
+    }
+}
+
+impl Recover<Option<ParseTree>> for PartialTreeBuilder {
+    fn error(&mut self, _symbol: Symbol, _start: uint, _end: uint) -> Option<ParseTree> {
+        None
+    }
+}
+
+/// Add an error to those found, unless one was already found at or after its position, which it
+/// would most likely be a consequence of.
+pub fn report(errors: &mut Vec<ParseError>, error: ParseError) {
+    if errors.last().map_or(true, |last| last.position < error.position) {
+        errors.push(error);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{Recover, SyncSets};
+    use super::super::{ParseError, Symbol, Token, Variable};
+    use actions::Reducer;
+    use ll1::Ll1Parser;
+    use lr::LrParser;
+    use testing::{grammar, tokens, variable};
+
+    /// Assignments of identifiers or parenthesized identifiers, which is both LL(1) and LALR(1).
+    const STATEMENTS: &'static str = "
+    <p> ::= <s> <p> | \"\"
+    <s> ::= \"id\" \"=\" <e> \";\"
+    <e> ::= \"id\" | \"(\" <e> \")\"
+    ";
+
+    fn positions(errors: &[ParseError]) -> Vec<uint> {
+        errors.iter().map(|error| error.position).collect()
+    }
+
+    /// Counts the tokens skipped in recovering.
+    struct Skipped;
+
+    impl Reducer<uint> for Skipped {
+        fn shift(&mut self, _token: Token, _position: uint) -> uint {
+            0
+        }
+
+        fn reduce(&mut self, _variable: Variable, _rule: uint, children: Vec<uint>) -> uint {
+            children.iter().fold(0, |sum, &n| sum + n)
+        }
+    }
+
+    impl Recover<uint> for Skipped {
+        fn error(&mut self, _symbol: Symbol, start: uint, end: uint) -> uint {
+            end - start
+        }
+    }
+
+    #[test]
+    fn sync_sets() {
+        let cfg = grammar(STATEMENTS);
+        let (p, e) = (variable(&cfg, "p"), variable(&cfg, "e"));
+        let semicolon = cfg.token(&";".to_string()).unwrap();
+        let mut sync = SyncSets::follow(&cfg);
+        assert!(sync.syncs(e, Some(semicolon)));
+        assert!(sync.syncs(p, None));
+        assert!(!sync.syncs(p, Some(semicolon)));
+        assert!(sync.insert(p, semicolon));
+        assert!(!sync.insert(p, semicolon));
+        assert!(sync.syncs(p, Some(semicolon)));
+        assert!(sync.remove(p, semicolon));
+        assert!(!sync.syncs(p, Some(semicolon)));
+        let none = SyncSets::new(cfg.num_variables());
+        assert!(none.get(e).is_empty());
+        assert!(!none.syncs(e, Some(semicolon)));
+        assert!(none.syncs(e, None));
+    }
+
+    #[test]
+    fn recover_ll1() {
+        let cfg = grammar(STATEMENTS);
+        let parser = Ll1Parser::new(&cfg).unwrap();
+        let sync = SyncSets::follow(&cfg);
+        let input = tokens(&cfg, "id = ( id ) ; id = id ;");
+        let recovered = parser.parse_recovering(input.as_slice(), &sync);
+        assert_eq!(recovered.ok(), parser.parse(input.as_slice()).ok());
+
+        let input = tokens(&cfg, "id = ; id = id ; id = ( id ;");
+        let errors = parser.parse_recovering(input.as_slice(), &sync).unwrap_err();
+        assert_eq!(positions(errors.as_slice()), vec![2, 11]);
+        let close = cfg.token(&")".to_string());
+        assert_eq!(errors[1].expected, vec![close]);
+
+        let input = tokens(&cfg, "id = = ; id = id ;");
+        let (skipped, errors) = parser.parse_with_recovery(input.as_slice(), &sync, &mut Skipped);
+        assert_eq!(skipped, Some(1));
+        assert_eq!(positions(errors.as_slice()), vec![2]);
+    }
+
+    #[test]
+    fn recover_lr() {
+        let cfg = grammar(STATEMENTS);
+        let parser = LrParser::new(&cfg).unwrap();
+        let sync = SyncSets::follow(&cfg);
+        let input = tokens(&cfg, "id = ( id ) ; id = id ;");
+        let recovered = parser.parse_recovering(input.as_slice(), &sync);
+        assert_eq!(recovered.ok(), parser.parse(input.as_slice()).ok());
+
+        let input = tokens(&cfg, "id = ; id = id ; id = ( id ;");
+        let errors = parser.parse_recovering(input.as_slice(), &sync).unwrap_err();
+        assert_eq!(positions(errors.as_slice()), vec![2, 11]);
+
+        let input = tokens(&cfg, "id = = ; id = id ;");
+        let (skipped, errors) = parser.parse_with_recovery(input.as_slice(), &sync, &mut Skipped);
+        assert_eq!(skipped, Some(1));
+        assert_eq!(positions(errors.as_slice()), vec![2]);
+    }
+}
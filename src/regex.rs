@@ -0,0 +1,339 @@
+//! Regular expressions over the terminals of a grammar.
+
+use super::{Cfg, Symbol, SyntaxError, Token, Variable};
+use import::{Scanner, is_space};
+
+impl Cfg<String> {
+    /// A grammar for the language of a regular expression, written as for `add_regex`, with a
+    /// start symbol named `regex`.
+    pub fn from_regex(regex: &str) -> Result<Cfg<String>, SyntaxError> {
+        let mut cfg = Cfg::new();
+        try!(cfg.add_regex("regex", regex));
+        Ok(cfg)
+    }
+
+    /// Add rules for a regular expression over terminals under a new variable named `name`,
+    /// returning the variable.
+    ///
+    /// A terminal is an identifier, or is quoted with `"` or `'`, and is added as a token if the
+    /// grammar doesn't have it yet. `.` matches any token of the grammar, `[a b c]` any of those
+    /// listed, and `[^a b c]` any token of the grammar but those; the tokens of the grammar
+    /// include every terminal the expression names. Alternatives are separated by `|`, `( ... )`
+    /// groups, and a suffix `?`, `*`, `+`, `{n}`, `{n,}` or `{n,m}` repeats the preceding item.
+    /// An empty expression or alternative matches the empty string.
+    ///
+    /// Groups, classes and repetitions are lowered to fresh variables named after `name`, with
+    /// repetition expressed by right recursion. The grammar is left unchanged if the expression
+    /// can't be read.
+    pub fn add_regex(&mut self, name: &str, regex: &str) -> Result<Variable, SyntaxError> {
+        let mut s = Scanner::new(regex);
+        let regex = try!(alternatives(&mut s));
+        s.skip_while(is_space);
+        match s.peek() {
+            Some(c) => return Err(s.error(format!("unexpected `{}`", c).as_slice())),
+            None => { }
+        }
+
+        add_terminals(self, &regex);
+        let v = self.add_variable(name);
+        let bodies = match regex {
+            Regex::Alternatives(alternatives) => {
+                alternatives.iter().map(|regex| lower(self, name, regex)).collect()
+            },
+            regex => vec![lower(self, name, &regex)]
+        };
+        for body in bodies.into_iter() {
+            self.add_rule(v, body).unwrap();
+        }
+        Ok(v)
+    }
+}
+
+/// A regular expression, as read.
+enum Regex {
+    Terminal(String),
+    /// Any token of those listed or, if negated, of those not listed.
+    Class(bool, Vec<String>),
+    Sequence(Vec<Regex>),
+    Alternatives(Vec<Regex>),
+    /// An item repeated at least, and at most, the given number of times.
+    Repeat(Box<Regex>, uint, Option<uint>)
+}
+
+fn in_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_digit(10)
+}
+
+fn alternatives(s: &mut Scanner) -> Result<Regex, SyntaxError> {
+    let mut alternatives = Vec::new();
+    loop {
+        alternatives.push(try!(sequence(s)));
+        s.skip_while(is_space);
+        if !s.eat("|") {
+            break
+        }
+    }
+    if alternatives.len() == 1 {
+        return Ok(alternatives.pop().unwrap())
+    }
+    Ok(Regex::Alternatives(alternatives))
+}
+
+fn sequence(s: &mut Scanner) -> Result<Regex, SyntaxError> {
+    let mut items = Vec::new();
+    loop {
+        s.skip_while(is_space);
+        let mut item = match s.peek() {
+            Some('"') | Some('\'') => Regex::Terminal(try!(s.quoted())),
+            Some('(') => {
+                let open = s.mark();
+                s.bump();
+                let inner = try!(alternatives(s));
+                if !s.eat(")") {
+                    s.reset(open);
+                    return Err(s.error("unclosed group, expected `)`"))
+                }
+                inner
+            },
+            Some('[') => try!(class(s)),
+            Some('.') => {
+                s.bump();
+                Regex::Class(true, Vec::new())
+            },
+            Some(c) if in_ident(c) => Regex::Terminal(s.take_while(in_ident)),
+            _ => break
+        };
+        loop {
+            s.skip_while(is_space);
+            let (min, max) = if s.eat("?") {
+                (0, Some(1))
+            } else if s.eat("*") {
+                (0, None)
+            } else if s.eat("+") {
+                (1, None)
+            } else if s.looking_at("{") {
+                try!(bounds(s))
+            } else {
+                break
+            };
+            item = Regex::Repeat(box item, min, max);
+        }
+        items.push(item);
+    }
+    if items.len() == 1 {
+        return Ok(items.pop().unwrap())
+    }
+    Ok(Regex::Sequence(items))
+}
+
+/// Parse a bracketed list of terminals.
+fn class(s: &mut Scanner) -> Result<Regex, SyntaxError> {
+    let open = s.mark();
+    s.bump();
+    let negated = s.eat("^");
+    let mut terminals = Vec::new();
+    loop {
+        s.skip_while(is_space);
+        match s.peek() {
+            Some(']') => {
+                s.bump();
+                return Ok(Regex::Class(negated, terminals))
+            },
+            Some('"') | Some('\'') => terminals.push(try!(s.quoted())),
+            Some(c) if in_ident(c) => terminals.push(s.take_while(in_ident)),
+            Some(_) => return Err(s.error("expected a terminal or `]`")),
+            None => {
+                s.reset(open);
+                return Err(s.error("unclosed class, expected `]`"))
+            }
+        }
+    }
+}
+
+/// Parse a bound on repetitions, `{n}`, `{n,}` or `{n,m}`.
+fn bounds(s: &mut Scanner) -> Result<(uint, Option<uint>), SyntaxError> {
+    let open = s.mark();
+    s.bump();
+    s.skip_while(is_space);
+    let min = try!(number(s));
+    s.skip_while(is_space);
+    let max = if s.eat(",") {
+        s.skip_while(is_space);
+        if s.looking_at("}") { None } else { Some(try!(number(s))) }
+    } else {
+        Some(min)
+    };
+    s.skip_while(is_space);
+    if !s.eat("}") {
+        return Err(s.error("expected `}`"))
+    }
+    match max {
+        Some(max) if max < min => {
+            s.reset(open);
+            Err(s.error("repetition bound is less than its minimum"))
+        },
+        _ => Ok((min, max))
+    }
+}
+
+fn number(s: &mut Scanner) -> Result<uint, SyntaxError> {
+    let at = s.mark();
+    match s.take_while(is_digit).parse() {
+        Some(n) => Ok(n),
+        None => {
+            s.reset(at);
+            Err(s.error("expected a number"))
+        }
+    }
+}
+
+fn add_terminals(cfg: &mut Cfg<String>, regex: &Regex) {
+    match *regex {
+        Regex::Terminal(ref terminal) => { cfg.add_token(terminal.clone()); },
+        Regex::Class(_, ref terminals) => for terminal in terminals.iter() {
+            cfg.add_token(terminal.clone());
+        },
+        Regex::Sequence(ref items) | Regex::Alternatives(ref items) => for item in items.iter() {
+            add_terminals(cfg, item);
+        },
+        Regex::Repeat(ref item, _, _) => add_terminals(cfg, &**item)
+    }
+}
+
+/// The sequence of symbols standing for `regex`, whose terminals have all been added.
+fn lower(cfg: &mut Cfg<String>, base: &str, regex: &Regex) -> Vec<Symbol> {
+    match *regex {
+        Regex::Terminal(ref terminal) => vec![Symbol::Tok(cfg.token(terminal).unwrap())],
+        Regex::Class(negated, ref terminals) => {
+            let listed: Vec<Token> = terminals.iter().map(|t| cfg.token(t).unwrap()).collect();
+            let all = range(0, cfg.num_tokens()).map(|t| Token(t as u32));
+            let tokens: Vec<Token> = all.filter(|t| listed.contains(t) != negated).collect();
+            if tokens.len() == 1 {
+                return vec![Symbol::Tok(tokens[0])]
+            }
+            let v = fresh(cfg, format!("{}_class", base).as_slice());
+            for &t in tokens.iter() {
+                cfg.add_rule(v, vec![Symbol::Tok(t)]).unwrap();
+            }
+            vec![Symbol::Var(v)]
+        },
+        Regex::Sequence(ref items) => {
+            let mut body = Vec::new();
+            for item in items.iter() {
+                body.extend(lower(cfg, base, item).into_iter());
+            }
+            body
+        },
+        Regex::Alternatives(ref alternatives) => {
+            let v = fresh(cfg, format!("{}_group", base).as_slice());
+            for regex in alternatives.iter() {
+                let body = lower(cfg, base, regex);
+                cfg.add_rule(v, body).unwrap();
+            }
+            vec![Symbol::Var(v)]
+        },
+        Regex::Repeat(ref item, min, max) => {
+            let item = lower(cfg, base, &**item);
+            let mut body = Vec::new();
+            for _ in range(0, min) {
+                body.push_all(item.as_slice());
+            }
+            match max {
+                // Each optional item holds the rest, so there's one way to match each count.
+                Some(max) => {
+                    let mut rest = Vec::new();
+                    for _ in range(min, max) {
+                        let v = fresh(cfg, format!("{}_opt", base).as_slice());
+                        let mut inner = item.clone();
+                        inner.extend(rest.into_iter());
+                        cfg.add_rule(v, inner).unwrap();
+                        cfg.add_rule(v, vec![]).unwrap();
+                        rest = vec![Symbol::Var(v)];
+                    }
+                    body.extend(rest.into_iter());
+                },
+                None => {
+                    let v = fresh(cfg, format!("{}_star", base).as_slice());
+                    let mut inner = item;
+                    inner.push(Symbol::Var(v));
+                    cfg.add_rule(v, inner).unwrap();
+                    cfg.add_rule(v, vec![]).unwrap();
+                    body.push(Symbol::Var(v));
+                }
+            }
+            body
+        }
+    }
+}
+
+/// A new variable, named after `base` differently from any other in the grammar.
+fn fresh(cfg: &mut Cfg<String>, base: &str) -> Variable {
+    let mut n = 1u;
+    loop {
+        let name = format!("{}_{}", base, n);
+        let taken = range(0, cfg.num_variables()).any(|v| {
+            cfg.name(Variable(v as u32)) == Some(name.as_slice())
+        });
+        if !taken {
+            return cfg.add_variable(name.as_slice())
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Cfg;
+    use testing::{EXPRESSIONS, grammar, sentences};
+
+    /// The sentences of at most `max_len` tokens of a regular expression, with their terminals
+    /// separated by spaces.
+    fn language(regex: &str, max_len: uint) -> Vec<String> {
+        let cfg = Cfg::from_regex(regex).unwrap();
+        sentences(&cfg, max_len).iter().map(|sentence| sentence.as_slice().connect(" ")).collect()
+    }
+
+    fn strs(expected: &[&str]) -> Vec<String> {
+        expected.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn from_regex() {
+        assert_eq!(language("a (b | c) d?", 4), strs(&["a b", "a b d", "a c", "a c d"]));
+        assert_eq!(language("x{2,3}", 5), strs(&["x x", "x x x"]));
+        assert_eq!(language("x{2,}", 4), strs(&["x x", "x x x", "x x x x"]));
+        assert_eq!(language("x{2}", 4), strs(&["x x"]));
+        assert_eq!(language("a* b", 3), strs(&["a a b", "a b", "b"]));
+        assert_eq!(language("a+ | \"b c\"", 2), strs(&["a", "a a", "b c"]));
+        assert_eq!(language("[^ a] b | [a] .", 2), strs(&["a a", "a b", "b b"]));
+        assert_eq!(language("", 2), strs(&[""]));
+    }
+
+    #[test]
+    fn add_regex() {
+        let mut cfg = grammar(EXPRESSIONS);
+        let (variables, tokens) = (cfg.num_variables(), cfg.num_tokens());
+        let v = cfg.add_regex("ops", "[\"+\" \"*\"] id | minus").unwrap();
+        assert_eq!(cfg.name(v), Some("ops"));
+        assert!(cfg.num_variables() > variables);
+        assert_eq!(cfg.num_tokens(), tokens + 1);
+        cfg.set_start(v).unwrap();
+        assert_eq!(sentences(&cfg, 3).len(), 3);
+    }
+
+    #[test]
+    fn errors() {
+        let mut cfg = grammar(EXPRESSIONS);
+        let (variables, tokens) = (cfg.num_variables(), cfg.num_tokens());
+        assert!(cfg.add_regex("r", "a (b").is_err());
+        assert!(cfg.add_regex("r", "[a b").is_err());
+        assert!(cfg.add_regex("r", "a{3,2}").is_err());
+        assert!(cfg.add_regex("r", "a{x}").is_err());
+        assert!(cfg.add_regex("r", "a )").is_err());
+        assert_eq!((cfg.num_variables(), cfg.num_tokens()), (variables, tokens));
+    }
+}
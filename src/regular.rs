@@ -0,0 +1,789 @@
+//! Finite automata over the tokens of a grammar, and how they combine with grammars.
+
+use std::collections::{BTreeSet, HashMap, VecMap};
+use std::hash::Hash;
+use std::mem;
+
+use super::{Cfg, Rule, Symbol, Token, Variable};
+use graph;
+
+/// A deterministic finite automaton reading tokens, given by their index in a grammar.
+///
+/// States are numbered from 0. A state need not have a transition on every token; the automaton
+/// rejects any input that leads to a missing one.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Dfa {
+    tokens: uint,
+    start: uint,
+    accepting: Vec<bool>,
+    /// The transition from each state on each token, indexed by state times `tokens` plus token.
+    transitions: Vec<Option<uint>>
+}
+
+impl Dfa {
+    /// An automaton over `tokens` tokens with a single, rejecting, start state.
+    pub fn new(tokens: uint) -> Dfa {
+        Dfa {
+            tokens: tokens,
+            start: 0,
+            accepting: vec![false],
+            transitions: Vec::from_elem(tokens, None)
+        }
+    }
+
+    /// Add a state with no transitions, returning its number.
+    pub fn add_state(&mut self, accepting: bool) -> uint {
+        self.accepting.push(accepting);
+        let tokens = self.tokens;
+        self.transitions.extend(range(0, tokens).map(|_| None));
+        self.accepting.len() - 1
+    }
+
+    /// Set the transition from `from` on `token`, replacing any there was.
+    ///
+    /// Panics if either state or the token is out of range.
+    pub fn set_transition(&mut self, from: uint, token: Token, to: uint) {
+        assert!(to < self.accepting.len() && token.index() < self.tokens);
+        let cell = self.cell(from, token);
+        self.transitions[cell] = Some(to);
+    }
+
+    pub fn set_accepting(&mut self, state: uint, accepting: bool) {
+        self.accepting[state] = accepting;
+    }
+
+    pub fn set_start(&mut self, state: uint) {
+        assert!(state < self.accepting.len());
+        self.start = state;
+    }
+
+    pub fn start(&self) -> uint {
+        self.start
+    }
+
+    pub fn is_accepting(&self, state: uint) -> bool {
+        self.accepting[state]
+    }
+
+    /// The state reached from `state` on `token`, if any.
+    pub fn next(&self, state: uint, token: Token) -> Option<uint> {
+        if token.index() >= self.tokens {
+            return None
+        }
+        self.transitions[self.cell(state, token)]
+    }
+
+    pub fn num_states(&self) -> uint {
+        self.accepting.len()
+    }
+
+    pub fn num_tokens(&self) -> uint {
+        self.tokens
+    }
+
+    /// Whether the automaton accepts `input`.
+    pub fn accepts(&self, input: &[Token]) -> bool {
+        let mut state = self.start;
+        for &t in input.iter() {
+            match self.next(state, t) {
+                Some(next) => state = next,
+                None => return false
+            }
+        }
+        self.accepting[state]
+    }
+
+    fn cell(&self, state: uint, token: Token) -> uint {
+        state * self.tokens + token.index()
+    }
+}
+
+/// A nondeterministic finite automaton reading tokens, given by their index in a grammar, with
+/// empty transitions.
+///
+/// States are numbered from 0, and an automaton accepts an input if some path from the start
+/// state reading it ends in an accepting state.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Nfa {
+    tokens: uint,
+    start: uint,
+    accepting: Vec<bool>,
+    /// The transitions out of each state, on a token or, for `None`, on nothing.
+    transitions: Vec<Vec<(Option<Token>, uint)>>
+}
+
+impl Nfa {
+    /// An automaton over `tokens` tokens with a single, rejecting, start state.
+    pub fn new(tokens: uint) -> Nfa {
+        Nfa {
+            tokens: tokens,
+            start: 0,
+            accepting: vec![false],
+            transitions: vec![Vec::new()]
+        }
+    }
+
+    /// Add a state with no transitions, returning its number.
+    pub fn add_state(&mut self, accepting: bool) -> uint {
+        self.accepting.push(accepting);
+        self.transitions.push(Vec::new());
+        self.accepting.len() - 1
+    }
+
+    /// Add a transition from `from` on `token`, or on nothing for `None`.
+    ///
+    /// Panics if either state or the token is out of range.
+    pub fn add_transition(&mut self, from: uint, token: Option<Token>, to: uint) {
+        assert!(to < self.accepting.len() && token.map_or(true, |t| t.index() < self.tokens));
+        if !self.transitions[from].contains(&(token, to)) {
+            self.transitions[from].push((token, to));
+        }
+    }
+
+    pub fn set_accepting(&mut self, state: uint, accepting: bool) {
+        self.accepting[state] = accepting;
+    }
+
+    pub fn set_start(&mut self, state: uint) {
+        assert!(state < self.accepting.len());
+        self.start = state;
+    }
+
+    pub fn start(&self) -> uint {
+        self.start
+    }
+
+    pub fn is_accepting(&self, state: uint) -> bool {
+        self.accepting[state]
+    }
+
+    /// The transitions out of `state`, in the order they were added.
+    pub fn transitions(&self, state: uint) -> &[(Option<Token>, uint)] {
+        self.transitions[state].as_slice()
+    }
+
+    pub fn num_states(&self) -> uint {
+        self.accepting.len()
+    }
+
+    pub fn num_tokens(&self) -> uint {
+        self.tokens
+    }
+
+    /// Whether the automaton accepts `input`.
+    pub fn accepts(&self, input: &[Token]) -> bool {
+        let mut states = self.closure(vec![self.start]);
+        for &t in input.iter() {
+            states = self.step(states.as_slice(), t);
+            if states.is_empty() {
+                return false
+            }
+        }
+        states.iter().any(|&q| self.accepting[q])
+    }
+
+    /// An equivalent deterministic automaton, by the subset construction. Only the subsets
+    /// reachable from the start are made, and the empty subset isn't: its transitions are left
+    /// missing.
+    pub fn to_dfa(&self) -> Dfa {
+        let start = self.closure(vec![self.start]);
+        let mut dfa = Dfa::new(self.tokens);
+        dfa.set_accepting(0, start.iter().any(|&q| self.accepting[q]));
+        let mut ids = HashMap::new();
+        ids.insert(start.clone(), 0u);
+        let mut subsets = vec![start];
+        let mut next = 0;
+        while next < subsets.len() {
+            for t in range(0, self.tokens) {
+                let token = Token(t as u32);
+                let to = self.step(subsets[next].as_slice(), token);
+                if to.is_empty() {
+                    continue
+                }
+                let id = match ids.get(&to) {
+                    Some(&id) => id,
+                    None => {
+                        let id = dfa.add_state(to.iter().any(|&q| self.accepting[q]));
+                        ids.insert(to.clone(), id);
+                        subsets.push(to);
+                        id
+                    }
+                };
+                dfa.set_transition(next, token, id);
+            }
+            next += 1;
+        }
+        dfa
+    }
+
+    /// The states reachable from `states` on `token`, with those reachable from them on nothing,
+    /// sorted.
+    fn step(&self, states: &[uint], token: Token) -> Vec<uint> {
+        let mut to = Vec::new();
+        for &q in states.iter() {
+            for &(t, r) in self.transitions[q].iter() {
+                if t == Some(token) && !to.contains(&r) {
+                    to.push(r);
+                }
+            }
+        }
+        self.closure(to)
+    }
+
+    /// The states reachable from `states` on nothing, sorted.
+    fn closure(&self, states: Vec<uint>) -> Vec<uint> {
+        let mut seen = Vec::from_elem(self.accepting.len(), false);
+        for &q in states.iter() {
+            seen[q] = true;
+        }
+        let mut stack = states;
+        loop {
+            let q = match stack.pop() {
+                Some(q) => q,
+                None => break
+            };
+            for &(t, r) in self.transitions[q].iter() {
+                if t.is_none() && !seen[r] {
+                    seen[r] = true;
+                    stack.push(r);
+                }
+            }
+        }
+        range(0, seen.len()).filter(|&q| seen[q]).collect()
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// A grammar for the sentences of this one that `dfa` accepts, by the construction of
+    /// Bar-Hillel, Perles and Shamir.
+    ///
+    /// Each variable of the result stands for a variable `A` of this grammar deriving a string
+    /// that takes the automaton from a state `p` to a state `q`, and is named `A[p,q]`. Only
+    /// those that derive some string of tokens are made, so the result is empty exactly when no
+    /// sentence is accepted. Tokens keep their numbering, and the new start symbol is named
+    /// `start`.
+    pub fn intersect_regular(&self, dfa: &Dfa) -> Cfg<T> {
+        let states = dfa.num_states();
+        let n = self.num_variables();
+
+        // The states each variable can take the automaton to from each state, found bottom-up.
+        let mut ends: HashMap<(uint, Variable), BTreeSet<uint>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in range(0, n) {
+                let variable = Variable(v as u32);
+                for rule in rules(self, variable).iter() {
+                    for p in range(0, states) {
+                        for q in run(dfa, &ends, rule.as_slice(), p).into_iter() {
+                            if !ends.contains_key(&(p, variable)) {
+                                ends.insert((p, variable), BTreeSet::new());
+                            }
+                            if ends.get_mut(&(p, variable)).unwrap().insert(q) {
+                                changed = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut cfg = Cfg {
+            rules: VecMap::new(),
+            terminals: self.terminals.clone(),
+            token_map: self.token_map.clone(),
+            variable_names: VecMap::new(),
+            variables: 0,
+            start: Variable(0),
+            precedence: self.precedence.clone(),
+            rule_precedence: HashMap::new(),
+            rule_ids: VecMap::new(),
+            next_rule_id: 0
+        };
+        let start = cfg.add_variable("start");
+        let mut triples: Vec<(uint, Variable, uint)> = Vec::new();
+        for (&(p, v), qs) in ends.iter() {
+            triples.extend(qs.iter().map(|&q| (p, v, q)));
+        }
+        triples.sort_by(|&(p, v, q), &(p2, v2, q2)| (v, p, q).cmp(&(v2, p2, q2)));
+        let mut ids = HashMap::new();
+        for &(p, v, q) in triples.iter() {
+            let name = match self.name(v) {
+                Some(name) => format!("{}[{},{}]", name, p, q),
+                None => format!("{}[{},{}]", v.index(), p, q)
+            };
+            ids.insert((p, v, q), cfg.add_variable(name.as_slice()));
+        }
+
+        for q in range(0, states).filter(|&q| dfa.is_accepting(q)) {
+            match ids.get(&(dfa.start(), self.get_start(), q)) {
+                Some(&v) => cfg.add_rule(start, vec![Symbol::Var(v)]).unwrap(),
+                None => { }
+            }
+        }
+        for &(p, v, q) in triples.iter() {
+            let id = *ids.get(&(p, v, q)).unwrap();
+            let mut bodies: Vec<Rule> = Vec::new();
+            for rule in rules(self, v).iter() {
+                // The states reachable after each prefix of the rule, with the rule so far.
+                let mut partial = vec![(p, Vec::new())];
+                for &symbol in rule.iter() {
+                    let mut next = Vec::new();
+                    for (state, body) in partial.into_iter() {
+                        match symbol {
+                            Symbol::Tok(t) => match dfa.next(state, t) {
+                                Some(to) => {
+                                    let mut body = body;
+                                    body.push(Symbol::Tok(t));
+                                    next.push((to, body));
+                                },
+                                None => { }
+                            },
+                            Symbol::Var(w) => match ends.get(&(state, w)) {
+                                Some(tos) => for &to in tos.iter() {
+                                    let mut body = body.clone();
+                                    body.push(Symbol::Var(*ids.get(&(state, w, to)).unwrap()));
+                                    next.push((to, body));
+                                },
+                                None => { }
+                            }
+                        }
+                    }
+                    partial = next;
+                }
+                for (state, body) in partial.into_iter() {
+                    if state == q && !bodies.contains(&body) {
+                        bodies.push(body);
+                    }
+                }
+            }
+            for body in bodies.into_iter() {
+                cfg.add_rule(id, body).unwrap();
+            }
+        }
+        cfg
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// A finite automaton accepting every sentence of the grammar, and perhaps more, by the
+    /// approximation of Mohri and Nederhof.
+    ///
+    /// Every strongly connected component of variables that is neither right- nor left-linear is
+    /// first made right-linear: each variable `A` of it gets a copy `A'` standing for what
+    /// follows `A`, and a rule `A -> a0 B1 a1 ... Bm am`, with `B1` to `Bm` in the component, is
+    /// split into `A -> a0 B1`, `B1' -> a1 B2`, ..., `Bm' -> am A'`, with `A' -> ε`. This only
+    /// forgets how the variables nest, so the automaton is exact for grammars with no
+    /// self-embedding, and in particular for regular ones. The automaton is then built by
+    /// expanding the start symbol, each use of a variable outside a recursive component getting
+    /// its own copy of the states for it, so it can be exponentially larger than the grammar.
+    pub fn regular_approximation(&self) -> Nfa {
+        let mut bodies: Vec<Vec<Rule>> = range(0, self.num_variables()).map(|v| {
+            rules(self, Variable(v as u32)).to_vec()
+        }).collect();
+        let (components, of) = strong_components(bodies.as_slice());
+        for component in components.iter() {
+            if shape(bodies.as_slice(), component.as_slice(), of.as_slice()).is_some() {
+                continue
+            }
+            let base = bodies.len();
+            bodies.extend(component.iter().map(|_| vec![Vec::new()]));
+            let prime = |v: Variable| {
+                let k = component.iter().position(|&w| w == v).unwrap();
+                Variable((base + k) as u32)
+            };
+            for &a in component.iter() {
+                let old = mem::replace(&mut bodies[a.index()], Vec::new());
+                for rule in old.into_iter() {
+                    // The body of the rule being built, and the variable it belongs to.
+                    let mut from = a;
+                    let mut body = Vec::new();
+                    for symbol in rule.into_iter() {
+                        body.push(symbol);
+                        match symbol {
+                            Symbol::Var(w) if of[w.index()] == of[a.index()] => {
+                                bodies[from.index()].push(mem::replace(&mut body, Vec::new()));
+                                from = prime(w);
+                            },
+                            _ => { }
+                        }
+                    }
+                    body.push(Symbol::Var(prime(a)));
+                    bodies[from.index()].push(body);
+                }
+            }
+        }
+
+        automaton(bodies.as_slice(), self.num_tokens(), self.get_start()).unwrap()
+    }
+
+    /// A finite automaton accepting exactly the sentences of the grammar, if it's regular by the
+    /// shape of its rules: if every strongly connected component of variables that the start
+    /// symbol uses is right-linear, left-linear, or not recursive.
+    ///
+    /// A variable outside a recursive component gets its own copy of the states for it at each
+    /// use, so the automaton can be exponentially larger than the grammar. Use `Nfa::to_dfa` to
+    /// compile it further.
+    pub fn as_regular(&self) -> Option<Nfa> {
+        self.variable_as_regular(self.get_start())
+    }
+
+    /// A finite automaton accepting exactly the strings `variable` derives, if the rules it uses
+    /// are regular in the same way as for `as_regular`. This finds the regular sublanguages of
+    /// a grammar that isn't.
+    pub fn variable_as_regular(&self, variable: Variable) -> Option<Nfa> {
+        let bodies: Vec<Vec<Rule>> = range(0, self.num_variables()).map(|v| {
+            rules(self, Variable(v as u32)).to_vec()
+        }).collect();
+        automaton(bodies.as_slice(), self.num_tokens(), variable)
+    }
+}
+
+/// How the rules of a strongly connected component of variables use the component.
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+enum Shape {
+    /// No variable of the component derives a string containing one.
+    NonRecursive,
+    /// Each rule has at most one variable of the component, at its end.
+    RightLinear,
+    /// Each rule has at most one variable of the component, at its start.
+    LeftLinear
+}
+
+/// An automaton for what `start` derives with the given rules, if every recursive component of
+/// variables it uses is right- or left-linear.
+fn automaton(bodies: &[Vec<Rule>], tokens: uint, start: Variable) -> Option<Nfa> {
+    let (components, of) = strong_components(bodies);
+    let shapes: Vec<Option<Shape>> = components.iter().map(|component| {
+        shape(bodies, component.as_slice(), of.as_slice())
+    }).collect();
+
+    let mut used = Vec::from_elem(bodies.len(), false);
+    used[start.index()] = true;
+    let mut stack = vec![start];
+    loop {
+        let v = match stack.pop() {
+            Some(v) => v,
+            None => break
+        };
+        if shapes[of[v.index()]].is_none() {
+            return None
+        }
+        for rule in bodies[v.index()].iter() {
+            for &symbol in rule.iter() {
+                match symbol {
+                    Symbol::Var(w) if !used[w.index()] => {
+                        used[w.index()] = true;
+                        stack.push(w);
+                    },
+                    _ => { }
+                }
+            }
+        }
+    }
+
+    let mut builder = Builder {
+        bodies: bodies,
+        components: components,
+        of: of,
+        shapes: shapes,
+        nfa: Nfa::new(tokens)
+    };
+    let end = builder.nfa.add_state(true);
+    builder.symbol(0, Symbol::Var(start), end);
+    Some(builder.nfa)
+}
+
+/// The state of building an automaton from a grammar whose recursive components are all right-
+/// or left-linear.
+struct Builder<'a> {
+    bodies: &'a [Vec<Rule>],
+    components: Vec<Vec<Variable>>,
+    /// The component each variable is in.
+    of: Vec<uint>,
+    /// The shape of each component, if it's one the automaton can be built for.
+    shapes: Vec<Option<Shape>>,
+    nfa: Nfa
+}
+
+impl<'a> Builder<'a> {
+    /// Add paths from `from` to `to` reading what `symbols` derives.
+    fn sequence(&mut self, from: uint, symbols: &[Symbol], to: uint) {
+        match symbols.len() {
+            0 => self.nfa.add_transition(from, None, to),
+            1 => self.symbol(from, symbols[0], to),
+            _ => {
+                let middle = self.nfa.add_state(false);
+                self.symbol(from, symbols[0], middle);
+                self.sequence(middle, symbols.slice_from(1), to);
+            }
+        }
+    }
+
+    /// Add paths from `from` to `to` reading what `symbol` derives.
+    fn symbol(&mut self, from: uint, symbol: Symbol, to: uint) {
+        let a = match symbol {
+            Symbol::Tok(t) => return self.nfa.add_transition(from, Some(t), to),
+            Symbol::Var(a) => a
+        };
+        let bodies = self.bodies;
+        let c = self.of[a.index()];
+        let component = self.components[c].clone();
+        let inside = |symbol: Option<&Symbol>| match symbol {
+            Some(&Symbol::Var(w)) => component.iter().position(|&v| v == w),
+            _ => None
+        };
+        let shape = self.shapes[c].expect("a recursive component neither right- nor left-linear");
+        if shape == Shape::NonRecursive {
+            for rule in bodies[a.index()].iter() {
+                self.sequence(from, rule.as_slice(), to);
+            }
+            return
+        }
+        // A state for each variable of the component, standing for having read a prefix of what
+        // its variable derives, in a right-linear component, or for having read what it derives,
+        // in a left-linear one.
+        let states: Vec<uint> = component.iter().map(|_| self.nfa.add_state(false)).collect();
+        let k = inside(Some(&symbol)).unwrap();
+        if shape == Shape::RightLinear {
+            self.nfa.add_transition(from, None, states[k]);
+            for (i, &v) in component.iter().enumerate() {
+                for rule in bodies[v.index()].iter() {
+                    match inside(rule.last()) {
+                        Some(j) => {
+                            let body = rule.slice_to(rule.len() - 1);
+                            self.sequence(states[i], body, states[j])
+                        },
+                        None => self.sequence(states[i], rule.as_slice(), to)
+                    }
+                }
+            }
+        } else {
+            self.nfa.add_transition(states[k], None, to);
+            for (i, &v) in component.iter().enumerate() {
+                for rule in bodies[v.index()].iter() {
+                    match inside(rule.get(0)) {
+                        Some(j) => self.sequence(states[j], rule.slice_from(1), states[i]),
+                        None => self.sequence(from, rule.as_slice(), states[i])
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The strongly connected components of the variables with the given rules, and the one each
+/// variable is in.
+fn strong_components(bodies: &[Vec<Rule>]) -> (Vec<Vec<Variable>>, Vec<uint>) {
+    let mut edges = Vec::from_fn(bodies.len(), |_| Vec::new());
+    for v in range(0, bodies.len()) {
+        for rule in bodies[v].iter() {
+            for &symbol in rule.iter() {
+                match symbol {
+                    Symbol::Var(w) if !edges[v].contains(&w) => edges[v].push(w),
+                    _ => { }
+                }
+            }
+        }
+    }
+    let components = graph::components(edges.as_slice());
+    let mut of = Vec::from_elem(bodies.len(), 0);
+    for (c, component) in components.iter().enumerate() {
+        for &v in component.iter() {
+            of[v.index()] = c;
+        }
+    }
+    (components, of)
+}
+
+/// How the rules of `component` use it, or `None` if it's recursive but neither right- nor
+/// left-linear.
+fn shape(bodies: &[Vec<Rule>], component: &[Variable], of: &[uint]) -> Option<Shape> {
+    let c = of[component[0].index()];
+    let inside = |symbol: &Symbol| match *symbol {
+        Symbol::Var(w) => of[w.index()] == c,
+        Symbol::Tok(_) => false
+    };
+    let mut right = true;
+    let mut left = true;
+    let mut recursive = component.len() > 1;
+    for &v in component.iter() {
+        for rule in bodies[v.index()].iter() {
+            let count = rule.iter().filter(|s| inside(*s)).count();
+            if count > 0 {
+                recursive = true;
+            }
+            right = right && (count == 0 || count == 1 && inside(rule.last().unwrap()));
+            left = left && (count == 0 || count == 1 && inside(&rule[0]));
+        }
+    }
+    if !recursive {
+        Some(Shape::NonRecursive)
+    } else if right {
+        Some(Shape::RightLinear)
+    } else if left {
+        Some(Shape::LeftLinear)
+    } else {
+        None
+    }
+}
+
+/// The states `rule` can take the automaton to from `from`, given the states each variable can.
+fn run(dfa: &Dfa, ends: &HashMap<(uint, Variable), BTreeSet<uint>>, rule: &[Symbol], from: uint)
+       -> BTreeSet<uint> {
+    let mut states = BTreeSet::new();
+    states.insert(from);
+    for &symbol in rule.iter() {
+        let mut next = BTreeSet::new();
+        for &state in states.iter() {
+            match symbol {
+                Symbol::Tok(t) => next.extend(dfa.next(state, t).into_iter()),
+                Symbol::Var(w) => match ends.get(&(state, w)) {
+                    Some(tos) => next.extend(tos.iter().map(|&to| to)),
+                    None => { }
+                }
+            }
+        }
+        states = next;
+    }
+    states
+}
+
+fn rules<T: Eq + Hash + Clone>(cfg: &Cfg<T>, variable: Variable) -> &[Rule] {
+    cfg.get_rules(variable).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dfa, Nfa};
+    use super::super::Token;
+    use earley;
+    use testing::{EXPRESSIONS, SMALL, grammar, strings, tokens, variable};
+
+    /// An automaton over the tokens of `cfg` accepting the strings with an even number of `token`.
+    fn even(tokens: uint, token: Token) -> Dfa {
+        let mut dfa = Dfa::new(tokens);
+        dfa.set_accepting(0, true);
+        let odd = dfa.add_state(false);
+        for t in range(0, tokens).map(|t| Token(t as u32)) {
+            let (stay, flip) = if t == token { (odd, 0) } else { (0, odd) };
+            dfa.set_transition(0, t, stay);
+            dfa.set_transition(odd, t, flip);
+        }
+        dfa
+    }
+
+    #[test]
+    fn dfa() {
+        let cfg = grammar(EXPRESSIONS);
+        let plus = cfg.token(&"+".to_string()).unwrap();
+        let dfa = even(cfg.num_tokens(), plus);
+        assert_eq!(dfa.num_states(), 2);
+        assert_eq!(dfa.next(0, plus), Some(1));
+        assert!(dfa.accepts(&[]));
+        assert!(dfa.accepts(tokens(&cfg, "id + id + id").as_slice()));
+        assert!(!dfa.accepts(tokens(&cfg, "id + id").as_slice()));
+
+        let mut partial = Dfa::new(cfg.num_tokens());
+        let end = partial.add_state(true);
+        partial.set_transition(0, plus, end);
+        assert!(partial.accepts(tokens(&cfg, "+").as_slice()));
+        assert!(!partial.accepts(tokens(&cfg, "+ +").as_slice()));
+        assert!(!partial.accepts(tokens(&cfg, "id").as_slice()));
+    }
+
+    #[test]
+    fn intersect_regular() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            for t in range(0, cfg.num_tokens()).map(|t| Token(t as u32)) {
+                let dfa = even(cfg.num_tokens(), t);
+                let both = cfg.intersect_regular(&dfa);
+                assert_eq!(both.num_tokens(), cfg.num_tokens());
+                for input in strings(&cfg, 4).iter() {
+                    let expected = earley::recognize(&cfg, input.as_slice()) &&
+                                   dfa.accepts(input.as_slice());
+                    assert_eq!(earley::recognize(&both, input.as_slice()), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nfa() {
+        // Strings of a and b ending in "a b", with an empty transition from the start.
+        let mut nfa = Nfa::new(2);
+        let (a, b) = (Token(0), Token(1));
+        let any = nfa.add_state(false);
+        let seen_a = nfa.add_state(false);
+        let end = nfa.add_state(true);
+        nfa.add_transition(0, None, any);
+        nfa.add_transition(any, Some(a), any);
+        nfa.add_transition(any, Some(b), any);
+        nfa.add_transition(any, Some(a), seen_a);
+        nfa.add_transition(seen_a, Some(b), end);
+        assert_eq!(nfa.transitions(any).len(), 3);
+
+        let dfa = nfa.to_dfa();
+        let inputs: Vec<Vec<Token>> = vec![vec![], vec![a], vec![a, b], vec![b, a, b],
+                                           vec![a, b, a], vec![a, a, b, b], vec![b, b, a, b]];
+        let accepted: Vec<bool> = inputs.iter().map(|input| {
+            nfa.accepts(input.as_slice())
+        }).collect();
+        assert_eq!(accepted, vec![false, false, true, true, false, false, true]);
+        for input in inputs.iter() {
+            assert_eq!(dfa.accepts(input.as_slice()), nfa.accepts(input.as_slice()));
+        }
+    }
+
+    #[test]
+    fn regular_approximation() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let nfa = cfg.regular_approximation();
+            let dfa = nfa.to_dfa();
+            for input in strings(&cfg, 5).iter() {
+                if earley::recognize(&cfg, input.as_slice()) {
+                    assert!(nfa.accepts(input.as_slice()));
+                    assert!(dfa.accepts(input.as_slice()));
+                }
+            }
+        }
+
+        // The approximation of balanced parentheses forgets how they nest.
+        let cfg = grammar("<s> ::= \"(\" <s> \")\" | \"\"");
+        let nfa = cfg.regular_approximation();
+        assert!(nfa.accepts(tokens(&cfg, "( ( ) )").as_slice()));
+        assert!(nfa.accepts(tokens(&cfg, "( ( )").as_slice()));
+        assert!(!nfa.accepts(tokens(&cfg, ") (").as_slice()));
+    }
+
+    #[test]
+    fn as_regular() {
+        let cfg = grammar("
+            <s> ::= \"a\" <s> | \"b\" <t>
+            <t> ::= \"b\" <t> | \"\"
+        ");
+        let nfa = cfg.as_regular().unwrap();
+        for input in strings(&cfg, 5).iter() {
+            assert_eq!(nfa.accepts(input.as_slice()), earley::recognize(&cfg, input.as_slice()));
+        }
+
+        let cfg = grammar("
+            <s> ::= \"(\" <s> \")\" | <l>
+            <l> ::= \"x\" <l> | \"\"
+        ");
+        assert!(cfg.as_regular().is_none());
+        let nfa = cfg.variable_as_regular(variable(&cfg, "l")).unwrap();
+        assert!(nfa.accepts(&[]));
+        assert!(nfa.accepts(tokens(&cfg, "x x x").as_slice()));
+        assert!(!nfa.accepts(tokens(&cfg, "( x )").as_slice()));
+
+        // Left-linear recursion is regular too.
+        let cfg = grammar("<s> ::= <s> \"a\" | \"b\"");
+        let nfa = cfg.as_regular().unwrap();
+        for input in strings(&cfg, 5).iter() {
+            assert_eq!(nfa.accepts(input.as_slice()), earley::recognize(&cfg, input.as_slice()));
+        }
+    }
+}
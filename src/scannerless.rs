@@ -0,0 +1,165 @@
+//! Parsing text directly, with grammars whose terminals are characters.
+//!
+//! Small languages often don't need a lexer: a grammar with `char` terminals, such as one read
+//! from ABNF or converted from one with string terminals by `to_char_grammar`, can be run on a
+//! `&str` with the general parsers, which don't mind the lookahead and ambiguity that lexing
+//! usually takes care of.
+
+use std::hash::Hash;
+
+use super::{Cfg, Rule, Symbol, Token};
+use earley;
+use forest::Forest;
+use glr::Glr;
+
+impl Cfg<char> {
+    /// The body of a rule matching `text` exactly, adding a token for each of its characters
+    /// the grammar doesn't have yet.
+    pub fn add_literal(&mut self, text: &str) -> Rule {
+        text.chars().map(|c| Symbol::Tok(self.add_token(c))).collect()
+    }
+
+    /// The tokens of the characters of `text`, or the index of the first character the grammar
+    /// has no token for.
+    pub fn tokenize_str(&self, text: &str) -> Result<Vec<Token>, uint> {
+        let mut tokens = Vec::with_capacity(text.len());
+        for (i, c) in text.chars().enumerate() {
+            match self.token(&c) {
+                Some(t) => tokens.push(t),
+                None => return Err(i)
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Whether the grammar derives `text`, using an Earley parser.
+    pub fn recognize_str(&self, text: &str) -> bool {
+        match self.tokenize_str(text) {
+            Ok(tokens) => earley::recognize(self, tokens.as_slice()),
+            Err(_) => false
+        }
+    }
+
+    /// Parse `text` with an Earley parser, giving the forest of its parses with a token for each
+    /// character, or `None` if the grammar doesn't derive it.
+    pub fn parse_str(&self, text: &str) -> Option<Forest> {
+        match self.tokenize_str(text) {
+            Ok(tokens) => earley::parse(self, tokens.as_slice()),
+            Err(_) => None
+        }
+    }
+
+    /// Parse `text` with a GLR parser, as `parse_str` does.
+    ///
+    /// Building the parser's table takes a while for a large grammar, so to parse many texts,
+    /// build a `Glr` once and give it the tokens from `tokenize_str`.
+    pub fn parse_str_glr(&self, text: &str) -> Option<Forest> {
+        match self.tokenize_str(text) {
+            Ok(tokens) => Glr::new(self).parse(tokens.as_slice()),
+            Err(_) => None
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone + Str> Cfg<T> {
+    /// An equivalent grammar over the characters of the terminals, each token replaced by the
+    /// characters of its terminal in turn, so that a grammar written with string terminals, such
+    /// as one read from BNF, can be run on text without a lexer.
+    ///
+    /// Variables keep their numbering and names; tokens are numbered in order of the first
+    /// occurrence of each character among the terminals. A terminal that is the empty string
+    /// matches the empty string. Precedences are dropped.
+    pub fn to_char_grammar(&self) -> Cfg<char> {
+        let mut cfg = Cfg::new();
+        let mut tokens = Vec::with_capacity(self.num_tokens());
+        for t in self.tokens() {
+            let literal = cfg.add_literal(self.terminal(t).unwrap().as_slice());
+            tokens.push(literal);
+        }
+        cfg.variables = self.variables;
+        cfg.variable_names = self.variable_names.clone();
+        for v in self.variables() {
+            for rule in self.get_rules(v).unwrap_or(&[]).iter() {
+                let mut body = Vec::with_capacity(rule.len());
+                for &symbol in rule.iter() {
+                    match symbol {
+                        Symbol::Tok(t) => body.push_all(tokens[t.index()].as_slice()),
+                        Symbol::Var(w) => body.push(Symbol::Var(w))
+                    }
+                }
+                cfg.add_rule(v, body).unwrap();
+            }
+        }
+        cfg.start = self.get_start();
+        cfg
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Cfg, Symbol};
+    use testing::{grammar, variable};
+
+    /// Balanced parentheses, in characters.
+    fn balanced() -> Cfg<char> {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let open = cfg.add_token('(');
+        let close = cfg.add_token(')');
+        let body = vec![Symbol::Tok(open), Symbol::Var(s), Symbol::Tok(close), Symbol::Var(s)];
+        cfg.add_rule(s, body).unwrap();
+        cfg.add_rule(s, vec![]).unwrap();
+        cfg
+    }
+
+    #[test]
+    fn parse_str() {
+        let cfg = balanced();
+        assert_eq!(cfg.tokenize_str("(x)"), Err(1));
+        assert!(cfg.recognize_str("(()())"));
+        assert!(cfg.recognize_str(""));
+        assert!(!cfg.recognize_str("(()"));
+        assert!(!cfg.recognize_str("(x)"));
+        let forest = cfg.parse_str("()()").unwrap();
+        assert_eq!(forest.count_trees(), Some(1));
+        assert_eq!(cfg.parse_str_glr("()()").unwrap().tree(), forest.tree());
+        assert!(cfg.parse_str(")(").is_none());
+        assert!(cfg.parse_str_glr(")(").is_none());
+    }
+
+    #[test]
+    fn add_literal() {
+        let mut cfg = Cfg::new();
+        let s = cfg.add_variable("s");
+        let keyword = cfg.add_literal("let");
+        assert_eq!(cfg.num_tokens(), 3);
+        let other = cfg.add_literal("get");
+        assert_eq!(cfg.num_tokens(), 4);
+        assert_eq!(keyword.len(), 3);
+        assert_eq!(other[1], keyword[1]);
+        cfg.add_rule(s, keyword).unwrap();
+        cfg.add_rule(s, other).unwrap();
+        assert!(cfg.recognize_str("let"));
+        assert!(cfg.recognize_str("get"));
+        assert!(!cfg.recognize_str("le"));
+    }
+
+    #[test]
+    fn to_char_grammar() {
+        let cfg = grammar("
+    <s> ::= \"if\" <s> \"fi\" | <x>
+    <x> ::= \"x\" | \"\"
+    ");
+        let chars = cfg.to_char_grammar();
+        assert_eq!(chars.num_tokens(), 3);
+        assert_eq!(chars.num_variables(), 2);
+        assert_eq!(chars.name(variable(&cfg, "x")), Some("x"));
+        assert_eq!(chars.get_start(), cfg.get_start());
+        for text in ["x", "", "ifxfi", "ififfifi"].iter() {
+            assert!(chars.recognize_str(*text));
+        }
+        for text in ["if x fi", "ifx", "xx", "fiif"].iter() {
+            assert!(!chars.recognize_str(*text));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Reading the input of a parser from a lexer.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use super::Token;
+
+/// A stretch of the input text of a parser, as byte offsets.
+#[deriving(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub struct ByteSpan {
+    pub start: uint,
+    /// The offset just past the end.
+    pub end: uint
+}
+
+/// A source of tokens for a parser, such as a lexer: each token with where it was found in the
+/// text and a payload, such as the text itself or a value read from it.
+///
+/// Any iterator of such triples is a source, so a list of tokens for a test needs no glue.
+pub trait TokenSource<P> {
+    /// The next token, or `None` at the end of the input.
+    fn next_token(&mut self) -> Option<(Token, ByteSpan, P)>;
+}
+
+impl<P, I: Iterator<(Token, ByteSpan, P)>> TokenSource<P> for I {
+    fn next_token(&mut self) -> Option<(Token, ByteSpan, P)> {
+        self.next()
+    }
+}
+
+/// The tokens read from a source, with their spans and payloads kept by position for the parse
+/// results that refer to them.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct TokenStream<P> {
+    pub tokens: Vec<Token>,
+    pub spans: Vec<ByteSpan>,
+    pub payloads: Vec<P>
+}
+
+impl<P> TokenStream<P> {
+    /// Read every token from a source.
+    pub fn read<S: TokenSource<P>>(source: &mut S) -> TokenStream<P> {
+        let mut stream = TokenStream {
+            tokens: Vec::new(),
+            spans: Vec::new(),
+            payloads: Vec::new()
+        };
+        loop {
+            match source.next_token() {
+                Some((token, span, payload)) => {
+                    stream.tokens.push(token);
+                    stream.spans.push(span);
+                    stream.payloads.push(payload);
+                },
+                None => return stream
+            }
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.tokens.len()
+    }
+
+    /// The span covering the tokens from `start` up to `end`. If there are none, it's an empty
+    /// span where the token at `start` begins, or at the end of the last token for the end of
+    /// the input.
+    pub fn span_of(&self, start: uint, end: uint) -> ByteSpan {
+        let first = self.span(start);
+        if end <= start {
+            return ByteSpan { start: first.start, end: first.start }
+        }
+        ByteSpan { start: first.start, end: self.span(end - 1).end }
+    }
+
+    /// The span of the token at a position, or an empty span at the end of the last token for
+    /// the end of the input.
+    pub fn span(&self, position: uint) -> ByteSpan {
+        match self.spans.get(position) {
+            Some(&span) => span,
+            None => {
+                let end = self.spans.last().map_or(0, |span| span.end);
+                ByteSpan { start: end, end: end }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{ByteSpan, TokenStream};
+    use super::super::{Cfg, Token};
+    use earley;
+    use glr::Glr;
+    use ll1::Ll1Parser;
+    use lr::LrParser;
+    use testing::{EXPRESSIONS, EXPRESSIONS_LL1, grammar};
+
+    /// Split text at spaces into tokens of the grammar, with the words as payloads.
+    fn lex(cfg: &Cfg<String>, text: &str) -> Vec<(Token, ByteSpan, String)> {
+        let mut found = vec![];
+        let mut start = 0;
+        for word in text.split(' ') {
+            let end = start + word.len();
+            if !word.is_empty() {
+                let token = cfg.token(&word.to_string()).unwrap();
+                found.push((token, ByteSpan { start: start, end: end }, word.to_string()));
+            }
+            start = end + 1;
+        }
+        found
+    }
+
+    #[test]
+    fn read() {
+        let cfg = grammar(EXPRESSIONS);
+        let stream = TokenStream::read(&mut lex(&cfg, "id  +  ( id )").into_iter());
+        assert_eq!(stream.len(), 5);
+        let payloads: Vec<&str> = stream.payloads.iter().map(|p| p.as_slice()).collect();
+        assert_eq!(payloads, vec!["id", "+", "(", "id", ")"]);
+        assert_eq!(stream.span(1), ByteSpan { start: 4, end: 5 });
+        assert_eq!(stream.span(4), ByteSpan { start: 12, end: 13 });
+        assert_eq!(stream.span(5), ByteSpan { start: 13, end: 13 });
+        let empty: TokenStream<String> = TokenStream::read(&mut lex(&cfg, "").into_iter());
+        assert_eq!(empty.len(), 0);
+        assert_eq!(empty.span(0), ByteSpan { start: 0, end: 0 });
+    }
+
+    #[test]
+    fn parse_source() {
+        let cfg = grammar(EXPRESSIONS);
+        let text = "id * ( id + id )";
+        let stream = TokenStream::read(&mut lex(&cfg, text).into_iter());
+        let lr = LrParser::new(&cfg).unwrap();
+        let tree = lr.parse(stream.tokens.as_slice()).unwrap();
+        assert_eq!(lr.parse_source(&mut lex(&cfg, text).into_iter()), Ok(tree.clone()));
+        let forest = earley::parse_source(&cfg, &mut lex(&cfg, text).into_iter()).unwrap();
+        assert_eq!(forest.tree(), tree);
+        let glr = Glr::new(&cfg);
+        assert_eq!(glr.parse_source(&mut lex(&cfg, text).into_iter()).unwrap().tree(), tree);
+        assert!(lr.parse_source(&mut lex(&cfg, "id id").into_iter()).is_err());
+
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let ll1 = Ll1Parser::new(&cfg).unwrap();
+        let input = lex(&cfg, text);
+        let tokens: Vec<Token> = input.iter().map(|&(t, _, _)| t).collect();
+        assert_eq!(ll1.parse_source(&mut input.into_iter()), ll1.parse(tokens.as_slice()));
+    }
+}
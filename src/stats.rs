@@ -0,0 +1,160 @@
+//! Figures describing the size and shape of a grammar.
+
+use std::cmp::max;
+use std::fmt;
+use std::hash::Hash;
+
+use super::{Cfg, Symbol};
+use graph;
+
+/// Figures describing a grammar, found by `Cfg::stats`, for tracking how it changes over time.
+/// They are shown one to a line.
+#[deriving(Clone, PartialEq)]
+pub struct Stats {
+    pub tokens: uint,
+    pub variables: uint,
+    pub rules: uint,
+    /// The number of symbols in the longest rule body.
+    pub max_rule_len: uint,
+    /// The average number of symbols in a rule body, or 0 if there are no rules.
+    pub avg_rule_len: f64,
+    /// The number of rules with an empty body.
+    pub empty_rules: uint,
+    /// The number of variables used by another variable's rules or their own.
+    pub used_variables: uint,
+    /// The number of strongly connected components in the graph of which variables the rules
+    /// of each use, on the longest path through them.
+    pub height: uint,
+    /// The number of variables that derive a sentential form containing themselves.
+    pub recursive_variables: uint,
+    /// The number of variables that derive the empty string.
+    pub nullable_variables: uint
+}
+
+impl fmt::Show for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "tokens: {}", self.tokens));
+        try!(writeln!(f, "variables: {}", self.variables));
+        try!(writeln!(f, "rules: {}", self.rules));
+        try!(writeln!(f, "max rule length: {}", self.max_rule_len));
+        try!(writeln!(f, "average rule length: {:.2}", self.avg_rule_len));
+        try!(writeln!(f, "empty rules: {}", self.empty_rules));
+        try!(writeln!(f, "used variables: {}", self.used_variables));
+        try!(writeln!(f, "height: {}", self.height));
+        try!(writeln!(f, "recursive variables: {}", self.recursive_variables));
+        writeln!(f, "nullable variables: {}", self.nullable_variables)
+    }
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Count the symbols and rules of the grammar and measure its shape.
+    pub fn stats(&self) -> Stats {
+        let n = self.num_variables();
+        let mut rules = 0u;
+        let mut symbols = 0u;
+        let mut max_rule_len = 0u;
+        let mut empty_rules = 0u;
+        let mut used = Vec::from_elem(n, false);
+        let mut edges = Vec::from_fn(n, |_| Vec::new());
+        for (v, rule) in self.rules() {
+            rules += 1;
+            symbols += rule.len();
+            max_rule_len = max(max_rule_len, rule.len());
+            if rule.is_empty() {
+                empty_rules += 1;
+            }
+            for &symbol in rule.iter() {
+                match symbol {
+                    Symbol::Var(w) => {
+                        used[w.index()] = true;
+                        if !edges[v.index()].contains(&w) {
+                            edges[v.index()].push(w);
+                        }
+                    },
+                    Symbol::Tok(_) => { }
+                }
+            }
+        }
+
+        // Each component comes after those it has an edge to, so their heights are known first.
+        let components = graph::components(edges.as_slice());
+        let mut component_of = Vec::from_elem(n, 0u);
+        for (c, component) in components.iter().enumerate() {
+            for &v in component.iter() {
+                component_of[v.index()] = c;
+            }
+        }
+        let mut heights = Vec::from_elem(components.len(), 0u);
+        let mut recursive_variables = 0;
+        for (c, component) in components.iter().enumerate() {
+            let mut height = 0;
+            let mut cyclic = component.len() > 1;
+            for &v in component.iter() {
+                for &w in edges[v.index()].iter() {
+                    if component_of[w.index()] == c {
+                        cyclic = true;
+                    } else {
+                        height = max(height, heights[component_of[w.index()]]);
+                    }
+                }
+            }
+            heights[c] = height + 1;
+            if cyclic {
+                recursive_variables += component.len();
+            }
+        }
+
+        Stats {
+            tokens: self.num_tokens(),
+            variables: n,
+            rules: rules,
+            max_rule_len: max_rule_len,
+            avg_rule_len: if rules == 0 { 0.0 } else { symbols as f64 / rules as f64 },
+            empty_rules: empty_rules,
+            used_variables: used.iter().filter(|&&u| u).count(),
+            height: heights.iter().map(|&h| h).max().unwrap_or(0),
+            recursive_variables: recursive_variables,
+            nullable_variables: self.nullable().len()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Cfg;
+    use testing::{EXPRESSIONS_LL1, grammar};
+
+    #[test]
+    fn expressions() {
+        let stats = grammar(EXPRESSIONS_LL1).stats();
+        assert_eq!(format!("{}", stats).as_slice(), "\
+    tokens: 5
+    variables: 5
+    rules: 8
+    max rule length: 3
+    average rule length: 1.75
+    empty rules: 2
+    used variables: 5
+    height: 1
+    recursive variables: 5
+    nullable variables: 2
+    ");
+    }
+
+    #[test]
+    fn layers() {
+        let stats = grammar("<s> ::= <a> <b>\n<a> ::= \"a\"\n<b> ::= <a> | \"\"").stats();
+        assert_eq!((stats.variables, stats.rules, stats.empty_rules), (3, 4, 1));
+        assert_eq!(stats.used_variables, 2);
+        assert_eq!(stats.height, 3);
+        assert_eq!(stats.recursive_variables, 0);
+        assert_eq!(stats.nullable_variables, 1);
+        assert_eq!(stats.avg_rule_len, 1.0);
+
+        let mut empty = Cfg::<char>::new();
+        empty.add_variable("s");
+        let stats = empty.stats();
+        assert_eq!((stats.rules, stats.max_rule_len, stats.height), (0, 0, 1));
+        assert_eq!(stats.avg_rule_len, 0.0);
+    }
+}
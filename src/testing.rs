@@ -0,0 +1,160 @@
+//! Grammars and helpers shared by the tests of several modules.
+
+use std::collections::BTreeSet;
+
+use super::{Cfg, Rule, Symbol, Token, Variable};
+use tree::ParseTree;
+
+/// The textbook grammar of sums and products of identifiers, which is LALR(1) but, being left
+/// recursive, not LL(1).
+pub const EXPRESSIONS: &'static str = "
+<e> ::= <e> \"+\" <t> | <t>
+<t> ::= <t> \"*\" <f> | <f>
+<f> ::= \"(\" <e> \")\" | \"id\"
+";
+
+/// The same language with the left recursion removed, which is LL(1).
+pub const EXPRESSIONS_LL1: &'static str = "
+<e> ::= <t> <ep>
+<ep> ::= \"+\" <t> <ep> | \"\"
+<t> ::= <f> <tp>
+<tp> ::= \"*\" <f> <tp> | \"\"
+<f> ::= \"(\" <e> \")\" | \"id\"
+";
+
+pub fn grammar(bnf: &str) -> Cfg<String> {
+    Cfg::parse_bnf(bnf).unwrap()
+}
+
+/// The variable with the given name.
+pub fn variable(cfg: &Cfg<String>, name: &str) -> Variable {
+    range(0, cfg.num_variables()).map(|i| Variable(i as u32))
+                                 .find(|&v| cfg.name(v) == Some(name)).unwrap()
+}
+
+/// The rules of a symbol expected to be a variable.
+pub fn rules(cfg: &Cfg<String>, symbol: Symbol) -> &[Rule] {
+    match symbol {
+        Symbol::Var(v) => cfg.get_rules(v).unwrap(),
+        Symbol::Tok(_) => panic!("expected a variable")
+    }
+}
+
+/// The tokens of the terminals in `text`, separated by spaces.
+pub fn tokens(cfg: &Cfg<String>, text: &str) -> Vec<Token> {
+    let terminals: Vec<String> = text.words().map(|w| w.to_string()).collect();
+    cfg.tokenize(terminals.as_slice()).unwrap()
+}
+
+/// Every sentence of at most `max_len` terminals the grammar derives.
+///
+/// This doesn't use any of the parsers, so it can check them, and compares grammars whose
+/// symbols are numbered differently.
+pub fn sentences(cfg: &Cfg<String>, max_len: uint) -> BTreeSet<Vec<String>> {
+    let mut derived: Vec<BTreeSet<Vec<String>>> =
+        Vec::from_fn(cfg.num_variables(), |_| BTreeSet::new());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for v in range(0, cfg.num_variables()) {
+            for rule in cfg.get_rules(Variable(v as u32)).unwrap_or(&[]).iter() {
+                let mut partial: Vec<Vec<String>> = vec![Vec::new()];
+                for &symbol in rule.iter() {
+                    let mut next = Vec::new();
+                    for prefix in partial.iter() {
+                        match symbol {
+                            Symbol::Tok(t) => {
+                                if prefix.len() < max_len {
+                                    let mut sentence = prefix.clone();
+                                    sentence.push(cfg.terminal(t).unwrap().clone());
+                                    next.push(sentence);
+                                }
+                            },
+                            Symbol::Var(w) => {
+                                for suffix in derived[w.index()].iter() {
+                                    if prefix.len() + suffix.len() <= max_len {
+                                        let mut sentence = prefix.clone();
+                                        sentence.push_all(suffix.as_slice());
+                                        next.push(sentence);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    partial = next;
+                }
+                for sentence in partial.into_iter() {
+                    if derived[v].insert(sentence) {
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    derived[cfg.get_start().index()].clone()
+}
+
+/// Small grammars for checking transformations and parsers, with left and right recursion,
+/// empty rules, ambiguity and cycles of unit rules.
+pub const SMALL: &'static [&'static str] = &[
+    EXPRESSIONS,
+    EXPRESSIONS_LL1,
+    "<s> ::= <s> <s> | \"(\" <s> \")\" | \"\"",
+    "<p> ::= \"a\" <p> \"a\" | \"b\" <p> \"b\" | \"a\" | \"b\" | \"\"",
+    "<e> ::= <e> \"+\" <e> | <e> <e> | \"x\"",
+    "<a> ::= <b> | \"x\" <a> | \"\"\n<b> ::= <a> \"y\" | <a>"
+];
+
+/// Every string of at most `max_len` tokens of the grammar, shortest first.
+pub fn strings(cfg: &Cfg<String>, max_len: uint) -> Vec<Vec<Token>> {
+    let all: Vec<Token> = range(0, cfg.num_tokens()).map(|t| Token(t as u32)).collect();
+    let mut strings = vec![Vec::new()];
+    let mut i = 0;
+    while i < strings.len() {
+        if strings[i].len() < max_len {
+            for &t in all.iter() {
+                let mut longer = strings[i].clone();
+                longer.push(t);
+                strings.push(longer);
+            }
+        }
+        i += 1;
+    }
+    strings
+}
+
+/// Check that `tree` derives `input` from the start symbol, using the rules it claims to.
+pub fn check_tree(cfg: &Cfg<String>, tree: &ParseTree, input: &[Token]) {
+    match *tree {
+        ParseTree::Node(v, _, _) => assert_eq!(v, cfg.get_start()),
+        ParseTree::Leaf(_) => panic!("expected the start symbol at the root")
+    }
+    let mut leaves = Vec::new();
+    check_node(cfg, tree, &mut leaves);
+    assert_eq!(leaves.as_slice(), input);
+}
+
+fn check_node(cfg: &Cfg<String>, tree: &ParseTree, leaves: &mut Vec<Token>) {
+    match *tree {
+        ParseTree::Leaf(t) => leaves.push(t),
+        ParseTree::Node(v, rule, ref children) => {
+            let body = &cfg.get_rules(v).unwrap()[rule];
+            assert_eq!(children.len(), body.len());
+            for (child, &symbol) in children.iter().zip(body.iter()) {
+                let found = match *child {
+                    ParseTree::Leaf(t) => Symbol::Tok(t),
+                    ParseTree::Node(w, _, _) => Symbol::Var(w)
+                };
+                assert_eq!(found, symbol);
+                check_node(cfg, child, leaves);
+            }
+        }
+    }
+}
+
+/// Sums and products of the digits 1 to 3, with the shape of `EXPRESSIONS`.
+pub const DIGITS: &'static str = "
+<e> ::= <e> \"+\" <t> | <t>
+<t> ::= <t> \"*\" <f> | <f>
+<f> ::= \"(\" <e> \")\" | \"1\" | \"2\" | \"3\"
+";
@@ -0,0 +1,1208 @@
+//! Transformations producing a new grammar from an existing one.
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecMap};
+use std::hash::Hash;
+use std::mem::replace;
+
+use super::{Cfg, CfgError, Rule, Symbol, SymbolMap, Token, Variable};
+
+/// What `Cfg::remove_useless` removed from a grammar. Symbols are those of the original grammar.
+pub struct UselessReport {
+    /// The variables that can't derive any string of tokens.
+    pub nonproductive: BTreeSet<Variable>,
+    /// The symbols that can't be reached from the start symbol once the nonproductive variables
+    /// are gone.
+    pub unreachable: BTreeSet<Symbol>,
+    /// Every rule removed, with the variable it belonged to.
+    pub rules: Vec<(Variable, Rule)>,
+    /// Where each remaining symbol ended up in the new grammar.
+    pub symbols: SymbolMap
+}
+
+/// Why a transformation introduced a variable.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum Origin {
+    /// The new start symbol, deriving the original one.
+    Start,
+    /// Stands for a token, in rules that can't contain tokens directly.
+    Terminal(Token),
+    /// Derives the given symbols of the new grammar, the tail of a longer rule.
+    Suffix(Rule),
+    /// Derives what can follow the given variable at the start of one of its sentential forms,
+    /// after its left recursion was removed.
+    Tail(Variable),
+    /// Derives the rest of some rules of the given variable, or of a variable introduced for it,
+    /// after a prefix they share.
+    Factor(Variable)
+}
+
+impl<T: Eq + Hash + Clone> Cfg<T> {
+    /// Remove every variable that is nonproductive or unreachable, every token that is
+    /// unreachable, and every rule mentioning any of them.
+    ///
+    /// Nonproductive variables are removed first, since that can make more symbols unreachable.
+    /// The remaining symbols are renumbered in their original order, and the rules kept keep
+    /// their precedences, with the tokens giving them. The start symbol is always kept, so if it
+    /// is nonproductive the result has a start symbol with no rules.
+    pub fn remove_useless(&self) -> (Cfg<T>, UselessReport) {
+        let nonproductive = self.nonproductive_symbols();
+        let mut keep = Vec::new();
+        let mut removed = Vec::new();
+        for v in range(0, self.num_variables()) {
+            let variable = Variable(v as u32);
+            let rules = match self.get_rules(variable) {
+                Some(rules) => rules,
+                None => continue
+            };
+            for (i, rule) in rules.iter().enumerate() {
+                let productive = rule.iter().all(|&s| match s {
+                    Symbol::Var(w) => !nonproductive.contains(&w),
+                    Symbol::Tok(_) => true
+                });
+                if productive && !nonproductive.contains(&variable) {
+                    keep.push((variable, i, rule.clone()));
+                } else {
+                    removed.push((variable, rule.clone()));
+                }
+            }
+        }
+
+        let (variables, tokens) = reachable(self, keep.as_slice(), self.get_start());
+        let mut unreachable = BTreeSet::new();
+        for v in range(0, variables.len()).filter(|&v| !variables[v]) {
+            if !nonproductive.contains(&Variable(v as u32)) {
+                unreachable.insert(Symbol::Var(Variable(v as u32)));
+            }
+        }
+        for t in range(0, tokens.len()).filter(|&t| !tokens[t]) {
+            unreachable.insert(Symbol::Tok(Token(t as u32)));
+        }
+        let mut rules = Vec::new();
+        for (v, i, rule) in keep.into_iter() {
+            if variables[v.index()] {
+                rules.push((v, i, rule));
+            } else {
+                removed.push((v, rule));
+            }
+        }
+
+        let (cfg, symbols) = self.restrict(variables.as_slice(), tokens.as_slice(), rules);
+        let report = UselessReport {
+            nonproductive: nonproductive,
+            unreachable: unreachable,
+            rules: removed,
+            symbols: symbols
+        };
+        (cfg, report)
+    }
+
+    /// The part of the grammar used by `variable`, as a grammar with it as the start symbol.
+    ///
+    /// The variables and tokens that appear in sentential forms derived from `variable` are kept
+    /// with all their rules, and renumbered in their original order; the map gives where each
+    /// ended up.
+    pub fn subgrammar(&self, variable: Variable) -> (Cfg<T>, SymbolMap) {
+        let mut rules = Vec::new();
+        for v in range(0, self.num_variables()) {
+            for (i, rule) in self.get_rules(Variable(v as u32)).unwrap_or(&[]).iter().enumerate() {
+                rules.push((Variable(v as u32), i, rule.clone()));
+            }
+        }
+        let (variables, tokens) = reachable(self, rules.as_slice(), variable);
+        let rules = rules.into_iter().filter(|&(v, _, _)| variables[v.index()]).collect();
+        let (mut cfg, map) = self.restrict(variables.as_slice(), tokens.as_slice(), rules);
+        cfg.start = map.variable(variable).unwrap_or(Variable(0));
+        (cfg, map)
+    }
+
+    /// An equivalent grammar without `variable`, each of its uses replaced by each of its rules.
+    ///
+    /// A rule using the variable more than once is replaced by a rule for every combination of
+    /// its rules, each keeping its precedence, and duplicate rules are dropped. The other
+    /// symbols are renumbered in their original order; the map gives where each ended up. Fails
+    /// if the variable is the start symbol or derives itself.
+    pub fn inline(&self, variable: Variable) -> Result<(Cfg<T>, SymbolMap), CfgError> {
+        try!(self.check_symbol(Symbol::Var(variable)));
+        if variable == self.start {
+            return Err(CfgError::StartVariable { variable: variable })
+        }
+        let mut rules = Vec::new();
+        for v in range(0, self.num_variables()) {
+            for (i, rule) in self.get_rules(Variable(v as u32)).unwrap_or(&[]).iter().enumerate() {
+                rules.push((Variable(v as u32), i, rule.clone()));
+            }
+        }
+        let alternatives = self.get_rules(variable).unwrap_or(&[]);
+        for rule in alternatives.iter() {
+            for &symbol in rule.iter() {
+                let w = match symbol {
+                    Symbol::Var(w) => w,
+                    Symbol::Tok(_) => continue
+                };
+                let (variables, _) = reachable(self, rules.as_slice(), w);
+                if variables[variable.index()] {
+                    return Err(CfgError::RecursiveVariable { variable: variable })
+                }
+            }
+        }
+
+        let mut inlined: Vec<(Variable, uint, Rule)> = Vec::new();
+        for (v, i, rule) in rules.into_iter().filter(|&(v, _, _)| v != variable) {
+            let mut bodies = vec![Vec::new()];
+            for &symbol in rule.iter() {
+                if symbol != Symbol::Var(variable) {
+                    for body in bodies.iter_mut() {
+                        body.push(symbol);
+                    }
+                    continue
+                }
+                let mut next = Vec::new();
+                for body in bodies.iter() {
+                    for alternative in alternatives.iter() {
+                        next.push(concat(body.as_slice(), alternative.as_slice()));
+                    }
+                }
+                bodies = next;
+            }
+            for body in bodies.into_iter() {
+                if !inlined.iter().any(|&(u, _, ref other)| u == v && *other == body) {
+                    inlined.push((v, i, body));
+                }
+            }
+        }
+        let mut variables = Vec::from_elem(self.num_variables(), true);
+        variables[variable.index()] = false;
+        let tokens = Vec::from_elem(self.num_tokens(), true);
+        Ok(self.restrict(variables.as_slice(), tokens.as_slice(), inlined))
+    }
+
+    /// An equivalent grammar with no empty rules, except that it doesn't derive the empty string.
+    ///
+    /// Each rule is replaced by every nonempty rule obtained by leaving out some of the
+    /// occurrences of nullable variables in its body, so `A -> B B` with `B` nullable becomes
+    /// `A -> B B | B`. Variables that derive only the empty string are left with no rules and
+    /// don't appear in any rule body. Symbols keep their numbering. Whether the original grammar
+    /// derives the empty string can be checked with `nullable()`.
+    pub fn eliminate_epsilon(&self) -> Cfg<T> {
+        let nullable = self.nullable();
+        let nonempty = nonempty_flags(self);
+        let mut cfg = self.without_rules();
+        for v in range(0, self.num_variables()) {
+            let rules = match self.get_rules(Variable(v as u32)) {
+                Some(rules) => rules,
+                None => continue
+            };
+            let mut bodies: Vec<Rule> = Vec::new();
+            for rule in rules.iter() {
+                let mut partial = vec![Vec::new()];
+                for &symbol in rule.iter() {
+                    let (optional, present) = match symbol {
+                        Symbol::Var(w) => {
+                            let optional = nullable.contains(&w);
+                            (optional, !optional || nonempty[w.index()])
+                        },
+                        Symbol::Tok(_) => (false, true)
+                    };
+                    if !present {
+                        continue
+                    }
+                    let mut next = Vec::with_capacity(partial.len() * 2);
+                    for body in partial.into_iter() {
+                        if optional {
+                            next.push(body.clone());
+                        }
+                        let mut body = body;
+                        body.push(symbol);
+                        next.push(body);
+                    }
+                    partial = next;
+                }
+                for body in partial.into_iter() {
+                    if !body.is_empty() && !bodies.contains(&body) {
+                        bodies.push(body);
+                    }
+                }
+            }
+            if !bodies.is_empty() {
+                cfg.set_rules(Variable(v as u32), bodies);
+            }
+        }
+        cfg
+    }
+
+    /// An equivalent grammar with no unit rules, those whose body is a single variable.
+    ///
+    /// Each variable gets the other rules of every variable it derives through a chain of unit
+    /// rules, so `A -> B`, `B -> C`, `C -> "c"` leaves `A -> "c"`. Symbols keep their numbering.
+    pub fn eliminate_unit_rules(&self) -> Cfg<T> {
+        let mut cfg = self.without_rules();
+        for v in range(0, self.num_variables()) {
+            let mut chain = vec![Variable(v as u32)];
+            let mut bodies: Vec<Rule> = Vec::new();
+            let mut i = 0;
+            while i < chain.len() {
+                let rules = match self.get_rules(chain[i]) {
+                    Some(rules) => rules,
+                    None => { i += 1; continue }
+                };
+                for rule in rules.iter() {
+                    match unit(rule.as_slice()) {
+                        Some(w) => {
+                            if !chain.contains(&w) {
+                                chain.push(w);
+                            }
+                        },
+                        None => {
+                            if !bodies.contains(rule) {
+                                bodies.push(rule.clone());
+                            }
+                        }
+                    }
+                }
+                i += 1;
+            }
+            if !bodies.is_empty() {
+                cfg.set_rules(Variable(v as u32), bodies);
+            }
+        }
+        cfg
+    }
+
+    /// An equivalent grammar in Chomsky Normal Form, with the reason each new variable was
+    /// introduced.
+    ///
+    /// Every rule of the result has a body of two variables or a single token, except that the
+    /// new start symbol derives the empty string if the original grammar did. The start symbol
+    /// never appears in a rule body. The original variables keep their numbering, though some may
+    /// be left with no rules; `remove_useless` can be used to drop them.
+    pub fn to_cnf(&self) -> (Cfg<T>, VecMap<Origin>) {
+        let mut origins = VecMap::new();
+        let mut augmented = self.copy();
+        let base = self.name(self.start).unwrap_or("start").to_string();
+        let start = FreshNames::new(&augmented).variable(&mut augmented, base.as_slice());
+        if self.start.index() < self.num_variables() {
+            augmented.add_rule(start, vec![Symbol::Var(self.start)]).unwrap();
+        }
+        augmented.start = start;
+        origins.insert(start.index(), Origin::Start);
+
+        let mut cfg = augmented.eliminate_epsilon().eliminate_unit_rules();
+        if self.nullable().contains(&self.start) {
+            cfg.add_rule(start, Vec::new()).unwrap();
+        }
+
+        let mut cnf = cfg.without_rules();
+        let mut fresh = FreshNames::new(&cnf);
+        let mut lifted: VecMap<Variable> = VecMap::new();
+        let mut suffixes: HashMap<Rule, Variable> = HashMap::new();
+        for v in range(0, cfg.num_variables()) {
+            let rules = match cfg.get_rules(Variable(v as u32)) {
+                Some(rules) => rules,
+                None => continue
+            };
+            for rule in rules.iter() {
+                if rule.len() < 2 {
+                    cnf.add_rule(Variable(v as u32), rule.clone()).unwrap();
+                    continue
+                }
+                let mut body = Vec::with_capacity(rule.len());
+                for &symbol in rule.iter() {
+                    let t = match symbol {
+                        Symbol::Tok(t) => t,
+                        Symbol::Var(_) => {
+                            body.push(symbol);
+                            continue
+                        }
+                    };
+                    let existing = lifted.get(&t.index()).map(|&w| w);
+                    let w = match existing {
+                        Some(w) => w,
+                        None => {
+                            let w = fresh.variable(&mut cnf, "token");
+                            cnf.add_rule(w, vec![symbol]).unwrap();
+                            lifted.insert(t.index(), w);
+                            origins.insert(w.index(), Origin::Terminal(t));
+                            w
+                        }
+                    };
+                    body.push(Symbol::Var(w));
+                }
+
+                let base = cfg.name(Variable(v as u32)).unwrap_or("rule").to_string();
+                let mut head = Variable(v as u32);
+                while body.len() > 2 {
+                    let rest = body.slice_from(1).to_vec();
+                    let existing = suffixes.get(&rest).map(|&w| w);
+                    let tail = match existing {
+                        Some(w) => w,
+                        None => fresh.variable(&mut cnf, base.as_slice())
+                    };
+                    cnf.add_rule(head, vec![body[0], Symbol::Var(tail)]).unwrap();
+                    if existing.is_some() {
+                        break
+                    }
+                    suffixes.insert(rest.clone(), tail);
+                    origins.insert(tail.index(), Origin::Suffix(rest.clone()));
+                    head = tail;
+                    body = rest;
+                }
+                if body.len() <= 2 {
+                    cnf.add_rule(head, body).unwrap();
+                }
+            }
+        }
+        (cnf, origins)
+    }
+
+    /// An equivalent grammar in Greibach Normal Form.
+    ///
+    /// Every rule of the result has a body of a token followed by zero or more variables, except
+    /// that the start symbol derives the empty string if the original grammar did. The start
+    /// symbol never appears in a rule body. Useless symbols are removed, and the variables are
+    /// renumbered.
+    pub fn to_gnf(&self) -> Cfg<T> {
+        let (reduced, _) = self.eliminate_epsilon().eliminate_unit_rules().remove_useless();
+        let mut g = reduced.without_rules();
+        let base = reduced.name(reduced.start).unwrap_or("start").to_string();
+        let mut fresh = FreshNames::new(&g);
+        let start = fresh.variable(&mut g, base.as_slice());
+        g.start = start;
+        let mut rules: Vec<Vec<Rule>> = range(0, reduced.num_variables()).map(|v| {
+            reduced.get_rules(Variable(v as u32)).map_or(Vec::new(), |r| r.to_vec())
+        }).collect();
+        // The new start symbol gets the rules of the old one at the end.
+        rules.push(Vec::new());
+
+        // Order the variables, and make each rule of a variable start with a token or a later
+        // variable, introducing tail variables to remove direct left recursion.
+        let n = reduced.num_variables();
+        for i in range(0, n) {
+            for j in range(0, i) {
+                let old = replace(&mut rules[i], Vec::new());
+                let mut new = Vec::new();
+                for rule in old.into_iter() {
+                    if rule[0] == Symbol::Var(Variable(j as u32)) {
+                        for prefix in rules[j].iter() {
+                            push_unique(&mut new, concat(prefix.as_slice(), rule.slice_from(1)));
+                        }
+                    } else {
+                        push_unique(&mut new, rule);
+                    }
+                }
+                rules[i] = new;
+            }
+
+            let (recursive, other) = replace(&mut rules[i], Vec::new()).partition(|rule| {
+                rule[0] == Symbol::Var(Variable(i as u32))
+            });
+            if recursive.is_empty() {
+                rules[i] = other;
+                continue
+            }
+            let base = g.name(Variable(i as u32)).unwrap_or("rule").to_string();
+            let tail = Symbol::Var(fresh.variable(&mut g, base.as_slice()));
+            let mut heads = Vec::new();
+            for rule in other.into_iter() {
+                push_unique(&mut heads, concat(rule.as_slice(), &[tail]));
+                push_unique(&mut heads, rule);
+            }
+            let mut tails = Vec::new();
+            for rule in recursive.iter() {
+                push_unique(&mut tails, concat(rule.slice_from(1), &[tail]));
+                push_unique(&mut tails, rule.slice_from(1).to_vec());
+            }
+            rules[i] = heads;
+            rules.push(tails);
+        }
+
+        // Each rule of the last variable now starts with a token, so substituting backwards
+        // makes every rule of an ordered variable start with one. A tail variable's rules start
+        // with a token, an ordered variable, or the tail of an earlier variable.
+        for i in range(0, n).rev().chain(range(n + 1, rules.len())) {
+            let old = replace(&mut rules[i], Vec::new());
+            let mut new = Vec::new();
+            for rule in old.into_iter() {
+                match rule[0] {
+                    Symbol::Var(w) => {
+                        for prefix in rules[w.index()].iter() {
+                            push_unique(&mut new, concat(prefix.as_slice(), rule.slice_from(1)));
+                        }
+                    },
+                    Symbol::Tok(_) => push_unique(&mut new, rule)
+                }
+            }
+            rules[i] = new;
+        }
+
+        // Replace the tokens after the first symbol of a rule with variables deriving them.
+        let mut lifted: VecMap<Variable> = VecMap::new();
+        for i in range(0, rules.len()) {
+            for j in range(0, rules[i].len()) {
+                for k in range(1, rules[i][j].len()) {
+                    let t = match rules[i][j][k] {
+                        Symbol::Tok(t) => t,
+                        Symbol::Var(_) => continue
+                    };
+                    let existing = lifted.get(&t.index()).map(|&w| w);
+                    let w = match existing {
+                        Some(w) => w,
+                        None => {
+                            let w = fresh.variable(&mut g, "token");
+                            g.add_rule(w, vec![Symbol::Tok(t)]).unwrap();
+                            lifted.insert(t.index(), w);
+                            w
+                        }
+                    };
+                    rules[i][j][k] = Symbol::Var(w);
+                }
+            }
+        }
+
+        if reduced.start.index() < n {
+            rules[start.index()] = rules[reduced.start.index()].clone();
+        }
+        for (v, bodies) in rules.into_iter().enumerate() {
+            for body in bodies.into_iter() {
+                g.add_rule(Variable(v as u32), body).unwrap();
+            }
+        }
+        if self.nullable().contains(&self.start) {
+            g.add_rule(start, Vec::new()).unwrap();
+        }
+        g.remove_useless().0
+    }
+
+    /// An equivalent grammar without left recursion, using Paull's algorithm, with the reason each
+    /// new variable was introduced.
+    ///
+    /// The left recursive variables are ordered by index. Each has the rules of earlier ones
+    /// substituted into those of its rules beginning with them, then its direct left recursion
+    /// `A -> A x | y` is replaced by `A -> y A_tail`, `A_tail -> x A_tail | ε`. Rules `A -> A`
+    /// are dropped. Variables keep their numbering and other variables are left alone.
+    ///
+    /// Left recursion hidden behind a nullable prefix isn't removed, so the result is only
+    /// guaranteed to be free of left recursion if the grammar has no empty rules.
+    pub fn eliminate_left_recursion(&self) -> (Cfg<T>, VecMap<Origin>) {
+        let mut cfg = self.copy();
+        let mut fresh = FreshNames::new(&cfg);
+        let mut origins = VecMap::new();
+        let recursive: Vec<Variable> = self.left_recursion().iter().map(|l| l.variable)
+                                                              .collect();
+        for (i, &a) in recursive.iter().enumerate() {
+            let mut rules = cfg.take_rules(a);
+            for &b in recursive.slice_to(i).iter() {
+                let old = replace(&mut rules, Vec::new());
+                for rule in old.into_iter() {
+                    if rule.len() > 0 && rule[0] == Symbol::Var(b) {
+                        for prefix in cfg.get_rules(b).unwrap_or(&[]).iter() {
+                            push_unique(&mut rules, concat(prefix.as_slice(), rule.slice_from(1)));
+                        }
+                    } else {
+                        push_unique(&mut rules, rule);
+                    }
+                }
+            }
+
+            let (left, other) = rules.partition(|rule| {
+                rule.len() > 0 && rule[0] == Symbol::Var(a)
+            });
+            if left.is_empty() {
+                cfg.set_rules(a, other);
+                continue
+            }
+            let base = format!("{}_tail", self.name(a).unwrap_or("rule"));
+            let tail = fresh.variable(&mut cfg, base.as_slice());
+            origins.insert(tail.index(), Origin::Tail(a));
+            let heads: Vec<Rule> = other.iter().map(|rule| {
+                concat(rule.as_slice(), &[Symbol::Var(tail)])
+            }).collect();
+            let mut tails: Vec<Rule> = left.iter().filter(|rule| rule.len() > 1).map(|rule| {
+                concat(rule.slice_from(1), &[Symbol::Var(tail)])
+            }).collect();
+            tails.push(Vec::new());
+            if !heads.is_empty() {
+                cfg.set_rules(a, heads);
+            }
+            cfg.set_rules(tail, tails);
+        }
+        (cfg, origins)
+    }
+
+    /// An equivalent grammar where no two rules of a variable begin with the same symbol, with the
+    /// reason each new variable was introduced.
+    ///
+    /// Rules sharing a first symbol are replaced by their longest common prefix followed by a new
+    /// variable deriving the rest of each, so `A -> x y | x z` becomes `A -> x A_factor`,
+    /// `A_factor -> y | z`. This is repeated for the new variables until nothing changes.
+    /// Variables keep their numbering.
+    pub fn left_factor(&self) -> (Cfg<T>, VecMap<Origin>) {
+        let mut cfg = self.copy();
+        let mut fresh = FreshNames::new(&cfg);
+        let mut origins = VecMap::new();
+        let mut pending: Vec<Variable> = range(0, self.num_variables()).rev()
+                                                                       .map(|v| Variable(v as u32))
+                                                                       .collect();
+        loop {
+            let v = match pending.pop() {
+                Some(v) => v,
+                None => break
+            };
+            let mut rules = Vec::new();
+            for rule in cfg.take_rules(v).into_iter() {
+                push_unique(&mut rules, rule);
+            }
+            let root = match origins.get(&v.index()) {
+                Some(&Origin::Factor(root)) => root,
+                _ => v
+            };
+            let mut i = 0;
+            while i < rules.len() {
+                if rules[i].is_empty() {
+                    i += 1;
+                    continue
+                }
+                let first = rules[i][0];
+                let (group, rest) = rules.partition(|rule| rule.len() > 0 && rule[0] == first);
+                if group.len() == 1 {
+                    rules = rest;
+                    rules.insert(i, group.into_iter().next().unwrap());
+                    i += 1;
+                    continue
+                }
+                let mut len = 1;
+                while group.iter().all(|rule| rule.len() > len && rule[len] == group[0][len]) {
+                    len += 1;
+                }
+                let base = format!("{}_factor", cfg.name(root).unwrap_or("rule"));
+                let factor = fresh.variable(&mut cfg, base.as_slice());
+                origins.insert(factor.index(), Origin::Factor(root));
+                let mut suffixes = Vec::new();
+                for rule in group.iter() {
+                    push_unique(&mut suffixes, rule.slice_from(len).to_vec());
+                }
+                cfg.set_rules(factor, suffixes);
+                pending.push(factor);
+                rules = rest;
+                rules.insert(i, concat(group[0].slice_to(len), &[Symbol::Var(factor)]));
+                i += 1;
+            }
+            if !rules.is_empty() {
+                cfg.set_rules(v, rules);
+            }
+        }
+        (cfg, origins)
+    }
+
+    /// A grammar with the same symbols, start symbol and token precedences as this one, but no
+    /// rules.
+    fn without_rules(&self) -> Cfg<T> {
+        Cfg {
+            rules: VecMap::new(),
+            terminals: self.terminals.clone(),
+            token_map: self.token_map.clone(),
+            variable_names: self.variable_names.clone(),
+            variables: self.variables,
+            start: self.start,
+            precedence: self.precedence.clone(),
+            rule_precedence: HashMap::new(),
+            rule_ids: VecMap::new(),
+            next_rule_id: 0
+        }
+    }
+
+    /// A grammar with the same symbols, rules, start symbol and token precedences as this one.
+    fn copy(&self) -> Cfg<T> {
+        let mut cfg = self.without_rules();
+        for (v, rules) in self.rules.iter() {
+            cfg.set_rules(Variable(v as u32), rules.clone());
+        }
+        cfg
+    }
+
+    /// Build a grammar from the given variables and tokens, by index, and rules of this grammar,
+    /// renumbering the symbols kept in their original order. Each rule comes with the index of
+    /// the rule of this grammar it was made from, whose precedence it keeps.
+    fn restrict(&self, variables: &[bool], tokens: &[bool], rules: Vec<(Variable, uint, Rule)>)
+                -> (Cfg<T>, SymbolMap) {
+        let mut cfg = Cfg::new();
+        let mut map = SymbolMap::new();
+        for t in range(0, tokens.len()).filter(|&t| tokens[t]) {
+            let new = cfg.add_token(self.terminals[t].clone());
+            map.insert_token(Token(t as u32), new);
+            match self.precedence.get(&t) {
+                Some(&p) => { cfg.precedence.insert(new.index(), p); },
+                None => { }
+            }
+        }
+        for v in range(0, variables.len()).filter(|&v| variables[v]) {
+            let new = Variable(cfg.variables);
+            cfg.variables += 1;
+            match self.variable_names.get(&v) {
+                Some(name) => { cfg.variable_names.insert(new.index(), name.clone()); },
+                None => { }
+            }
+            map.insert_variable(Variable(v as u32), new);
+        }
+        for (v, i, rule) in rules.into_iter() {
+            let new = map.variable(v).unwrap();
+            let body = rule.iter().map(|&s| map.symbol(s).unwrap()).collect();
+            cfg.add_rule(new, body).unwrap();
+            match self.rule_precedence_token(v, i).and_then(|t| map.token(t)) {
+                Some(t) => {
+                    let r = cfg.get_rules(new).unwrap().len() - 1;
+                    cfg.set_rule_precedence(new, r, t).unwrap();
+                },
+                None => { }
+            }
+        }
+        cfg.start = map.variable(self.get_start()).unwrap_or(Variable(0));
+        (cfg, map)
+    }
+}
+
+impl<T: Ord + Eq + Hash + Clone> Cfg<T> {
+    /// The same grammar with its symbols renumbered and its rules sorted in a fixed order, so
+    /// that grammars differing only in how they were numbered come out the same, and are printed
+    /// the same.
+    ///
+    /// Tokens are numbered in order of their terminals. Variables are numbered breadth-first
+    /// from the start symbol, through the rules of each in order, and then the variables not
+    /// reached, in order of their names; variables are only told apart by their numbering where
+    /// they have the same names and are reached in the same way. The rules of each variable are
+    /// sorted by their renumbered bodies, with tokens before variables. Precedences are kept.
+    pub fn canonicalize(&self) -> (Cfg<T>, SymbolMap) {
+        let mut cfg = Cfg::new();
+        let mut map = SymbolMap::new();
+        let mut tokens: Vec<uint> = range(0, self.num_tokens()).collect();
+        tokens.sort_by(|&a, &b| self.terminals[a].cmp(&self.terminals[b]));
+        for &t in tokens.iter() {
+            let new = cfg.add_token(self.terminals[t].clone());
+            map.insert_token(Token(t as u32), new);
+            match self.precedence.get(&t) {
+                Some(&p) => { cfg.precedence.insert(new.index(), p); },
+                None => { }
+            }
+        }
+
+        let n = self.num_variables();
+        let mut order: Vec<Variable> = Vec::with_capacity(n);
+        if n > 0 {
+            map.insert_variable(self.get_start(), Variable(0));
+            order.push(self.get_start());
+        }
+        let mut unreached: Vec<Variable> = range(0, n).map(|v| Variable(v as u32)).collect();
+        unreached.sort_by(|&a, &b| self.name(a).cmp(&self.name(b)));
+        unreached.reverse();
+        let mut next = 0;
+        while order.len() < n {
+            if next == order.len() {
+                // Nothing reached is left to visit, so start from the first variable by name.
+                let v = unreached.pop().unwrap();
+                if !order.contains(&v) {
+                    map.insert_variable(v, Variable(order.len() as u32));
+                    order.push(v);
+                }
+                continue
+            }
+            let v = order[next];
+            next += 1;
+            for (_, body) in self.sorted_rules(v, &map).into_iter() {
+                for &symbol in body.iter() {
+                    match symbol {
+                        Symbol::Var(w) if map.variable(w).is_none() => {
+                            map.insert_variable(w, Variable(order.len() as u32));
+                            order.push(w);
+                        },
+                        _ => { }
+                    }
+                }
+            }
+        }
+
+        for &v in order.iter() {
+            let new = Variable(cfg.variables);
+            cfg.variables += 1;
+            match self.variable_names.get(&v.index()) {
+                Some(name) => { cfg.variable_names.insert(new.index(), name.clone()); },
+                None => { }
+            }
+        }
+        for &v in order.iter() {
+            let new = map.variable(v).unwrap();
+            // Every symbol is numbered now, so the rules are sorted by their new bodies.
+            for (r, (i, body)) in self.sorted_rules(v, &map).into_iter().enumerate() {
+                cfg.add_rule(new, body.iter().map(|&s| map.symbol(s).unwrap()).collect()).unwrap();
+                match self.rule_precedence_token(v, i) {
+                    Some(t) => {
+                        cfg.set_rule_precedence(new, r, map.token(t).unwrap()).unwrap();
+                    },
+                    None => { }
+                }
+            }
+        }
+        cfg.start = map.variable(self.get_start()).unwrap_or(Variable(0));
+        (cfg, map)
+    }
+
+    /// The rules of `v` with their indices, sorted by their bodies with the symbols numbered so
+    /// far by `map` renumbered, and the other variables in order of their names.
+    fn sorted_rules(&self, v: Variable, map: &SymbolMap) -> Vec<(uint, &Rule)> {
+        let key = |body: &Rule| -> Vec<(uint, uint, Option<&str>)> {
+            body.iter().map(|&s| match s {
+                Symbol::Tok(t) => (0, map.token(t).unwrap().index(), None),
+                Symbol::Var(w) => match map.variable(w) {
+                    Some(new) => (1, new.index(), None),
+                    None => (2, 0, self.name(w))
+                }
+            }).collect()
+        };
+        let mut rules: Vec<(uint, &Rule)> = self.get_rules(v).unwrap_or(&[]).iter().enumerate()
+                                                                           .collect();
+        rules.sort_by(|&(_, a), &(_, b)| key(a).cmp(&key(b)));
+        rules
+    }
+}
+
+/// Add `rule` to `rules` if it isn't already there.
+/// The variable names used in a grammar a transformation is adding variables to, so a fresh
+/// name can be found without searching them all each time.
+struct FreshNames {
+    taken: HashSet<String>
+}
+
+impl FreshNames {
+    fn new<T: Eq + Hash + Clone>(cfg: &Cfg<T>) -> FreshNames {
+        FreshNames { taken: cfg.variable_names.values().map(|name| name.clone()).collect() }
+    }
+
+    /// Allocate a new variable named `base_N`, with a number `N` no other variable's name uses.
+    fn variable<T: Eq + Hash + Clone>(&mut self, cfg: &mut Cfg<T>, base: &str) -> Variable {
+        let mut n = cfg.num_variables();
+        loop {
+            let name = format!("{}_{}", base, n);
+            if !self.taken.contains(&name) {
+                let v = cfg.add_variable(name.as_slice());
+                self.taken.insert(name);
+                return v
+            }
+            n += 1;
+        }
+    }
+}
+
+fn push_unique(rules: &mut Vec<Rule>, rule: Rule) {
+    if !rules.contains(&rule) {
+        rules.push(rule);
+    }
+}
+
+/// The concatenation of two strings of symbols.
+fn concat(a: &[Symbol], b: &[Symbol]) -> Rule {
+    let mut rule = a.to_vec();
+    rule.push_all(b);
+    rule
+}
+
+/// The variable a unit rule's body consists of, if it is one.
+fn unit(body: &[Symbol]) -> Option<Variable> {
+    match body {
+        [Symbol::Var(v)] => Some(v),
+        _ => None
+    }
+}
+
+/// Whether each variable of `cfg`, by index, derives some nonempty string of tokens. A variable
+/// with no rules counts as deriving one, so its occurrences are treated like those of a token.
+fn nonempty_flags<T: Eq + Hash + Clone>(cfg: &Cfg<T>) -> Vec<bool> {
+    let nonproductive = cfg.nonproductive_symbols();
+    let n = cfg.num_variables();
+    let mut nonempty = Vec::from_elem(n, false);
+    for v in range(0, n).filter(|&v| cfg.get_rules(Variable(v as u32)).is_none()) {
+        nonempty[v] = true;
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for v in range(0, n) {
+            if nonempty[v] {
+                continue
+            }
+            let rules = match cfg.get_rules(Variable(v as u32)) {
+                Some(rules) => rules,
+                None => continue
+            };
+            let derives = rules.iter().any(|rule| {
+                rule.iter().all(|&s| match s {
+                    Symbol::Var(w) => !nonproductive.contains(&w),
+                    Symbol::Tok(_) => true
+                }) && rule.iter().any(|&s| match s {
+                    Symbol::Var(w) => nonempty[w.index()],
+                    Symbol::Tok(_) => true
+                })
+            });
+            if derives {
+                nonempty[v] = true;
+                changed = true;
+            }
+        }
+    }
+    nonempty
+}
+
+/// Whether each variable and each token of `cfg`, by index, can be reached from `root` using only
+/// `rules`, each given with its index in `cfg`. The root itself always counts as reachable, and
+/// so does the token giving the precedence of a rule that's reached.
+fn reachable<T: Eq + Hash + Clone>(cfg: &Cfg<T>, rules: &[(Variable, uint, Rule)],
+                                   root: Variable) -> (Vec<bool>, Vec<bool>) {
+    let mut variables = Vec::from_elem(cfg.num_variables(), false);
+    let mut tokens = Vec::from_elem(cfg.num_tokens(), false);
+    if root.index() >= variables.len() {
+        return (variables, tokens)
+    }
+    variables[root.index()] = true;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &(v, i, ref rule) in rules.iter() {
+            if !variables[v.index()] {
+                continue
+            }
+            match cfg.rule_precedence_token(v, i) {
+                Some(t) => tokens[t.index()] = true,
+                None => { }
+            }
+            for &symbol in rule.iter() {
+                match symbol {
+                    Symbol::Tok(t) => tokens[t.index()] = true,
+                    Symbol::Var(w) => {
+                        if !variables[w.index()] {
+                            variables[w.index()] = true;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    (variables, tokens)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::Origin;
+    use super::super::{Associativity, Cfg, CfgError, Symbol, Token, Variable};
+    use testing::{EXPRESSIONS, SMALL, grammar, rules, sentences, variable};
+
+    fn token(cfg: &Cfg<String>, terminal: &str) -> Token {
+        cfg.token(&terminal.to_string()).unwrap()
+    }
+
+    #[test]
+    fn remove_useless() {
+        let cfg = grammar("
+            <s> ::= <a> \"x\" | <b> | \"y\"
+            <a> ::= \"a\"
+            <b> ::= <b> \"b\"
+            <c> ::= \"c\"
+        ");
+        let (s, a, b, c) = (variable(&cfg, "s"), variable(&cfg, "a"), variable(&cfg, "b"),
+                            variable(&cfg, "c"));
+        let (useless, report) = cfg.remove_useless();
+
+        let nonproductive: Vec<Variable> = report.nonproductive.iter().map(|&v| v).collect();
+        assert_eq!(nonproductive, vec![b]);
+        assert_eq!(report.unreachable.len(), 3);
+        assert!(report.unreachable.contains(&Symbol::Var(c)));
+        assert!(report.unreachable.contains(&Symbol::Tok(token(&cfg, "b"))));
+        assert!(report.unreachable.contains(&Symbol::Tok(token(&cfg, "c"))));
+        assert_eq!(report.rules.len(), 3);
+        assert!(report.rules.contains(&(s, vec![Symbol::Var(b)])));
+        assert!(report.rules.contains(&(b, vec![Symbol::Var(b), Symbol::Tok(token(&cfg, "b"))])));
+        assert!(report.rules.contains(&(c, vec![Symbol::Tok(token(&cfg, "c"))])));
+
+        assert_eq!(useless.num_variables(), 2);
+        assert_eq!(useless.num_tokens(), 3);
+        assert_eq!(report.symbols.variable(s), Some(useless.get_start()));
+        assert!(report.symbols.variable(a).is_some());
+        assert_eq!(report.symbols.variable(b), None);
+        assert_eq!(report.symbols.variable(c), None);
+        let x = report.symbols.token(token(&cfg, "x")).unwrap();
+        assert_eq!(useless.terminal(x), Some(&"x".to_string()));
+        assert_eq!(report.symbols.token(token(&cfg, "c")), None);
+        assert_eq!(sentences(&useless, 4), sentences(&cfg, 4));
+    }
+
+    #[test]
+    fn remove_useless_start() {
+        let cfg = grammar("<s> ::= <s> \"x\"");
+        let (useless, report) = cfg.remove_useless();
+        assert_eq!(useless.num_variables(), 1);
+        assert_eq!(useless.get_rules(useless.get_start()), None);
+        assert_eq!(report.symbols.token(Token(0)), None);
+    }
+
+    #[test]
+    fn remove_useless_keeps_precedence() {
+        let mut cfg = grammar("
+            <e> ::= <x> | <e> \"-\" <e> | \"-\" <e> | \"n\"
+            <x> ::= <x> \"?\"
+        ");
+        let uminus = cfg.add_token("uminus".to_string());
+        let minus = token(&cfg, "-");
+        cfg.set_precedence(minus, 1, Associativity::Left).unwrap();
+        cfg.set_precedence(uminus, 2, Associativity::Right).unwrap();
+        cfg.set_rule_precedence(variable(&cfg, "e"), 2, uminus).unwrap();
+
+        let (out, report) = cfg.remove_useless();
+        assert_eq!(report.rules.len(), 2);
+        let e = variable(&out, "e");
+        assert_eq!(out.get_rules(e).unwrap().len(), 3);
+        assert!(out.token(&"uminus".to_string()).is_some());
+        assert_eq!(out.rule_precedence(e, 0), Some((1, Associativity::Left)));
+        assert_eq!(out.rule_precedence(e, 1), Some((2, Associativity::Right)));
+        assert_eq!(out.rule_precedence(e, 2), None);
+        assert_eq!(sentences(&out, 4), sentences(&cfg, 4));
+    }
+
+    #[test]
+    fn inline_and_subgrammar_keep_precedence() {
+        let mut cfg = grammar("
+            <s> ::= <e>
+            <e> ::= <e> \"-\" <e> | \"-\" <e> | <n>
+            <n> ::= \"n\"
+        ");
+        let uminus = cfg.add_token("uminus".to_string());
+        cfg.set_precedence(uminus, 2, Associativity::Right).unwrap();
+        cfg.set_rule_precedence(variable(&cfg, "e"), 1, uminus).unwrap();
+
+        let (inlined, _) = cfg.inline(variable(&cfg, "n")).unwrap();
+        let e = variable(&inlined, "e");
+        assert_eq!(inlined.rule_precedence(e, 1), Some((2, Associativity::Right)));
+        assert_eq!(sentences(&inlined, 4), sentences(&cfg, 4));
+
+        let (sub, _) = cfg.subgrammar(variable(&cfg, "e"));
+        let e = variable(&sub, "e");
+        assert_eq!(sub.get_start(), e);
+        assert_eq!(sub.rule_precedence(e, 1), Some((2, Associativity::Right)));
+        assert!(sub.token(&"uminus".to_string()).is_some());
+    }
+
+    #[test]
+    fn eliminate_epsilon() {
+        let cfg = grammar("
+            <s> ::= <a> <a> \"x\" | <a> | <z> \"y\"
+            <a> ::= \"a\" | \"\"
+            <z> ::= \"\"
+        ");
+        let (s, a, z) = (variable(&cfg, "s"), variable(&cfg, "a"), variable(&cfg, "z"));
+        let nonempty = cfg.eliminate_epsilon();
+        assert_eq!(nonempty.num_variables(), cfg.num_variables());
+        assert_eq!(rules(&nonempty, Symbol::Var(a)).len(), 1);
+        assert_eq!(nonempty.get_rules(z), None);
+        let bodies = rules(&nonempty, Symbol::Var(s));
+        assert_eq!(bodies.len(), 5);
+        assert!(bodies.iter().all(|rule| !rule.is_empty() && !rule.contains(&Symbol::Var(z))));
+        assert!(bodies.contains(&vec![Symbol::Var(a)]));
+        assert!(bodies.contains(&vec![Symbol::Tok(token(&cfg, "y"))]));
+
+        let mut expected = sentences(&cfg, 4);
+        expected.remove(&Vec::new());
+        assert_eq!(sentences(&nonempty, 4), expected);
+    }
+
+    #[test]
+    fn eliminate_unit_rules() {
+        let cfg = grammar("
+            <s> ::= <a> | <b> \"x\"
+            <a> ::= <b> | \"a\"
+            <b> ::= <a> | \"b\"
+        ");
+        let (s, a, b) = (variable(&cfg, "s"), variable(&cfg, "a"), variable(&cfg, "b"));
+        let (ta, tb, tx) = (Symbol::Tok(token(&cfg, "a")), Symbol::Tok(token(&cfg, "b")),
+                            Symbol::Tok(token(&cfg, "x")));
+        let direct = cfg.eliminate_unit_rules();
+        let bodies = rules(&direct, Symbol::Var(s));
+        assert_eq!(bodies.len(), 3);
+        assert!(bodies.contains(&vec![Symbol::Var(b), tx]));
+        assert!(bodies.contains(&vec![ta]));
+        assert!(bodies.contains(&vec![tb]));
+        for &v in [a, b].iter() {
+            let bodies = rules(&direct, Symbol::Var(v));
+            assert_eq!(bodies.len(), 2);
+            assert!(bodies.contains(&vec![ta]) && bodies.contains(&vec![tb]));
+        }
+        assert_eq!(sentences(&direct, 4), sentences(&cfg, 4));
+    }
+
+    #[test]
+    fn cnf() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let (cnf, origins) = cfg.to_cnf();
+            assert_eq!(sentences(&cnf, 5), sentences(&cfg, 5));
+            let start = cnf.get_start();
+            assert_eq!(origins.get(&start.index()), Some(&Origin::Start));
+            for v in range(0, cnf.num_variables()).map(|v| Variable(v as u32)) {
+                let bodies = cnf.get_rules(v).unwrap_or(&[]);
+                match origins.get(&v.index()) {
+                    Some(&Origin::Terminal(t)) => {
+                        assert_eq!(bodies.len(), 1);
+                        assert_eq!(bodies[0], vec![Symbol::Tok(t)]);
+                    },
+                    _ => { }
+                }
+                for rule in bodies.iter() {
+                    match rule.as_slice() {
+                        [Symbol::Var(b), Symbol::Var(c)] => assert!(b != start && c != start),
+                        [Symbol::Tok(_)] => { },
+                        [] => assert_eq!(v, start),
+                        _ => panic!("rule not in Chomsky Normal Form: {}", rule)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gnf() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let gnf = cfg.to_gnf();
+            assert_eq!(sentences(&gnf, 5), sentences(&cfg, 5));
+            let start = gnf.get_start();
+            for v in range(0, gnf.num_variables()).map(|v| Variable(v as u32)) {
+                for rule in gnf.get_rules(v).unwrap_or(&[]).iter() {
+                    match rule.as_slice() {
+                        [] => assert_eq!(v, start),
+                        [Symbol::Tok(_), rest..] => {
+                            assert!(rest.iter().all(|&s| match s {
+                                Symbol::Var(w) => w != start,
+                                Symbol::Tok(_) => false
+                            }));
+                        },
+                        _ => panic!("rule not in Greibach Normal Form: {}", rule)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn left_recursion() {
+        let sources = [
+            EXPRESSIONS,
+            "<e> ::= <e> \"+\" <e> | <e> <e> | \"x\"",
+            "<a> ::= <b> \"x\" | \"y\"\n<b> ::= <a> \"z\" | \"w\""
+        ];
+        for src in sources.iter() {
+            let cfg = grammar(*src);
+            assert!(!cfg.left_recursion().is_empty());
+            let (out, origins) = cfg.eliminate_left_recursion();
+            assert!(out.left_recursion().is_empty());
+            assert_eq!(sentences(&out, 6), sentences(&cfg, 6));
+            for (v, origin) in origins.iter() {
+                assert!(v >= cfg.num_variables());
+                match *origin {
+                    Origin::Tail(w) => assert!(w.index() < cfg.num_variables()),
+                    _ => { }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn left_factor() {
+        for src in SMALL.iter() {
+            let cfg = grammar(*src);
+            let (out, _) = cfg.left_factor();
+            assert_eq!(sentences(&out, 5), sentences(&cfg, 5));
+            for v in range(0, out.num_variables()).map(|v| Variable(v as u32)) {
+                let rules = out.get_rules(v).unwrap_or(&[]);
+                for (i, rule) in rules.iter().enumerate() {
+                    assert!(rule.is_empty() || rules.slice_from(i + 1).iter().all(|other| {
+                        other.get(0) != rule.get(0)
+                    }));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn subgrammar() {
+        let cfg = grammar("
+            <s> ::= <a> \"x\" | <b>
+            <a> ::= \"a\" <a> | <c>
+            <b> ::= \"b\"
+            <c> ::= \"c\"
+        ");
+        let (s, a, b, c) = (variable(&cfg, "s"), variable(&cfg, "a"), variable(&cfg, "b"),
+                            variable(&cfg, "c"));
+        let (sub, map) = cfg.subgrammar(a);
+        assert_eq!(sub.num_variables(), 2);
+        assert_eq!(sub.num_tokens(), 2);
+        assert_eq!(map.variable(a), Some(sub.get_start()));
+        assert!(map.variable(c).is_some());
+        assert_eq!(map.variable(s), None);
+        assert_eq!(map.variable(b), None);
+        assert_eq!(map.token(token(&cfg, "x")), None);
+        let expected: BTreeSet<Vec<String>> = ["c", "a c", "a a c"].iter().map(|sentence| {
+            sentence.words().map(|w| w.to_string()).collect()
+        }).collect();
+        assert_eq!(sentences(&sub, 3), expected);
+    }
+
+    #[test]
+    fn inline() {
+        let cfg = grammar("
+            <s> ::= <n> \"+\" <n> | <n>
+            <n> ::= \"x\" | \"y\"
+        ");
+        let (s, n) = (variable(&cfg, "s"), variable(&cfg, "n"));
+        let (inlined, map) = cfg.inline(n).unwrap();
+        assert_eq!(inlined.num_variables(), 1);
+        assert_eq!(map.variable(n), None);
+        let bodies = rules(&inlined, Symbol::Var(map.variable(s).unwrap()));
+        assert_eq!(bodies.len(), 6);
+        assert!(bodies.iter().all(|rule| rule.iter().all(|symbol| match *symbol {
+            Symbol::Tok(_) => true,
+            Symbol::Var(_) => false
+        })));
+        assert_eq!(sentences(&inlined, 3), sentences(&cfg, 3));
+
+        assert_eq!(cfg.inline(s).err(), Some(CfgError::StartVariable { variable: s }));
+        let cfg = grammar("
+            <s> ::= <r>
+            <r> ::= \"a\" <r> | \"b\"
+        ");
+        let r = variable(&cfg, "r");
+        assert_eq!(cfg.inline(r).err(), Some(CfgError::RecursiveVariable { variable: r }));
+    }
+
+    #[test]
+    fn canonicalize() {
+        let first = grammar("
+            <s> ::= <a> <b>
+            <a> ::= \"x\"
+            <b> ::= \"x\" | \"y\"
+        ");
+        let second = grammar("
+            <s> ::= <a> <b>
+            <b> ::= \"y\" | \"x\"
+            <a> ::= \"x\"
+        ");
+        assert!(first.token(&"y".to_string()) != second.token(&"y".to_string()));
+        let (canonical, map) = second.canonicalize();
+        let expected = "\
+    <s> ::= <a> <b>
+    <a> ::= \"x\"
+    <b> ::= \"x\" | \"y\"
+    ";
+        assert_eq!(canonical.to_bnf().as_slice(), expected);
+        let (canonical_first, _) = first.canonicalize();
+        assert_eq!(canonical_first.to_bnf().as_slice(), expected);
+        assert_eq!(map.variable(variable(&second, "s")), Some(canonical.get_start()));
+        assert_eq!(map.token(token(&second, "x")), Some(token(&canonical, "x")));
+    }
+}
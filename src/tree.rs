@@ -0,0 +1,324 @@
+//! Parse trees.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use super::{Symbol, Token, Variable};
+use actions::{AstBuilder, FromParse, Reducer, SpanBuilder};
+use source::{ByteSpan, TokenStream};
+
+/// A derivation of part of the input.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum ParseTree {
+    /// A token of the input.
+    Leaf(Token),
+    /// A variable expanded by the rule with the given index in `get_rules`, with a subtree for
+    /// each symbol of the rule.
+    Node(Variable, uint, Vec<ParseTree>)
+}
+
+impl ParseTree {
+    /// The symbol at the root of the tree.
+    pub fn symbol(&self) -> Symbol {
+        match *self {
+            ParseTree::Leaf(t) => Symbol::Tok(t),
+            ParseTree::Node(v, _, _) => Symbol::Var(v)
+        }
+    }
+
+    /// The subtrees of the root, empty for a leaf.
+    pub fn children(&self) -> &[ParseTree] {
+        match *self {
+            ParseTree::Leaf(_) => &[],
+            ParseTree::Node(_, _, ref children) => children.as_slice()
+        }
+    }
+
+    /// The tokens at the leaves, in order.
+    pub fn tokens(&self) -> Vec<Token> {
+        self.pre_order().filter_map(|tree| match *tree {
+            ParseTree::Leaf(t) => Some(t),
+            ParseTree::Node(..) => None
+        }).collect()
+    }
+
+    /// The rules used, as variables and indices in `get_rules`, in the order a leftmost
+    /// derivation applies them.
+    pub fn leftmost_derivation(&self) -> Vec<(Variable, uint)> {
+        self.pre_order().filter_map(|tree| match *tree {
+            ParseTree::Leaf(_) => None,
+            ParseTree::Node(variable, rule, _) => Some((variable, rule))
+        }).collect()
+    }
+
+    /// Iterate over the subtrees, each before its children.
+    pub fn pre_order(&self) -> PreOrder {
+        PreOrder { stack: vec![self] }
+    }
+
+    /// Iterate over the subtrees, each after its children.
+    pub fn post_order(&self) -> PostOrder {
+        PostOrder { stack: vec![(self, 0)] }
+    }
+
+    /// Build a value from the tree bottom-up with `reducer`, as a parser would have, numbering
+    /// the tokens from 0.
+    pub fn fold<V, R: Reducer<V>>(&self, reducer: &mut R) -> V {
+        let mut position = 0;
+        self.fold_from(reducer, &mut position)
+    }
+
+    /// Convert the tree to a syntax tree of type `A`, by its `FromParse` constructors.
+    pub fn to_ast<A: FromParse>(&self) -> A {
+        self.fold(&mut AstBuilder::new())
+    }
+
+    fn fold_from<V, R: Reducer<V>>(&self, reducer: &mut R, position: &mut uint) -> V {
+        match *self {
+            ParseTree::Leaf(t) => {
+                *position += 1;
+                reducer.shift(t, *position - 1)
+            },
+            ParseTree::Node(variable, rule, ref children) => {
+                let mut values = Vec::with_capacity(children.len());
+                for child in children.iter() {
+                    values.push(child.fold_from(reducer, position));
+                }
+                reducer.reduce(variable, rule, values)
+            }
+        }
+    }
+
+    /// The tree with the span of text each subtree covers, given the tokens it was parsed from.
+    pub fn with_spans<P>(&self, stream: &TokenStream<P>) -> SpannedTree {
+        self.fold(&mut SpanBuilder::new(stream))
+    }
+
+    /// Walk the tree depth-first, calling the visitor on entering and leaving each subtree.
+    pub fn visit<V: Visitor>(&self, visitor: &mut V) {
+        match *self {
+            ParseTree::Leaf(t) => visitor.leaf(t),
+            ParseTree::Node(variable, rule, ref children) => {
+                visitor.enter(variable, rule, children.as_slice());
+                for child in children.iter() {
+                    child.visit(visitor);
+                }
+                visitor.leave(variable, rule, children.as_slice());
+            }
+        }
+    }
+}
+
+/// A parse tree in which each subtree has the span of the input text it covers, as built by
+/// `SpanBuilder`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum SpannedTree {
+    Leaf(Token, ByteSpan),
+    Node(Variable, uint, ByteSpan, Vec<SpannedTree>)
+}
+
+impl SpannedTree {
+    /// The symbol at the root of the tree.
+    pub fn symbol(&self) -> Symbol {
+        match *self {
+            SpannedTree::Leaf(t, _) => Symbol::Tok(t),
+            SpannedTree::Node(v, _, _, _) => Symbol::Var(v)
+        }
+    }
+
+    /// The span of text the tree covers.
+    pub fn span(&self) -> ByteSpan {
+        match *self {
+            SpannedTree::Leaf(_, span) | SpannedTree::Node(_, _, span, _) => span
+        }
+    }
+
+    /// The subtrees of the root, empty for a leaf.
+    pub fn children(&self) -> &[SpannedTree] {
+        match *self {
+            SpannedTree::Leaf(..) => &[],
+            SpannedTree::Node(_, _, _, ref children) => children.as_slice()
+        }
+    }
+
+    /// The tree without its spans.
+    pub fn to_tree(&self) -> ParseTree {
+        match *self {
+            SpannedTree::Leaf(t, _) => ParseTree::Leaf(t),
+            SpannedTree::Node(v, rule, _, ref children) => {
+                ParseTree::Node(v, rule, children.iter().map(|c| c.to_tree()).collect())
+            }
+        }
+    }
+
+    /// The innermost subtree whose span contains the byte at `offset`, if any.
+    pub fn find(&self, offset: uint) -> Option<&SpannedTree> {
+        let span = self.span();
+        if offset < span.start || offset >= span.end {
+            return None
+        }
+        for child in self.children().iter() {
+            match child.find(offset) {
+                Some(found) => return Some(found),
+                None => { }
+            }
+        }
+        Some(self)
+    }
+}
+
+/// Callbacks for walking a parse tree with `ParseTree::visit`. Each does nothing by default.
+pub trait Visitor {
+    /// Visit a token.
+    fn leaf(&mut self, _token: Token) { }
+
+    /// Visit a variable expanded by a rule, before its children.
+    fn enter(&mut self, _variable: Variable, _rule: uint, _children: &[ParseTree]) { }
+
+    /// Visit a variable expanded by a rule, after its children.
+    fn leave(&mut self, _variable: Variable, _rule: uint, _children: &[ParseTree]) { }
+}
+
+/// An iterator over the subtrees of a parse tree in pre-order.
+pub struct PreOrder<'a> {
+    stack: Vec<&'a ParseTree>
+}
+
+impl<'a> Iterator<&'a ParseTree> for PreOrder<'a> {
+    fn next(&mut self) -> Option<&'a ParseTree> {
+        let tree = match self.stack.pop() {
+            Some(tree) => tree,
+            None => return None
+        };
+        self.stack.extend(tree.children().iter().rev());
+        Some(tree)
+    }
+}
+
+/// An iterator over the subtrees of a parse tree in post-order.
+pub struct PostOrder<'a> {
+    /// The subtrees being walked, with how many of their children have been.
+    stack: Vec<(&'a ParseTree, uint)>
+}
+
+impl<'a> Iterator<&'a ParseTree> for PostOrder<'a> {
+    fn next(&mut self) -> Option<&'a ParseTree> {
+        loop {
+            let (tree, k) = match self.stack.pop() {
+                Some(top) => top,
+                None => return None
+            };
+            let children = tree.children();
+            if k == children.len() {
+                return Some(tree)
+            }
+            self.stack.push((tree, k + 1));
+            self.stack.push((&children[k], 0));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{ParseTree, SpannedTree, Visitor};
+    use super::super::{Symbol, Token, Variable};
+    use actions::SpanBuilder;
+    use earley;
+    use ll1::Ll1Parser;
+    use source::ByteSpan;
+    use testing::{EXPRESSIONS_LL1, grammar, variable};
+
+    /// The tree of `s -> a "y"`, `a -> "z" | "x"` deriving `x y`.
+    fn example() -> ParseTree {
+        let a = ParseTree::Node(Variable(1), 1, vec![ParseTree::Leaf(Token(0))]);
+        ParseTree::Node(Variable(0), 0, vec![a, ParseTree::Leaf(Token(1))])
+    }
+
+    #[test]
+    fn traversals() {
+        let tree = example();
+        assert_eq!(tree.symbol(), Symbol::Var(Variable(0)));
+        assert_eq!(tree.children().len(), 2);
+        assert!(tree.children()[1].children().is_empty());
+        assert_eq!(tree.tokens(), vec![Token(0), Token(1)]);
+        let pre: Vec<Symbol> = tree.pre_order().map(|t| t.symbol()).collect();
+        assert_eq!(pre, vec![Symbol::Var(Variable(0)), Symbol::Var(Variable(1)),
+                             Symbol::Tok(Token(0)), Symbol::Tok(Token(1))]);
+        let post: Vec<Symbol> = tree.post_order().map(|t| t.symbol()).collect();
+        assert_eq!(post, vec![Symbol::Tok(Token(0)), Symbol::Var(Variable(1)),
+                              Symbol::Tok(Token(1)), Symbol::Var(Variable(0))]);
+    }
+
+    struct Trace {
+        events: Vec<String>
+    }
+
+    impl Visitor for Trace {
+        fn leaf(&mut self, token: Token) {
+            self.events.push(format!("leaf {}", token.index()));
+        }
+
+        fn enter(&mut self, variable: Variable, rule: uint, children: &[ParseTree]) {
+            self.events.push(format!("enter {} {} {}", variable.index(), rule, children.len()));
+        }
+
+        fn leave(&mut self, variable: Variable, _rule: uint, _children: &[ParseTree]) {
+            self.events.push(format!("leave {}", variable.index()));
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let mut trace = Trace { events: Vec::new() };
+        example().visit(&mut trace);
+        let events: Vec<&str> = trace.events.iter().map(|e| e.as_slice()).collect();
+        assert_eq!(events, vec!["enter 0 0 2", "enter 1 1 1", "leaf 0", "leave 1", "leaf 1",
+                                "leave 0"]);
+    }
+
+    #[test]
+    fn leftmost_derivation() {
+        let b = ParseTree::Node(Variable(2), 0, vec![ParseTree::Leaf(Token(2))]);
+        let tree = ParseTree::Node(Variable(3), 0, vec![example(), b]);
+        assert_eq!(tree.leftmost_derivation(),
+                   vec![(Variable(3), 0), (Variable(0), 0), (Variable(1), 1), (Variable(2), 0)]);
+    }
+
+    fn span(start: uint, end: uint) -> ByteSpan {
+        ByteSpan { start: start, end: end }
+    }
+
+    #[test]
+    fn with_spans() {
+        let cfg = grammar(EXPRESSIONS_LL1);
+        let patterns = [("id".to_string(), "[a-z]+"),
+                        ("+".to_string(), "\\+"),
+                        ("*".to_string(), "\\*"),
+                        ("(".to_string(), "\\("),
+                        (")".to_string(), "\\)")];
+        let lexer = cfg.lexer(&patterns, &[" +"]).unwrap();
+        let stream = lexer.tokenize("id *  ( id )").unwrap();
+        let parser = Ll1Parser::new(&cfg).unwrap();
+        let tree = parser.parse(stream.tokens.as_slice()).unwrap();
+        let spanned = tree.with_spans(&stream);
+        assert_eq!(spanned.to_tree(), tree);
+        assert_eq!(parser.parse_with(stream.tokens.as_slice(), &mut SpanBuilder::new(&stream)),
+                   Ok(spanned.clone()));
+
+        assert_eq!(spanned.span(), span(0, 12));
+        let spans: Vec<ByteSpan> = spanned.children().iter().map(|c| c.span()).collect();
+        assert_eq!(spans, vec![span(0, 12), span(12, 12)]);
+        let id = cfg.token(&"id".to_string()).unwrap();
+        assert_eq!(spanned.find(9), Some(&SpannedTree::Leaf(id, span(8, 10))));
+        assert_eq!(spanned.find(0), Some(&SpannedTree::Leaf(id, span(0, 2))));
+        let between = spanned.find(5).unwrap();
+        assert_eq!(between.symbol(), Symbol::Var(variable(&cfg, "tp")));
+        assert_eq!(between.span(), span(3, 12));
+        assert_eq!(spanned.find(12), None);
+
+        let forest = earley::parse(&cfg, stream.tokens.as_slice()).unwrap();
+        assert_eq!(forest.byte_span(forest.root, &stream), span(0, 12));
+        assert_eq!(stream.span_of(1, 3), span(3, 7));
+        assert_eq!(stream.span_of(2, 2), span(6, 6));
+    }
+}
@@ -0,0 +1,270 @@
+//! Reading tree-sitter grammars from the `grammar.json` it generates.
+
+use std::collections::HashSet;
+
+use super::{Cfg, Rule, Symbol, SyntaxError};
+use import::Symbols;
+use json::Json;
+
+impl Cfg<String> {
+    /// Read a tree-sitter grammar from its `grammar.json`.
+    ///
+    /// Each rule becomes a variable, except for rules that are just a `PATTERN`, `TOKEN` or
+    /// `IMMEDIATE_TOKEN`, which become a token named after the rule, as do `externals`. Strings
+    /// become a token named by their value between double quotes, so the keyword `"number"`
+    /// stays apart from a rule `number`, and other patterns a token named by the pattern
+    /// between slashes. Precedence, fields and aliases don't affect the language and are
+    /// dropped, as are `extras`. Nested choices and repetitions are lowered to fresh variables
+    /// named after the rule they appear in. The first rule is the start symbol.
+    pub fn from_tree_sitter_json(src: &str) -> Result<Cfg<String>, SyntaxError> {
+        let grammar = try!(::json::parse(src));
+        let rules = match grammar.get("rules").and_then(|r| r.as_object()) {
+            Some(rules) => rules,
+            None => return Err(grammar.error("expected a `rules` object"))
+        };
+
+        let mut reader = Reader { syms: Symbols::new(), lexical: HashSet::new(), tokens: 0 };
+        match grammar.get("externals").and_then(|e| e.as_array()) {
+            Some(externals) => {
+                for external in externals.iter() {
+                    match external.get("name").and_then(|n| n.as_str()) {
+                        Some(name) => { reader.lexical.insert(name.to_string()); },
+                        None => { }
+                    }
+                }
+            },
+            None => { }
+        }
+        for &(ref name, ref body) in rules.iter() {
+            match try!(node_type(strip(body))) {
+                "PATTERN" | "TOKEN" | "IMMEDIATE_TOKEN" => {
+                    reader.lexical.insert(name.clone());
+                },
+                _ => { }
+            }
+        }
+
+        for &(ref name, ref body) in rules.iter() {
+            if reader.lexical.contains(name) {
+                continue
+            }
+            let lhs = reader.syms.define(name.as_slice());
+            let body = strip(body);
+            if try!(node_type(body)) == "CHOICE" {
+                for member in try!(members(body)).iter() {
+                    let rule = try!(reader.lower(name.as_slice(), member));
+                    reader.syms.add_rule(lhs, rule);
+                }
+            } else {
+                let rule = try!(reader.lower(name.as_slice(), body));
+                reader.syms.add_rule(lhs, rule);
+            }
+        }
+        reader.syms.finish()
+    }
+}
+
+struct Reader {
+    syms: Symbols<String>,
+    /// Rules and externals that stand for tokens.
+    lexical: HashSet<String>,
+    /// The number of anonymous tokens made from complex `TOKEN` nodes.
+    tokens: uint
+}
+
+impl Reader {
+    fn lower(&mut self, rule: &str, node: &Json) -> Result<Rule, SyntaxError> {
+        let node = strip(node);
+        Ok(match try!(node_type(node)) {
+            "BLANK" => vec![],
+            "STRING" => vec![self.syms.token(format!("\"{}\"", try!(string(node, "value"))))],
+            "PATTERN" => vec![self.syms.token(format!("/{}/", try!(string(node, "value"))))],
+            "SYMBOL" => {
+                let name = try!(string(node, "name"));
+                if self.lexical.contains(&name.to_string()) {
+                    vec![self.syms.token(name.to_string())]
+                } else {
+                    vec![Symbol::Var(self.syms.variable(name, node.at))]
+                }
+            },
+            "SEQ" => {
+                let mut seq = Vec::new();
+                for member in try!(members(node)).iter() {
+                    seq.extend(try!(self.lower(rule, member)).into_iter());
+                }
+                seq
+            },
+            "CHOICE" => {
+                let mut bodies = Vec::new();
+                for member in try!(members(node)).iter() {
+                    bodies.push(try!(self.lower(rule, member)));
+                }
+                self.syms.choice(rule, bodies)
+            },
+            "REPEAT" => {
+                let item = try!(self.lower(rule, try!(content(node))));
+                vec![self.syms.star(rule, item)]
+            },
+            "REPEAT1" => {
+                let mut item = try!(self.lower(rule, try!(content(node))));
+                let rest = self.syms.star(rule, item.clone());
+                item.push(rest);
+                item
+            },
+            "TOKEN" | "IMMEDIATE_TOKEN" => {
+                let inner = strip(try!(content(node)));
+                let name = match try!(node_type(inner)) {
+                    "STRING" => format!("\"{}\"", try!(string(inner, "value"))),
+                    "PATTERN" => format!("/{}/", try!(string(inner, "value"))),
+                    _ => {
+                        self.tokens += 1;
+                        format!("{}_token_{}", rule, self.tokens)
+                    }
+                };
+                vec![self.syms.token(name)]
+            },
+            other => return Err(node.error(format!("unknown rule type `{}`", other).as_slice()))
+        })
+    }
+}
+
+/// Look through the nodes that don't affect the language: precedence, fields, and aliases.
+fn strip(node: &Json) -> &Json {
+    let mut node = node;
+    loop {
+        match node_type(node) {
+            Ok("PREC") | Ok("PREC_LEFT") | Ok("PREC_RIGHT") | Ok("PREC_DYNAMIC") | Ok("FIELD") |
+            Ok("ALIAS") | Ok("RESERVED") => match node.get("content") {
+                Some(inner) => node = inner,
+                None => return node
+            },
+            _ => return node
+        }
+    }
+}
+
+fn node_type(node: &Json) -> Result<&str, SyntaxError> {
+    string(node, "type")
+}
+
+fn string<'a>(node: &'a Json, key: &str) -> Result<&'a str, SyntaxError> {
+    match node.get(key).and_then(|v| v.as_str()) {
+        Some(s) => Ok(s),
+        None => Err(node.error(format!("expected a string `{}`", key).as_slice()))
+    }
+}
+
+fn members(node: &Json) -> Result<&[Json], SyntaxError> {
+    match node.get("members").and_then(|m| m.as_array()) {
+        Some(members) => Ok(members),
+        None => Err(node.error("expected a `members` array"))
+    }
+}
+
+fn content(node: &Json) -> Result<&Json, SyntaxError> {
+    match node.get("content") {
+        Some(content) => Ok(content),
+        None => Err(node.error("expected a `content` node"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Cfg, Symbol};
+    use testing::{rules, variable};
+
+    static ARITH: &'static str = r#"{
+        "name": "arith",
+        "externals": [{"type": "SYMBOL", "name": "heredoc"}],
+        "rules": {
+            "program": {"type": "REPEAT", "content": {"type": "SYMBOL", "name": "statement"}},
+            "statement": {"type": "CHOICE", "members": [
+                {"type": "SEQ", "members": [
+                    {"type": "FIELD", "name": "value",
+                     "content": {"type": "SYMBOL", "name": "expression"}},
+                    {"type": "STRING", "value": ";"}
+                ]},
+                {"type": "SYMBOL", "name": "heredoc"}
+            ]},
+            "expression": {"type": "CHOICE", "members": [
+                {"type": "PREC_LEFT", "value": 1, "content": {"type": "SEQ", "members": [
+                    {"type": "SYMBOL", "name": "expression"},
+                    {"type": "STRING", "value": "+"},
+                    {"type": "SYMBOL", "name": "expression"}
+                ]}},
+                {"type": "SYMBOL", "name": "number"},
+                {"type": "REPEAT1", "content": {"type": "PATTERN", "value": "[a-z]"}}
+            ]},
+            "number": {"type": "TOKEN", "content": {"type": "PATTERN", "value": "[0-9]+"}}
+        }
+    }"#;
+
+    #[test]
+    fn arithmetic() {
+        let cfg = Cfg::from_tree_sitter_json(ARITH).unwrap();
+        let token = |name: &str| Symbol::Tok(cfg.token(&name.to_string()).unwrap());
+        let statement = Symbol::Var(variable(&cfg, "statement"));
+        let expression = Symbol::Var(variable(&cfg, "expression"));
+        // `;`, `+`, the external `heredoc`, the token rule `number` and the pattern `[a-z]`.
+        assert_eq!(cfg.num_tokens(), 5);
+        assert_eq!(cfg.get_start(), variable(&cfg, "program"));
+
+        let program = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(program.len(), 1);
+        let star = program[0][0];
+        assert_eq!(rules(&cfg, star), [vec![statement, star], vec![]].as_slice());
+
+        let statements = cfg.get_rules(variable(&cfg, "statement")).unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!((statements[0].len(), statements[0][0]), (2, expression));
+        assert_eq!(statements[1], vec![token("heredoc")]);
+
+        let expressions = cfg.get_rules(variable(&cfg, "expression")).unwrap();
+        assert_eq!(expressions.len(), 3);
+        assert_eq!((expressions[0].len(), expressions[0][0], expressions[0][2]),
+                   (3, expression, expression));
+        assert_eq!(expressions[1], vec![token("number")]);
+        let letter = token("/[a-z]/");
+        assert_eq!(expressions[2][0], letter);
+        let expected = [vec![letter, expressions[2][1]], vec![]];
+        assert_eq!(rules(&cfg, expressions[2][1]), expected.as_slice());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Cfg::from_tree_sitter_json(r#"{"name": "x"}"#).is_err());
+        assert!(Cfg::from_tree_sitter_json(r#"{"rules": {"a": {"type": "SYMBOL", "name": "b"}}}"#)
+                   .is_err());
+        let error = Cfg::from_tree_sitter_json(r#"{"rules": {"a": {"type": "MAYBE"}}}"#)
+                        .unwrap_err();
+        assert_eq!((error.line, error.column), (1, 17));
+    }
+
+    #[test]
+    fn strings_and_rules_stay_apart() {
+        let cfg = Cfg::from_tree_sitter_json(r#"{
+            "name": "numbers",
+            "rules": {
+                "value": {
+                    "type": "CHOICE",
+                    "members": [
+                        {"type": "SYMBOL", "name": "number"},
+                        {"type": "SEQ", "members": [
+                            {"type": "STRING", "value": "number"},
+                            {"type": "TOKEN", "content": {"type": "STRING", "value": "("}},
+                            {"type": "SYMBOL", "name": "number"},
+                            {"type": "STRING", "value": ")"}
+                        ]}
+                    ]
+                },
+                "number": {"type": "PATTERN", "value": "[0-9]+"}
+            }
+        }"#).unwrap();
+        let token = |name: &str| cfg.token(&name.to_string()).unwrap();
+        assert_eq!(cfg.num_tokens(), 4);
+        let values = cfg.get_rules(cfg.get_start()).unwrap();
+        assert_eq!(values[0], vec![Symbol::Tok(token("number"))]);
+        assert_eq!(values[1], vec![Symbol::Tok(token("\"number\"")), Symbol::Tok(token("\"(\"")),
+                                  Symbol::Tok(token("number")), Symbol::Tok(token("\")\""))]);
+    }
+}
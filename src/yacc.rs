@@ -0,0 +1,456 @@
+//! Reading the grammar of a Yacc or Bison `.y` file.
+//!
+//! Only the declarations that introduce tokens, their precedence and the start symbol are
+//! interpreted, along with the rules section; C code, semantic actions and the epilogue are
+//! skipped.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Associativity, Cfg, Rule, SourceMap, Span, Symbol, SyntaxError, Token, Variable};
+use import::{Mark, Scanner, Symbols, body_span, is_space, span};
+
+/// Parse the grammar of a Yacc or Bison file.
+///
+/// Tokens are those declared by `%token`, `%left`, `%right`, `%nonassoc` or `%precedence`,
+/// together with the predefined `error` token and any character or string literals used in rules.
+/// Declared tokens are named by their identifier, with a string alias such as the `"=="` in
+/// `%token EQ "=="` standing for the same token; other literals are named by their quoted text,
+/// e.g. `'+'`. The start symbol is given by `%start`, or is the variable of the first rule.
+///
+/// Each `%left`, `%right`, `%nonassoc` or `%precedence` line gives its tokens a precedence level
+/// one higher than the line before, and a rule's `%prec` gives it the precedence of a token.
+pub fn parse(src: &str) -> Result<Cfg<String>, SyntaxError> {
+    parse_with_spans(src).map(|(cfg, _)| cfg)
+}
+
+/// Parse the grammar of a Yacc or Bison file as for `parse`, also giving where each variable was
+/// first defined and each rule written, including its actions.
+pub fn parse_with_spans(src: &str) -> Result<(Cfg<String>, SourceMap), SyntaxError> {
+    let mut reader = Reader {
+        syms: Symbols::new(),
+        tokens: HashSet::new(),
+        aliases: HashMap::new(),
+        start: None,
+        level: 0,
+        precedence: Vec::new(),
+        rule_precedence: Vec::new()
+    };
+    let mut s = Scanner::new(src);
+    try!(reader.declarations(&mut s));
+    try!(reader.rules(&mut s));
+    match reader.start.take() {
+        Some((name, at)) => {
+            let v = reader.syms.variable(name.as_slice(), at);
+            reader.syms.set_start(v);
+        },
+        None => { }
+    }
+    let (mut cfg, spans) = try!(reader.syms.finish_with_spans());
+    for &(t, level, associativity) in reader.precedence.iter() {
+        cfg.set_precedence(t, level, associativity).unwrap();
+    }
+    for &(v, rule, t) in reader.rule_precedence.iter() {
+        cfg.set_rule_precedence(v, rule, t).unwrap();
+    }
+    Ok((cfg, spans))
+}
+
+struct Reader {
+    syms: Symbols<String>,
+    tokens: HashSet<String>,
+    /// The token named by each string alias.
+    aliases: HashMap<String, String>,
+    start: Option<(String, Mark)>,
+    /// The precedence level of the last `%left`-like declaration.
+    level: uint,
+    precedence: Vec<(Token, uint, Associativity)>,
+    /// The `%prec` token of each rule, by the index of the rule.
+    rule_precedence: Vec<(Variable, uint, Token)>
+}
+
+impl Reader {
+    fn declare(&mut self, name: String) -> Token {
+        self.tokens.insert(name.clone());
+        self.token(name)
+    }
+
+    fn token(&mut self, name: String) -> Token {
+        match self.syms.token(name) {
+            Symbol::Tok(t) => t,
+            Symbol::Var(_) => unreachable!()
+        }
+    }
+
+    /// The name of the token standing for the string literal `lit`.
+    fn literal(&self, lit: String) -> String {
+        match self.aliases.get(&lit) {
+            Some(name) => name.clone(),
+            None => format!("\"{}\"", lit)
+        }
+    }
+
+    fn declarations(&mut self, s: &mut Scanner) -> Result<(), SyntaxError> {
+        loop {
+            try!(skip_space(s));
+            if s.at_end() {
+                return Err(s.error("expected `%%` before the rules"))
+            }
+            if s.eat("%%") {
+                return Ok(())
+            }
+            if s.eat("%{") {
+                while !s.eat("%}") {
+                    if s.bump().is_none() {
+                        return Err(s.error("unterminated `%{`"))
+                    }
+                }
+                continue
+            }
+            if !s.eat("%") {
+                return Err(s.error("expected a declaration"))
+            }
+            let directive = s.take_while(in_ident);
+            match directive.as_slice() {
+                "token" => try!(self.token_list(s, None)),
+                "left" | "right" | "nonassoc" | "precedence" => {
+                    let associativity = match directive.as_slice() {
+                        "left" => Associativity::Left,
+                        "right" => Associativity::Right,
+                        "nonassoc" => Associativity::NonAssoc,
+                        _ => Associativity::Unspecified
+                    };
+                    self.level += 1;
+                    try!(self.token_list(s, Some(associativity)));
+                },
+                "start" => {
+                    try!(skip_space(s));
+                    let at = s.mark();
+                    let name = s.take_while(in_ident);
+                    if name.is_empty() {
+                        return Err(s.error("expected the start symbol"))
+                    }
+                    self.start = Some((name, at));
+                },
+                _ => try!(skip_directive(s))
+            }
+        }
+    }
+
+    /// Read the symbols declared by a `%token`-like directive, giving them the current
+    /// precedence level if the directive has an associativity.
+    fn token_list(&mut self, s: &mut Scanner, associativity: Option<Associativity>)
+                  -> Result<(), SyntaxError> {
+        let mut last = None;
+        loop {
+            try!(skip_space(s));
+            let token = match s.peek() {
+                Some('<') => {
+                    while s.bump().map_or(false, |c| c != '>') { }
+                    continue
+                },
+                Some('\'') => {
+                    let c = try!(s.quoted());
+                    last = None;
+                    self.declare(format!("'{}'", c))
+                },
+                Some('"') => {
+                    let alias = try!(s.quoted());
+                    match last.take() {
+                        Some(name) => {
+                            self.aliases.insert(alias, name);
+                            continue
+                        },
+                        None => self.declare(format!("\"{}\"", alias))
+                    }
+                },
+                Some(c) if c.is_digit(10) => {
+                    s.skip_while(is_digit);
+                    continue
+                },
+                Some(c) if is_ident_start(c) => {
+                    let name = s.take_while(in_ident);
+                    last = Some(name.clone());
+                    self.declare(name)
+                },
+                _ => return Ok(())
+            };
+            match associativity {
+                Some(a) => self.precedence.push((token, self.level, a)),
+                None => { }
+            }
+        }
+    }
+
+    fn rules(&mut self, s: &mut Scanner) -> Result<(), SyntaxError> {
+        loop {
+            try!(skip_space(s));
+            if s.at_end() || s.looking_at("%%") {
+                return Ok(())
+            }
+            if !s.peek().map_or(false, is_ident_start) {
+                return Err(s.error("expected a rule"))
+            }
+            let at = s.mark();
+            let name = s.take_while(in_ident);
+            let lhs = self.syms.define_at(name.as_slice(), span(at, s.mark()));
+            try!(skip_space(s));
+            try!(skip_named_ref(s));
+            if !s.eat(":") {
+                return Err(s.error("expected `:`"))
+            }
+            loop {
+                let (body, prec, rule_span) = try!(self.alternative(s));
+                match prec {
+                    Some(t) => self.rule_precedence.push((lhs, self.syms.num_rules(lhs), t)),
+                    None => { }
+                }
+                self.syms.add_rule_at(lhs, body, rule_span);
+                if !s.eat("|") {
+                    break
+                }
+            }
+            s.eat(";");
+        }
+    }
+
+    /// Read the body of a rule, along with the token given by its `%prec`, if any, and where it's
+    /// written.
+    fn alternative(&mut self, s: &mut Scanner)
+                   -> Result<(Rule, Option<Token>, Span), SyntaxError> {
+        let before = s.mark();
+        try!(skip_space(s));
+        let start = s.mark();
+        let mut end = start;
+        let mut body = Vec::new();
+        let mut prec = None;
+        loop {
+            try!(skip_space(s));
+            match s.peek() {
+                Some('{') => {
+                    try!(skip_braced(s));
+                    end = s.mark();
+                },
+                Some('\'') => {
+                    let c = try!(s.quoted());
+                    body.push(self.syms.token(format!("'{}'", c)));
+                    end = s.mark();
+                },
+                Some('"') => {
+                    let lit = try!(s.quoted());
+                    let name = self.literal(lit);
+                    body.push(self.syms.token(name));
+                    end = s.mark();
+                },
+                Some('%') if !s.looking_at("%%") => {
+                    s.bump();
+                    let directive = s.take_while(in_ident);
+                    match directive.as_slice() {
+                        "empty" => { },
+                        "prec" => prec = Some(try!(self.prec_token(s))),
+                        "dprec" | "merge" => try!(skip_argument(s)),
+                        _ => return Err(s.error(format!("unexpected `%{}` in a rule",
+                                                        directive).as_slice()))
+                    }
+                    end = s.mark();
+                },
+                Some(c) if is_ident_start(c) => {
+                    if starts_rule(s) {
+                        return Ok((body, prec, body_span(before, start, end)))
+                    }
+                    let at = s.mark();
+                    let name = s.take_while(in_ident);
+                    end = s.mark();
+                    try!(skip_space(s));
+                    try!(skip_named_ref(s));
+                    if self.tokens.contains(&name) || name.as_slice() == "error" {
+                        body.push(self.syms.token(name));
+                    } else {
+                        body.push(Symbol::Var(self.syms.variable(name.as_slice(), at)));
+                    }
+                },
+                _ => return Ok((body, prec, body_span(before, start, end)))
+            }
+        }
+    }
+
+    /// Read the token named by `%prec`.
+    fn prec_token(&mut self, s: &mut Scanner) -> Result<Token, SyntaxError> {
+        try!(skip_space(s));
+        let name = match s.peek() {
+            Some('\'') => format!("'{}'", try!(s.quoted())),
+            Some('"') => {
+                let lit = try!(s.quoted());
+                self.literal(lit)
+            },
+            Some(c) if is_ident_start(c) => {
+                let at = s.mark();
+                let name = s.take_while(in_ident);
+                if !self.tokens.contains(&name) {
+                    s.reset(at);
+                    return Err(s.error(format!("`{}` is not a token", name).as_slice()))
+                }
+                name
+            },
+            _ => return Err(s.error("expected a token after `%prec`"))
+        };
+        Ok(self.token(name))
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '.'
+}
+
+fn in_ident(c: char) -> bool {
+    is_ident_start(c) || c.is_digit(10) || c == '-'
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_digit(10)
+}
+
+/// Skip whitespace and C comments.
+fn skip_space(s: &mut Scanner) -> Result<(), SyntaxError> {
+    loop {
+        s.skip_while(is_space);
+        if s.looking_at("//") {
+            s.skip_line();
+        } else if s.looking_at("/*") {
+            let start = s.mark();
+            s.eat("/*");
+            while !s.eat("*/") {
+                if s.bump().is_none() {
+                    s.reset(start);
+                    return Err(s.error("unterminated comment"))
+                }
+            }
+        } else {
+            return Ok(())
+        }
+    }
+}
+
+/// Skip a brace-delimited block of C code, such as an action.
+fn skip_braced(s: &mut Scanner) -> Result<(), SyntaxError> {
+    let start = s.mark();
+    let mut depth = 0u;
+    loop {
+        try!(skip_space(s));
+        match s.peek() {
+            Some('{') => depth += 1,
+            Some('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    s.bump();
+                    return Ok(())
+                }
+            },
+            Some('"') | Some('\'') => {
+                try!(s.quoted());
+                continue
+            },
+            Some(_) => { },
+            None => {
+                s.reset(start);
+                return Err(s.error("unterminated code block"))
+            }
+        }
+        s.bump();
+    }
+}
+
+/// Skip the rest of a declaration that doesn't affect the grammar, including any code block.
+fn skip_directive(s: &mut Scanner) -> Result<(), SyntaxError> {
+    loop {
+        match s.peek() {
+            Some('{') => try!(skip_braced(s)),
+            Some('\n') | None => return Ok(()),
+            Some('"') | Some('\'') => { try!(s.quoted()); },
+            Some(_) => { s.bump(); }
+        }
+    }
+}
+
+/// Skip a named reference such as the `[left]` in `expr[left]`.
+fn skip_named_ref(s: &mut Scanner) -> Result<(), SyntaxError> {
+    if s.eat("[") {
+        s.skip_while(in_ident);
+        if !s.eat("]") {
+            return Err(s.error("expected `]`"))
+        }
+        try!(skip_space(s));
+    }
+    Ok(())
+}
+
+/// Skip the argument of `%dprec` or `%merge`.
+fn skip_argument(s: &mut Scanner) -> Result<(), SyntaxError> {
+    try!(skip_space(s));
+    match s.peek() {
+        Some('\'') | Some('"') => { try!(s.quoted()); },
+        Some('<') => {
+            while s.bump().map_or(false, |c| c != '>') { }
+        },
+        _ => s.skip_while(in_ident)
+    }
+    Ok(())
+}
+
+/// Whether the cursor is at the `name:` beginning the next rule.
+fn starts_rule(s: &mut Scanner) -> bool {
+    let mark = s.mark();
+    s.skip_while(in_ident);
+    let starts = skip_space(s).is_ok() && skip_named_ref(s).is_ok() && s.looking_at(":");
+    s.reset(mark);
+    starts
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use super::super::Symbol;
+    use testing::variable;
+
+    static CALC: &'static str = r#"
+    %{
+    #include <stdio.h>
+    %}
+    %token NUM
+    %token EQ "=="
+    %left '+' '-'
+    %start stmt
+    %%
+    expr : expr '+' expr { $$ = $1 + $3; }
+         | NUM
+         | '(' expr[inner] ')' /* parenthesised */
+         ;
+    stmt : expr "==" expr | %empty | error ;
+    %%
+    int main(void) { return yyparse(); }
+    "#;
+
+    #[test]
+    fn calculator() {
+        let cfg = parse(CALC).unwrap();
+        let token = |name: &str| Symbol::Tok(cfg.token(&name.to_string()).unwrap());
+        let (expr, stmt) = (Symbol::Var(variable(&cfg, "expr")), variable(&cfg, "stmt"));
+        assert_eq!(cfg.get_start(), stmt);
+        assert_eq!(cfg.get_rules(variable(&cfg, "expr")).unwrap(),
+                   [vec![expr, token("'+'"), expr], vec![token("NUM")],
+                    vec![token("'('"), expr, token("')'")]].as_slice());
+        assert_eq!(cfg.get_rules(stmt).unwrap(),
+                   [vec![expr, token("EQ"), expr], vec![], vec![token("error")]].as_slice());
+        // Declared tokens are part of the grammar even when unused.
+        assert!(cfg.token(&"'-'".to_string()).is_some());
+        assert!(cfg.token(&"\"==\"".to_string()).is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse("%token A\n").is_err());
+        let error = parse("%%\ns : A %foo ;\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 11));
+        assert!(parse("%%\ns : t ;\n").is_err());
+        assert!(parse("%%\ns : A { unterminated ;\n").is_err());
+    }
+}